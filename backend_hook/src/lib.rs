@@ -1,9 +1,7 @@
 use flowsnet_platform_sdk::logger;
-use gosim_project::db_manipulate::*;
+use gosim_project::backend_api::{self, ApiResponse};
 use gosim_project::db_populate::*;
-use gosim_project::vector_search::*;
-use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::collections::HashMap;
 use webhook_flows::{
     create_endpoint, request_handler,
@@ -11,15 +9,6 @@ use webhook_flows::{
     send_response,
 };
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
-pub struct BodyLoad {
-    pub issue_id: Option<String>,
-    pub issue_budget: Option<i64>,
-    pub admin_feedback: Option<String>,
-    pub issue_budget_approved: Option<bool>,
-    pub review_status_flipper: Option<bool>,
-}
-
 #[no_mangle]
 #[tokio::main(flavor = "current_thread")]
 pub async fn on_deploy() {
@@ -49,7 +38,13 @@ async fn handler(
         .insert("/issue", vec![post(get_issue_w_comments_by_post_handler)])
         .unwrap();
     router
-        .insert("/projects", vec![get(list_projects_handler)])
+        .insert(
+            "/projects",
+            vec![
+                get(list_projects_handler),
+                post(list_projects_by_post_handler),
+            ],
+        )
         .unwrap();
     router
         .insert("/budget", vec![post(approve_issue_budget_handler)])
@@ -64,6 +59,30 @@ async fn handler(
     router
         .insert("/conclude", vec![post(conclude_issue_handler)])
         .unwrap();
+    router
+        .insert("/runs", vec![get(list_runs_handler)])
+        .unwrap();
+    router
+        .insert("/history", vec![post(issue_history_handler)])
+        .unwrap();
+    router
+        .insert("/review", vec![post(review_issue_handler)])
+        .unwrap();
+    router
+        .insert("/adjust", vec![post(adjust_budget_handler)])
+        .unwrap();
+    router
+        .insert("/states", vec![post(issue_states_handler)])
+        .unwrap();
+    router
+        .insert("/ledger", vec![post(ledger_handler)])
+        .unwrap();
+    router
+        .insert("/export", vec![post(export_handler)])
+        .unwrap();
+    router
+        .insert("/stats/budget", vec![get(budget_stats_handler)])
+        .unwrap();
 
     if let Err(e) = route(router).await {
         match e {
@@ -77,54 +96,17 @@ async fn handler(
     }
 }
 
+fn send_api_response(res: ApiResponse) {
+    send_response(res.status, res.headers, res.body);
+}
+
 async fn approve_issue_budget_handler(
-    _headers: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    let load: BodyLoad = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse body: {}", _e);
-            return;
-        }
-    };
-
-    let issue_budget = load.issue_budget.unwrap_or_default();
-    let issue_id = load.issue_id.unwrap_or_default();
     let pool = get_pool().await;
-    let success_str = format!("{issue_id} approved for budget: {issue_budget}");
-    let fail_str = format!("budget approval operation failed on {issue_id}");
-    match assign_issue_budget_in_db(&pool, &issue_id, issue_budget).await {
-        Ok(()) => send_response(
-            200,
-            vec![
-                (
-                    String::from("content-type"),
-                    String::from("application/json"),
-                ),
-                (
-                    String::from("Access-Control-Allow-Origin"),
-                    String::from("*"),
-                ),
-            ],
-            success_str.as_bytes().to_vec(),
-        ),
-        Err(_) => send_response(
-            500,
-            vec![
-                (
-                    String::from("content-type"),
-                    String::from("application/json"),
-                ),
-                (
-                    String::from("Access-Control-Allow-Origin"),
-                    String::from("*"),
-                ),
-            ],
-            fail_str.as_bytes().to_vec(),
-        ),
-    }
+    send_api_response(backend_api::approve_issue_budget(&pool, &headers, &_body).await);
 }
 
 async fn search_handler(
@@ -132,118 +114,25 @@ async fn search_handler(
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    #[derive(Serialize, Deserialize, Clone, Debug, Default)]
-    pub struct SearchLoad {
-        pub query: String,
-    }
-
-    let load: SearchLoad = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse body: {}", _e);
-            return;
-        }
-    };
-
-    let query = load.query;
-    match search_collection_hybrid(&query, "gosim_search").await {
-        Ok(search_result) => {
-            let search_result_str = json!(search_result).to_string();
-
-            send_response(
-                200,
-                vec![
-                    (
-                        String::from("content-type"),
-                        String::from("application/json"),
-                    ),
-                    (
-                        String::from("Access-Control-Allow-Origin"),
-                        String::from("*"),
-                    ),
-                ],
-                search_result_str.as_bytes().to_vec(),
-            );
-        }
-        Err(e) => {
-            log::error!("Error searching vector db: {:?}", e);
-        }
-    }
+    send_api_response(backend_api::search(&_body).await);
 }
 
 async fn conclude_issue_handler(
-    _headers: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    let load: BodyLoad = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse body: {}", _e);
-            return;
-        }
-    };
-
-    let approve = load.issue_budget_approved.unwrap_or_default();
-    let issue_id = load.issue_id.unwrap_or_default();
     let pool = get_pool().await;
-    if approve {
-        let _ = conclude_issue_in_db(&pool, &issue_id).await;
-    }
+    send_api_response(backend_api::conclude_issue(&pool, &headers, &_body).await);
 }
 
 async fn batch_decline_issue_handler(
-    _headers: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    #[derive(Serialize, Deserialize)]
-    struct IssueIds {
-        issue_ids: Vec<String>,
-    }
-    let load: IssueIds = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse IssueSubset: {}", _e);
-            return;
-        }
-    };
-
-    let issue_ids = load.issue_ids;
     let pool = get_pool().await;
-    match batch_decline_issues_in_db(&pool, issue_ids).await {
-        Ok(_) => {
-            send_response(
-                200,
-                vec![
-                    (String::from("content-type"), String::from("plain/text")),
-                    (
-                        String::from("Access-Control-Allow-Origin"),
-                        String::from("*"),
-                    ),
-                ],
-                "all issue_ids successfully processed".as_bytes().to_vec(),
-            );
-        }
-        Err(failed_ids) => {
-            log::error!("Error, failed processing these: {:?}", failed_ids);
-            let fail_str = json!(failed_ids).to_string();
-            send_response(
-                500,
-                vec![
-                    (
-                        String::from("content-type"),
-                        String::from("application/json"),
-                    ),
-                    (
-                        String::from("Access-Control-Allow-Origin"),
-                        String::from("*"),
-                    ),
-                ],
-                fail_str.as_bytes().to_vec(),
-            );
-        }
-    }
+    send_api_response(backend_api::batch_decline_issues(&pool, &headers, &_body).await);
 }
 
 async fn list_issues_by_get_handler(
@@ -251,99 +140,17 @@ async fn list_issues_by_get_handler(
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    let page = match _qry
-        .get("page")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page' parameter");
-            1
-        }
-    };
-
-    let page_size = match _qry
-        .get("page_size")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page_size' parameter");
-            5
-        }
-    };
-
-    let list_by: Option<&str> = _qry.get("list_by").and_then(|v| v.as_str());
-    log::info!(
-        "page: {} page_size: {}, list_by: {:?}",
-        page,
-        page_size,
-        list_by
-    );
-
     let pool = get_pool().await;
-    let issues_obj = list_issues_by_single(&pool, list_by, page, page_size)
-        .await
-        .expect("msg");
-
-    let issues_str = json!(issues_obj).to_string();
-
-    send_response(
-        200,
-        vec![
-            (String::from("content-type"), String::from("text/html")),
-            (
-                String::from("Access-Control-Allow-Origin"),
-                String::from("*"),
-            ),
-        ],
-        issues_str.as_bytes().to_vec(),
-    );
+    send_api_response(backend_api::list_issues(&pool, &_qry).await);
 }
 
 async fn get_issue_w_comments_by_post_handler(
-    _headers: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    #[derive(Serialize, Deserialize)]
-    struct IssueId {
-        issue_id: String,
-    }
-    let load: IssueId = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse IssueSubset: {}", _e);
-            return;
-        }
-    };
-
-    let issue_id = &load.issue_id;
-
-    log::info!("Issue_id: {}", issue_id);
     let pool = get_pool().await;
-
-    let issue = get_issue_w_comments_by_id(&pool, issue_id)
-        .await
-        .expect("msg");
-
-    let issues_str = json!(issue).to_string();
-    log::info!("issues_str: {}", issues_str);
-
-    send_response(
-        200,
-        vec![
-            (
-                String::from("content-type"),
-                String::from("application/json"),
-            ),
-            (
-                String::from("Access-Control-Allow-Origin"),
-                String::from("*"),
-            ),
-        ],
-        issues_str.as_bytes().to_vec(),
-    );
+    send_api_response(backend_api::get_issue_w_comments(&pool, &headers, &_body).await);
 }
 
 async fn list_projects_handler(
@@ -351,49 +158,17 @@ async fn list_projects_handler(
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    log::info!("Received query parameters: {:?}", _qry);
-
-    let page = match _qry
-        .get("page")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page' parameter");
-            return;
-        }
-    };
-
-    let page_size = match _qry
-        .get("page_size")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page_size' parameter");
-            return;
-        }
-    };
-    let list_by = _qry.get("list_by").and_then(|v| v.as_str());
-    log::info!(
-        "page: {} page_size: {}, list_by: {:?}",
-        page,
-        page_size,
-        list_by
-    );
-
     let pool = get_pool().await;
-    let projects_obj = list_projects_by(&pool, list_by, page, page_size)
-        .await
-        .expect("msg");
-
-    let projects_str = json!(projects_obj).to_string();
+    send_api_response(backend_api::list_projects(&pool, &_qry).await);
+}
 
-    send_response(
-        200,
-        vec![(String::from("content-type"), String::from("text/html"))],
-        projects_str.as_bytes().to_vec(),
-    );
+async fn list_projects_by_post_handler(
+    _headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::list_projects_filtered(&pool, &_qry, &_body).await);
 }
 
 async fn list_issues_multi_by_post_handler(
@@ -401,67 +176,78 @@ async fn list_issues_multi_by_post_handler(
     _qry: HashMap<String, Value>,
     _body: Vec<u8>,
 ) {
-    let page = match _qry
-        .get("page")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page' parameter");
-            1
-        }
-    };
+    let pool = get_pool().await;
+    send_api_response(backend_api::list_issues_multi(&pool, &_qry, &_body).await);
+}
 
-    let page_size = match _qry
-        .get("page_size")
-        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
-    {
-        Some(m) if m > 0 => m,
-        _ => {
-            log::error!("Invalid or missing 'page_size' parameter");
-            5
-        }
-    };
-    log::info!("page: {}, page_size: {}", page, page_size);
+async fn list_runs_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::list_runs(&pool, &headers, &_qry).await);
+}
 
-    #[derive(Serialize, Deserialize)]
-    struct Filters {
-        filter_strs: Vec<String>,
-    }
-    let load: Filters = match serde_json::from_slice(&_body) {
-        Ok(obj) => obj,
-        Err(_e) => {
-            log::error!("failed to parse IssueSubset: {}", _e);
-            return;
-        }
-    };
+async fn issue_history_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::issue_history(&pool, &headers, &_body).await);
+}
 
-    let filter_strs = &load.filter_strs;
+async fn review_issue_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::review_issue(&pool, &headers, &_body).await);
+}
 
-    log::info!(
-        "page: {} page_size: {}, list_by: {:?}",
-        page,
-        page_size,
-        filter_strs
-    );
+async fn adjust_budget_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::adjust_budget(&pool, &headers, &_body).await);
+}
+
+async fn issue_states_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::issue_states(&pool, &headers, &_body).await);
+}
 
+async fn ledger_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
     let pool = get_pool().await;
+    send_api_response(backend_api::ledger(&pool, &headers, &_body).await);
+}
 
-    let filter_str_slices: Vec<&str> = filter_strs.iter().map(|s| s.as_str()).collect();
-    let issues_obj = list_issues_by_multi(&pool, filter_str_slices, page, page_size)
-        .await
-        .expect("Failed to list issues");
-    let issues_str = json!(issues_obj).to_string();
+async fn export_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::export(&pool, &headers, &_body).await);
+}
 
-    send_response(
-        200,
-        vec![
-            (String::from("content-type"), String::from("text/html")),
-            (
-                String::from("Access-Control-Allow-Origin"),
-                String::from("*"),
-            ),
-        ],
-        issues_str.as_bytes().to_vec(),
-    );
+async fn budget_stats_handler(
+    headers: Vec<(String, String)>,
+    _qry: HashMap<String, Value>,
+    _body: Vec<u8>,
+) {
+    let pool = get_pool().await;
+    send_api_response(backend_api::budget_stats(&pool, &headers, &_qry).await);
 }
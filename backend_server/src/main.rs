@@ -0,0 +1,136 @@
+use dotenv::dotenv;
+use gosim_project::backend_api::{self, ApiResponse};
+use gosim_project::db_migrate::{ensure_schema_current, run_migrations};
+use gosim_project::db_populate::get_pool;
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_LENGTH;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use mysql_async::Pool;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+// Every route takes a small JSON body, anything past this is refused with a 413.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+// Serves the backend_hook routes without flows.network, listening on
+// BACKEND_ADDR (default 127.0.0.1:8080). Refuses to start on an out of date schema.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let addr: SocketAddr = std::env::var("BACKEND_ADDR")
+        .unwrap_or_else(|_| String::from("127.0.0.1:8080"))
+        .parse()?;
+    let pool = get_pool().await;
+
+    // GOSIM_AUTO_MIGRATE=1 applies pending migrations, otherwise an old schema stops startup
+    if matches!(std::env::var("GOSIM_AUTO_MIGRATE").as_deref(), Ok("1")) {
+        let applied = run_migrations(&pool).await?;
+        log::info!("{} migrations applied", applied.len());
+    } else {
+        ensure_schema_current(&pool).await?;
+    }
+
+    let make_svc = make_service_fn(move |_conn| {
+        let pool = pool.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(pool.clone(), req))) }
+    });
+
+    log::info!("Listening on http://{}", addr);
+    Server::bind(&addr).serve(make_svc).await?;
+
+    Ok(())
+}
+
+async fn handle(pool: Pool, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let qry = parse_query(req.uri().query().unwrap_or_default());
+    let headers: Vec<(String, String)> = req
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    // CORS preflight from the admin frontend
+    if method == "OPTIONS" {
+        let res = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
+            .header(
+                "Access-Control-Allow-Headers",
+                "content-type, authorization",
+            )
+            .body(Body::empty())
+            .unwrap();
+        return Ok(res);
+    }
+
+    let too_large = || {
+        log::warn!("{} {}: body over {} bytes", method, path, MAX_BODY_BYTES);
+        to_response(ApiResponse::text(413, "Request body too large"))
+    };
+    let declared: Option<u64> = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse().ok());
+    if declared.is_some_and(|len| len > MAX_BODY_BYTES as u64) {
+        return Ok(too_large());
+    }
+    let body = match read_body(req.into_body()).await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => return Ok(too_large()),
+        Err(e) => {
+            log::error!("failed to read body: {}", e);
+            Vec::new()
+        }
+    };
+
+    log::info!("{} {}", method, path);
+    let api_res = backend_api::route(&pool, &method, &path, &headers, &qry, &body).await;
+    Ok(to_response(api_res))
+}
+
+// The body, or None once it grows past MAX_BODY_BYTES. A Content-Length can be
+// left out or be wrong, so the chunks are counted as they arrive.
+async fn read_body(mut body: Body) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn to_response(api_res: ApiResponse) -> Response<Body> {
+    let mut res = Response::builder().status(api_res.status);
+    for (name, value) in &api_res.headers {
+        res = res.header(name.as_str(), value.as_str());
+    }
+    res.body(Body::from(api_res.body)).unwrap()
+}
+
+// Same shape as the query map webhook_flows hands to its handlers.
+fn parse_query(query: &str) -> HashMap<String, Value> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| {
+                urlencoding::decode(&s.replace('+', " "))
+                    .map(|s| s.into_owned())
+                    .unwrap_or_else(|_| s.to_string())
+            };
+            (decode(key), Value::String(decode(value)))
+        })
+        .collect()
+}
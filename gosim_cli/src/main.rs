@@ -0,0 +1,870 @@
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+use dotenv::dotenv;
+use gosim_project::csv_util::ImportFormat;
+use gosim_project::currency::{self, Currency};
+use gosim_project::db_adjust;
+use gosim_project::db_approval;
+use gosim_project::db_audit::AuditInfo;
+use gosim_project::db_caps::{self, BudgetCaps};
+use gosim_project::db_export::*;
+use gosim_project::db_import::*;
+use gosim_project::db_ledger::{self, DEFAULT_CAMPAIGN};
+use gosim_project::db_manipulate::*;
+use gosim_project::db_migrate::*;
+use gosim_project::db_payout::{self, PayoutFormat};
+use gosim_project::db_populate::get_pool;
+use gosim_project::db_query::{SortKey, SortOrder};
+use gosim_project::db_rates;
+use gosim_project::db_risk::{self, RiskFlag};
+use gosim_project::db_runs::*;
+use gosim_project::db_snapshot::*;
+use gosim_project::the_paced_runner::populate_vector_db;
+use gosim_project::the_runner::*;
+use mysql_async::Pool;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(name = "gosim", about = "Run the gosim pipeline from a terminal")]
+struct Cli {
+    /// TOML file with database_url, github_token, together_api_key, collection_name,
+    /// reporting_currency, risk_threshold
+    #[arg(long, default_value = "gosim.toml")]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Apply pending schema migrations
+    Migrate {
+        /// Only report which migrations are applied, pending or modified
+        #[arg(long)]
+        status: bool,
+    },
+    /// Collect the current hour, join and clean up, same as the scheduled run
+    Sync,
+    /// Collect every day in [from, to], then join and clean up
+    Backfill {
+        #[arg(long)]
+        from: NaiveDate,
+        #[arg(long)]
+        to: NaiveDate,
+    },
+    /// Consolidate the staging tables into issues_master and projects
+    Join,
+    /// Remove linked pull requests and already merged staging rows
+    Cleanup,
+    /// Summarize issues that have no summary yet
+    Summarize,
+    /// Upload summaries that are not indexed yet to the vector store
+    Index,
+    /// Post the pending issue comments
+    Notify {
+        /// Print the comments instead of posting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Fetch hand-picked issues from GitHub and add them to issues_master
+    Import {
+        /// CSV with an issue_url column and optional budget and notes, or a JSON array
+        file: PathBuf,
+        /// csv or json, by default taken from the file extension
+        #[arg(long)]
+        format: Option<ImportFormat>,
+    },
+    /// Write issues, projects, pull_requests, comments or payouts to a file
+    Export {
+        /// issues, projects, pull_requests, comments or payouts
+        table: ExportTable,
+        /// csv, jsonl or parquet (needs the `parquet` feature)
+        #[arg(long, default_value = "csv")]
+        format: ExportFormat,
+        /// Filter tree as JSON, same as the `filter` of POST /issues or /projects
+        #[arg(long)]
+        filter: Option<String>,
+        /// Sort key as field or field:asc / field:desc, can be repeated
+        #[arg(long)]
+        sort: Vec<String>,
+        /// Defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Record today's issue and project state in the snapshot tables
+    Snapshot {
+        /// Defaults to today (UTC), must not be before the latest snapshot
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// Print issue, budget and recent run statistics
+    Stats {
+        /// Issue counts from the snapshots, and the budget from the ledger, on this date
+        #[arg(long)]
+        as_of: Option<NaiveDate>,
+    },
+    /// Show or correct the budget ledger
+    Ledger {
+        #[command(subcommand)]
+        command: LedgerCommand,
+    },
+    /// Pay out concluded issues in batches and reconcile them
+    Payouts {
+        #[command(subcommand)]
+        command: PayoutCommand,
+    },
+    /// Load or show the exchange rates budget totals are converted with
+    Rates {
+        #[command(subcommand)]
+        command: RatesCommand,
+    },
+    /// Show the risk flags the join raised, or dismiss one
+    Risk {
+        #[command(subcommand)]
+        command: RiskCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum RiskCommand {
+    /// Print the issues with undismissed flags, highest score first
+    List {
+        /// Only issues scoring this much or more
+        #[arg(long, default_value_t = 1)]
+        min_score: i32,
+    },
+    /// Print an issue's flags and whether its conclusion is blocked
+    Show { issue_id: String },
+    /// Stop a flag counting towards the issue's score
+    Dismiss {
+        issue_id: String,
+        /// self_merged, no_engagement, trivial_diff, many_claims, new_account or own_issue
+        flag: RiskFlag,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RatesCommand {
+    /// Store the rates of a file, replacing ones loaded before for the same day
+    Load {
+        /// CSV with date, currency and rate columns, or a JSON array; a rate is what
+        /// one unit of the currency is worth in USD
+        file: PathBuf,
+        /// csv or json, by default taken from the file extension
+        #[arg(long)]
+        format: Option<ImportFormat>,
+    },
+    /// Print the rates in effect, the latest or the ones of a day
+    Show {
+        #[arg(long)]
+        as_of: Option<NaiveDate>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LedgerCommand {
+    /// Print each campaign's funded, available, allocated, approved and paid amounts
+    Balance,
+    /// Print a page of the ledger transactions, oldest first
+    Show {
+        /// Only the transactions of this issue
+        #[arg(long)]
+        issue: Option<String>,
+        /// The page to print, from 1
+        #[arg(long, default_value_t = 1)]
+        page: usize,
+        /// Transactions per page
+        #[arg(long, default_value_t = 100)]
+        page_size: usize,
+    },
+    /// Add budget to a campaign, creating it if needed; a negative amount takes it away
+    Fund {
+        #[arg(long, default_value = DEFAULT_CAMPAIGN)]
+        campaign: String,
+        #[arg(long, allow_negative_numbers = true)]
+        amount: i64,
+        /// USD, EUR or CNY
+        #[arg(long, default_value = "USD")]
+        currency: Currency,
+        #[arg(long)]
+        memo: Option<String>,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Undo a transaction with a reversal that negates its entries
+    Reverse {
+        txn_id: u64,
+        #[arg(long)]
+        memo: Option<String>,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Print a campaign's budget caps, changing the ones given (a number or `none`)
+    Caps {
+        #[arg(long, default_value = DEFAULT_CAMPAIGN)]
+        campaign: String,
+        /// What all issues of one project may hold
+        #[arg(long, value_parser = parse_cap)]
+        project_cap: Option<Cap>,
+        /// What all issues assigned to one contributor may hold
+        #[arg(long, value_parser = parse_cap)]
+        contributor_cap: Option<Cap>,
+        #[arg(long, value_parser = parse_cap)]
+        issue_min: Option<Cap>,
+        #[arg(long, value_parser = parse_cap)]
+        issue_max: Option<Cap>,
+    },
+    /// Give one project its own cap in place of the campaign's project cap
+    ProjectCap {
+        project_id: String,
+        /// A number, or `none` to use the campaign's project cap again
+        #[arg(value_parser = parse_cap)]
+        cap: Cap,
+        #[arg(long, default_value = DEFAULT_CAMPAIGN)]
+        campaign: String,
+    },
+    /// Print how many reviewers must approve a budget, setting one tier if given
+    Quorum {
+        #[arg(long, default_value = DEFAULT_CAMPAIGN)]
+        campaign: String,
+        /// The smallest budget the tier applies to
+        #[arg(long, requires = "votes")]
+        min_budget: Option<i64>,
+        /// Reviewers a budget in the tier needs, or `none` to remove the tier
+        #[arg(long, requires = "min_budget", value_parser = parse_votes)]
+        votes: Option<Votes>,
+    },
+    /// Change the budget an approved issue holds
+    Adjust {
+        issue_id: String,
+        budget: i64,
+        /// Recorded with the change, and posted with the announcement
+        #[arg(long)]
+        reason: Option<String>,
+        /// Comment on the issue about the change
+        #[arg(long)]
+        announce: bool,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Move an approved budget to another issue of the same project
+    Move {
+        from_issue_id: String,
+        to_issue_id: String,
+        /// Defaults to the whole budget
+        #[arg(long)]
+        amount: Option<i64>,
+        #[arg(long)]
+        reason: Option<String>,
+        #[arg(long)]
+        announce: bool,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Take an approved budget back and expire the issue
+    Clawback {
+        issue_id: String,
+        #[arg(long)]
+        reason: Option<String>,
+        #[arg(long)]
+        announce: bool,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PayoutCommand {
+    /// Collect a campaign's concluded issues that aren't in a batch into a draft batch
+    Create {
+        #[arg(long, default_value = DEFAULT_CAMPAIGN)]
+        campaign: String,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Print the batches, newest first
+    List,
+    /// Print a batch with its entries
+    Show { batch_id: u64 },
+    /// Write the payout file of a batch for the payment provider
+    File {
+        batch_id: u64,
+        /// csv or json
+        #[arg(long, default_value = "csv")]
+        format: PayoutFormat,
+        /// Defaults to stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Record that a draft batch was sent, paying its issues
+    Send {
+        batch_id: u64,
+        #[arg(long, default_value = "cli")]
+        actor: String,
+    },
+    /// Check a sent batch against the payment provider's confirmation file
+    Reconcile {
+        batch_id: u64,
+        /// CSV with issue_id, payee, amount and optional reference columns, or a JSON array
+        file: PathBuf,
+        /// csv or json, by default taken from the file extension
+        #[arg(long)]
+        format: Option<ImportFormat>,
+    },
+    /// Drop a draft batch, its issues go into the next one
+    Cancel { batch_id: u64 },
+}
+
+// A tier's required votes on the command line, `none` to remove it.
+#[derive(Clone, Copy)]
+struct Votes(Option<u32>);
+
+fn parse_votes(s: &str) -> Result<Votes, String> {
+    match s {
+        "none" => Ok(Votes(None)),
+        s => s
+            .parse::<u32>()
+            .map(|votes| Votes(Some(votes)))
+            .map_err(|_| format!("expected a number or `none`, got '{}'", s)),
+    }
+}
+
+// A budget cap on the command line, `none` for no cap.
+#[derive(Clone, Copy)]
+struct Cap(Option<i64>);
+
+fn parse_cap(s: &str) -> Result<Cap, String> {
+    match s {
+        "none" => Ok(Cap(None)),
+        s => s
+            .parse::<i64>()
+            .map(|cap| Cap(Some(cap)))
+            .map_err(|_| format!("expected a number or `none`, got '{}'", s)),
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct Config {
+    database_url: Option<String>,
+    github_token: Option<String>,
+    together_api_key: Option<String>,
+    collection_name: Option<String>,
+    reporting_currency: Option<String>,
+    risk_threshold: Option<i32>,
+}
+
+// The library reads its settings from the environment, config values fill the gaps.
+fn load_config(path: &PathBuf, explicit: bool) -> anyhow::Result<()> {
+    let config: Config = match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text)?,
+        Err(e) if !explicit && e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(anyhow::anyhow!("Cannot read {}: {}", path.display(), e)),
+    };
+
+    for (key, value) in [
+        ("DATABASE_URL", config.database_url),
+        ("GITHUB_TOKEN", config.github_token),
+        ("TOGETHER_API_KEY", config.together_api_key),
+        ("collection_name", config.collection_name),
+        ("REPORTING_CURRENCY", config.reporting_currency),
+        (
+            "RISK_THRESHOLD",
+            config.risk_threshold.map(|threshold| threshold.to_string()),
+        ),
+    ] {
+        if let Some(value) = value {
+            if std::env::var(key).is_err() {
+                std::env::set_var(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_sort_key(key: &str) -> SortKey {
+    let (field, order) = match key.rsplit_once(':') {
+        Some((field, "asc")) => (field, Some(SortOrder::Asc)),
+        Some((field, "desc")) => (field, Some(SortOrder::Desc)),
+        _ => (key, None),
+    };
+    SortKey {
+        field: field.to_string(),
+        order,
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let cli = Cli::parse();
+    let explicit = std::env::args().any(|a| a.starts_with("--config"));
+    load_config(&cli.config, explicit)?;
+
+    let pool = get_pool().await;
+    let result = run_command(&pool, cli.command).await;
+    pool.disconnect().await?;
+
+    result
+}
+
+async fn run_command(pool: &Pool, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Migrate { status: true } => {
+            for (migration, state) in migration_status(pool).await? {
+                let label = match state {
+                    MigrationState::Applied => String::from("applied"),
+                    MigrationState::Pending => String::from("pending"),
+                    MigrationState::Modified { applied_checksum } => {
+                        format!("modified, applied as {}", applied_checksum)
+                    }
+                };
+                println!("{}_{}: {}", migration.version, migration.name, label);
+            }
+            Ok(())
+        }
+        Command::Migrate { status: false } => {
+            let applied = run_migrations(pool).await?;
+            println!("{} migrations applied", applied.len());
+            Ok(())
+        }
+        Command::Sync => run_hourly(pool).await,
+        Command::Backfill { from, to } => {
+            if from > to {
+                return Err(anyhow::anyhow!("--from must not be after --to"));
+            }
+            run_backfill(pool, from, to).await
+        }
+        Command::Join => {
+            let run = start_run(pool, "join").await?;
+            let result = join_ops(pool, &run).await;
+            run.finish(&result).await;
+            result
+        }
+        Command::Cleanup => {
+            let run = start_run(pool, "cleanup").await?;
+            let result = cleanup_ops(pool, &run).await;
+            run.finish(&result).await;
+            result
+        }
+        Command::Summarize => {
+            let run = start_run(pool, "summarize").await?;
+            let result = run
+                .step("summarize_pending_issues", summarize_pending_issues(pool))
+                .await
+                .map(|_| ());
+            run.finish(&result).await;
+            result
+        }
+        Command::Index => {
+            let run = start_run(pool, "index").await?;
+            let result = run
+                .step("populate_vector_db", populate_vector_db(pool))
+                .await
+                .map(|_| ());
+            run.finish(&result).await;
+            result
+        }
+        Command::Notify { dry_run } => {
+            ensure_schema_current(pool).await?;
+            let notes = note_issues(pool, dry_run).await?;
+            for note in &notes {
+                println!("{}\n    {}", note.issue_id, note.comment);
+            }
+            println!(
+                "{} comments {}",
+                notes.len(),
+                if dry_run { "pending" } else { "posted" }
+            );
+            Ok(())
+        }
+        Command::Import { file, format } => {
+            let format = format.unwrap_or_else(|| match file.extension() {
+                Some(ext) if ext.eq_ignore_ascii_case("json") => ImportFormat::Json,
+                _ => ImportFormat::Csv,
+            });
+            let rows = parse_import(&std::fs::read_to_string(&file)?, format)?;
+
+            let outcomes = run_import(pool, rows).await?;
+            let failed = outcomes.iter().filter(|o| !o.imported).count();
+            println!("{}", serde_json::to_string_pretty(&outcomes)?);
+            println!("{} imported, {} failed", outcomes.len() - failed, failed);
+            Ok(())
+        }
+        Command::Export {
+            table,
+            format,
+            filter,
+            sort,
+            output,
+        } => {
+            ensure_schema_current(pool).await?;
+            let filter = filter
+                .map(|filter| serde_json::from_str(&filter))
+                .transpose()?;
+            let sort = sort.iter().map(|key| parse_sort_key(key)).collect();
+            let request = ExportRequest::new(table, filter, sort)?;
+
+            let out: Box<dyn Write + Send> = match &output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout())),
+            };
+            let (_, rows) = export_table(pool, &request, format, out, None).await?;
+            log::info!("Exported {} rows", rows);
+            Ok(())
+        }
+        Command::Snapshot { date } => {
+            let date = date.unwrap_or_else(|| chrono::Utc::now().date_naive());
+            run_snapshot(pool, date).await
+        }
+        Command::Stats { as_of: Some(as_of) } => {
+            ensure_schema_current(pool).await?;
+            let (total, queue, approve, decline) = count_issues_as_of(pool, as_of).await?;
+            let (total_budget, allocated, balance) = count_budget_as_of(pool, as_of).await?;
+
+            let stats = json!({
+                "as_of": as_of.format("%Y-%m-%d").to_string(),
+                "issues": {
+                    "total": total,
+                    "queue": queue,
+                    "approve": approve,
+                    "decline": decline,
+                },
+                "budget": {
+                    "currency": currency::reporting_currency(),
+                    "total": total_budget,
+                    "allocated": allocated,
+                    "balance": balance,
+                },
+            });
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            Ok(())
+        }
+        Command::Stats { as_of: None } => {
+            ensure_schema_current(pool).await?;
+            let (total, queue, approve, decline) = count_issues_by_status(pool).await?;
+            let (total_budget, allocated, balance) = count_budget_by_status(pool).await?;
+            let runs = list_recent_runs(pool, 5).await?;
+
+            let stats = json!({
+                "issues": {
+                    "total": total,
+                    "queue": queue,
+                    "approve": approve,
+                    "decline": decline,
+                },
+                "budget": {
+                    "currency": currency::reporting_currency(),
+                    "total": total_budget,
+                    "allocated": allocated,
+                    "balance": balance,
+                },
+                "recent_runs": runs,
+            });
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            Ok(())
+        }
+        Command::Ledger { command } => {
+            ensure_schema_current(pool).await?;
+            run_ledger_command(pool, command).await
+        }
+        Command::Payouts { command } => {
+            ensure_schema_current(pool).await?;
+            run_payout_command(pool, command).await
+        }
+        Command::Rates {
+            command: RatesCommand::Load { file, format },
+        } => {
+            ensure_schema_current(pool).await?;
+            let format = format.unwrap_or_else(|| match file.extension() {
+                Some(ext) if ext.eq_ignore_ascii_case("json") => ImportFormat::Json,
+                _ => ImportFormat::Csv,
+            });
+            let rates = currency::parse_rates(&std::fs::read_to_string(&file)?, format)?;
+
+            let loaded = db_rates::load_rates(pool, &rates).await?;
+            println!("{} rates loaded", loaded);
+            Ok(())
+        }
+        Command::Rates {
+            command: RatesCommand::Show { as_of },
+        } => {
+            ensure_schema_current(pool).await?;
+            let rates = db_rates::rates_as_of(&mut pool.get_conn().await?, as_of).await?;
+            println!("{}", serde_json::to_string_pretty(&rates)?);
+            Ok(())
+        }
+        Command::Risk { command } => {
+            ensure_schema_current(pool).await?;
+            run_risk_command(pool, command).await
+        }
+    }
+}
+
+async fn run_risk_command(pool: &Pool, command: RiskCommand) -> anyhow::Result<()> {
+    match command {
+        RiskCommand::List { min_score } => {
+            let risks = db_risk::list_risky_issues(pool, min_score).await?;
+            println!("{}", serde_json::to_string_pretty(&risks)?);
+        }
+        RiskCommand::Show { issue_id } => {
+            let risk = db_risk::issue_risk(&mut pool.get_conn().await?, &issue_id).await?;
+            println!("{}", serde_json::to_string_pretty(&risk)?);
+        }
+        RiskCommand::Dismiss {
+            issue_id,
+            flag,
+            actor,
+        } => {
+            let risk = db_risk::dismiss_flag(pool, &issue_id, flag, &actor).await?;
+            log::info!(
+                "Dismissed {} on {}, it now scores {} of {}",
+                flag,
+                issue_id,
+                risk.risk_score,
+                risk.threshold
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn run_ledger_command(pool: &Pool, command: LedgerCommand) -> anyhow::Result<()> {
+    match command {
+        LedgerCommand::Balance => {
+            let balances = db_ledger::campaign_balances(pool).await?;
+            println!("{}", serde_json::to_string_pretty(&balances)?);
+        }
+        LedgerCommand::Show {
+            issue,
+            page,
+            page_size,
+        } => {
+            let txns =
+                db_ledger::list_transactions(pool, issue.as_deref(), page, page_size).await?;
+            println!("{}", serde_json::to_string_pretty(&txns)?);
+        }
+        LedgerCommand::Fund {
+            campaign,
+            amount,
+            currency,
+            memo,
+            actor,
+        } => {
+            let txn_id = db_ledger::fund_campaign(
+                pool,
+                &campaign,
+                amount,
+                currency,
+                &actor,
+                memo.as_deref(),
+            )
+            .await?;
+            log::info!(
+                "Funded {} with {} {} in transaction {}",
+                campaign,
+                amount,
+                currency,
+                txn_id
+            );
+        }
+        LedgerCommand::Reverse {
+            txn_id,
+            memo,
+            actor,
+        } => {
+            let reversal_id =
+                db_ledger::reverse_transaction(pool, txn_id, &actor, memo.as_deref()).await?;
+            log::info!("Reversed transaction {} in {}", txn_id, reversal_id);
+        }
+        LedgerCommand::Caps {
+            campaign,
+            project_cap,
+            contributor_cap,
+            issue_min,
+            issue_max,
+        } => {
+            let current = db_caps::get_caps(&mut pool.get_conn().await?, &campaign).await?;
+            let caps = BudgetCaps {
+                campaign_id: campaign,
+                project_cap: project_cap.map_or(current.project_cap, |cap| cap.0),
+                contributor_cap: contributor_cap.map_or(current.contributor_cap, |cap| cap.0),
+                issue_budget_min: issue_min.map_or(current.issue_budget_min, |cap| cap.0),
+                issue_budget_max: issue_max.map_or(current.issue_budget_max, |cap| cap.0),
+            };
+            if caps != current {
+                db_caps::set_caps(pool, &caps).await?;
+            }
+            println!("{}", serde_json::to_string_pretty(&caps)?);
+        }
+        LedgerCommand::ProjectCap {
+            project_id,
+            cap,
+            campaign,
+        } => {
+            db_caps::set_project_cap(pool, &campaign, &project_id, cap.0).await?;
+            log::info!(
+                "Set the cap of {} in {} to {:?}",
+                project_id,
+                campaign,
+                cap.0
+            );
+        }
+        LedgerCommand::Quorum {
+            campaign,
+            min_budget,
+            votes,
+        } => {
+            if let (Some(min_budget), Some(votes)) = (min_budget, votes) {
+                db_approval::set_tier(pool, &campaign, min_budget, votes.0).await?;
+            }
+            let tiers = db_approval::list_tiers(&mut pool.get_conn().await?, &campaign).await?;
+            println!("{}", serde_json::to_string_pretty(&tiers)?);
+        }
+        LedgerCommand::Adjust {
+            issue_id,
+            budget,
+            reason,
+            announce,
+            actor,
+        } => {
+            let audit = AuditInfo::new(Some(actor), reason);
+            let change = db_adjust::adjust_budget(pool, &issue_id, budget, &audit).await?;
+            print_changes(vec![change], announce, &audit).await?;
+        }
+        LedgerCommand::Move {
+            from_issue_id,
+            to_issue_id,
+            amount,
+            reason,
+            announce,
+            actor,
+        } => {
+            let audit = AuditInfo::new(Some(actor), reason);
+            let changes =
+                db_adjust::move_budget(pool, &from_issue_id, &to_issue_id, amount, &audit).await?;
+            print_changes(changes, announce, &audit).await?;
+        }
+        LedgerCommand::Clawback {
+            issue_id,
+            reason,
+            announce,
+            actor,
+        } => {
+            let audit = AuditInfo::new(Some(actor), reason);
+            let change = db_adjust::clawback_budget(pool, &issue_id, &audit).await?;
+            print_changes(vec![change], announce, &audit).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn print_changes(
+    mut changes: Vec<db_adjust::BudgetChange>,
+    announce: bool,
+    audit: &AuditInfo,
+) -> anyhow::Result<()> {
+    if announce {
+        db_adjust::announce(&mut changes, audit.admin_feedback.as_deref()).await;
+    }
+    println!("{}", serde_json::to_string_pretty(&changes)?);
+    Ok(())
+}
+
+async fn run_payout_command(pool: &Pool, command: PayoutCommand) -> anyhow::Result<()> {
+    match command {
+        PayoutCommand::Create { campaign, actor } => {
+            let (batch, skipped) = db_payout::create_batch(pool, &campaign, &actor).await?;
+            println!("{}", serde_json::to_string_pretty(&batch)?);
+            for issue_id in &skipped {
+                log::warn!(
+                    "Skipped {}, it has no assignee or approved budget",
+                    issue_id
+                );
+            }
+            log::info!(
+                "Created batch {} paying {} in {} entries",
+                batch.batch_id,
+                totals_text(&batch),
+                batch.entries.len()
+            );
+        }
+        PayoutCommand::List => {
+            let batches = db_payout::list_batches(pool).await?;
+            println!("{}", serde_json::to_string_pretty(&batches)?);
+        }
+        PayoutCommand::Show { batch_id } => {
+            let batch = db_payout::get_batch(&mut pool.get_conn().await?, batch_id).await?;
+            println!("{}", serde_json::to_string_pretty(&batch)?);
+        }
+        PayoutCommand::File {
+            batch_id,
+            format,
+            output,
+        } => {
+            let batch = db_payout::get_batch(&mut pool.get_conn().await?, batch_id).await?;
+            let out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(BufWriter::new(std::io::stdout())),
+            };
+            db_payout::write_payout_file(&batch, format, out)?;
+        }
+        PayoutCommand::Send { batch_id, actor } => {
+            let batch =
+                db_payout::mark_sent(pool, batch_id, &AuditInfo::new(Some(actor), None)).await?;
+            log::info!("Sent batch {} paying {}", batch_id, totals_text(&batch));
+        }
+        PayoutCommand::Reconcile {
+            batch_id,
+            file,
+            format,
+        } => {
+            let format = format.unwrap_or_else(|| match file.extension() {
+                Some(ext) if ext.eq_ignore_ascii_case("json") => ImportFormat::Json,
+                _ => ImportFormat::Csv,
+            });
+            let confirmations =
+                db_payout::parse_confirmations(&std::fs::read_to_string(&file)?, format)?;
+
+            let report = db_payout::reconcile(pool, batch_id, &confirmations).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            println!(
+                "{} confirmed, {} flagged",
+                report.confirmed,
+                report.flagged.len()
+            );
+        }
+        PayoutCommand::Cancel { batch_id } => {
+            db_payout::cancel_batch(pool, batch_id).await?;
+            log::info!("Cancelled batch {}", batch_id);
+        }
+    }
+    Ok(())
+}
+
+// e.g. "1200 USD, 300 EUR"
+fn totals_text(batch: &db_payout::PayoutBatch) -> String {
+    batch
+        .totals()
+        .iter()
+        .map(|(currency, total)| format!("{} {}", total, currency))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn start_run<'a>(pool: &'a Pool, run_kind: &str) -> anyhow::Result<PipelineRun<'a>> {
+    match PipelineRun::start_locked(pool, run_kind).await? {
+        Some(run) => Ok(run),
+        None => Err(anyhow::anyhow!(
+            "Another pipeline run holds the lock, try again later"
+        )),
+    }
+}
@@ -42,20 +42,13 @@ CREATE TABLE issues_repos_indexed (
     issue_or_project_id VARCHAR(255) PRIMARY KEY  -- url of an issue
 ) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
 
-CREATE TABLE issues_repos_summarized (
-    issue_or_project_id VARCHAR(255) PRIMARY KEY, -- url of an issue
-    issue_or_project_summary TEXT NOT NULL,
-    keyword_tags JSON,
-    indexed BOOLEAN DEFAULT 0
-) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
-
 CREATE TABLE issues_repos_summarized (
     issue_or_project_id VARCHAR(255) PRIMARY KEY, -- url of an issue
     issue_or_project_summary TEXT NOT NULL,
     keyword_tags JSON,
     keyword_tags_text TEXT GENERATED ALWAYS AS (JSON_UNQUOTE(JSON_EXTRACT(keyword_tags, '$'))) STORED,
-    keyword_tags_text FULLTEXT,
-    indexed BOOLEAN  DEFAULT 0
+    indexed BOOLEAN DEFAULT 0,
+    FULLTEXT INDEX ft_keyword_tags_text (keyword_tags_text)
 ) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
 
 
@@ -87,60 +80,3 @@ CREATE TABLE pull_requests (
     project_id VARCHAR(255) NOT NULL,
     date_merged DATETIME
 ) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
-
-
-UPDATE issues_master im
-JOIN projects p ON im.project_id = p.project_id
-SET im.main_language = p.main_language,
-    im.repo_stars = p.repo_stars;
-
-
-UPDATE issues_master im
-JOIN projects p ON im.project_id = p.project_id
-SET im.project_logo = p.project_logo
-
-
--- Add a generated column that converts the JSON array to a comma-separated string
-ALTER TABLE issues_repos_summarized
-ADD COLUMN keyword_tags_text TEXT GENERATED ALWAYS AS (JSON_UNQUOTE(JSON_EXTRACT(keyword_tags, '$'))) STORED;
-
--- Add a full-text index to the generated column
-ALTER TABLE issues_repos_summarized
-ADD FULLTEXT(keyword_tags_text);
-
-
-WITH FilteredProjects AS (
-                SELECT 
-                    project_id, 
-                    project_logo, 
-                    repo_stars, 
-                    main_language, 
-                    project_description, 
-                    issues_list,   
-                    total_budget_allocated
-                FROM 
-                    projects
-                WHERE LENGTH(main_language) > 0 ORDER BY main_language ASC
-            ),
-            TotalCount AS (
-                SELECT COUNT(*) AS total_count FROM FilteredProjects
-            )
-            SELECT 
-                fp.project_id, 
-                fp.project_logo, 
-                fp.repo_stars, 
-                fp.main_language, 
-                fp.project_description, 
-                fp.issues_list,   
-                fp.total_budget_allocated,
-                tc.total_count
-            FROM 
-                FilteredProjects fp, TotalCount tc
-
-
-
-SELECT keyword, COUNT(*) as frequency
-FROM issues_repos_summarized,
-     JSON_TABLE(keyword_tags, '$[*]' COLUMNS(keyword VARCHAR(255) PATH '$')) AS keywords
-GROUP BY keyword
-ORDER BY frequency DESC;
\ No newline at end of file
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS pipeline_runs (
+    run_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    run_kind VARCHAR(50) NOT NULL,  -- hourly, manual, ...
+    date_started DATETIME NOT NULL,
+    date_finished DATETIME,
+    run_status ENUM('running', 'success', 'failed') DEFAULT 'running',
+    rows_fetched INT DEFAULT 0,
+    rows_written INT DEFAULT 0,
+    github_points_spent INT DEFAULT 0,
+    error_text TEXT
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS pipeline_steps (
+    step_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    run_id BIGINT NOT NULL,
+    step_name VARCHAR(100) NOT NULL,
+    date_started DATETIME NOT NULL,
+    date_finished DATETIME,
+    step_status ENUM('running', 'success', 'failed') DEFAULT 'running',
+    rows_fetched INT DEFAULT 0,
+    rows_written INT DEFAULT 0,
+    github_points_spent INT DEFAULT 0,  -- GraphQL rate limit points consumed by the step
+    error_text TEXT,
+    INDEX idx_pipeline_steps_run_id (run_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS run_locks (
+    lock_name VARCHAR(100) PRIMARY KEY,
+    holder VARCHAR(100) NOT NULL,
+    acquired_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL  -- lease end, an expired lock can be taken over by the next run
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
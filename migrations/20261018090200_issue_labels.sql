@@ -0,0 +1,3 @@
+ALTER TABLE issues_open ADD COLUMN issue_labels JSON;  -- label names as a JSON array
+
+ALTER TABLE issues_master ADD COLUMN issue_labels JSON;
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS issue_assignees (
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    login VARCHAR(50) NOT NULL,
+    source ENUM('assigned', 'closed', 'backfill') NOT NULL,  -- staging table the row came from
+    assigned_at DATETIME,
+    PRIMARY KEY (issue_id, login, source),
+    INDEX idx_issue_assignees_login (login)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+INSERT IGNORE INTO issue_assignees (issue_id, login, source, assigned_at)
+SELECT im.issue_id, jt.login, 'backfill', im.date_issue_assigned
+FROM issues_master im
+CROSS JOIN JSON_TABLE(im.issue_assignees, '$[*]' COLUMNS (login VARCHAR(50) PATH '$')) jt
+WHERE jt.login IS NOT NULL;
+
+ALTER TABLE issues_master DROP COLUMN issue_assignees;
+
+-- JSON array of each issue's logins, what the API has always returned as issue_assignees
+CREATE OR REPLACE VIEW issue_assignee_lists AS
+SELECT issue_id, JSON_ARRAYAGG(login) AS issue_assignees
+FROM (SELECT DISTINCT issue_id, login FROM issue_assignees) AS distinct_assignees
+GROUP BY issue_id;
@@ -0,0 +1,12 @@
+-- Append-only, the code never updates or deletes these rows.
+CREATE TABLE IF NOT EXISTS admin_actions (
+    action_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    actor VARCHAR(100) NOT NULL,
+    action VARCHAR(50) NOT NULL,  -- approve_budget, decline, conclude, ...
+    before_state JSON,
+    after_state JSON,
+    admin_feedback TEXT,
+    created_at DATETIME NOT NULL,
+    INDEX idx_admin_actions_issue (issue_id, action_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
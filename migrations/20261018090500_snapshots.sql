@@ -0,0 +1,24 @@
+-- A row is written only when an issue or project differs from its previous
+-- snapshot, the state on a date is the latest row on or before it.
+CREATE TABLE IF NOT EXISTS issue_snapshots (
+    snapshot_date DATE NOT NULL,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    project_id VARCHAR(255) NOT NULL,
+    review_status VARCHAR(20),
+    issue_budget INT,
+    issue_budget_approved BOOLEAN,
+    issue_assignees JSON,
+    issue_linked_pr VARCHAR(255),
+    PRIMARY KEY (issue_id, snapshot_date),
+    INDEX idx_issue_snapshots_date (snapshot_date)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS project_snapshots (
+    snapshot_date DATE NOT NULL,
+    project_id VARCHAR(255) NOT NULL,  -- url of a project repo
+    repo_stars INT,
+    total_budget_allocated INT,
+    issues_count INT,
+    PRIMARY KEY (project_id, snapshot_date),
+    INDEX idx_project_snapshots_date (snapshot_date)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
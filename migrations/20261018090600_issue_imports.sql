@@ -0,0 +1,10 @@
+-- Issues added by hand through `gosim import`, with the budget and notes given.
+CREATE TABLE IF NOT EXISTS issue_imports (
+    import_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    issue_budget INT,
+    notes TEXT,
+    run_id BIGINT,  -- the pipeline_runs row of the import
+    imported_at DATETIME NOT NULL,
+    INDEX idx_issue_imports_issue (issue_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
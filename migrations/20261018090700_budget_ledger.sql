@@ -0,0 +1,97 @@
+-- Budget money lives in a double-entry ledger: every transaction's entries sum to
+-- zero and rows are only ever appended, corrections are reversal transactions.
+CREATE TABLE IF NOT EXISTS campaigns (
+    campaign_id VARCHAR(50) PRIMARY KEY,
+    campaign_name VARCHAR(255),
+    created_at DATETIME NOT NULL
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+INSERT IGNORE INTO campaigns (campaign_id, campaign_name, created_at)
+VALUES ('gosim', 'GOSIM', NOW());
+
+ALTER TABLE issues_master ADD COLUMN campaign_id VARCHAR(50) NOT NULL DEFAULT 'gosim';
+
+CREATE TABLE IF NOT EXISTS ledger_transactions (
+    txn_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    campaign_id VARCHAR(50) NOT NULL,
+    kind ENUM('fund', 'allocate', 'approve', 'release', 'payout', 'reversal') NOT NULL,
+    project_id VARCHAR(255),  -- url of a repo
+    issue_id VARCHAR(255),  -- url of an issue
+    reverses_txn_id BIGINT,  -- a transaction is reversed at most once
+    actor VARCHAR(255) NOT NULL,
+    memo TEXT,
+    created_at DATETIME NOT NULL,
+    UNIQUE KEY uq_ledger_transactions_reverses (reverses_txn_id),
+    INDEX idx_ledger_transactions_issue (issue_id),
+    INDEX idx_ledger_transactions_campaign (campaign_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- funding and available are campaign accounts, the others are kept per issue
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    entry_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    txn_id BIGINT NOT NULL,
+    campaign_id VARCHAR(50) NOT NULL,
+    account ENUM('funding', 'available', 'allocated', 'approved', 'paid') NOT NULL,
+    project_id VARCHAR(255),
+    issue_id VARCHAR(255),
+    amount BIGINT NOT NULL,
+    INDEX idx_ledger_entries_txn (txn_id),
+    INDEX idx_ledger_entries_account (campaign_id, account),
+    INDEX idx_ledger_entries_issue (issue_id),
+    INDEX idx_ledger_entries_project (project_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- the budget that used to be the TOTAL_BUDGET constant
+INSERT INTO ledger_transactions (campaign_id, kind, actor, memo, created_at)
+VALUES ('gosim', 'fund', 'migration', 'initial budget', NOW());
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, amount)
+SELECT txn_id, 'gosim', 'funding', -50000 FROM ledger_transactions
+WHERE actor = 'migration' AND kind = 'fund';
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, amount)
+SELECT txn_id, 'gosim', 'available', 50000 FROM ledger_transactions
+WHERE actor = 'migration' AND kind = 'fund';
+
+-- Backfill: approved issues hold their budget as allocated, concluded ones as approved.
+-- Declined issues and budgets that were never approved are not money spent.
+INSERT INTO ledger_transactions (campaign_id, kind, project_id, issue_id, actor, memo, created_at)
+SELECT campaign_id, 'allocate', project_id, issue_id, 'migration', 'backfill', NOW()
+FROM issues_master
+WHERE issue_budget > 0 AND review_status <> 'decline'
+    AND (review_status = 'approve' OR issue_budget_approved = 1);
+
+INSERT INTO ledger_transactions (campaign_id, kind, project_id, issue_id, actor, memo, created_at)
+SELECT campaign_id, 'approve', project_id, issue_id, 'migration', 'backfill', NOW()
+FROM issues_master
+WHERE issue_budget > 0 AND review_status <> 'decline' AND issue_budget_approved = 1;
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, project_id, issue_id, amount)
+SELECT t.txn_id, t.campaign_id, 'available', NULL, NULL, -im.issue_budget
+FROM ledger_transactions t JOIN issues_master im ON im.issue_id = t.issue_id
+WHERE t.actor = 'migration' AND t.kind = 'allocate';
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, project_id, issue_id, amount)
+SELECT t.txn_id, t.campaign_id, 'allocated', t.project_id, t.issue_id, im.issue_budget
+FROM ledger_transactions t JOIN issues_master im ON im.issue_id = t.issue_id
+WHERE t.actor = 'migration' AND t.kind = 'allocate';
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, project_id, issue_id, amount)
+SELECT t.txn_id, t.campaign_id, 'allocated', t.project_id, t.issue_id, -im.issue_budget
+FROM ledger_transactions t JOIN issues_master im ON im.issue_id = t.issue_id
+WHERE t.actor = 'migration' AND t.kind = 'approve';
+
+INSERT INTO ledger_entries (txn_id, campaign_id, account, project_id, issue_id, amount)
+SELECT t.txn_id, t.campaign_id, 'approved', t.project_id, t.issue_id, im.issue_budget
+FROM ledger_transactions t JOIN issues_master im ON im.issue_id = t.issue_id
+WHERE t.actor = 'migration' AND t.kind = 'approve';
+
+-- total_budget_allocated now counts the ledger's allocated, approved and paid money
+UPDATE projects p
+LEFT JOIN (
+    SELECT project_id, SUM(amount) AS total_budget
+    FROM ledger_entries
+    WHERE account IN ('allocated', 'approved', 'paid')
+    GROUP BY project_id
+) AS ledger_budgets ON p.project_id = ledger_budgets.project_id
+SET p.total_budget_allocated = ledger_budgets.total_budget;
@@ -0,0 +1,14 @@
+-- Limits on approved budgets, NULL for none. Checked when a budget is approved.
+ALTER TABLE campaigns
+    ADD COLUMN project_cap INT,
+    ADD COLUMN contributor_cap INT,
+    ADD COLUMN issue_budget_min INT,
+    ADD COLUMN issue_budget_max INT;
+
+-- a project's own cap, in place of the campaign's project_cap
+CREATE TABLE IF NOT EXISTS project_budget_caps (
+    campaign_id VARCHAR(50) NOT NULL,
+    project_id VARCHAR(255) NOT NULL,  -- url of a repo
+    budget_cap INT NOT NULL,
+    PRIMARY KEY (campaign_id, project_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
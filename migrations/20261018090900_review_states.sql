@@ -0,0 +1,39 @@
+-- The review lifecycle, see src/review_state.rs. review_status and
+-- issue_budget_approved are kept in step with it.
+ALTER TABLE issues_master ADD COLUMN review_state ENUM('queued', 'approved', 'in_progress',
+    'pr_linked', 'concluded', 'paid', 'declined', 'withdrawn', 'expired') NOT NULL DEFAULT 'queued';
+
+UPDATE issues_master SET review_state = CASE
+    WHEN review_status = 'decline' THEN 'declined'
+    WHEN issue_budget_approved = 1 THEN 'concluded'
+    WHEN review_status = 'approve' AND issue_linked_pr IS NOT NULL THEN 'pr_linked'
+    WHEN review_status = 'approve' AND date_issue_assigned IS NOT NULL THEN 'in_progress'
+    WHEN review_status = 'approve' THEN 'approved'
+    ELSE 'queued'
+END;
+
+-- a declined issue isn't funded, a concluded one was approved
+UPDATE issues_master SET issue_budget_approved = 0 WHERE review_state = 'declined';
+UPDATE issues_master SET review_status = 'approve' WHERE review_state = 'concluded';
+
+-- Each time an issue entered a state. The backfilled rows only know the current one.
+CREATE TABLE IF NOT EXISTS issue_state_changes (
+    change_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    from_state VARCHAR(20),
+    to_state VARCHAR(20) NOT NULL,
+    action VARCHAR(20) NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    changed_at DATETIME NOT NULL,
+    INDEX idx_issue_state_changes_issue (issue_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+INSERT INTO issue_state_changes (issue_id, from_state, to_state, action, actor, changed_at)
+SELECT issue_id, NULL, review_state, 'backfill', 'migration', CASE review_state
+    WHEN 'declined' THEN COALESCE(date_declined, NOW())
+    WHEN 'approved' THEN COALESCE(date_approved, NOW())
+    WHEN 'in_progress' THEN COALESCE(date_issue_assigned, NOW())
+    WHEN 'concluded' THEN COALESCE(date_budget_approved, NOW())
+    ELSE NOW()
+END
+FROM issues_master;
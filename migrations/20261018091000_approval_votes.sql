@@ -0,0 +1,19 @@
+-- How many reviewers must agree on a budget of at least min_budget. A budget
+-- below every tier needs one.
+CREATE TABLE IF NOT EXISTS approval_tiers (
+    campaign_id VARCHAR(50) NOT NULL,
+    min_budget INT NOT NULL,
+    required_votes INT NOT NULL,
+    PRIMARY KEY (campaign_id, min_budget)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- The votes of an issue's reviewers that haven't reached quorum yet, one per
+-- reviewer. Cast votes are recorded in admin_actions as well.
+CREATE TABLE IF NOT EXISTS approval_votes (
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    reviewer VARCHAR(255) NOT NULL,
+    issue_budget INT NOT NULL,
+    admin_feedback TEXT,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (issue_id, reviewer)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
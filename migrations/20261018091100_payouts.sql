@@ -0,0 +1,28 @@
+-- Payout batches: concluded issues collected for paying, sent, then reconciled
+-- against the payment provider's confirmation file.
+CREATE TABLE IF NOT EXISTS payout_batches (
+    batch_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    campaign_id VARCHAR(50) NOT NULL,
+    status ENUM('draft', 'sent', 'reconciled') NOT NULL DEFAULT 'draft',
+    created_by VARCHAR(255) NOT NULL,
+    created_at DATETIME NOT NULL,
+    sent_at DATETIME,
+    reconciled_at DATETIME
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- One payee's share of an issue's approved budget. An issue and payee are paid
+-- in one batch only.
+CREATE TABLE IF NOT EXISTS payout_entries (
+    entry_id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    batch_id BIGINT NOT NULL,
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    project_id VARCHAR(255) NOT NULL,  -- url of a repo
+    payee VARCHAR(255) NOT NULL,  -- GitHub login
+    amount INT NOT NULL,
+    status ENUM('pending', 'sent', 'confirmed', 'mismatch') NOT NULL DEFAULT 'pending',
+    reference VARCHAR(255),  -- the payment provider's id
+    confirmed_amount INT,
+    note TEXT,
+    UNIQUE KEY uq_payout_entries_issue_payee (issue_id, payee),
+    INDEX idx_payout_entries_batch (batch_id)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
@@ -0,0 +1,21 @@
+-- Budgets and ledger money carry their currency. Everything before this was in USD.
+ALTER TABLE issues_open ADD COLUMN issue_budget_currency CHAR(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE issues_master ADD COLUMN issue_budget_currency CHAR(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE issue_imports ADD COLUMN issue_budget_currency CHAR(3) NOT NULL DEFAULT 'USD';
+
+-- the entries of a transaction are all in one currency, and every account is kept
+-- per currency
+ALTER TABLE ledger_entries ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE ledger_entries DROP INDEX idx_ledger_entries_account,
+    ADD INDEX idx_ledger_entries_account (campaign_id, account, currency);
+
+ALTER TABLE payout_entries ADD COLUMN currency CHAR(3) NOT NULL DEFAULT 'USD';
+
+-- What one unit of a currency was worth in USD on a day, loaded from a rates file.
+-- Totals use the latest rate of each currency up to the day they are for.
+CREATE TABLE IF NOT EXISTS exchange_rates (
+    rate_date DATE NOT NULL,
+    currency CHAR(3) NOT NULL,
+    rate DECIMAL(20, 10) NOT NULL,
+    PRIMARY KEY (rate_date, currency)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
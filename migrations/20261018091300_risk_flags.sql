@@ -0,0 +1,24 @@
+-- What the risk checks read from a pull request: who merged it, the size of its
+-- diff, and when its author signed up.
+ALTER TABLE pull_requests
+    ADD COLUMN merged_by VARCHAR(50),
+    ADD COLUMN additions INT,
+    ADD COLUMN deletions INT,
+    ADD COLUMN changed_files INT,
+    ADD COLUMN author_created_at DATETIME;
+
+-- The flags the join phase raises on an issue, one per check. A dismissed flag
+-- no longer counts towards the issue's risk_score.
+CREATE TABLE IF NOT EXISTS issue_risk_flags (
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    flag VARCHAR(30) NOT NULL,
+    points INT NOT NULL,
+    detail TEXT,
+    flagged_at DATETIME NOT NULL,
+    dismissed_by VARCHAR(255),
+    dismissed_at DATETIME,
+    PRIMARY KEY (issue_id, flag)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
+
+-- the points of the undismissed flags, conclusion is blocked at the risk threshold
+ALTER TABLE issues_master ADD COLUMN risk_score INT NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Steps that ran inside a join/cleanup transaction which was then rolled back.
+ALTER TABLE pipeline_steps
+    MODIFY step_status ENUM('running', 'success', 'failed', 'rolled_back') DEFAULT 'running';
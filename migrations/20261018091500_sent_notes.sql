@@ -0,0 +1,8 @@
+-- The comments `gosim notify` posted, one per issue and kind, so a later run
+-- does not post the same one again.
+CREATE TABLE IF NOT EXISTS sent_notes (
+    issue_id VARCHAR(255) NOT NULL,  -- url of an issue
+    kind VARCHAR(30) NOT NULL,
+    sent_at DATETIME NOT NULL,
+    PRIMARY KEY (issue_id, kind)
+) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci;
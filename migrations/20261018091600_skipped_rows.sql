@@ -0,0 +1,7 @@
+-- Rows a step left out without failing, e.g. projects whose budget sum needs a
+-- missing exchange rate.
+ALTER TABLE pipeline_steps
+    ADD COLUMN rows_skipped INT DEFAULT 0 AFTER rows_written;
+
+ALTER TABLE pipeline_runs
+    ADD COLUMN rows_skipped INT DEFAULT 0 AFTER rows_written;
@@ -1,3 +1,6 @@
+-- One-off maintenance queries and test data, run by hand. Not a migration,
+-- db_migrate only applies the files under migrations/.
+
 INSERT INTO issues_master (
     issue_id,
     project_id,
@@ -5,7 +8,6 @@ INSERT INTO issues_master (
     issue_creator,
     issue_description,
     issue_budget,
-    issue_assignees,
     date_issue_assigned,
     issue_linked_pr,
     issue_status,
@@ -20,7 +22,6 @@ SELECT
     CONCAT('User_', FLOOR(RAND() * 100) + 1) AS issue_creator,
     SUBSTRING(comment_body, 1, 255) AS issue_description,  -- Truncate the first comment to fit the issue_description
     FLOOR(RAND() * 5000) + 500 AS issue_budget,
-    JSON_ARRAY(CONCAT('User_', FLOOR(RAND() * 100) + 1)) AS issue_assignees,
     NOW() AS date_issue_assigned,
     CONCAT('PR_', FLOOR(RAND() * 1000) + 1) AS issue_linked_pr,
     '' AS issue_status,
@@ -30,7 +31,6 @@ SELECT
 FROM issues_comment
 ON DUPLICATE KEY UPDATE
     issue_budget = VALUES(issue_budget),
-    issue_assignees = VALUES(issue_assignees),
     date_issue_assigned = VALUES(date_issue_assigned),
     issue_linked_pr = VALUES(issue_linked_pr),
     issue_status = VALUES(issue_status),
@@ -38,6 +38,10 @@ ON DUPLICATE KEY UPDATE
     date_approved = VALUES(date_approved),
     date_declined = VALUES(date_declined);
 
+INSERT IGNORE INTO issue_assignees (issue_id, login, source, assigned_at)
+SELECT issue_id, CONCAT('User_', FLOOR(RAND() * 100) + 1), 'backfill', date_issue_assigned
+FROM issues_master;
+
 
 ALTER DATABASE gosim CHARACTER SET = utf8mb4 COLLATE = utf8mb4_unicode_ci;
 
@@ -172,10 +176,6 @@ WHERE pull_id IN (
     SELECT issue_linked_pr FROM issues_master WHERE issue_linked_pr IS NOT NULL
 );
 
-UPDATE issues_master
-SET issue_assignees = JSON_ARRAY()
-WHERE issue_assignees IS NULL;
-
 select issue_id, issue_description from issues_master where  project_id not in (SELECT issue_or_project_id FROM issues_repos_indexed) limit 1;
 
 select project_id, project_description from projects where  project_id not in (SELECT issue_or_project_id FROM issues_repos_indexed) limit 1;
@@ -183,7 +183,57 @@ select project_id, project_description from projects where  project_id not in (S
 SELECT COUNT(DISTINCT issue_id) FROM issues_comment;
 
 
-        SELECT issue_id, project_id, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master  LIMIT 50 OFFSET 1
+        SELECT issue_id, project_id, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id) LIMIT 50 OFFSET 1
+
+
+COUNT(*) OVER() AS total_count
+
+
+UPDATE issues_master im
+JOIN projects p ON im.project_id = p.project_id
+SET im.main_language = p.main_language,
+    im.repo_stars = p.repo_stars;
+
+
+UPDATE issues_master im
+JOIN projects p ON im.project_id = p.project_id
+SET im.project_logo = p.project_logo;
+
+
+
+
+WITH FilteredProjects AS (
+                SELECT 
+                    project_id, 
+                    project_logo, 
+                    repo_stars, 
+                    main_language, 
+                    project_description, 
+                    issues_list,   
+                    total_budget_allocated
+                FROM 
+                    projects
+                WHERE LENGTH(main_language) > 0 ORDER BY main_language ASC
+            ),
+            TotalCount AS (
+                SELECT COUNT(*) AS total_count FROM FilteredProjects
+            )
+            SELECT 
+                fp.project_id, 
+                fp.project_logo, 
+                fp.repo_stars, 
+                fp.main_language, 
+                fp.project_description, 
+                fp.issues_list,   
+                fp.total_budget_allocated,
+                tc.total_count
+            FROM 
+                FilteredProjects fp, TotalCount tc
+
 
 
-COUNT(*) OVER() AS total_count
\ No newline at end of file
+SELECT keyword, COUNT(*) as frequency
+FROM issues_repos_summarized,
+     JSON_TABLE(keyword_tags, '$[*]' COLUMNS(keyword VARCHAR(255) PATH '$')) AS keywords
+GROUP BY keyword
+ORDER BY frequency DESC;
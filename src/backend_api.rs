@@ -0,0 +1,704 @@
+use crate::db_adjust::announce;
+use crate::db_audit::AuditInfo;
+use crate::db_export::{export_table, ExportFormat, ExportRequest, ExportTable};
+use crate::db_query::{IssueFilter, IssueQuery, ProjectQuery, SortKey};
+use crate::db_storage::Storage;
+use crate::error::Error;
+use crate::review_state::ReviewAction;
+use crate::reviewers::Reviewers;
+use crate::vector_search::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+// /export answers with the whole file in memory, larger exports go through
+// `gosim export`, which writes each page out as it goes.
+pub const MAX_BACKEND_EXPORT_ROWS: usize = 10_000;
+
+// Route handlers shared by the flows.network webhook (backend_hook) and the
+// native server (backend_server). Each one turns query params and a request body
+// into an ApiResponse, the caller only has to send it. Handlers take any Storage,
+// which is a MySQL pool in both.
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BodyLoad {
+    pub issue_id: Option<String>,
+    pub issue_budget: Option<i64>,
+    pub admin_feedback: Option<String>,
+    pub issue_budget_approved: Option<bool>,
+    pub review_status_flipper: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ApiResponse {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        ApiResponse {
+            status,
+            headers: vec![
+                (String::from("content-type"), content_type.to_string()),
+                (
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                ),
+            ],
+            body,
+        }
+    }
+
+    pub fn json<T: Serialize>(value: &T) -> Self {
+        ApiResponse::new(
+            200,
+            "application/json",
+            json!(value).to_string().into_bytes(),
+        )
+    }
+
+    pub fn text(status: u16, text: &str) -> Self {
+        ApiResponse::new(status, "text/plain", text.as_bytes().to_vec())
+    }
+
+    pub fn error(e: &Error) -> Self {
+        log::error!("Error: {:?}", e);
+        ApiResponse::new(
+            e.status_code(),
+            "application/json",
+            json!({ "error": e.to_string() }).to_string().into_bytes(),
+        )
+    }
+}
+
+// Dispatches on method and path, for servers that have no router of their own.
+pub async fn route(
+    store: &impl Storage,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    qry: &HashMap<String, Value>,
+    body: &[u8],
+) -> ApiResponse {
+    match (method, path.trim_end_matches('/')) {
+        ("GET", "/issues") => list_issues(store, qry).await,
+        ("POST", "/issues") => list_issues_multi(store, qry, body).await,
+        ("POST", "/issue") => get_issue_w_comments(store, headers, body).await,
+        ("GET", "/projects") => list_projects(store, qry).await,
+        ("POST", "/projects") => list_projects_filtered(store, qry, body).await,
+        ("POST", "/budget") => approve_issue_budget(store, headers, body).await,
+        ("POST", "/search") => search(body).await,
+        ("POST", "/decline") => batch_decline_issues(store, headers, body).await,
+        ("POST", "/conclude") => conclude_issue(store, headers, body).await,
+        ("GET", "/runs") => list_runs(store, headers, qry).await,
+        ("POST", "/history") => issue_history(store, headers, body).await,
+        ("POST", "/review") => review_issue(store, headers, body).await,
+        ("POST", "/adjust") => adjust_budget(store, headers, body).await,
+        ("POST", "/states") => issue_states(store, headers, body).await,
+        ("POST", "/ledger") => ledger(store, headers, body).await,
+        ("POST", "/export") => export(store, headers, body).await,
+        ("GET", "/stats/budget") => budget_stats(store, headers, qry).await,
+        (
+            _,
+            "/issues" | "/issue" | "/projects" | "/budget" | "/search" | "/decline" | "/conclude"
+            | "/runs" | "/history" | "/review" | "/adjust" | "/states" | "/ledger" | "/export"
+            | "/stats/budget",
+        ) => ApiResponse::text(405, "Method not allowed"),
+        _ => ApiResponse::text(404, "No route matched"),
+    }
+}
+
+// The login of the reviewer behind an admin action, from the token in the
+// request headers. Bodies can't name their own actor.
+fn reviewer(headers: &[(String, String)]) -> Result<String, ApiResponse> {
+    Reviewers::from_env()
+        .authenticate(headers)
+        .map_err(|e| ApiResponse::error(&e))
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, ApiResponse> {
+    serde_json::from_slice(body).map_err(|e| {
+        log::error!("failed to parse body: {}", e);
+        ApiResponse::error(&Error::Validation(format!("failed to parse body: {}", e)))
+    })
+}
+
+fn query_usize(qry: &HashMap<String, Value>, key: &str) -> Option<usize> {
+    match qry
+        .get(key)
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+    {
+        Some(m) if m > 0 => Some(m),
+        _ => {
+            log::error!("Invalid or missing '{}' parameter", key);
+            None
+        }
+    }
+}
+
+pub async fn list_issues(store: &impl Storage, qry: &HashMap<String, Value>) -> ApiResponse {
+    let page = query_usize(qry, "page").unwrap_or(1);
+    let page_size = query_usize(qry, "page_size").unwrap_or(5);
+    let list_by: Option<&str> = qry
+        .get("list_by")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty());
+    log::info!(
+        "page: {} page_size: {}, list_by: {:?}",
+        page,
+        page_size,
+        list_by
+    );
+
+    match store.list_issues_by_single(list_by, page, page_size).await {
+        Ok(issues_obj) => ApiResponse::json(&issues_obj),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+pub async fn list_issues_multi(
+    store: &impl Storage,
+    qry: &HashMap<String, Value>,
+    body: &[u8],
+) -> ApiResponse {
+    // filter_strs are the preset names, filter/sort the composable query; both
+    // may be given and are ANDed.
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct IssuesLoad {
+        #[serde(default)]
+        filter_strs: Vec<String>,
+        #[serde(default)]
+        filter: Option<IssueFilter>,
+        #[serde(default)]
+        sort: Vec<SortKey>,
+    }
+
+    let page = query_usize(qry, "page").unwrap_or(1);
+    let page_size = query_usize(qry, "page_size").unwrap_or(5);
+    let load: IssuesLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+    let query = IssueQuery {
+        filter: load.filter,
+        sort: load.sort,
+    };
+    log::info!(
+        "page: {} page_size: {}, filter_strs: {:?}, query: {:?}",
+        page,
+        page_size,
+        load.filter_strs,
+        query
+    );
+
+    let filter_strs: Vec<&str> = load.filter_strs.iter().map(|s| s.as_str()).collect();
+    let query = match IssueQuery::from_names(&filter_strs) {
+        Ok(named) => named.and(query),
+        Err(e) => return ApiResponse::error(&e),
+    };
+    match store.list_issues_by_multi(&query, page, page_size).await {
+        Ok(issues_obj) => ApiResponse::json(&issues_obj),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// One issue with its comments. The pending votes are left out unless a
+// reviewer asks.
+pub async fn get_issue_w_comments(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    #[derive(Serialize, Deserialize)]
+    struct IssueId {
+        issue_id: String,
+    }
+
+    let load: IssueId = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+    log::info!("Issue_id: {}", load.issue_id);
+
+    match store.get_issue_w_comments_by_id(&load.issue_id).await {
+        Ok(mut issue) => {
+            if Reviewers::from_env().authenticate(headers).is_err() {
+                issue.pending_votes.clear();
+            }
+            ApiResponse::json(&issue)
+        }
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+fn required_page(qry: &HashMap<String, Value>) -> Result<(usize, usize), ApiResponse> {
+    match (query_usize(qry, "page"), query_usize(qry, "page_size")) {
+        (Some(page), Some(page_size)) => Ok((page, page_size)),
+        _ => Err(ApiResponse::error(&Error::Validation(String::from(
+            "'page' and 'page_size' are required",
+        )))),
+    }
+}
+
+pub async fn list_projects(store: &impl Storage, qry: &HashMap<String, Value>) -> ApiResponse {
+    log::info!("Received query parameters: {:?}", qry);
+
+    let (page, page_size) = match required_page(qry) {
+        Ok(page) => page,
+        Err(res) => return res,
+    };
+    let list_by = qry
+        .get("list_by")
+        .and_then(|v| v.as_str())
+        .filter(|s| !s.is_empty());
+    log::info!(
+        "page: {} page_size: {}, list_by: {:?}",
+        page,
+        page_size,
+        list_by
+    );
+
+    let query = match ProjectQuery::from_list_by(list_by) {
+        Ok(query) => query,
+        Err(e) => return ApiResponse::error(&e),
+    };
+    match store.list_projects_by(&query, page, page_size).await {
+        Ok(projects_obj) => ApiResponse::json(&projects_obj),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+pub async fn list_projects_filtered(
+    store: &impl Storage,
+    qry: &HashMap<String, Value>,
+    body: &[u8],
+) -> ApiResponse {
+    let (page, page_size) = match required_page(qry) {
+        Ok(page) => page,
+        Err(res) => return res,
+    };
+    let query: ProjectQuery = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+    log::info!(
+        "page: {} page_size: {}, query: {:?}",
+        page,
+        page_size,
+        query
+    );
+
+    match store.list_projects_by(&query, page, page_size).await {
+        Ok(projects_obj) => ApiResponse::json(&projects_obj),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// A reviewer's vote for the budget, 202 while it waits for more votes.
+pub async fn approve_issue_budget(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    let reviewer = match reviewer(headers) {
+        Ok(reviewer) => reviewer,
+        Err(res) => return res,
+    };
+    let load: BodyLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    let issue_budget = load.issue_budget.unwrap_or_default();
+    let issue_id = load.issue_id.unwrap_or_default();
+    let audit = AuditInfo::new(Some(reviewer), load.admin_feedback);
+    match store
+        .vote_issue_budget_in_db(&issue_id, issue_budget, &audit)
+        .await
+    {
+        Ok(vote) if vote.approved => ApiResponse::new(
+            200,
+            "application/json",
+            format!("{issue_id} approved for budget: {issue_budget}").into_bytes(),
+        ),
+        Ok(vote) => ApiResponse::text(
+            202,
+            &format!(
+                "{issue_id} budget {issue_budget}: {} of {} approvals",
+                vote.votes, vote.required_votes
+            ),
+        ),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+pub async fn search(body: &[u8]) -> ApiResponse {
+    #[derive(Serialize, Deserialize, Clone, Debug, Default)]
+    pub struct SearchLoad {
+        pub query: String,
+    }
+
+    let load: SearchLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    match search_collection_hybrid(&load.query, "gosim_search").await {
+        Ok(search_result) => ApiResponse::json(&search_result),
+        Err(e) => {
+            log::error!("Error searching vector db: {:?}", e);
+            ApiResponse::error(&e)
+        }
+    }
+}
+
+pub async fn batch_decline_issues(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    let reviewer = match reviewer(headers) {
+        Ok(reviewer) => reviewer,
+        Err(res) => return res,
+    };
+    #[derive(Serialize, Deserialize)]
+    struct IssueIds {
+        issue_ids: Vec<String>,
+        admin_feedback: Option<String>,
+    }
+
+    let load: IssueIds = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    let audit = AuditInfo::new(Some(reviewer), load.admin_feedback);
+    match store
+        .batch_decline_issues_in_db(load.issue_ids, &audit)
+        .await
+    {
+        Ok(_) => ApiResponse::text(200, "all issue_ids successfully processed"),
+        Err(e) => {
+            log::error!("Error, failed processing issue_ids: {:?}", e);
+            ApiResponse::error(&e)
+        }
+    }
+}
+
+pub async fn conclude_issue(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    let reviewer = match reviewer(headers) {
+        Ok(reviewer) => reviewer,
+        Err(res) => return res,
+    };
+    let load: BodyLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    let approve = load.issue_budget_approved.unwrap_or_default();
+    let issue_id = load.issue_id.unwrap_or_default();
+    if !approve {
+        return ApiResponse::text(200, &format!("{issue_id} left unchanged"));
+    }
+
+    let audit = AuditInfo::new(Some(reviewer), load.admin_feedback);
+    match store.conclude_issue_in_db(&issue_id, &audit).await {
+        Ok(()) => ApiResponse::text(200, &format!("{issue_id} concluded")),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+pub async fn list_runs(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    qry: &HashMap<String, Value>,
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    let limit = match qry
+        .get("limit")
+        .and_then(|v| v.as_str().and_then(|s| s.parse::<usize>().ok()))
+    {
+        Some(m) if m > 0 => m,
+        _ => 10,
+    };
+
+    match store.list_recent_runs(limit).await {
+        Ok(runs) => ApiResponse::json(&runs),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// Admin actions on one issue, oldest first.
+pub async fn issue_history(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    #[derive(Serialize, Deserialize)]
+    struct IssueId {
+        issue_id: String,
+    }
+
+    let load: IssueId = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    match store.list_issue_history(&load.issue_id).await {
+        Ok(history) => ApiResponse::json(&history),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// Takes a review action (withdraw, expire, reopen, ...) on one issue. Budgets are
+// approved through /budget.
+pub async fn review_issue(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    let reviewer = match reviewer(headers) {
+        Ok(reviewer) => reviewer,
+        Err(res) => return res,
+    };
+    #[derive(Serialize, Deserialize)]
+    struct ReviewLoad {
+        issue_id: String,
+        action: ReviewAction,
+        admin_feedback: Option<String>,
+    }
+
+    let load: ReviewLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    let audit = AuditInfo::new(Some(reviewer), load.admin_feedback);
+    match store
+        .review_issue_in_db(&load.issue_id, load.action, &audit)
+        .await
+    {
+        Ok(()) => ApiResponse::text(200, &format!("{} {}", load.issue_id, load.action.as_str())),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// Adjusts, moves or claws back an approved budget. With `announce` each changed
+// issue gets a comment on GitHub once the change is committed.
+pub async fn adjust_budget(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    let reviewer = match reviewer(headers) {
+        Ok(reviewer) => reviewer,
+        Err(res) => return res,
+    };
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum AdjustAction {
+        Adjust,
+        Move,
+        Clawback,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct AdjustLoad {
+        issue_id: String,
+        action: AdjustAction,
+        // the new budget of an adjust
+        issue_budget: Option<i64>,
+        // where a move goes, and how much of the budget, all of it by default
+        to_issue_id: Option<String>,
+        amount: Option<i64>,
+        #[serde(default)]
+        announce: bool,
+        admin_feedback: Option<String>,
+    }
+
+    let load: AdjustLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    let audit = AuditInfo::new(Some(reviewer), load.admin_feedback);
+    let changes = match (load.action, load.issue_budget, load.to_issue_id) {
+        (AdjustAction::Adjust, Some(issue_budget), _) => store
+            .adjust_budget_in_db(&load.issue_id, issue_budget, &audit)
+            .await
+            .map(|change| vec![change]),
+        (AdjustAction::Move, _, Some(to_issue_id)) => {
+            store
+                .move_budget_in_db(&load.issue_id, &to_issue_id, load.amount, &audit)
+                .await
+        }
+        (AdjustAction::Clawback, _, _) => store
+            .clawback_budget_in_db(&load.issue_id, &audit)
+            .await
+            .map(|change| vec![change]),
+        (AdjustAction::Adjust, None, _) => Err(Error::Validation(String::from(
+            "an adjust needs the new issue_budget",
+        ))),
+        (AdjustAction::Move, _, None) => Err(Error::Validation(String::from(
+            "a move needs the to_issue_id",
+        ))),
+    };
+
+    match changes {
+        Ok(mut changes) => {
+            if load.announce {
+                announce(&mut changes, audit.admin_feedback.as_deref()).await;
+            }
+            ApiResponse::json(&changes)
+        }
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// The review states one issue went through, oldest first.
+pub async fn issue_states(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    #[derive(Serialize, Deserialize)]
+    struct IssueId {
+        issue_id: String,
+    }
+
+    let load: IssueId = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+
+    match store.list_state_changes(&load.issue_id).await {
+        Ok(changes) => ApiResponse::json(&changes),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// Campaign balances and the ledger transactions, of one issue when the body names it.
+pub async fn ledger(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    #[derive(Serialize, Deserialize, Default)]
+    struct LedgerLoad {
+        #[serde(default)]
+        issue_id: Option<String>,
+        #[serde(default)]
+        page: Option<usize>,
+        #[serde(default)]
+        page_size: Option<usize>,
+    }
+
+    let load: LedgerLoad = match body.iter().all(u8::is_ascii_whitespace) {
+        true => LedgerLoad::default(),
+        false => match parse_body(body) {
+            Ok(obj) => obj,
+            Err(res) => return res,
+        },
+    };
+
+    let balances = match store.campaign_balances().await {
+        Ok(balances) => balances,
+        Err(e) => return ApiResponse::error(&e),
+    };
+    let page = load.page.unwrap_or(1);
+    let page_size = load.page_size.unwrap_or(100);
+    match store
+        .list_ledger_transactions(load.issue_id.as_deref(), page, page_size)
+        .await
+    {
+        Ok(transactions) => ApiResponse::json(&json!({
+            "balances": balances,
+            "transactions": transactions,
+        })),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// The whole file in one response, built from the table a page at a time.
+pub async fn export(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    #[derive(Serialize, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ExportLoad {
+        table: ExportTable,
+        #[serde(default)]
+        format: ExportFormat,
+        #[serde(default)]
+        filter: Option<Value>,
+        #[serde(default)]
+        sort: Vec<SortKey>,
+    }
+
+    let load: ExportLoad = match parse_body(body) {
+        Ok(obj) => obj,
+        Err(res) => return res,
+    };
+    let request = match ExportRequest::new(load.table, load.filter, load.sort) {
+        Ok(request) => request,
+        Err(e) => return ApiResponse::error(&e),
+    };
+
+    match export_table(
+        store,
+        &request,
+        load.format,
+        Vec::new(),
+        Some(MAX_BACKEND_EXPORT_ROWS),
+    )
+    .await
+    {
+        Ok((body, _)) => ApiResponse::new(200, load.format.content_type(), body),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
+
+// Burn-down, forecast and breakdowns of the budget, for one campaign with
+// `?campaign=` or all of them.
+pub async fn budget_stats(
+    store: &impl Storage,
+    headers: &[(String, String)],
+    qry: &HashMap<String, Value>,
+) -> ApiResponse {
+    if let Err(res) = reviewer(headers) {
+        return res;
+    }
+    let campaign_id = qry
+        .get("campaign")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+
+    match store.budget_report(campaign_id).await {
+        Ok(report) => ApiResponse::json(&report),
+        Err(e) => ApiResponse::error(&e),
+    }
+}
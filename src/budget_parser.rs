@@ -0,0 +1,292 @@
+use crate::currency::Currency;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+
+// Where a budget was read from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetSource {
+    #[default]
+    Body,
+    Label,
+}
+
+// How sure the parser is that it read a budget and not some other number.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    // an amount of money without a budget keyword
+    #[default]
+    Low,
+    // a budget keyword without a currency, or a range
+    Medium,
+    // a budget keyword and a currency, or a label naming an amount
+    High,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ParsedBudget {
+    // the lower end of a range
+    pub amount: i32,
+    // the upper end of a range, amount otherwise
+    pub max_amount: i32,
+    pub currency: Currency,
+    pub source: BudgetSource,
+    pub confidence: Confidence,
+    // the text the budget was read from
+    pub text: String,
+}
+
+impl ParsedBudget {
+    pub fn is_range(&self) -> bool {
+        self.max_amount != self.amount
+    }
+}
+
+pub const MIN_BUDGET: i64 = 10;
+pub const MAX_BUDGET: i64 = 999_999;
+
+// An amount with an optional currency before or after it, "1k" style
+// thousands, and "100-200" / "100 to 200" ranges.
+const AMOUNT: &str = r"(?:(?P<pre>us\$|\$|€|¥|￥|usd|eur|cny|rmb)\s*)?(?P<low>\d{1,3}(?:[,.]\d{3})+(?:[.,]\d{1,2})?|\d+(?:[.,]\d{1,2})?)(?P<low_k>k)?(?:\s*(?:-|–|—|~|to)\s*(?:us\$|\$|€|¥|￥)?\s*(?P<high>\d{1,3}(?:[,.]\d{3})+(?:[.,]\d{1,2})?|\d+(?:[.,]\d{1,2})?)(?P<high_k>k)?)?(?:\s*(?P<post>usd|eur|cny|rmb|dollars?|euros?|yuan|元|\$|€|¥|￥))?";
+
+// What may follow an amount: not a letter or digit, so "50x" or "2023" in
+// "v2023" aren't read, but a sentence may end right after it.
+const AMOUNT_END: &str = r"(?:[.,]?(?:[^\w.,]|$))";
+
+lazy_static! {
+    static ref KEYWORD: Regex =
+        Regex::new(r"(?i)\b(?:budget|bounty|reward|prize)s?\b[\s:=*_|-]*(?:(?:is|of)\s+)?")
+            .unwrap();
+    static ref AMOUNT_AT: Regex = Regex::new(&format!(r"(?i)^{}{}", AMOUNT, AMOUNT_END)).unwrap();
+    static ref AMOUNT_ANYWHERE: Regex = Regex::new(&format!(
+        r"(?i)(?:^|[^\w$€¥￥.,])({}){}",
+        AMOUNT, AMOUNT_END
+    ))
+    .unwrap();
+    static ref FENCE: Regex = Regex::new(r"^\s{0,3}(```|~~~)").unwrap();
+    static ref INLINE_CODE: Regex = Regex::new(r"`[^`\n]*`").unwrap();
+    static ref HTML_COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->").unwrap();
+}
+
+// The budget of an issue, from its labels and body. A label naming a budget
+// ("bounty: $100", "💎 $250") is set by the maintainers and wins over the body
+// unless the body is surer. In the body the first "budget: <amount>" outside
+// code is taken, and failing that the first amount of money. None if there's
+// no budget.
+pub fn parse_budget(body: &str, labels: &[String]) -> Option<ParsedBudget> {
+    let from_labels = labels
+        .iter()
+        .filter_map(|label| parse_label(label))
+        .max_by_key(|budget| budget.confidence);
+    let from_body = parse_body(body);
+
+    match (from_labels, from_body) {
+        (Some(label), Some(body)) if body.confidence > label.confidence => Some(body),
+        (Some(label), _) => Some(label),
+        (None, body) => body,
+    }
+}
+
+// The amount and currency of parse_budget, or (0, USD) without a budget.
+pub fn extract_budget(body: &str, labels: &[String]) -> (i32, Currency) {
+    parse_budget(body, labels).map_or((0, Currency::Usd), |budget| {
+        (budget.amount, budget.currency)
+    })
+}
+
+pub fn parse_body(body: &str) -> Option<ParsedBudget> {
+    let text = strip_code(body);
+    parse_text(&text, BudgetSource::Body)
+}
+
+pub fn parse_label(label: &str) -> Option<ParsedBudget> {
+    let mut budget = parse_text(label, BudgetSource::Label)?;
+    // a label only ever holds the budget, a bare amount in one is still sure
+    budget.confidence = match budget.confidence {
+        Confidence::Low => Confidence::Medium,
+        _ => Confidence::High,
+    };
+    Some(budget)
+}
+
+fn parse_text(text: &str, source: BudgetSource) -> Option<ParsedBudget> {
+    for keyword in KEYWORD.find_iter(text) {
+        let rest = &text[keyword.end()..];
+        if let Some(cap) = AMOUNT_AT.captures(rest) {
+            if let Some(mut budget) = read_amount(&cap, source) {
+                budget.confidence = if budget.is_range() || !has_currency(&cap) {
+                    Confidence::Medium
+                } else {
+                    Confidence::High
+                };
+                budget.text = format!("{}{}", keyword.as_str(), cap[0].trim_end())
+                    .trim()
+                    .to_string();
+                return Some(budget);
+            }
+        }
+    }
+
+    // no keyword, an amount counts only when it names its currency
+    AMOUNT_ANYWHERE
+        .captures_iter(text)
+        .filter(has_currency)
+        .find_map(|cap| {
+            let mut budget = read_amount(&cap, source)?;
+            budget.text = cap[1].trim().to_string();
+            Some(budget)
+        })
+}
+
+fn has_currency(cap: &Captures) -> bool {
+    cap.name("pre").is_some() || cap.name("post").is_some()
+}
+
+fn read_amount(cap: &Captures, source: BudgetSource) -> Option<ParsedBudget> {
+    let low = parse_number(&cap["low"], cap.name("low_k").is_some())?;
+    let high = match cap.name("high") {
+        Some(high) => parse_number(high.as_str(), cap.name("high_k").is_some())?,
+        None => low,
+    };
+    // "2023-10-18" isn't a range
+    if high < low || !(MIN_BUDGET..=MAX_BUDGET).contains(&low) || high > MAX_BUDGET {
+        return None;
+    }
+    let currency = cap
+        .name("pre")
+        .or_else(|| cap.name("post"))
+        .and_then(|code| code.as_str().parse().ok())
+        .unwrap_or_default();
+
+    Some(ParsedBudget {
+        amount: low as i32,
+        max_amount: high as i32,
+        currency,
+        source,
+        confidence: Confidence::Low,
+        text: String::new(),
+    })
+}
+
+// "1,000", "1.000" and "1,000.50" are a thousand, "12,50" and "12.50" twelve and
+// a half; the cents are dropped. "1.5k" is 1500.
+pub fn parse_number(number: &str, thousands: bool) -> Option<i64> {
+    let (whole, fraction) = match number.rfind([',', '.']) {
+        Some(i) if number.len() - i - 1 < 3 => (&number[..i], &number[i + 1..]),
+        _ => (number, ""),
+    };
+    let whole: String = whole.chars().filter(|c| c.is_ascii_digit()).collect();
+    let value: f64 = format!("{}.{}0", whole, fraction).parse().ok()?;
+    let value = if thousands { value * 1000.0 } else { value };
+    Some(value.trunc() as i64)
+}
+
+// The body without fenced code blocks, inline code and HTML comments, where
+// issue templates keep their examples. Line breaks are kept.
+pub fn strip_code(body: &str) -> String {
+    let body = HTML_COMMENT.replace_all(body, " ");
+    let mut fence: Option<&str> = None;
+    let mut lines = Vec::new();
+    for line in body.lines() {
+        if let Some(cap) = FENCE.captures(line) {
+            let marker = cap.get(1).map_or("", |m| m.as_str());
+            match fence {
+                None => fence = Some(marker),
+                Some(open) if open == marker => fence = None,
+                Some(_) => {}
+            }
+            lines.push(String::new());
+            continue;
+        }
+        if fence.is_some() {
+            lines.push(String::new());
+        } else {
+            lines.push(INLINE_CODE.replace_all(line, " ").into_owned());
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Confidence::{High, Low, Medium};
+    use Currency::{Cny, Eur, Usd};
+
+    // (labels, body, expected amount, currency and confidence)
+    type Case = (
+        &'static [&'static str],
+        &'static str,
+        Option<(i32, Currency, Confidence)>,
+    );
+
+    const CORPUS: &[Case] = &[
+        // keywords in the body
+        (&[], "Budget: 150", Some((150, Usd, Medium))),
+        (&[], "**Bounty:** 1,000 USD", Some((1000, Usd, High))),
+        (&[], "reward 1.5k", Some((1500, Usd, Medium))),
+        (&[], "Budget: $100-$200", Some((100, Usd, Medium))),
+        (&[], "The prize is 300 euros.", Some((300, Eur, High))),
+        // thousands separators and cents
+        (&[], "Budget: 1.000 EUR", Some((1000, Eur, High))),
+        (&[], "Bounty: ¥12,500", Some((12500, Cny, High))),
+        (&[], "Budget: $2,500.75", Some((2500, Usd, High))),
+        (&[], "Budget: 12,50 EUR", Some((12, Eur, High))),
+        (&[], "Bounty: $1,000,000", None),
+        // an amount of money without a keyword
+        (
+            &[],
+            "Fixing this saves us €150 a month.",
+            Some((150, Eur, Low)),
+        ),
+        // numbers that aren't budgets
+        (&[], "Fails on line 150 of v2023, see #1234.", None),
+        (
+            &[],
+            "Since 2023-10-18, 3 of 40 tests fail in 50x mode.",
+            None,
+        ),
+        (&[], "Budget: 5", None),
+        (&[], "```\nBudget: $500\n```\nNo budget yet.", None),
+        (&[], "Use `budget: 500` in the config.", None),
+        (
+            &[],
+            "<!-- Budget: $500 -->\nBudget: 200",
+            Some((200, Usd, Medium)),
+        ),
+        // labels
+        (
+            &["good first issue", "💎 Bounty $250"],
+            "",
+            Some((250, Usd, High)),
+        ),
+        (&["bounty-100"], "", Some((100, Usd, High))),
+        (&["$250"], "", Some((250, Usd, Medium))),
+        (&["priority 1"], "Budget: 150", Some((150, Usd, Medium))),
+        // a label and a body that disagree: the label wins unless the body is surer
+        (&["bounty: $250"], "Budget: 150 EUR", Some((250, Usd, High))),
+        (&["bounty-100"], "Budget: 150 EUR", Some((100, Usd, High))),
+        (&["$250"], "Budget: 300 EUR", Some((300, Eur, High))),
+        (&["$250"], "Budget: 300", Some((250, Usd, Medium))),
+    ];
+
+    #[test]
+    fn corpus() {
+        for (labels, body, expected) in CORPUS {
+            let labels: Vec<String> = labels.iter().map(|l| l.to_string()).collect();
+            let parsed = parse_budget(body, &labels)
+                .map(|budget| (budget.amount, budget.currency, budget.confidence));
+            assert_eq!(parsed, *expected, "labels {:?}, body {:?}", labels, body);
+        }
+    }
+
+    #[test]
+    fn ranges_keep_both_ends() {
+        let budget = parse_body("Budget: $100 to $200").unwrap();
+        assert_eq!((budget.amount, budget.max_amount), (100, 200));
+        assert!(budget.is_range());
+        assert_eq!(extract_budget("nothing here", &[]), (0, Usd));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::csv_util::{csv_records, ImportFormat};
+use crate::error::{Error, GosimResult};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// The currencies budgets are given in. Ledger entries keep the currency of their
+// transaction, and totals over several currencies are converted into the
+// reporting currency with the exchange rates loaded from a rates file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    #[default]
+    Usd,
+    Eur,
+    Cny,
+}
+
+impl Currency {
+    pub const ALL: [Currency; 3] = [Currency::Usd, Currency::Eur, Currency::Cny];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Cny => "CNY",
+        }
+    }
+
+    // Stored as its code, anything unknown is read as USD like the column default.
+    pub fn from_db(s: &str) -> Self {
+        s.parse().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Codes, symbols and the names issue bodies use for them.
+impl FromStr for Currency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "usd" | "$" | "us$" | "dollar" | "dollars" => Ok(Currency::Usd),
+            "eur" | "€" | "euro" | "euros" => Ok(Currency::Eur),
+            "cny" | "rmb" | "¥" | "￥" | "yuan" | "元" => Ok(Currency::Cny),
+            _ => Err(Error::Validation(format!("unknown currency '{}'", s))),
+        }
+    }
+}
+
+// The reporting currency totals are converted into, REPORTING_CURRENCY or USD.
+pub fn reporting_currency() -> Currency {
+    std::env::var("REPORTING_CURRENCY")
+        .ok()
+        .and_then(|code| code.parse().ok())
+        .unwrap_or_default()
+}
+
+// What one unit of `currency` was worth in USD on `rate_date`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ExchangeRate {
+    #[serde(alias = "date")]
+    pub rate_date: String,
+    pub currency: Currency,
+    #[serde(alias = "usd_value")]
+    pub rate: f64,
+}
+
+// The rates in effect on a day: the latest one of each currency up to that day.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Rates {
+    pub as_of: Option<String>,
+    pub rates: BTreeMap<Currency, f64>,
+}
+
+impl Rates {
+    // `as_of` is a %Y-%m-%d date, None for the latest rates.
+    pub fn snapshot(rates: &[ExchangeRate], as_of: Option<&str>) -> Self {
+        let mut latest: BTreeMap<Currency, &ExchangeRate> = BTreeMap::new();
+        for rate in rates {
+            if as_of.is_some_and(|as_of| rate.rate_date.as_str() > as_of) {
+                continue;
+            }
+            match latest.get(&rate.currency) {
+                Some(current) if current.rate_date >= rate.rate_date => {}
+                _ => {
+                    latest.insert(rate.currency, rate);
+                }
+            }
+        }
+        Rates {
+            as_of: as_of.map(String::from),
+            rates: latest
+                .into_iter()
+                .map(|(currency, rate)| (currency, rate.rate))
+                .collect(),
+        }
+    }
+
+    fn usd_value(&self, currency: Currency) -> GosimResult<f64> {
+        match currency {
+            Currency::Usd => Ok(1.0),
+            _ => self.rates.get(&currency).copied().ok_or_else(|| {
+                Error::Validation(match &self.as_of {
+                    Some(as_of) => format!("no exchange rate for {} on {}", currency, as_of),
+                    None => format!("no exchange rate for {}", currency),
+                })
+            }),
+        }
+    }
+
+    // `amount` of `from` in `to`, rounded to a whole unit.
+    pub fn convert(&self, amount: i64, from: Currency, to: Currency) -> GosimResult<i64> {
+        if from == to || amount == 0 {
+            return Ok(amount);
+        }
+        let value = amount as f64 * self.usd_value(from)? / self.usd_value(to)?;
+        Ok(value.round() as i64)
+    }
+}
+
+pub fn validate_rate(rate: &ExchangeRate) -> GosimResult<()> {
+    if NaiveDate::parse_from_str(&rate.rate_date, "%Y-%m-%d").is_err() {
+        return Err(Error::Validation(format!(
+            "invalid rate date '{}', expected YYYY-MM-DD",
+            rate.rate_date
+        )));
+    }
+    if !rate.rate.is_finite() || rate.rate <= 0.0 {
+        return Err(Error::Validation(format!(
+            "the {} rate of {} must be positive",
+            rate.currency, rate.rate_date
+        )));
+    }
+    if rate.currency == Currency::Usd && rate.rate != 1.0 {
+        return Err(Error::Validation(String::from(
+            "rates are in USD, the USD rate is always 1",
+        )));
+    }
+    Ok(())
+}
+
+// A JSON array of ExchangeRate objects, or CSV with a header naming date (or
+// rate_date), currency and rate (or usd_value).
+pub fn parse_rates(text: &str, format: ImportFormat) -> GosimResult<Vec<ExchangeRate>> {
+    let rates: Vec<ExchangeRate> = match format {
+        ImportFormat::Json => serde_json::from_str(text)
+            .map_err(|e| Error::Validation(format!("invalid rates file: {}", e)))?,
+        ImportFormat::Csv => {
+            let mut records = csv_records(text).into_iter();
+            let header = records
+                .next()
+                .ok_or_else(|| Error::Validation(String::from("rates file is empty")))?;
+            let column = |names: &[&str]| {
+                header
+                    .iter()
+                    .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+                    .ok_or_else(|| {
+                        Error::Validation(format!("rates file has no {} column", names[0]))
+                    })
+            };
+            let date_column = column(&["date", "rate_date"])?;
+            let currency_column = column(&["currency"])?;
+            let rate_column = column(&["rate", "usd_value"])?;
+
+            let mut rates = Vec::new();
+            for (i, record) in records.enumerate() {
+                let field = |column: usize| record.get(column).map_or("", |f| f.trim());
+                rates.push(ExchangeRate {
+                    rate_date: field(date_column).to_string(),
+                    currency: field(currency_column)
+                        .parse()
+                        .map_err(|e| Error::Validation(format!("row {}: {}", i + 1, e)))?,
+                    rate: field(rate_column).parse().map_err(|_| {
+                        Error::Validation(format!(
+                            "row {}: invalid rate '{}'",
+                            i + 1,
+                            field(rate_column)
+                        ))
+                    })?,
+                });
+            }
+            rates
+        }
+    };
+    for rate in &rates {
+        validate_rate(rate)?;
+    }
+    Ok(rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(rate_date: &str, currency: Currency, rate: f64) -> ExchangeRate {
+        ExchangeRate {
+            rate_date: rate_date.to_string(),
+            currency,
+            rate,
+        }
+    }
+
+    #[test]
+    fn snapshot_takes_the_latest_rate_up_to_the_day() {
+        let rates = [
+            rate("2023-10-01", Currency::Eur, 1.05),
+            rate("2023-10-10", Currency::Eur, 1.10),
+            rate("2023-10-05", Currency::Cny, 0.14),
+            rate("2023-10-20", Currency::Cny, 0.13),
+        ];
+
+        let latest = Rates::snapshot(&rates, None);
+        assert_eq!(latest.rates[&Currency::Eur], 1.10);
+        assert_eq!(latest.rates[&Currency::Cny], 0.13);
+
+        let on_the_8th = Rates::snapshot(&rates, Some("2023-10-08"));
+        assert_eq!(on_the_8th.as_of.as_deref(), Some("2023-10-08"));
+        assert_eq!(on_the_8th.rates[&Currency::Eur], 1.05);
+        assert_eq!(on_the_8th.rates[&Currency::Cny], 0.14);
+
+        // a rate of the day itself is in effect, none exists before the first
+        let on_the_10th = Rates::snapshot(&rates, Some("2023-10-10"));
+        assert_eq!(on_the_10th.rates[&Currency::Eur], 1.10);
+        assert!(Rates::snapshot(&rates, Some("2023-09-30")).rates.is_empty());
+    }
+
+    #[test]
+    fn convert_goes_through_usd_and_rounds() {
+        let rates = Rates::snapshot(
+            &[
+                rate("2023-10-01", Currency::Eur, 1.1),
+                rate("2023-10-01", Currency::Cny, 0.14),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            rates.convert(100, Currency::Eur, Currency::Usd).unwrap(),
+            110
+        );
+        assert_eq!(
+            rates.convert(110, Currency::Usd, Currency::Eur).unwrap(),
+            100
+        );
+        // 500 CNY is 70 USD, 63.6 EUR
+        assert_eq!(
+            rates.convert(500, Currency::Cny, Currency::Usd).unwrap(),
+            70
+        );
+        assert_eq!(
+            rates.convert(500, Currency::Cny, Currency::Eur).unwrap(),
+            64
+        );
+        assert_eq!(
+            rates.convert(-100, Currency::Eur, Currency::Usd).unwrap(),
+            -110
+        );
+    }
+
+    #[test]
+    fn convert_fails_without_a_rate() {
+        let rates = Rates::snapshot(
+            &[rate("2023-10-01", Currency::Eur, 1.1)],
+            Some("2023-10-02"),
+        );
+
+        let err = rates
+            .convert(100, Currency::Cny, Currency::Usd)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            Error::Validation(String::from("no exchange rate for CNY on 2023-10-02")).to_string()
+        );
+        assert!(rates.convert(100, Currency::Usd, Currency::Cny).is_err());
+        // nothing to convert needs no rate
+        assert_eq!(
+            rates.convert(100, Currency::Cny, Currency::Cny).unwrap(),
+            100
+        );
+        assert_eq!(rates.convert(0, Currency::Cny, Currency::Usd).unwrap(), 0);
+    }
+
+    #[test]
+    fn parse_rates_reads_csv_and_json() {
+        let csv = "date,currency,rate\n2023-10-01,EUR,1.1\n2023-10-01,\"cny\",0.14\n";
+        assert_eq!(
+            parse_rates(csv, ImportFormat::Csv).unwrap(),
+            vec![
+                rate("2023-10-01", Currency::Eur, 1.1),
+                rate("2023-10-01", Currency::Cny, 0.14)
+            ]
+        );
+
+        // the aliases of both formats
+        let csv = "Currency,usd_value,rate_date\nEUR,1.1,2023-10-01\n";
+        assert_eq!(
+            parse_rates(csv, ImportFormat::Csv).unwrap(),
+            vec![rate("2023-10-01", Currency::Eur, 1.1)]
+        );
+        let json = r#"[{"date": "2023-10-01", "currency": "EUR", "usd_value": 1.1}]"#;
+        assert_eq!(
+            parse_rates(json, ImportFormat::Json).unwrap(),
+            vec![rate("2023-10-01", Currency::Eur, 1.1)]
+        );
+    }
+
+    #[test]
+    fn parse_rates_rejects_bad_rows() {
+        let rejected = [
+            "",
+            "date,currency\n2023-10-01,EUR\n",
+            "date,currency,rate\n2023-10-01,GBP,1.2\n",
+            "date,currency,rate\n2023-10-01,EUR,abc\n",
+            "date,currency,rate\n2023-10-01,EUR,0\n",
+            "date,currency,rate\n2023-10-01,EUR,-1.1\n",
+            "date,currency,rate\n01/10/2023,EUR,1.1\n",
+            "date,currency,rate\n2023-10-01,USD,1.2\n",
+        ];
+        for csv in rejected {
+            assert!(
+                matches!(
+                    parse_rates(csv, ImportFormat::Csv),
+                    Err(Error::Validation(_))
+                ),
+                "{:?}",
+                csv
+            );
+        }
+        assert!(parse_rates("{not json", ImportFormat::Json).is_err());
+        assert!(parse_rates(
+            r#"[{"date": "2023-10-01", "currency": "EUR", "rate": 0}]"#,
+            ImportFormat::Json
+        )
+        .is_err());
+    }
+}
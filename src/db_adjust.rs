@@ -0,0 +1,705 @@
+use crate::currency::Currency;
+use crate::db_approval::{discard_votes, issue_required_votes};
+use crate::db_audit::{audited_update_in, issue_state_for_update, AuditInfo};
+use crate::db_ledger::{apply_issue_op, issue_balance, lock_issue_campaign, IssueLedgerOp};
+use crate::error::{Error, GosimResult};
+use crate::issue_tracker::comment_on_issue;
+use crate::review_state::{ReviewAction, ReviewState};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// Changes to budgets that were already approved:
+//
+//   adjust    hold another amount for the issue, its state stays
+//   move      hand some or all of it to another issue of the same project, which
+//             is approved with it. A source left without money is withdrawn
+//   clawback  take all of it back before the issue is concluded, which expires it
+//
+// Each issue goes through audited_update_in, so the money is booked in the ledger
+// and the change recorded in admin_actions like any review action, with the
+// admin's feedback as the memo.
+
+// What an issue holds, as a change to its budget sees it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeldBudget {
+    pub review_state: ReviewState,
+    pub project_id: String,
+    pub currency: Currency,
+    // allocated and approved
+    pub held: i64,
+}
+
+// One issue's budget before and after a change.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BudgetChange {
+    pub issue_id: String,
+    pub action: ReviewAction,
+    pub review_state: ReviewState,
+    pub currency: Currency,
+    pub budget_before: i64,
+    pub budget_after: i64,
+    // the other issue of a move
+    pub counterpart: Option<String>,
+    pub announced: bool,
+}
+
+pub fn check_adjust(
+    issue_id: &str,
+    before: &HeldBudget,
+    issue_budget: i64,
+    required_votes: u32,
+) -> GosimResult<()> {
+    before.review_state.next(ReviewAction::Adjust)?;
+    if issue_budget <= 0 {
+        return Err(Error::Validation(String::from(
+            "an adjusted budget must be more than 0, claw it back to take all of it",
+        )));
+    }
+    if issue_budget == before.held {
+        return Err(Error::Validation(format!(
+            "{} already holds {} {}",
+            issue_id, issue_budget, before.currency
+        )));
+    }
+    if issue_budget > before.held {
+        check_quorum(issue_budget, required_votes)?;
+    }
+    Ok(())
+}
+
+// A raise past what one reviewer may approve needs a quorum again, so it can't be
+// made by an adjust or a move.
+pub fn check_quorum(issue_budget: i64, required_votes: u32) -> GosimResult<()> {
+    if required_votes > 1 {
+        return Err(Error::Validation(format!(
+            "a budget of {} needs {} reviewers to approve it",
+            issue_budget, required_votes
+        )));
+    }
+    Ok(())
+}
+
+// The amount a move hands over, `amount` or everything the source holds.
+pub fn check_move(
+    from_issue_id: &str,
+    source: &HeldBudget,
+    to_issue_id: &str,
+    target: &HeldBudget,
+    amount: Option<i64>,
+) -> GosimResult<i64> {
+    if from_issue_id == to_issue_id {
+        return Err(Error::Validation(String::from(
+            "a budget moves to another issue",
+        )));
+    }
+    // a concluded budget is owed to whoever fixed the issue
+    if !matches!(
+        source.review_state,
+        ReviewState::Approved | ReviewState::InProgress | ReviewState::PrLinked
+    ) {
+        return Err(Error::Transition(format!(
+            "cannot move the budget of an issue that is {}",
+            source.review_state
+        )));
+    }
+    target.review_state.next(ReviewAction::Approve)?;
+    if source.project_id != target.project_id {
+        return Err(Error::Validation(format!(
+            "budgets move within a project, {} is in {} and {} in {}",
+            from_issue_id, source.project_id, to_issue_id, target.project_id
+        )));
+    }
+    if target.held > 0 && target.currency != source.currency {
+        return Err(Error::Validation(format!(
+            "{} holds {} and {} holds {}, a budget moves within one currency",
+            from_issue_id, source.currency, to_issue_id, target.currency
+        )));
+    }
+
+    let amount = amount.unwrap_or(source.held);
+    if amount <= 0 || amount > source.held {
+        return Err(Error::Validation(format!(
+            "{} holds {} {}, {} can't be moved",
+            from_issue_id, source.held, source.currency, amount
+        )));
+    }
+    Ok(amount)
+}
+
+// The audit of one side of a move, the admin's feedback after what moved.
+pub fn move_audit(audit: &AuditInfo, note: String) -> AuditInfo {
+    AuditInfo {
+        actor: audit.actor.clone(),
+        admin_feedback: Some(match &audit.admin_feedback {
+            Some(feedback) => format!("{}: {}", note, feedback),
+            None => note,
+        }),
+    }
+}
+
+// The comment that tells an issue's thread about the change.
+pub fn announcement(change: &BudgetChange, reason: Option<&str>) -> String {
+    let currency = change.currency;
+    let moved = (change.budget_after - change.budget_before).abs();
+    let mut comment = match (change.action, &change.counterpart) {
+        (ReviewAction::Approve, Some(from)) => format!(
+            "{} {} of GOSIM grant budget was moved to this issue from {}.",
+            moved, currency, from
+        ),
+        (_, Some(to)) => format!(
+            "{} {} of the GOSIM grant budget of this issue was moved to {}.",
+            moved, currency, to
+        ),
+        (ReviewAction::Clawback, None) => format!(
+            "The GOSIM grant budget of {} {} for this issue was withdrawn.",
+            change.budget_before, currency
+        ),
+        _ => format!(
+            "The GOSIM grant budget of this issue was changed from {} to {} {}.",
+            change.budget_before, change.budget_after, currency
+        ),
+    };
+    if change.counterpart.is_some() {
+        comment.push_str(&match change.budget_after {
+            0 => String::from(" This issue no longer has a budget."),
+            budget => format!(" Its budget is now {} {}.", budget, currency),
+        });
+    }
+    if let Some(reason) = reason {
+        comment.push_str(&format!("\n\nReason: {}", reason));
+    }
+    comment
+}
+
+// Comments on each changed issue. The changes are committed already, a comment
+// that fails is logged and leaves `announced` false.
+pub async fn announce(changes: &mut [BudgetChange], reason: Option<&str>) {
+    for change in changes.iter_mut() {
+        match comment_on_issue(&change.issue_id, &announcement(change, reason)).await {
+            Ok(()) => change.announced = true,
+            Err(e) => log::error!(
+                "Error announcing the budget of {}: {:?}",
+                change.issue_id,
+                e
+            ),
+        }
+    }
+}
+
+const ADJUST_QUERY: &str = r"UPDATE issues_master SET issue_budget = :issue_budget
+                  WHERE issue_id = :issue_id";
+
+const MOVE_OUT_QUERY: &str = r"UPDATE issues_master SET issue_budget = null
+                  WHERE issue_id = :issue_id";
+
+const MOVE_IN_QUERY: &str = r"UPDATE issues_master
+                  SET issue_budget = :issue_budget,
+                      issue_budget_currency = :currency,
+                      date_approved = COALESCE(date_approved, NOW())
+                  WHERE issue_id = :issue_id";
+
+// Locks the issue and its campaign and reads what the issue holds.
+async fn held_budget(tx: &mut Transaction<'_>, issue_id: &str) -> GosimResult<HeldBudget> {
+    let state = issue_state_for_update(tx, issue_id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Issue with ID {} doesn't exist", issue_id)))?;
+    let issue = lock_issue_campaign(tx, issue_id).await?;
+    let balance = issue_balance(tx, issue_id).await?;
+
+    Ok(HeldBudget {
+        review_state: state.review_state,
+        project_id: issue.project_id,
+        currency: issue.currency,
+        held: balance.allocated + balance.approved,
+    })
+}
+
+// A draft payout batch splits the budget as it was, it has to be cancelled first.
+async fn check_not_in_batch(tx: &mut Transaction<'_>, issue_id: &str) -> GosimResult<()> {
+    let batch_id: Option<u64> = tx
+        .exec_first(
+            "SELECT batch_id FROM payout_entries WHERE issue_id = :issue_id LIMIT 1",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    match batch_id {
+        Some(batch_id) => Err(Error::Transition(format!(
+            "{} is in payout batch {}, cancel the batch first",
+            issue_id, batch_id
+        ))),
+        None => Ok(()),
+    }
+}
+
+// A concluded issue holds approved money, so a raise is approved right away.
+async fn adjust_in(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+    review_state: ReviewState,
+    issue_budget: i64,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    audited_update_in(
+        tx,
+        issue_id,
+        ReviewAction::Adjust,
+        audit,
+        Some((
+            ADJUST_QUERY,
+            params! {
+                "issue_id" => issue_id,
+                "issue_budget" => issue_budget,
+            },
+        )),
+        Some(IssueLedgerOp::Allocate(issue_budget)),
+    )
+    .await?;
+    if review_state == ReviewState::Concluded {
+        apply_issue_op(
+            tx,
+            issue_id,
+            IssueLedgerOp::Approve,
+            &audit.actor,
+            audit.admin_feedback.as_deref(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Holds `issue_budget` for an approved issue instead of what it holds now. A raise
+// is checked against the caps.
+pub async fn adjust_budget(
+    pool: &Pool,
+    issue_id: &str,
+    issue_budget: i64,
+    audit: &AuditInfo,
+) -> GosimResult<BudgetChange> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    let before = held_budget(&mut tx, issue_id).await?;
+    let required_votes = issue_required_votes(&mut tx, issue_id, issue_budget).await?;
+    check_adjust(issue_id, &before, issue_budget, required_votes)?;
+    check_not_in_batch(&mut tx, issue_id).await?;
+    adjust_in(&mut tx, issue_id, before.review_state, issue_budget, audit).await?;
+    tx.commit().await?;
+
+    Ok(BudgetChange {
+        issue_id: issue_id.to_string(),
+        action: ReviewAction::Adjust,
+        review_state: before.review_state,
+        currency: before.currency,
+        budget_before: before.held,
+        budget_after: issue_budget,
+        counterpart: None,
+        announced: false,
+    })
+}
+
+// Moves `amount`, or everything, from one issue's budget to another's in the same
+// project. The target is approved with what it held and the amount, without a new
+// vote, so only when one reviewer may approve that much. The caps are checked once
+// the source gave the amount back.
+pub async fn move_budget(
+    pool: &Pool,
+    from_issue_id: &str,
+    to_issue_id: &str,
+    amount: Option<i64>,
+    audit: &AuditInfo,
+) -> GosimResult<Vec<BudgetChange>> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    // locked in issue_id order, so moves in both directions between two issues
+    // wait for each other instead of deadlocking
+    let (source, target) = if from_issue_id <= to_issue_id {
+        let source = held_budget(&mut tx, from_issue_id).await?;
+        (source, held_budget(&mut tx, to_issue_id).await?)
+    } else {
+        let target = held_budget(&mut tx, to_issue_id).await?;
+        (held_budget(&mut tx, from_issue_id).await?, target)
+    };
+    let amount = check_move(from_issue_id, &source, to_issue_id, &target, amount)?;
+    check_not_in_batch(&mut tx, from_issue_id).await?;
+    check_not_in_batch(&mut tx, to_issue_id).await?;
+    let target_budget = target.held + amount;
+    let required_votes = issue_required_votes(&mut tx, to_issue_id, target_budget).await?;
+    check_quorum(target_budget, required_votes)?;
+    let currency = source.currency;
+
+    let source_audit = move_audit(
+        audit,
+        format!("moved {} {} to {}", amount, currency, to_issue_id),
+    );
+    let remaining = source.held - amount;
+    let source_action = match remaining {
+        0 => {
+            audited_update_in(
+                &mut tx,
+                from_issue_id,
+                ReviewAction::Withdraw,
+                &source_audit,
+                Some((MOVE_OUT_QUERY, params! { "issue_id" => from_issue_id })),
+                Some(IssueLedgerOp::Release),
+            )
+            .await?;
+            ReviewAction::Withdraw
+        }
+        _ => {
+            adjust_in(
+                &mut tx,
+                from_issue_id,
+                source.review_state,
+                remaining,
+                &source_audit,
+            )
+            .await?;
+            ReviewAction::Adjust
+        }
+    };
+
+    let target_audit = move_audit(
+        audit,
+        format!("moved {} {} from {}", amount, currency, from_issue_id),
+    );
+    audited_update_in(
+        &mut tx,
+        to_issue_id,
+        ReviewAction::Approve,
+        &target_audit,
+        Some((
+            MOVE_IN_QUERY,
+            params! {
+                "issue_id" => to_issue_id,
+                "issue_budget" => target_budget,
+                "currency" => currency.as_str(),
+            },
+        )),
+        Some(IssueLedgerOp::Allocate(target_budget)),
+    )
+    .await?;
+    discard_votes(&mut tx, to_issue_id).await?;
+    tx.commit().await?;
+
+    Ok(vec![
+        BudgetChange {
+            issue_id: from_issue_id.to_string(),
+            action: source_action,
+            review_state: source.review_state.next(source_action)?,
+            currency,
+            budget_before: source.held,
+            budget_after: remaining,
+            counterpart: Some(to_issue_id.to_string()),
+            announced: false,
+        },
+        BudgetChange {
+            issue_id: to_issue_id.to_string(),
+            action: ReviewAction::Approve,
+            review_state: target.review_state.next(ReviewAction::Approve)?,
+            currency,
+            budget_before: target.held,
+            budget_after: target_budget,
+            counterpart: Some(from_issue_id.to_string()),
+            announced: false,
+        },
+    ])
+}
+
+// Gives everything the issue holds back to its campaign and expires the issue.
+pub async fn clawback_budget(
+    pool: &Pool,
+    issue_id: &str,
+    audit: &AuditInfo,
+) -> GosimResult<BudgetChange> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    let before = held_budget(&mut tx, issue_id).await?;
+    let review_state = before.review_state.next(ReviewAction::Clawback)?;
+    check_not_in_batch(&mut tx, issue_id).await?;
+    audited_update_in(
+        &mut tx,
+        issue_id,
+        ReviewAction::Clawback,
+        audit,
+        None,
+        Some(IssueLedgerOp::Release),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(BudgetChange {
+        issue_id: issue_id.to_string(),
+        action: ReviewAction::Clawback,
+        review_state,
+        currency: before.currency,
+        budget_before: before.held,
+        budget_after: 0,
+        counterpart: None,
+        announced: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_ledger::campaign_balances;
+    use crate::db_manipulate::conclude_issue_in_db;
+    use crate::test_db;
+
+    fn held(review_state: ReviewState, currency: Currency, held: i64) -> HeldBudget {
+        HeldBudget {
+            review_state,
+            project_id: String::from("https://github.com/o/r"),
+            currency,
+            held,
+        }
+    }
+
+    fn change(
+        action: ReviewAction,
+        before: i64,
+        after: i64,
+        counterpart: Option<&str>,
+    ) -> BudgetChange {
+        BudgetChange {
+            issue_id: String::from("https://github.com/o/r/issues/1"),
+            action,
+            review_state: ReviewState::Approved,
+            currency: Currency::Usd,
+            budget_before: before,
+            budget_after: after,
+            counterpart: counterpart.map(String::from),
+            announced: false,
+        }
+    }
+
+    #[test]
+    fn adjust_needs_a_new_positive_budget_and_a_quorum_to_raise() {
+        let before = held(ReviewState::Approved, Currency::Usd, 100);
+        assert!(check_adjust("i", &before, 80, 1).is_ok());
+        assert!(check_adjust("i", &before, 150, 1).is_ok());
+        // lowering needs no quorum, raising past one reviewer does
+        assert!(check_adjust("i", &before, 80, 2).is_ok());
+        assert!(matches!(
+            check_adjust("i", &before, 150, 2),
+            Err(Error::Validation(_))
+        ));
+        for issue_budget in [0, -5, 100] {
+            assert!(matches!(
+                check_adjust("i", &before, issue_budget, 1),
+                Err(Error::Validation(_))
+            ));
+        }
+
+        let queued = held(ReviewState::Queued, Currency::Usd, 0);
+        assert!(matches!(
+            check_adjust("i", &queued, 50, 1),
+            Err(Error::Transition(_))
+        ));
+    }
+
+    #[test]
+    fn move_hands_over_part_or_all_of_the_source() {
+        let source = held(ReviewState::InProgress, Currency::Eur, 100);
+        let target = held(ReviewState::Queued, Currency::Usd, 0);
+        assert_eq!(
+            check_move("a", &source, "b", &target, Some(40)).unwrap(),
+            40
+        );
+        // an empty target takes the source's currency
+        assert_eq!(check_move("a", &source, "b", &target, None).unwrap(), 100);
+        assert!(matches!(
+            check_move("a", &source, "a", &target, None),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn move_refuses_other_projects_and_currencies() {
+        let source = held(ReviewState::Approved, Currency::Usd, 100);
+        let elsewhere = HeldBudget {
+            project_id: String::from("https://github.com/o/other"),
+            ..held(ReviewState::Queued, Currency::Usd, 0)
+        };
+        assert!(matches!(
+            check_move("a", &source, "b", &elsewhere, None),
+            Err(Error::Validation(_))
+        ));
+
+        let in_eur = held(ReviewState::Approved, Currency::Eur, 50);
+        assert!(matches!(
+            check_move("a", &source, "b", &in_eur, None),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn move_refuses_more_than_the_source_holds() {
+        let source = held(ReviewState::Approved, Currency::Usd, 100);
+        let target = held(ReviewState::Approved, Currency::Usd, 50);
+        for amount in [0, -1, 101] {
+            assert!(matches!(
+                check_move("a", &source, "b", &target, Some(amount)),
+                Err(Error::Validation(_))
+            ));
+        }
+        let empty = held(ReviewState::Approved, Currency::Usd, 0);
+        assert!(check_move("a", &empty, "b", &target, None).is_err());
+    }
+
+    #[test]
+    fn move_refuses_a_concluded_source_and_a_closed_target() {
+        let target = held(ReviewState::Queued, Currency::Usd, 0);
+        for review_state in [
+            ReviewState::Concluded,
+            ReviewState::Paid,
+            ReviewState::Queued,
+        ] {
+            let source = held(review_state, Currency::Usd, 100);
+            assert!(matches!(
+                check_move("a", &source, "b", &target, None),
+                Err(Error::Transition(_))
+            ));
+        }
+
+        let source = held(ReviewState::Approved, Currency::Usd, 100);
+        let declined = held(ReviewState::Declined, Currency::Usd, 0);
+        assert!(check_move("a", &source, "b", &declined, None).is_err());
+    }
+
+    #[test]
+    fn announcements_name_the_amount_and_the_other_issue() {
+        assert_eq!(
+            announcement(&change(ReviewAction::Adjust, 100, 150, None), None),
+            "The GOSIM grant budget of this issue was changed from 100 to 150 USD."
+        );
+        assert_eq!(
+            announcement(
+                &change(ReviewAction::Clawback, 100, 0, None),
+                Some("duplicate")
+            ),
+            "The GOSIM grant budget of 100 USD for this issue was withdrawn.\n\nReason: duplicate"
+        );
+        assert_eq!(
+            announcement(
+                &change(ReviewAction::Withdraw, 100, 0, Some("issue 2")),
+                None
+            ),
+            "100 USD of the GOSIM grant budget of this issue was moved to issue 2. \
+             This issue no longer has a budget."
+        );
+        assert_eq!(
+            announcement(
+                &change(ReviewAction::Approve, 50, 90, Some("issue 1")),
+                None
+            ),
+            "40 USD of GOSIM grant budget was moved to this issue from issue 1. \
+             Its budget is now 90 USD."
+        );
+    }
+
+    async fn held_by(pool: &Pool, issue_id: &str) -> (String, Option<i32>, i64) {
+        let (review_state, issue_budget) = test_db::issue_review(pool, issue_id).await;
+        let mut conn = pool.get_conn().await.unwrap();
+        let balance = issue_balance(&mut conn, issue_id).await.unwrap();
+        (
+            review_state,
+            issue_budget,
+            balance.allocated + balance.approved,
+        )
+    }
+
+    async fn available(pool: &Pool, campaign_id: &str) -> i64 {
+        campaign_balances(pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|b| b.campaign_id == campaign_id)
+            .map_or(0, |b| b.available)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_budget_is_moved_in_parts_and_clawed_back() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        let audit = AuditInfo::new(Some(String::from("rita")), None);
+        let source = test_db::approved_issue(&pool, &campaign_id, 1, 100, &[]).await;
+        let target = format!("{}-2", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &target).await;
+
+        let changes = move_budget(&pool, &source, &target, Some(40), &audit)
+            .await
+            .unwrap();
+        let actions: Vec<ReviewAction> = changes.iter().map(|c| c.action).collect();
+        assert_eq!(actions, [ReviewAction::Adjust, ReviewAction::Approve]);
+        assert_eq!(
+            held_by(&pool, &source).await,
+            (String::from("approved"), Some(60), 60)
+        );
+        assert_eq!(
+            held_by(&pool, &target).await,
+            (String::from("approved"), Some(40), 40)
+        );
+        assert_eq!(available(&pool, &campaign_id).await, 900);
+
+        // the rest withdraws the source
+        move_budget(&pool, &source, &target, None, &audit)
+            .await
+            .unwrap();
+        assert_eq!(
+            held_by(&pool, &source).await,
+            (String::from("withdrawn"), None, 0)
+        );
+        assert_eq!(
+            held_by(&pool, &target).await,
+            (String::from("approved"), Some(100), 100)
+        );
+        let refused = move_budget(&pool, &source, &target, None, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Transition(_)), "{:?}", refused);
+
+        let change = clawback_budget(&pool, &target, &audit).await.unwrap();
+        assert_eq!(change.review_state, ReviewState::Expired);
+        assert_eq!(held_by(&pool, &target).await.2, 0);
+        assert_eq!(held_by(&pool, &target).await.0, "expired");
+        assert_eq!(available(&pool, &campaign_id).await, 1000);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_concluded_budget_is_neither_moved_nor_clawed_back() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        let audit = AuditInfo::new(Some(String::from("rita")), None);
+        let concluded = test_db::approved_issue(&pool, &campaign_id, 1, 100, &["sam"]).await;
+        let other = test_db::approved_issue(&pool, &campaign_id, 2, 50, &[]).await;
+        conclude_issue_in_db(&pool, &concluded, &audit)
+            .await
+            .unwrap();
+
+        let refused = move_budget(&pool, &concluded, &other, None, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Transition(_)), "{:?}", refused);
+        let refused = move_budget(&pool, &other, &concluded, None, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Transition(_)), "{:?}", refused);
+        let refused = clawback_budget(&pool, &concluded, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Transition(_)), "{:?}", refused);
+
+        // nothing moved
+        assert_eq!(
+            held_by(&pool, &concluded).await,
+            (String::from("concluded"), Some(100), 100)
+        );
+        assert_eq!(
+            held_by(&pool, &other).await,
+            (String::from("approved"), Some(50), 50)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::db_audit::{AuditInfo, UNKNOWN_ACTOR};
+use crate::db_caps::get_caps;
+use crate::error::{Error, GosimResult};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// A budget of min_budget or more needs required_votes reviewers agreeing on it,
+// per campaign. A budget below every tier needs one.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ApprovalTier {
+    pub campaign_id: String,
+    pub min_budget: i64,
+    pub required_votes: u32,
+}
+
+// A reviewer's vote for a budget, pending until enough reviewers agree on it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ApprovalVote {
+    pub reviewer: String,
+    pub issue_budget: i64,
+    // what the tier of issue_budget needs
+    pub required_votes: u32,
+    pub admin_feedback: Option<String>,
+    pub created_at: String,
+}
+
+// Where an issue's budget stands after a vote.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetVote {
+    pub issue_id: String,
+    pub issue_budget: i64,
+    // reviewers agreeing on issue_budget, this one included
+    pub votes: u32,
+    pub required_votes: u32,
+    pub approved: bool,
+}
+
+pub fn required_votes(tiers: &[ApprovalTier], issue_budget: i64) -> u32 {
+    tiers
+        .iter()
+        .filter(|tier| tier.min_budget <= issue_budget)
+        .max_by_key(|tier| tier.min_budget)
+        .map_or(1, |tier| tier.required_votes)
+}
+
+// The pending votes for `issue_budget`.
+pub fn agreeing_votes(votes: &[ApprovalVote], issue_budget: i64) -> u32 {
+    votes
+        .iter()
+        .filter(|vote| vote.issue_budget == issue_budget)
+        .count() as u32
+}
+
+// Votes only count towards a quorum when each one names its reviewer.
+pub fn check_reviewer(audit: &AuditInfo, required_votes: u32) -> GosimResult<()> {
+    if required_votes > 1 && audit.actor == UNKNOWN_ACTOR {
+        return Err(Error::Validation(format!(
+            "this budget needs {} reviewers, the vote must name its actor",
+            required_votes
+        )));
+    }
+    Ok(())
+}
+
+// A reviewer can change their vote, but voting for the same budget again would
+// look like a second reviewer agreeing. `earlier` is the budget they voted for.
+pub fn check_new_vote(
+    audit: &AuditInfo,
+    earlier: Option<i64>,
+    issue_budget: i64,
+) -> GosimResult<()> {
+    if earlier == Some(issue_budget) {
+        return Err(Error::Duplicate(format!(
+            "{} already voted for a budget of {}",
+            audit.actor, issue_budget
+        )));
+    }
+    Ok(())
+}
+
+pub fn validate_tier(min_budget: i64, required_votes: Option<u32>) -> GosimResult<()> {
+    if min_budget < 0 {
+        return Err(Error::Validation(String::from(
+            "a tier's min budget must not be negative",
+        )));
+    }
+    if required_votes == Some(0) {
+        return Err(Error::Validation(String::from(
+            "a tier needs at least one vote",
+        )));
+    }
+    Ok(())
+}
+
+pub async fn list_tiers<Q: Queryable>(
+    conn: &mut Q,
+    campaign_id: &str,
+) -> GosimResult<Vec<ApprovalTier>> {
+    let tiers: Vec<(i64, u32)> = conn
+        .exec(
+            r"SELECT min_budget, required_votes FROM approval_tiers
+              WHERE campaign_id = :campaign_id ORDER BY min_budget",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+
+    Ok(tiers
+        .into_iter()
+        .map(|(min_budget, required_votes)| ApprovalTier {
+            campaign_id: campaign_id.to_string(),
+            min_budget,
+            required_votes,
+        })
+        .collect())
+}
+
+// Sets the votes a budget of `min_budget` or more needs, None removes the tier.
+pub async fn set_tier(
+    pool: &Pool,
+    campaign_id: &str,
+    min_budget: i64,
+    required_votes: Option<u32>,
+) -> GosimResult<()> {
+    validate_tier(min_budget, required_votes)?;
+    let mut conn = pool.get_conn().await?;
+    get_caps(&mut conn, campaign_id).await?;
+
+    match required_votes {
+        Some(required_votes) => {
+            conn.exec_drop(
+                r"INSERT INTO approval_tiers (campaign_id, min_budget, required_votes)
+                  VALUES (:campaign_id, :min_budget, :required_votes)
+                  ON DUPLICATE KEY UPDATE required_votes = VALUES(required_votes)",
+                params! {
+                    "campaign_id" => campaign_id,
+                    "min_budget" => min_budget,
+                    "required_votes" => required_votes,
+                },
+            )
+            .await?
+        }
+        None => {
+            conn.exec_drop(
+                r"DELETE FROM approval_tiers
+                  WHERE campaign_id = :campaign_id AND min_budget = :min_budget",
+                params! {
+                    "campaign_id" => campaign_id,
+                    "min_budget" => min_budget,
+                },
+            )
+            .await?
+        }
+    }
+
+    Ok(())
+}
+
+async fn issue_tiers<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<Vec<ApprovalTier>> {
+    let campaign_id: Option<String> = conn
+        .exec_first(
+            "SELECT campaign_id FROM issues_master WHERE issue_id = :issue_id",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    match campaign_id {
+        Some(campaign_id) => list_tiers(conn, &campaign_id).await,
+        None => Err(Error::NotFound(format!("Issue {}", issue_id))),
+    }
+}
+
+// The votes a budget for the issue needs, by the tiers of its campaign.
+pub async fn issue_required_votes<Q: Queryable>(
+    conn: &mut Q,
+    issue_id: &str,
+    issue_budget: i64,
+) -> GosimResult<u32> {
+    let tiers = issue_tiers(conn, issue_id).await?;
+    Ok(required_votes(&tiers, issue_budget))
+}
+
+pub async fn issue_votes<Q: Queryable>(
+    conn: &mut Q,
+    issue_id: &str,
+) -> GosimResult<Vec<ApprovalVote>> {
+    let tiers = issue_tiers(conn, issue_id).await?;
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT reviewer, issue_budget, admin_feedback,
+                DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at
+              FROM approval_votes WHERE issue_id = :issue_id ORDER BY reviewer",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let issue_budget: i64 = row.get("issue_budget").unwrap_or_default();
+            ApprovalVote {
+                reviewer: row.get("reviewer").unwrap_or_default(),
+                issue_budget,
+                required_votes: required_votes(&tiers, issue_budget),
+                admin_feedback: row
+                    .get::<Option<String>, _>("admin_feedback")
+                    .unwrap_or(None),
+                created_at: row.get("created_at").unwrap_or_default(),
+            }
+        })
+        .collect())
+}
+
+// A reviewer's new vote replaces their earlier one.
+pub async fn record_vote<Q: Queryable>(
+    conn: &mut Q,
+    issue_id: &str,
+    issue_budget: i64,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let earlier: Option<i64> = conn
+        .exec_first(
+            r"SELECT issue_budget FROM approval_votes
+              WHERE issue_id = :issue_id AND reviewer = :reviewer",
+            params! {
+                "issue_id" => issue_id,
+                "reviewer" => &audit.actor,
+            },
+        )
+        .await?;
+    check_new_vote(audit, earlier, issue_budget)?;
+
+    conn.exec_drop(
+        r"INSERT INTO approval_votes (issue_id, reviewer, issue_budget, admin_feedback, created_at)
+          VALUES (:issue_id, :reviewer, :issue_budget, :admin_feedback, NOW())
+          ON DUPLICATE KEY UPDATE issue_budget = VALUES(issue_budget),
+            admin_feedback = VALUES(admin_feedback), created_at = VALUES(created_at)",
+        params! {
+            "issue_id" => issue_id,
+            "reviewer" => &audit.actor,
+            "issue_budget" => issue_budget,
+            "admin_feedback" => &audit.admin_feedback,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn discard_votes<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<()> {
+    conn.exec_drop(
+        "DELETE FROM approval_votes WHERE issue_id = :issue_id",
+        params! { "issue_id" => issue_id },
+    )
+    .await?;
+
+    Ok(())
+}
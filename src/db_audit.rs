@@ -0,0 +1,330 @@
+use crate::db_approval::discard_votes;
+use crate::db_ledger::{apply_issue_op, IssueLedgerOp};
+use crate::db_risk::check_issue_risk;
+use crate::error::{Error, GosimResult};
+use crate::review_state::{ReviewAction, ReviewState};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// The actor of a request that doesn't name one.
+pub const UNKNOWN_ACTOR: &str = "unknown";
+
+// Who is acting, and the feedback they left, for the admin_actions row.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AuditInfo {
+    pub actor: String,
+    pub admin_feedback: Option<String>,
+}
+
+// Logins are stored trimmed and lowercased, GitHub doesn't tell `Rita` from `rita`.
+pub fn normalize_login(login: &str) -> String {
+    login.trim().to_lowercase()
+}
+
+impl AuditInfo {
+    pub fn new(actor: Option<String>, admin_feedback: Option<String>) -> Self {
+        AuditInfo {
+            actor: actor
+                .map(|a| normalize_login(&a))
+                .filter(|a| !a.is_empty())
+                .unwrap_or_else(|| UNKNOWN_ACTOR.to_string()),
+            admin_feedback: admin_feedback.filter(|f| !f.trim().is_empty()),
+        }
+    }
+}
+
+// The issues_master columns that admin actions change.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IssueState {
+    pub review_status: String,
+    pub issue_budget: Option<i32>,
+    pub issue_budget_approved: bool,
+    // missing from the actions recorded before the state machine
+    #[serde(default)]
+    pub review_state: ReviewState,
+}
+
+// An issue entering a review state, from issue_state_changes.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StateChange {
+    pub change_id: u64,
+    pub issue_id: String,
+    // None for the state an issue had when the table was created
+    pub from_state: Option<ReviewState>,
+    pub to_state: ReviewState,
+    pub action: String,
+    pub actor: String,
+    pub changed_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AdminAction {
+    pub action_id: u64,
+    pub issue_id: String,
+    pub actor: String,
+    pub action: String,
+    pub before_state: Option<IssueState>,
+    pub after_state: Option<IssueState>,
+    pub admin_feedback: Option<String>,
+    pub created_at: String,
+}
+
+// review_status, issue_budget, issue_budget_approved, review_state
+type IssueStateRow = (Option<String>, Option<i32>, Option<bool>, String);
+
+// Locks the row until the transaction ends, so before/after can't interleave
+// with another admin's change.
+pub async fn issue_state_for_update(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+) -> GosimResult<Option<IssueState>> {
+    let row: Option<IssueStateRow> = tx
+        .exec_first(
+            r"SELECT review_status, issue_budget, issue_budget_approved, review_state
+              FROM issues_master WHERE issue_id = :issue_id FOR UPDATE",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    // a state that doesn't parse fails the update rather than being checked as queued
+    let Some((review_status, issue_budget, issue_budget_approved, review_state)) = row else {
+        return Ok(None);
+    };
+    Ok(Some(IssueState {
+        review_status: review_status.unwrap_or_default(),
+        issue_budget,
+        issue_budget_approved: issue_budget_approved.unwrap_or_default(),
+        review_state: review_state.parse()?,
+    }))
+}
+
+// Moves the issue to `to`, with review_status and issue_budget_approved to match,
+// and records the change unless the state stays the same. Closing the review
+// drops the pending approval votes.
+pub async fn record_transition(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+    from: ReviewState,
+    to: ReviewState,
+    action: ReviewAction,
+    actor: &str,
+) -> GosimResult<()> {
+    tx.exec_drop(
+        r"UPDATE issues_master SET review_state = :review_state,
+            review_status = :review_status, issue_budget_approved = :issue_budget_approved
+          WHERE issue_id = :issue_id",
+        params! {
+            "issue_id" => issue_id,
+            "review_state" => to.as_str(),
+            "review_status" => to.review_status(),
+            "issue_budget_approved" => to.budget_approved(),
+        },
+    )
+    .await?;
+    if from == to {
+        return Ok(());
+    }
+    if to.is_closed() {
+        discard_votes(tx, issue_id).await?;
+    }
+
+    tx.exec_drop(
+        r"INSERT INTO issue_state_changes
+            (issue_id, from_state, to_state, action, actor, changed_at)
+          VALUES (:issue_id, :from_state, :to_state, :action, :actor, NOW())",
+        params! {
+            "issue_id" => issue_id,
+            "from_state" => from.as_str(),
+            "to_state" => to.as_str(),
+            "action" => action.as_str(),
+            "actor" => actor,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn record_action(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+    action: &str,
+    before_state: &IssueState,
+    after_state: &IssueState,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let to_json = |state: &IssueState| {
+        serde_json::to_string(state).map_err(|e| Error::Validation(e.to_string()))
+    };
+
+    tx.exec_drop(
+        r"INSERT INTO admin_actions
+            (issue_id, actor, action, before_state, after_state, admin_feedback, created_at)
+          VALUES
+            (:issue_id, :actor, :action, :before_state, :after_state, :admin_feedback, NOW())",
+        params! {
+            "issue_id" => issue_id,
+            "actor" => &audit.actor,
+            "action" => action,
+            "before_state" => to_json(before_state)?,
+            "after_state" => to_json(after_state)?,
+            "admin_feedback" => &audit.admin_feedback,
+        },
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Error recording admin action on {}: {:?}", issue_id, e);
+        e
+    })?;
+
+    Ok(())
+}
+
+// Takes `action` on the issue inside a transaction: checks that its review state
+// allows it, runs `update`, books what it does to the budget, with the feedback as
+// the memo, and records the change. Returns false, with nothing written, when the
+// issue doesn't exist.
+pub async fn audited_update(
+    pool: &Pool,
+    issue_id: &str,
+    action: ReviewAction,
+    audit: &AuditInfo,
+    update: Option<(&str, Params)>,
+    ledger_op: Option<IssueLedgerOp>,
+) -> GosimResult<bool> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    let found = audited_update_in(&mut tx, issue_id, action, audit, update, ledger_op).await?;
+    match found {
+        true => tx.commit().await?,
+        false => tx.rollback().await?,
+    }
+
+    Ok(found)
+}
+
+// audited_update in a transaction the caller commits.
+pub async fn audited_update_in(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+    action: ReviewAction,
+    audit: &AuditInfo,
+    update: Option<(&str, Params)>,
+    ledger_op: Option<IssueLedgerOp>,
+) -> GosimResult<bool> {
+    let before_state = match issue_state_for_update(tx, issue_id).await? {
+        Some(state) => state,
+        None => return Ok(false),
+    };
+    let review_state = before_state.review_state.next(action)?;
+    if action == ReviewAction::Conclude {
+        check_issue_risk(tx, issue_id).await?;
+    }
+
+    if let Some((update_query, update_params)) = update {
+        tx.exec_drop(update_query, update_params).await?;
+    }
+    record_transition(
+        tx,
+        issue_id,
+        before_state.review_state,
+        review_state,
+        action,
+        &audit.actor,
+    )
+    .await?;
+    if let Some(op) = ledger_op {
+        apply_issue_op(
+            tx,
+            issue_id,
+            op,
+            &audit.actor,
+            audit.admin_feedback.as_deref(),
+        )
+        .await?;
+    }
+    let after_state = issue_state_for_update(tx, issue_id)
+        .await?
+        .unwrap_or_default();
+
+    record_action(
+        tx,
+        issue_id,
+        action.audit_name(),
+        &before_state,
+        &after_state,
+        audit,
+    )
+    .await?;
+
+    Ok(true)
+}
+
+pub async fn list_issue_history(pool: &Pool, issue_id: &str) -> GosimResult<Vec<AdminAction>> {
+    let mut conn = pool.get_conn().await?;
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT action_id, issue_id, actor, action, before_state, after_state, admin_feedback,
+                DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at
+              FROM admin_actions WHERE issue_id = :issue_id ORDER BY action_id",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    let state = |row: &Row, column: &str| {
+        row.get::<Option<String>, _>(column)
+            .unwrap_or(None)
+            .and_then(|s| serde_json::from_str(&s).ok())
+    };
+    Ok(rows
+        .iter()
+        .map(|row| AdminAction {
+            action_id: row.get("action_id").unwrap_or_default(),
+            issue_id: row.get("issue_id").unwrap_or_default(),
+            actor: row.get("actor").unwrap_or_default(),
+            action: row.get("action").unwrap_or_default(),
+            before_state: state(row, "before_state"),
+            after_state: state(row, "after_state"),
+            admin_feedback: row
+                .get::<Option<String>, _>("admin_feedback")
+                .unwrap_or(None),
+            created_at: row.get("created_at").unwrap_or_default(),
+        })
+        .collect())
+}
+
+pub async fn list_state_changes(pool: &Pool, issue_id: &str) -> GosimResult<Vec<StateChange>> {
+    let mut conn = pool.get_conn().await?;
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT change_id, issue_id, from_state, to_state, action, actor,
+                DATE_FORMAT(changed_at, '%Y-%m-%d %H:%i:%s') AS changed_at
+              FROM issue_state_changes WHERE issue_id = :issue_id ORDER BY change_id",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(StateChange {
+                change_id: row.get("change_id").unwrap_or_default(),
+                issue_id: row.get("issue_id").unwrap_or_default(),
+                from_state: row
+                    .get::<Option<String>, _>("from_state")
+                    .unwrap_or(None)
+                    .map(|s| s.parse())
+                    .transpose()?,
+                to_state: row
+                    .get::<String, _>("to_state")
+                    .unwrap_or_default()
+                    .parse()?,
+                action: row.get("action").unwrap_or_default(),
+                actor: row.get("actor").unwrap_or_default(),
+                changed_at: row.get("changed_at").unwrap_or_default(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,575 @@
+use crate::currency::{reporting_currency, Currency, Rates};
+use crate::db_ledger::DEFAULT_CAMPAIGN;
+use crate::db_rates::{converted_amount_sql, rates_as_of};
+use crate::error::{Error, GosimResult};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// Limits on approved budgets, per campaign. A project cap can be overridden for
+// one project in project_budget_caps. NULL means no limit.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetCaps {
+    pub campaign_id: String,
+    // what all issues of one project may hold together
+    pub project_cap: Option<i64>,
+    // what all issues assigned to one contributor may hold together
+    pub contributor_cap: Option<i64>,
+    pub issue_budget_min: Option<i64>,
+    pub issue_budget_max: Option<i64>,
+}
+
+// What an issue, its project and its assignees hold in the ledger now, and the
+// caps that apply to them. Allocated, approved and paid money all count. Caps are
+// plain numbers in the reporting currency, so what the project and the
+// contributors hold in any currency is converted into it, and so is the budget
+// being checked. The issue's own amounts stay in its budget currency.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetRoom {
+    pub issue_id: String,
+    pub project_id: String,
+    pub currency: Currency,
+    pub cap_currency: Currency,
+    pub rates: Rates,
+    pub issue_held: i64,
+    pub project_held: i64,
+    pub available: i64,
+    pub project_cap: Option<i64>,
+    pub contributor_cap: Option<i64>,
+    pub issue_budget_min: Option<i64>,
+    pub issue_budget_max: Option<i64>,
+    // (login, what the issues assigned to them hold)
+    pub contributors: Vec<(String, i64)>,
+}
+
+fn cap_exceeded(msg: String) -> Error {
+    Error::BudgetCap(msg)
+}
+
+// Sums (currency, amount) rows into `to`. Under a cap a currency without a rate is
+// an error rather than left out, or it would slip past the cap. Without one the
+// sum checks nothing, so that money is left out as the listings do.
+pub fn held_in(
+    rates: &Rates,
+    held: &[(Currency, i64)],
+    to: Currency,
+    cap: Option<i64>,
+) -> GosimResult<i64> {
+    held.iter().try_fold(0, |sum, (currency, amount)| {
+        match rates.convert(*amount, *currency, to) {
+            Ok(amount) => Ok(sum + amount),
+            Err(_) if cap.is_none() => Ok(sum),
+            Err(e) => Err(e),
+        }
+    })
+}
+
+impl BudgetRoom {
+    fn to_cap_currency(&self, amount: i64) -> GosimResult<i64> {
+        self.rates.convert(amount, self.currency, self.cap_currency)
+    }
+
+    // Checks a new budget of `target`, in the issue's currency. Lowering a budget
+    // is always possible, as long as it stays within the issue min and max.
+    pub fn check(&self, target: i64) -> GosimResult<()> {
+        let cap_target = self.to_cap_currency(target)?;
+        if let Some(min) = self.issue_budget_min.filter(|min| cap_target < *min) {
+            return Err(cap_exceeded(format!(
+                "{} {} is below the minimum issue budget of {} {}",
+                target, self.currency, min, self.cap_currency
+            )));
+        }
+        if let Some(max) = self.issue_budget_max.filter(|max| cap_target > *max) {
+            return Err(cap_exceeded(format!(
+                "{} {} is above the maximum issue budget of {} {}",
+                target, self.currency, max, self.cap_currency
+            )));
+        }
+        if target <= self.issue_held {
+            return Ok(());
+        }
+
+        let increase = cap_target - self.to_cap_currency(self.issue_held)?;
+        if let Some(cap) = self
+            .project_cap
+            .filter(|cap| self.project_held + increase > *cap)
+        {
+            return Err(cap_exceeded(format!(
+                "project {} would hold {} {}, its cap is {}",
+                self.project_id,
+                self.project_held + increase,
+                self.cap_currency,
+                cap
+            )));
+        }
+        if let Some(cap) = self.contributor_cap {
+            for (login, held) in &self.contributors {
+                if held + increase > cap {
+                    return Err(cap_exceeded(format!(
+                        "contributor {} would hold {} {}, the cap is {}",
+                        login,
+                        held + increase,
+                        self.cap_currency,
+                        cap
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The highest budget the issue could be given now, in its currency, within the
+    // caps and the campaign's available money.
+    pub fn headroom(&self) -> GosimResult<i64> {
+        let issue_held = self.to_cap_currency(self.issue_held)?;
+        let mut limits: Vec<i64> = self.issue_budget_max.into_iter().collect();
+        if let Some(cap) = self.project_cap {
+            limits.push(cap - self.project_held + issue_held);
+        }
+        if let Some(cap) = self.contributor_cap {
+            for (_, held) in &self.contributors {
+                limits.push(cap - held + issue_held);
+            }
+        }
+
+        let mut room = self.issue_held + self.available;
+        if let Some(cap_room) = limits.into_iter().min() {
+            let cap_room = self
+                .rates
+                .convert(cap_room.max(0), self.cap_currency, self.currency)?;
+            room = room.min(cap_room);
+        }
+        Ok(room.max(0))
+    }
+}
+
+// project_cap, contributor_cap, issue_budget_min, issue_budget_max
+type CapsRow = (Option<i64>, Option<i64>, Option<i64>, Option<i64>);
+
+pub async fn get_caps<Q: Queryable>(conn: &mut Q, campaign_id: &str) -> GosimResult<BudgetCaps> {
+    let row: Option<CapsRow> = conn
+        .exec_first(
+            r"SELECT project_cap, contributor_cap, issue_budget_min, issue_budget_max
+              FROM campaigns WHERE campaign_id = :campaign_id",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+    let (project_cap, contributor_cap, issue_budget_min, issue_budget_max) =
+        row.ok_or_else(|| Error::NotFound(format!("Campaign {}", campaign_id)))?;
+
+    Ok(BudgetCaps {
+        campaign_id: campaign_id.to_string(),
+        project_cap,
+        contributor_cap,
+        issue_budget_min,
+        issue_budget_max,
+    })
+}
+
+pub fn validate_caps(caps: &BudgetCaps) -> GosimResult<()> {
+    let limits = [
+        caps.project_cap,
+        caps.contributor_cap,
+        caps.issue_budget_min,
+        caps.issue_budget_max,
+    ];
+    if limits.iter().flatten().any(|limit| *limit < 0) {
+        return Err(Error::Validation(String::from(
+            "budget caps must not be negative",
+        )));
+    }
+    if let (Some(min), Some(max)) = (caps.issue_budget_min, caps.issue_budget_max) {
+        if min > max {
+            return Err(Error::Validation(format!(
+                "issue budget min {} is above the max {}",
+                min, max
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Replaces the campaign's caps. Budgets already approved are not checked again.
+pub async fn set_caps(pool: &Pool, caps: &BudgetCaps) -> GosimResult<()> {
+    validate_caps(caps)?;
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_drop(
+        r"UPDATE campaigns SET project_cap = :project_cap, contributor_cap = :contributor_cap,
+            issue_budget_min = :issue_budget_min, issue_budget_max = :issue_budget_max
+          WHERE campaign_id = :campaign_id",
+        params! {
+            "campaign_id" => &caps.campaign_id,
+            "project_cap" => caps.project_cap,
+            "contributor_cap" => caps.contributor_cap,
+            "issue_budget_min" => caps.issue_budget_min,
+            "issue_budget_max" => caps.issue_budget_max,
+        },
+    )
+    .await?;
+    if conn.affected_rows() == 0 {
+        // also 0 when nothing changed
+        get_caps(&mut conn, &caps.campaign_id).await?;
+    }
+
+    Ok(())
+}
+
+// Overrides the campaign's project cap for one project, None goes back to it.
+pub async fn set_project_cap(
+    pool: &Pool,
+    campaign_id: &str,
+    project_id: &str,
+    budget_cap: Option<i64>,
+) -> GosimResult<()> {
+    if budget_cap.is_some_and(|cap| cap < 0) {
+        return Err(Error::Validation(String::from(
+            "budget caps must not be negative",
+        )));
+    }
+    let mut conn = pool.get_conn().await?;
+    get_caps(&mut conn, campaign_id).await?;
+
+    match budget_cap {
+        Some(budget_cap) => {
+            conn.exec_drop(
+                r"INSERT INTO project_budget_caps (campaign_id, project_id, budget_cap)
+                  VALUES (:campaign_id, :project_id, :budget_cap)
+                  ON DUPLICATE KEY UPDATE budget_cap = VALUES(budget_cap)",
+                params! {
+                    "campaign_id" => campaign_id,
+                    "project_id" => project_id,
+                    "budget_cap" => budget_cap,
+                },
+            )
+            .await?
+        }
+        None => {
+            conn.exec_drop(
+                r"DELETE FROM project_budget_caps
+                  WHERE campaign_id = :campaign_id AND project_id = :project_id",
+                params! {
+                    "campaign_id" => campaign_id,
+                    "project_id" => project_id,
+                },
+            )
+            .await?
+        }
+    }
+
+    Ok(())
+}
+
+const HELD_ACCOUNTS: &str = "('allocated', 'approved', 'paid')";
+
+// The issues matching `issues`, with what each holds, its campaign's available
+// money and the caps that apply. `lock` goes after each sum.
+fn room_query(issues: &str, lock: &str) -> String {
+    format!(
+        r"SELECT im.issue_id, im.campaign_id, im.project_id, im.issue_budget_currency,
+            (SELECT CAST(COALESCE(SUM(e.amount), 0) AS SIGNED) FROM ledger_entries e
+             WHERE e.issue_id = im.issue_id AND e.currency = im.issue_budget_currency
+               AND e.account IN {HELD_ACCOUNTS}
+             {lock}) AS issue_held,
+            (SELECT CAST(COALESCE(SUM(e.amount), 0) AS SIGNED) FROM ledger_entries e
+             WHERE e.campaign_id = im.campaign_id AND e.currency = im.issue_budget_currency
+               AND e.account = 'available'
+             {lock}) AS available,
+            COALESCE(pc.budget_cap, c.project_cap) AS project_cap,
+            c.contributor_cap, c.issue_budget_min, c.issue_budget_max
+          FROM issues_master im
+          JOIN campaigns c ON c.campaign_id = im.campaign_id
+          LEFT JOIN project_budget_caps pc
+            ON pc.campaign_id = im.campaign_id AND pc.project_id = im.project_id
+          WHERE {issues}"
+    )
+}
+
+fn held_rows(rows: Vec<(String, i64)>) -> Vec<(Currency, i64)> {
+    rows.into_iter()
+        .map(|(currency, amount)| (Currency::from_db(&currency), amount))
+        .collect()
+}
+
+// Puts a room_query row together with what its project and its assignees hold,
+// by currency. An assignee without a budget anywhere holds nothing.
+fn room_of(
+    row: &Row,
+    project_held: &[(Currency, i64)],
+    contributor_held: BTreeMap<String, Vec<(Currency, i64)>>,
+    rates: &Rates,
+    cap_currency: Currency,
+) -> GosimResult<BudgetRoom> {
+    let limit = |column: &str| row.get::<Option<i64>, _>(column).unwrap_or(None);
+    let contributors = contributor_held
+        .into_iter()
+        .map(|(login, held)| {
+            let held = held_in(rates, &held, cap_currency, limit("contributor_cap"))?;
+            Ok((login, held))
+        })
+        .collect::<GosimResult<_>>()?;
+
+    Ok(BudgetRoom {
+        issue_id: row.get("issue_id").unwrap_or_default(),
+        project_id: row.get("project_id").unwrap_or_default(),
+        currency: Currency::from_db(
+            &row.get::<String, _>("issue_budget_currency")
+                .unwrap_or_default(),
+        ),
+        cap_currency,
+        issue_held: row.get("issue_held").unwrap_or_default(),
+        project_held: held_in(rates, project_held, cap_currency, limit("project_cap"))?,
+        available: row.get("available").unwrap_or_default(),
+        project_cap: limit("project_cap"),
+        contributor_cap: limit("contributor_cap"),
+        issue_budget_min: limit("issue_budget_min"),
+        issue_budget_max: limit("issue_budget_max"),
+        contributors,
+        rates: rates.clone(),
+    })
+}
+
+// What the issue and the ones around it already hold against the caps. The sums
+// are locking reads: called after db_ledger::lock_issue_campaign, they see every
+// entry committed before the lock was granted, not just the transaction's snapshot.
+pub async fn budget_room<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<BudgetRoom> {
+    let row: Option<Row> = conn
+        .exec_first(
+            room_query("im.issue_id = :issue_id", "FOR SHARE"),
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    let row = row.ok_or_else(|| Error::NotFound(format!("Issue {}", issue_id)))?;
+    let campaign_id: String = row.get("campaign_id").unwrap_or_default();
+    let project_id: String = row.get("project_id").unwrap_or_default();
+
+    let project_held: Vec<(String, i64)> = conn
+        .exec(
+            format!(
+                r"SELECT e.currency, CAST(SUM(e.amount) AS SIGNED) FROM ledger_entries e
+                  WHERE e.campaign_id = :campaign_id AND e.project_id = :project_id
+                    AND e.account IN {HELD_ACCOUNTS}
+                  GROUP BY e.currency
+                  FOR SHARE"
+            ),
+            params! {
+                "campaign_id" => &campaign_id,
+                "project_id" => &project_id,
+            },
+        )
+        .await?;
+    let contributor_held: Vec<(String, Option<String>, i64)> = conn
+        .exec(
+            format!(
+                r"SELECT ia.login, e.currency, CAST(COALESCE(SUM(e.amount), 0) AS SIGNED)
+                  FROM (
+                    SELECT DISTINCT issue_id, login FROM issue_assignees
+                    WHERE login IN (SELECT login FROM issue_assignees WHERE issue_id = :issue_id)
+                  ) AS ia
+                  LEFT JOIN ledger_entries e ON e.issue_id = ia.issue_id
+                    AND e.campaign_id = :campaign_id
+                    AND e.account IN {HELD_ACCOUNTS}
+                  GROUP BY ia.login, e.currency
+                  ORDER BY ia.login
+                  FOR SHARE OF e"
+            ),
+            params! {
+                "issue_id" => issue_id,
+                "campaign_id" => &campaign_id,
+            },
+        )
+        .await?;
+
+    let rates = rates_as_of(conn, None).await?;
+    let mut by_login: BTreeMap<String, Vec<(Currency, i64)>> = BTreeMap::new();
+    for (login, currency, amount) in contributor_held {
+        let held = by_login.entry(login).or_default();
+        if let Some(currency) = currency {
+            held.push((Currency::from_db(&currency), amount));
+        }
+    }
+    room_of(
+        &row,
+        &held_rows(project_held),
+        by_login,
+        &rates,
+        reporting_currency(),
+    )
+}
+
+// budget_room for a page of issues in a fixed number of plain reads, for lists.
+// Issues that don't exist, or hold money in a currency without a rate, are left out.
+pub async fn budget_rooms<Q: Queryable>(
+    conn: &mut Q,
+    issue_ids: &[String],
+) -> GosimResult<HashMap<String, BudgetRoom>> {
+    if issue_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; issue_ids.len()].join(", ");
+    let ids = || Params::Positional(issue_ids.iter().map(Value::from).collect());
+
+    let rows: Vec<Row> = conn
+        .exec(
+            room_query(&format!("im.issue_id IN ({placeholders})"), ""),
+            ids(),
+        )
+        .await?;
+    // (campaign_id, project_id, currency, amount)
+    let project_held: Vec<(String, String, String, i64)> = conn
+        .exec(
+            format!(
+                r"SELECT e.campaign_id, e.project_id, e.currency, CAST(SUM(e.amount) AS SIGNED)
+                  FROM ledger_entries e
+                  WHERE e.project_id IN
+                      (SELECT project_id FROM issues_master WHERE issue_id IN ({placeholders}))
+                    AND e.account IN {HELD_ACCOUNTS}
+                  GROUP BY e.campaign_id, e.project_id, e.currency"
+            ),
+            ids(),
+        )
+        .await?;
+    let assignees: Vec<(String, String)> = conn
+        .exec(
+            format!(
+                r"SELECT DISTINCT issue_id, login FROM issue_assignees
+                  WHERE issue_id IN ({placeholders})"
+            ),
+            ids(),
+        )
+        .await?;
+    // (login, campaign_id, currency, amount) over every issue the login is assigned to
+    let login_held: Vec<(String, String, String, i64)> = conn
+        .exec(
+            format!(
+                r"SELECT ia.login, e.campaign_id, e.currency, CAST(SUM(e.amount) AS SIGNED)
+                  FROM (
+                    SELECT DISTINCT issue_id, login FROM issue_assignees
+                    WHERE login IN
+                      (SELECT login FROM issue_assignees WHERE issue_id IN ({placeholders}))
+                  ) AS ia
+                  JOIN ledger_entries e ON e.issue_id = ia.issue_id
+                    AND e.account IN {HELD_ACCOUNTS}
+                  GROUP BY ia.login, e.campaign_id, e.currency"
+            ),
+            ids(),
+        )
+        .await?;
+
+    let rates = rates_as_of(conn, None).await?;
+    let cap_currency = reporting_currency();
+    let mut by_project: HashMap<(String, String), Vec<(Currency, i64)>> = HashMap::new();
+    for (campaign_id, project_id, currency, amount) in project_held {
+        by_project
+            .entry((campaign_id, project_id))
+            .or_default()
+            .push((Currency::from_db(&currency), amount));
+    }
+    let mut by_login: HashMap<(String, String), Vec<(Currency, i64)>> = HashMap::new();
+    for (login, campaign_id, currency, amount) in login_held {
+        by_login
+            .entry((login, campaign_id))
+            .or_default()
+            .push((Currency::from_db(&currency), amount));
+    }
+
+    let mut rooms = HashMap::new();
+    for row in &rows {
+        let issue_id: String = row.get("issue_id").unwrap_or_default();
+        let campaign_id: String = row.get("campaign_id").unwrap_or_default();
+        let project_id: String = row.get("project_id").unwrap_or_default();
+        let contributors = assignees
+            .iter()
+            .filter(|(assigned, _)| *assigned == issue_id)
+            .map(|(_, login)| {
+                let key = (login.clone(), campaign_id.clone());
+                (
+                    login.clone(),
+                    by_login.get(&key).cloned().unwrap_or_default(),
+                )
+            })
+            .collect();
+        let project_held = by_project
+            .get(&(campaign_id, project_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        match room_of(row, project_held, contributors, &rates, cap_currency) {
+            Ok(room) => {
+                rooms.insert(issue_id, room);
+            }
+            Err(e) => log::warn!("No budget room for {}: {}", issue_id, e),
+        }
+    }
+    Ok(rooms)
+}
+
+// A projects column: what the project's issues can still be given under its cap
+// in the default campaign, in the reporting currency, NULL when it has none.
+pub fn project_headroom_column() -> String {
+    format!(
+        r"GREATEST(COALESCE(
+            (SELECT pc.budget_cap FROM project_budget_caps pc
+             WHERE pc.campaign_id = '{campaign}' AND pc.project_id = projects.project_id),
+            (SELECT c.project_cap FROM campaigns c WHERE c.campaign_id = '{campaign}')
+          ) - (SELECT COALESCE(SUM({held}), 0) FROM ledger_entries e
+             WHERE e.campaign_id = '{campaign}' AND e.project_id = projects.project_id
+               AND e.account IN ('allocated', 'approved', 'paid')), 0) AS budget_headroom",
+        campaign = DEFAULT_CAMPAIGN,
+        held = converted_amount_sql(reporting_currency())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_ledger::fund_campaign;
+    use crate::test_db;
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn concurrent_approvals_share_the_project_cap() {
+        let pool = test_db::pool().await;
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        fund_campaign(&pool, &campaign_id, 1000, Currency::Usd, "test", None)
+            .await
+            .unwrap();
+        set_caps(
+            &pool,
+            &BudgetCaps {
+                campaign_id: campaign_id.clone(),
+                project_cap: Some(100),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let issue_ids = [format!("{}-1", campaign_id), format!("{}-2", campaign_id)];
+        for issue_id in &issue_ids {
+            test_db::insert_issue(&pool, &campaign_id, issue_id).await;
+        }
+
+        // the campaign has the money for both, only the cap stops the second
+        let first = test_db::started_transaction(&pool).await;
+        let second = test_db::started_transaction(&pool).await;
+        let (first, second) = tokio::join!(
+            test_db::allocate(first, &issue_ids[0], 60),
+            test_db::allocate(second, &issue_ids[1], 60)
+        );
+
+        let refused = match (first, second) {
+            (Ok(()), Err(e)) | (Err(e), Ok(())) => e,
+            results => panic!("one approval should be refused: {:?}", results),
+        };
+        assert!(matches!(refused, Error::BudgetCap(_)), "{:?}", refused);
+        let mut conn = pool.get_conn().await.unwrap();
+        let room = budget_room(&mut conn, &issue_ids[0]).await.unwrap();
+        assert_eq!(room.project_held, 60);
+
+        // the list reads the same rooms in one go
+        let rooms = budget_rooms(&mut conn, &issue_ids).await.unwrap();
+        assert_eq!(rooms.len(), 2);
+        assert_eq!(rooms[&issue_ids[0]], room);
+        assert_eq!(rooms[&issue_ids[1]].project_held, 60);
+    }
+}
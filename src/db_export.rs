@@ -0,0 +1,569 @@
+use crate::db_query::{IssueQuery, ProjectQuery, SelectQuery, SortKey};
+use crate::db_storage::Storage;
+use crate::error::{Error, GosimResult};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::Write;
+use std::str::FromStr;
+
+// Rows are read a page at a time and written out before the next page is read,
+// so an export never holds more than one page of a table.
+pub const EXPORT_PAGE_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTable {
+    Issues,
+    Projects,
+    PullRequests,
+    Comments,
+    // approved budgets and who they go to
+    Payouts,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+    // only with the `parquet` feature
+    Parquet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnKind {
+    Text,
+    Int,
+    Bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ExportValue {
+    Null,
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+pub const ISSUE_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("issue_id", ColumnKind::Text),
+    ("project_id", ColumnKind::Text),
+    ("issue_title", ColumnKind::Text),
+    ("issue_creator", ColumnKind::Text),
+    ("issue_description", ColumnKind::Text),
+    ("main_language", ColumnKind::Text),
+    ("repo_stars", ColumnKind::Int),
+    ("issue_budget", ColumnKind::Int),
+    ("issue_budget_currency", ColumnKind::Text),
+    ("issue_assignees", ColumnKind::Text),
+    ("issue_labels", ColumnKind::Text),
+    ("issue_linked_pr", ColumnKind::Text),
+    ("issue_status", ColumnKind::Text),
+    ("review_status", ColumnKind::Text),
+    ("issue_budget_approved", ColumnKind::Bool),
+    ("date_issue_assigned", ColumnKind::Text),
+    ("date_approved", ColumnKind::Text),
+    ("date_declined", ColumnKind::Text),
+    ("date_budget_approved", ColumnKind::Text),
+];
+
+pub const PROJECT_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("project_id", ColumnKind::Text),
+    ("project_logo", ColumnKind::Text),
+    ("main_language", ColumnKind::Text),
+    ("repo_stars", ColumnKind::Int),
+    ("project_description", ColumnKind::Text),
+    ("issues_count", ColumnKind::Int),
+    ("total_budget_allocated", ColumnKind::Int),
+];
+
+pub const PULL_REQUEST_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("pull_id", ColumnKind::Text),
+    ("pull_title", ColumnKind::Text),
+    ("pull_author", ColumnKind::Text),
+    ("project_id", ColumnKind::Text),
+    ("date_merged", ColumnKind::Text),
+];
+
+pub const COMMENT_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("comment_id", ColumnKind::Int),
+    ("issue_id", ColumnKind::Text),
+    ("comment_creator", ColumnKind::Text),
+    ("comment_date", ColumnKind::Text),
+    ("comment_body", ColumnKind::Text),
+];
+
+pub const PAYOUT_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("issue_id", ColumnKind::Text),
+    ("project_id", ColumnKind::Text),
+    ("recipient", ColumnKind::Text),
+    ("issue_budget", ColumnKind::Int),
+    ("issue_budget_currency", ColumnKind::Text),
+    ("date_budget_approved", ColumnKind::Text),
+];
+
+// Columns in the order of the *_COLUMNS lists above. The issue and project
+// filters reference issues_master/issue_assignee_lists and projects columns, so
+// every select joins the table its filter applies to.
+const ISSUE_SELECT: &str = "SELECT issue_id, project_id, issue_title, issue_creator, issue_description, main_language, repo_stars, issue_budget, issue_budget_currency, issue_assignees, issue_labels, issue_linked_pr, issue_status, review_status, issue_budget_approved, DATE_FORMAT(date_issue_assigned, '%Y-%m-%d %H:%i:%s') AS date_issue_assigned, DATE_FORMAT(date_approved, '%Y-%m-%d %H:%i:%s') AS date_approved, DATE_FORMAT(date_declined, '%Y-%m-%d %H:%i:%s') AS date_declined, DATE_FORMAT(date_budget_approved, '%Y-%m-%d %H:%i:%s') AS date_budget_approved FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)";
+
+const PROJECT_SELECT: &str = "SELECT project_id, project_logo, main_language, repo_stars, project_description, COALESCE(JSON_LENGTH(issues_list), 0) AS issues_count, total_budget_allocated FROM projects";
+
+const PULL_REQUEST_SELECT: &str = "SELECT pull_id, pull_title, pull_author, project_id, DATE_FORMAT(date_merged, '%Y-%m-%d %H:%i:%s') AS date_merged FROM pull_requests LEFT JOIN projects USING (project_id)";
+
+const COMMENT_SELECT: &str = "SELECT comment_id, issue_id, comment_creator, DATE_FORMAT(comment_date, '%Y-%m-%d %H:%i:%s') AS comment_date, comment_body FROM issues_comment LEFT JOIN issues_master USING (issue_id) LEFT JOIN issue_assignee_lists USING (issue_id)";
+
+// the recipient is picked the same way as in get_issue_ids_distribute_fund
+const PAYOUT_SELECT: &str = "SELECT issue_id, project_id, (SELECT ia.login FROM issue_assignees ia WHERE ia.issue_id = issues_master.issue_id ORDER BY ia.assigned_at IS NULL, ia.assigned_at, ia.login LIMIT 1) AS recipient, issue_budget, issue_budget_currency, DATE_FORMAT(date_budget_approved, '%Y-%m-%d %H:%i:%s') AS date_budget_approved FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)";
+
+impl ExportTable {
+    pub fn columns(&self) -> &'static [(&'static str, ColumnKind)] {
+        match self {
+            ExportTable::Issues => ISSUE_COLUMNS,
+            ExportTable::Projects => PROJECT_COLUMNS,
+            ExportTable::PullRequests => PULL_REQUEST_COLUMNS,
+            ExportTable::Comments => COMMENT_COLUMNS,
+            ExportTable::Payouts => PAYOUT_COLUMNS,
+        }
+    }
+
+    // Breaks ties after the requested sort, so pages don't overlap.
+    fn key_column(&self) -> &'static str {
+        match self {
+            ExportTable::Issues | ExportTable::Payouts => "issue_id",
+            ExportTable::Projects => "project_id",
+            ExportTable::PullRequests => "pull_id",
+            ExportTable::Comments => "comment_id",
+        }
+    }
+
+    // Pull requests are filtered by their project, comments and payouts by their issue.
+    fn filters_projects(&self) -> bool {
+        matches!(self, ExportTable::Projects | ExportTable::PullRequests)
+    }
+}
+
+impl FromStr for ExportTable {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::Validation(format!("unknown export table '{}'", s)))
+    }
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::Validation(format!("unknown export format '{}'", s)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportQuery {
+    Issues(IssueQuery),
+    Projects(ProjectQuery),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportRequest {
+    pub table: ExportTable,
+    pub query: ExportQuery,
+}
+
+fn parse_filter<T: DeserializeOwned>(filter: Option<serde_json::Value>) -> GosimResult<Option<T>> {
+    filter
+        .map(serde_json::from_value)
+        .transpose()
+        .map_err(|e| Error::Validation(format!("invalid filter: {}", e)))
+}
+
+impl ExportRequest {
+    // `filter` is an issue filter, or a project filter for projects and pull requests.
+    pub fn new(
+        table: ExportTable,
+        filter: Option<serde_json::Value>,
+        sort: Vec<SortKey>,
+    ) -> GosimResult<ExportRequest> {
+        let query = if table.filters_projects() {
+            let query = ProjectQuery {
+                filter: parse_filter(filter)?,
+                sort,
+            };
+            query.validate()?;
+            ExportQuery::Projects(query)
+        } else {
+            let query = IssueQuery {
+                filter: parse_filter(filter)?,
+                sort,
+            };
+            query.validate()?;
+            ExportQuery::Issues(query)
+        };
+
+        Ok(ExportRequest { table, query })
+    }
+
+    fn select_query(&self) -> GosimResult<SelectQuery> {
+        let select = match self.table {
+            ExportTable::Issues => ISSUE_SELECT,
+            ExportTable::Projects => PROJECT_SELECT,
+            ExportTable::PullRequests => PULL_REQUEST_SELECT,
+            ExportTable::Comments => COMMENT_SELECT,
+            ExportTable::Payouts => PAYOUT_SELECT,
+        };
+        let mut select = match &self.query {
+            ExportQuery::Issues(query) => SelectQuery::new(select).issue_query(query)?,
+            ExportQuery::Projects(query) => SelectQuery::new(select).project_query(query)?,
+        };
+        if self.table == ExportTable::Payouts {
+            select = select.filter("issue_budget_approved = 1");
+        }
+
+        Ok(select.order_by(self.table.key_column(), false))
+    }
+}
+
+pub async fn export_page(
+    pool: &Pool,
+    request: &ExportRequest,
+    page: usize,
+    page_size: usize,
+) -> GosimResult<Vec<Vec<ExportValue>>> {
+    let mut conn = pool.get_conn().await?;
+
+    let (query, params) = request.select_query()?.page(page, page_size).build();
+    let rows: Vec<Row> = conn.exec(query, params).await?;
+
+    let columns = request.table.columns();
+    Ok(rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .enumerate()
+                .map(|(i, (_, kind))| {
+                    let value = match kind {
+                        ColumnKind::Text => row
+                            .get::<Option<String>, _>(i)
+                            .flatten()
+                            .map(ExportValue::Text),
+                        ColumnKind::Int => {
+                            row.get::<Option<i64>, _>(i).flatten().map(ExportValue::Int)
+                        }
+                        ColumnKind::Bool => row
+                            .get::<Option<bool>, _>(i)
+                            .flatten()
+                            .map(ExportValue::Bool),
+                    };
+                    value.unwrap_or(ExportValue::Null)
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn write_error(e: impl std::fmt::Display) -> Error {
+    Error::Export(e.to_string())
+}
+
+pub fn csv_field(value: &ExportValue) -> String {
+    let text = match value {
+        ExportValue::Null => return String::new(),
+        ExportValue::Text(text) => text.clone(),
+        ExportValue::Int(n) => n.to_string(),
+        ExportValue::Bool(b) => b.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+enum Sink<W: Write + Send> {
+    Csv(W),
+    Jsonl(W),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::file::writer::SerializedFileWriter<W>),
+}
+
+// Writes pages of rows in one format. Parquet writes each page as a row group.
+pub struct ExportWriter<W: Write + Send> {
+    columns: &'static [(&'static str, ColumnKind)],
+    sink: Sink<W>,
+}
+
+impl<W: Write + Send> ExportWriter<W> {
+    pub fn new(
+        format: ExportFormat,
+        columns: &'static [(&'static str, ColumnKind)],
+        mut out: W,
+    ) -> GosimResult<Self> {
+        let sink = match format {
+            ExportFormat::Csv => {
+                let header: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+                writeln!(out, "{}", header.join(",")).map_err(write_error)?;
+                Sink::Csv(out)
+            }
+            ExportFormat::Jsonl => Sink::Jsonl(out),
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => Sink::Parquet(parquet_export::file_writer(columns, out)?),
+            #[cfg(not(feature = "parquet"))]
+            ExportFormat::Parquet => {
+                return Err(Error::Validation(String::from(
+                    "parquet export needs the `parquet` feature",
+                )))
+            }
+        };
+
+        Ok(ExportWriter { columns, sink })
+    }
+
+    pub fn write_rows(&mut self, rows: &[Vec<ExportValue>]) -> GosimResult<()> {
+        match &mut self.sink {
+            Sink::Csv(out) => {
+                for row in rows {
+                    let fields: Vec<String> = row.iter().map(csv_field).collect();
+                    writeln!(out, "{}", fields.join(",")).map_err(write_error)?;
+                }
+            }
+            Sink::Jsonl(out) => {
+                // written by hand to keep the keys in column order
+                for row in rows {
+                    let fields: Vec<String> = self
+                        .columns
+                        .iter()
+                        .zip(row)
+                        .map(|((name, _), value)| {
+                            format!("{}:{}", serde_json::json!(name), serde_json::json!(value))
+                        })
+                        .collect();
+                    writeln!(out, "{{{}}}", fields.join(",")).map_err(write_error)?;
+                }
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => {
+                if !rows.is_empty() {
+                    parquet_export::write_row_group(writer, self.columns, rows)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> GosimResult<W> {
+        match self.sink {
+            Sink::Csv(mut out) | Sink::Jsonl(mut out) => {
+                out.flush().map_err(write_error)?;
+                Ok(out)
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.into_inner().map_err(write_error),
+        }
+    }
+}
+
+// Streams the rows matching `request` into `out`, returns it with the row count.
+// With `max_rows` the export fails once more rows than that match.
+pub async fn export_table<W: Write + Send>(
+    store: &impl Storage,
+    request: &ExportRequest,
+    format: ExportFormat,
+    out: W,
+    max_rows: Option<usize>,
+) -> GosimResult<(W, usize)> {
+    let mut writer = ExportWriter::new(format, request.table.columns(), out)?;
+
+    let mut rows_written = 0;
+    let mut page = 1;
+    loop {
+        let rows = store.export_page(request, page, EXPORT_PAGE_SIZE).await?;
+        if let Some(max_rows) = max_rows.filter(|max| rows_written + rows.len() > *max) {
+            return Err(Error::Validation(format!(
+                "export has more than {} rows, use `gosim export` for large exports",
+                max_rows
+            )));
+        }
+        writer.write_rows(&rows)?;
+        rows_written += rows.len();
+        if rows.len() < EXPORT_PAGE_SIZE {
+            break;
+        }
+        page += 1;
+    }
+
+    Ok((writer.finish()?, rows_written))
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_export {
+    use super::{write_error, ColumnKind, ExportValue};
+    use crate::error::GosimResult;
+    use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    // Every column is OPTIONAL, a definition level of 0 marks a NULL.
+    pub fn file_writer<W: Write + Send>(
+        columns: &[(&str, ColumnKind)],
+        out: W,
+    ) -> GosimResult<SerializedFileWriter<W>> {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|(name, kind)| match kind {
+                ColumnKind::Text => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+                ColumnKind::Int => format!("OPTIONAL INT64 {};", name),
+                ColumnKind::Bool => format!("OPTIONAL BOOLEAN {};", name),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message export {{ {} }}", fields.join(" ")))
+            .map_err(write_error)?;
+        let props = WriterProperties::builder().build();
+
+        SerializedFileWriter::new(out, Arc::new(schema), Arc::new(props)).map_err(write_error)
+    }
+
+    pub fn write_row_group<W: Write + Send>(
+        writer: &mut SerializedFileWriter<W>,
+        columns: &[(&str, ColumnKind)],
+        rows: &[Vec<ExportValue>],
+    ) -> GosimResult<()> {
+        let mut row_group = writer.next_row_group().map_err(write_error)?;
+        for (i, (_, kind)) in columns.iter().enumerate() {
+            let mut column = match row_group.next_column().map_err(write_error)? {
+                Some(column) => column,
+                None => break,
+            };
+            let def_levels: Vec<i16> = rows
+                .iter()
+                .map(|row| i16::from(row[i] != ExportValue::Null))
+                .collect();
+            match kind {
+                ColumnKind::Text => {
+                    let values: Vec<ByteArray> = rows
+                        .iter()
+                        .filter_map(|row| match &row[i] {
+                            ExportValue::Text(text) => Some(ByteArray::from(text.as_str())),
+                            _ => None,
+                        })
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, Some(&def_levels), None)
+                        .map_err(write_error)?;
+                }
+                ColumnKind::Int => {
+                    let values: Vec<i64> = rows
+                        .iter()
+                        .filter_map(|row| match row[i] {
+                            ExportValue::Int(n) => Some(n),
+                            _ => None,
+                        })
+                        .collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, Some(&def_levels), None)
+                        .map_err(write_error)?;
+                }
+                ColumnKind::Bool => {
+                    let values: Vec<bool> = rows
+                        .iter()
+                        .filter_map(|row| match row[i] {
+                            ExportValue::Bool(b) => Some(b),
+                            _ => None,
+                        })
+                        .collect();
+                    column
+                        .typed::<BoolType>()
+                        .write_batch(&values, Some(&def_levels), None)
+                        .map_err(write_error)?;
+                }
+            }
+            column.close().map_err(write_error)?;
+        }
+        row_group.close().map_err(write_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+    use std::fs::File;
+
+    const COLUMNS: &[(&str, ColumnKind)] = &[
+        ("issue_id", ColumnKind::Text),
+        ("issue_budget", ColumnKind::Int),
+        ("issue_budget_approved", ColumnKind::Bool),
+    ];
+
+    fn row(issue_id: &str, budget: Option<i64>, approved: bool) -> Vec<ExportValue> {
+        vec![
+            ExportValue::Text(issue_id.to_string()),
+            budget.map_or(ExportValue::Null, ExportValue::Int),
+            ExportValue::Bool(approved),
+        ]
+    }
+
+    #[test]
+    fn parquet_export_reads_back_page_by_page() {
+        let pages = [
+            vec![row("a", Some(100), true), row("b", None, false)],
+            vec![row("c", Some(-5), false)],
+        ];
+        let path =
+            std::env::temp_dir().join(format!("export-{:08x}.parquet", rand::random::<u32>()));
+        let mut writer =
+            ExportWriter::new(ExportFormat::Parquet, COLUMNS, File::create(&path).unwrap())
+                .unwrap();
+        for rows in &pages {
+            writer.write_rows(rows).unwrap();
+        }
+        // an empty page adds no row group
+        writer.write_rows(&[]).unwrap();
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let read: Vec<Vec<ExportValue>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(_, field)| match field {
+                        Field::Null => ExportValue::Null,
+                        Field::Str(text) => ExportValue::Text(text.clone()),
+                        Field::Long(n) => ExportValue::Int(*n),
+                        Field::Bool(b) => ExportValue::Bool(*b),
+                        field => panic!("unexpected {:?}", field),
+                    })
+                    .collect()
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, pages.concat());
+    }
+}
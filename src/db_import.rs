@@ -0,0 +1,320 @@
+use crate::csv_util::{csv_records, ImportFormat};
+use crate::currency::Currency;
+use crate::db_populate::add_issues_open;
+use crate::error::{Error, GosimResult};
+use crate::issue_tracker::{get_issue_open, issue_url_parts};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+// One line of a curated list. Budget and notes are optional, without a budget
+// the one in the issue body applies, as for issues found by the label search.
+// A budget without a currency is in USD.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ImportRow {
+    #[serde(alias = "issue_id", alias = "url")]
+    pub issue_url: String,
+    #[serde(default)]
+    pub budget: Option<i32>,
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportOutcome {
+    // 1-based, the header line of a CSV file isn't counted
+    pub row: usize,
+    pub issue_url: String,
+    // the url GitHub returned, once the issue was fetched
+    pub issue_id: Option<String>,
+    pub imported: bool,
+    pub error: Option<String>,
+}
+
+impl ImportOutcome {
+    pub fn failed(row: usize, issue_url: &str, error: impl ToString) -> Self {
+        ImportOutcome {
+            row,
+            issue_url: issue_url.to_string(),
+            issue_id: None,
+            imported: false,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+// A file that can't be read as a whole is rejected, problems with single issues
+// are reported per row by the import itself.
+pub fn parse_import(text: &str, format: ImportFormat) -> GosimResult<Vec<ImportRow>> {
+    match format {
+        ImportFormat::Csv => parse_import_csv(text),
+        ImportFormat::Json => parse_import_json(text),
+    }
+}
+
+// A JSON array of issue urls or of ImportRow objects.
+fn parse_import_json(text: &str) -> GosimResult<Vec<ImportRow>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Url(String),
+        Row(ImportRow),
+    }
+
+    let entries: Vec<Entry> = serde_json::from_str(text)
+        .map_err(|e| Error::Validation(format!("invalid import file: {}", e)))?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Url(issue_url) => ImportRow {
+                issue_url,
+                ..Default::default()
+            },
+            Entry::Row(row) => row,
+        })
+        .collect())
+}
+
+// A header line naming issue_url (or issue_id/url) and optionally budget, currency
+// and notes.
+fn parse_import_csv(text: &str) -> GosimResult<Vec<ImportRow>> {
+    let mut records = csv_records(text).into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| Error::Validation(String::from("import file is empty")))?;
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+    };
+    let url_column = column(&["issue_url", "issue_id", "url"])
+        .ok_or_else(|| Error::Validation(String::from("import file has no issue_url column")))?;
+    let budget_column = column(&["budget", "issue_budget"]);
+    let currency_column = column(&["currency", "issue_budget_currency"]);
+    let notes_column = column(&["notes"]);
+
+    let mut rows = Vec::new();
+    for (i, record) in records.enumerate() {
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .map(|f| f.trim())
+                .filter(|f| !f.is_empty())
+        };
+        let budget = field(budget_column)
+            .map(|budget| {
+                budget.parse::<i32>().map_err(|_| {
+                    Error::Validation(format!("row {}: invalid budget '{}'", i + 1, budget))
+                })
+            })
+            .transpose()?;
+        let currency = field(currency_column)
+            .map(|currency| {
+                currency
+                    .parse::<Currency>()
+                    .map_err(|e| Error::Validation(format!("row {}: {}", i + 1, e)))
+            })
+            .transpose()?;
+        rows.push(ImportRow {
+            issue_url: field(Some(url_column)).unwrap_or_default().to_string(),
+            budget,
+            currency,
+            notes: field(notes_column).map(String::from),
+        });
+    }
+    Ok(rows)
+}
+
+pub async fn issue_in_master(pool: &Pool, issue_id: &str) -> GosimResult<bool> {
+    let mut conn = pool.get_conn().await?;
+    let found: Option<u32> = conn
+        .exec_first(
+            "SELECT 1 FROM issues_master WHERE issue_id = :issue_id",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    Ok(found.is_some())
+}
+
+// What can be told about a row before its issue is fetched. A budget of 0 is
+// kept, like a 0 found in an issue body.
+pub fn check_import_row(row: &ImportRow) -> GosimResult<()> {
+    if row.budget.is_some_and(|budget| budget < 0) {
+        return Err(Error::Validation(String::from(
+            "budget must not be negative",
+        )));
+    }
+    issue_url_parts(&row.issue_url)?;
+    Ok(())
+}
+
+// Issues are told apart by the issue_id GitHub returns, so a url that only
+// differs in case or a trailing slash is still the same issue.
+pub fn mark_seen(seen: &mut HashSet<String>, issue_id: &str) -> GosimResult<()> {
+    if !seen.insert(issue_id.to_string()) {
+        return Err(Error::Duplicate(format!(
+            "{} is listed more than once",
+            issue_id
+        )));
+    }
+    Ok(())
+}
+
+// Fetches the issue and puts it in issues_open with the row's budget, returns the
+// issue_id it was staged under. `seen` catches issues listed twice.
+pub async fn stage_import_row(
+    pool: &Pool,
+    row: &ImportRow,
+    seen: &mut HashSet<String>,
+) -> GosimResult<String> {
+    check_import_row(row)?;
+
+    let mut issue = get_issue_open(&row.issue_url).await?;
+    mark_seen(seen, &issue.issue_id)?;
+    if issue_in_master(pool, &issue.issue_id).await? {
+        return Err(Error::Duplicate(format!(
+            "{} is already in issues_master",
+            issue.issue_id
+        )));
+    }
+    if let Some(budget) = row.budget {
+        issue.issue_budget = budget;
+        issue.issue_budget_currency = row.currency.unwrap_or_default();
+    }
+
+    add_issues_open(pool, &issue).await.map_err(|e| match e {
+        Error::Duplicate(_) => Error::Duplicate(format!(
+            "{} is already staged in issues_open",
+            issue.issue_id
+        )),
+        e => e,
+    })?;
+    Ok(issue.issue_id)
+}
+
+pub async fn record_import(
+    pool: &Pool,
+    issue_id: &str,
+    row: &ImportRow,
+    run_id: u64,
+) -> GosimResult<()> {
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_drop(
+        r"INSERT INTO issue_imports
+            (issue_id, issue_budget, issue_budget_currency, notes, run_id, imported_at)
+          VALUES (:issue_id, :issue_budget, :issue_budget_currency, :notes, :run_id, NOW())",
+        params! {
+            "issue_id" => issue_id,
+            "issue_budget" => row.budget,
+            "issue_budget_currency" => row.currency.unwrap_or_default().as_str(),
+            "notes" => &row.notes,
+            "run_id" => run_id,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISSUE: &str = "https://github.com/o/r/issues/1";
+
+    #[test]
+    fn csv_fields_can_be_quoted() {
+        let text = "url,Budget,currency,notes\r\n\
+                    https://github.com/o/r/issues/1,100,eur,\"first, \"\"quoted\"\"\nline\"\n\
+                    \n\
+                    \" https://github.com/o/r/issues/2 \",,,\n";
+        assert_eq!(
+            parse_import(text, ImportFormat::Csv).unwrap(),
+            [
+                ImportRow {
+                    issue_url: String::from(ISSUE),
+                    budget: Some(100),
+                    currency: Some(Currency::Eur),
+                    notes: Some(String::from("first, \"quoted\"\nline")),
+                },
+                ImportRow {
+                    issue_url: String::from("https://github.com/o/r/issues/2"),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_without_an_issue_column_is_rejected() {
+        for text in ["", "budget,notes\n100,x\n"] {
+            assert!(matches!(
+                parse_import(text, ImportFormat::Csv),
+                Err(Error::Validation(_))
+            ));
+        }
+        // the other columns are optional
+        let rows = parse_import(
+            "issue_id\nhttps://github.com/o/r/issues/1\n",
+            ImportFormat::Csv,
+        );
+        assert_eq!(rows.unwrap()[0].budget, None);
+    }
+
+    #[test]
+    fn bad_budgets_and_currencies_name_their_row() {
+        let text = "issue_url,budget,currency\nhttps://github.com/o/r/issues/1,ten,\n";
+        let err = parse_import(text, ImportFormat::Csv).unwrap_err();
+        assert!(err.to_string().contains("row 1"), "{}", err);
+        let text = "issue_url,budget,currency\nhttps://github.com/o/r/issues/1,10,doubloons\n";
+        assert!(parse_import(text, ImportFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn json_takes_urls_and_rows() {
+        let text = r#"["https://github.com/o/r/issues/1", {"url": "https://github.com/o/r/issues/2", "budget": 50}]"#;
+        let rows = parse_import(text, ImportFormat::Json).unwrap();
+        assert_eq!(rows[0].issue_url, ISSUE);
+        assert_eq!(rows[1].budget, Some(50));
+        assert!(parse_import(r#"{"url": "x"}"#, ImportFormat::Json).is_err());
+    }
+
+    #[test]
+    fn negative_budgets_are_refused_and_zero_is_kept() {
+        let row = |budget| ImportRow {
+            issue_url: String::from(ISSUE),
+            budget,
+            ..Default::default()
+        };
+        let text = "issue_url,budget\nhttps://github.com/o/r/issues/1,-5\nhttps://github.com/o/r/issues/1,0\n";
+        let rows = parse_import(text, ImportFormat::Csv).unwrap();
+        assert_eq!(rows, [row(Some(-5)), row(Some(0))]);
+        assert!(matches!(
+            check_import_row(&rows[0]),
+            Err(Error::Validation(_))
+        ));
+        assert!(check_import_row(&rows[1]).is_ok());
+        assert!(check_import_row(&row(None)).is_ok());
+
+        let not_an_issue = ImportRow {
+            issue_url: String::from("https://github.com/o/r/pull/1"),
+            ..Default::default()
+        };
+        assert!(check_import_row(&not_an_issue).is_err());
+    }
+
+    #[test]
+    fn an_issue_listed_twice_is_a_duplicate() {
+        let mut seen = HashSet::new();
+        assert!(mark_seen(&mut seen, ISSUE).is_ok());
+        assert!(mark_seen(&mut seen, "https://github.com/o/r/issues/2").is_ok());
+        assert!(matches!(
+            mark_seen(&mut seen, ISSUE),
+            Err(Error::Duplicate(_))
+        ));
+    }
+}
@@ -1,9 +1,28 @@
+use crate::currency::reporting_currency;
+use crate::db_rates::{converted_amount_sql, projects_without_rates};
+use crate::db_runs::StepStats;
+use crate::error::GosimResult;
+use crate::review_state::{ReviewAction, ReviewState};
 use mysql_async::prelude::*;
-use mysql_async::*;
-
-pub async fn open_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
+use mysql_async::{Params, Value};
+
+// The ledger accounts whose money counts towards a project's budget.
+const BUDGET_ACCOUNTS: &[&str] = &["allocated", "approved", "paid"];
+
+// The merge and purge statements take a Conn or a Transaction, so the pipeline can
+// run them as one unit (see the_runner::merge_and_purge_ops).
+async fn exec_counted<Q: Queryable>(conn: &mut Q, query: &str, context: &str) -> GosimResult<u64> {
+    let result = conn.query_iter(query).await.map_err(|e| {
+        log::error!("{}: {:?}", context, e);
+        e
+    })?;
+    let affected_rows = result.affected_rows();
+    result.drop_result().await?;
+
+    Ok(affected_rows)
+}
 
+pub async fn open_master<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
     let query = r"
     INSERT IGNORE INTO issues_master (
         issue_id, 
@@ -11,7 +30,9 @@ pub async fn open_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
         issue_title, 
         issue_creator,
         issue_budget,
-        issue_description
+        issue_budget_currency,
+        issue_description,
+        issue_labels
     )
     SELECT 
         io.issue_id, 
@@ -19,64 +40,114 @@ pub async fn open_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
         io.issue_title, 
         io.issue_creator,
         io.issue_budget,
-        io.issue_description
+        io.issue_budget_currency,
+        io.issue_description,
+        io.issue_labels
     FROM 
         issues_open io;
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error consolidating issues_open into issues_master: {:?}",
-            e
-        );
-    };
-
-    Ok(())
+    exec_counted(
+        conn,
+        query,
+        "Error consolidating issues_open into issues_master",
+    )
+    .await
 }
 
-pub async fn assigned_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"
-UPDATE issues_master im
-JOIN issues_assigned ia ON im.issue_id = ia.issue_id
-SET im.date_issue_assigned = ia.date_assigned,
-    im.issue_assignees = JSON_ARRAY(ia.issue_assignee);        
+// Assignees accumulate in issue_assignees, one row per source, so the assigned
+// and closed snapshots no longer overwrite each other.
+pub async fn assigned_master<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
+    let update_query = r"
+    UPDATE issues_master im
+    JOIN issues_assigned ia ON im.issue_id = ia.issue_id
+    SET im.date_issue_assigned = ia.date_assigned;
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error consolidating issues_assigned into issues_master: {:?}",
-            e
-        );
-    };
+    let assignees_query = r"
+    INSERT IGNORE INTO issue_assignees (issue_id, login, source, assigned_at)
+    SELECT ia.issue_id, ia.issue_assignee, 'assigned', ia.date_assigned
+    FROM issues_assigned ia
+    JOIN issues_master im ON im.issue_id = ia.issue_id
+    WHERE ia.issue_assignee IS NOT NULL AND ia.issue_assignee <> '';
+    ";
 
-    Ok(())
+    let msg = "Error consolidating issues_assigned into issues_master";
+    Ok(exec_counted(conn, update_query, msg).await?
+        + exec_counted(conn, assignees_query, msg).await?)
 }
 
-pub async fn closed_master(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"
+pub async fn closed_master<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
+    let update_query = r"
     UPDATE issues_master im
     JOIN issues_closed ic ON im.issue_id = ic.issue_id
     SET
-        im.issue_assignees = ic.issue_assignees,
         im.issue_linked_pr = ic.issue_linked_pr;
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error consolidating issues_closed into issues_master: {:?}",
-            e
+    let assignees_query = r"
+    INSERT IGNORE INTO issue_assignees (issue_id, login, source, assigned_at)
+    SELECT ic.issue_id, jt.login, 'closed', NULL
+    FROM issues_closed ic
+    JOIN issues_master im ON im.issue_id = ic.issue_id
+    CROSS JOIN JSON_TABLE(ic.issue_assignees, '$[*]' COLUMNS (login VARCHAR(50) PATH '$')) jt
+    WHERE jt.login IS NOT NULL;
+    ";
+
+    let msg = "Error consolidating issues_closed into issues_master";
+    Ok(exec_counted(conn, update_query, msg).await?
+        + exec_counted(conn, assignees_query, msg).await?)
+}
+
+// Moves approved issues along once they are assigned or a PR closes them, and
+// records the changes. Only the states ReviewState::next allows are touched.
+pub async fn advance_review_states<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
+    let steps = [
+        (ReviewAction::Assign, "im.date_issue_assigned IS NOT NULL"),
+        (ReviewAction::LinkPr, "im.issue_linked_pr IS NOT NULL"),
+    ];
+
+    let mut affected_rows = 0;
+    for (action, condition) in steps {
+        let sources = ReviewState::sources(action);
+        let to = sources[0].next(action)?;
+        let source_list = sources
+            .iter()
+            .map(|state| format!("'{}'", state.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let changes_query = format!(
+            r"
+    INSERT INTO issue_state_changes (issue_id, from_state, to_state, action, actor, changed_at)
+    SELECT im.issue_id, im.review_state, '{to}', '{action}', 'pipeline', NOW()
+    FROM issues_master im
+    WHERE im.review_state IN ({source_list}) AND {condition};
+    ",
+            to = to.as_str(),
+            action = action.as_str(),
         );
-    };
+        let update_query = format!(
+            r"
+    UPDATE issues_master im
+    SET im.review_state = '{to}', im.review_status = '{status}',
+        im.issue_budget_approved = {approved}
+    WHERE im.review_state IN ({source_list}) AND {condition};
+    ",
+            to = to.as_str(),
+            status = to.review_status(),
+            approved = to.budget_approved(),
+        );
+
+        let msg = "Error advancing review states";
+        exec_counted(conn, &changes_query, msg).await?;
+        affected_rows += exec_counted(conn, &update_query, msg).await?;
+    }
 
-    Ok(())
+    Ok(affected_rows)
 }
-pub async fn comment_master(pool: &mysql_async::Pool) -> Result<()> {
-    let mut conn = pool.get_conn().await?;
 
+pub async fn comment_master<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
     let query = r"
     UPDATE issues_master im
     JOIN issues_comment ic ON im.issue_id = ic.issue_id
@@ -84,19 +155,15 @@ pub async fn comment_master(pool: &mysql_async::Pool) -> Result<()> {
         im.issue_comment = CONCAT_WS('\n', im.issue_comment, ic.issue_comment);
     ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error consolidating issues_comment into issues_master: {:?}",
-            e
-        );
-    };
-
-    Ok(())
+    exec_counted(
+        conn,
+        query,
+        "Error consolidating issues_comment into issues_master",
+    )
+    .await
 }
 
-pub async fn project_master_back_sync(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
+pub async fn project_master_back_sync<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
     let query = r"
     UPDATE issues_master im
     JOIN projects p ON im.project_id = p.project_id
@@ -105,19 +172,15 @@ pub async fn project_master_back_sync(pool: &mysql_async::Pool) -> anyhow::Resul
         im.repo_stars = p.repo_stars;
         ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error syncing main_language, repo_stars to issues_master: {:?}",
-            e
-        );
-    };
-
-    Ok(())
+    exec_counted(
+        conn,
+        query,
+        "Error syncing main_language, repo_stars to issues_master",
+    )
+    .await
 }
 
-pub async fn remove_pull_by_issued_linked_pr(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
+pub async fn remove_pull_by_issued_linked_pr<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
     let query = r#"
     DELETE FROM pull_requests
     WHERE pull_id IN (
@@ -125,22 +188,21 @@ pub async fn remove_pull_by_issued_linked_pr(pool: &mysql_async::Pool) -> anyhow
     );
             "#;
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!(
-            "Error removing pull_request from issues_master by issue_linked_pr: {:?}",
-            e
-        );
-    };
-
-    Ok(())
+    exec_counted(
+        conn,
+        query,
+        "Error removing pull_request from issues_master by issue_linked_pr",
+    )
+    .await
 }
 
-pub async fn delete_issues_open_assigned_closed(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let queries = vec![
+pub async fn delete_issues_open_assigned_closed<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
+    let queries = [
         r#"
-        DELETE FROM issues_open;
+        DELETE FROM issues_open
+        WHERE issue_id IN (
+            SELECT issue_id FROM issues_master WHERE issue_id IS NOT NULL
+        );
         "#,
         r#"
         DELETE FROM issues_assigned
@@ -156,23 +218,20 @@ pub async fn delete_issues_open_assigned_closed(pool: &mysql_async::Pool) -> any
         "#,
     ];
 
-    let msgs = vec![
+    let msgs = [
         "Error deleting from issues_open",
         "Error deleting from issues_assigned",
         "Error deleting from issues_closed",
     ];
 
+    let mut rows_deleted = 0;
     for (query, msg) in queries.iter().zip(msgs.iter()) {
-        if let Err(e) = conn.query_drop(*query).await {
-            log::error!("{:?}: {:?}", msg, e);
-        };
+        rows_deleted += exec_counted(conn, query, msg).await?;
     }
 
-    Ok(())
+    Ok(rows_deleted)
 }
-pub async fn master_project(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
+pub async fn master_project<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
     let query = r"
     INSERT INTO projects (project_id, issues_list)
     SELECT 
@@ -186,28 +245,126 @@ pub async fn master_project(pool: &mysql_async::Pool) -> anyhow::Result<()> {
         issues_list = VALUES(issues_list);
         ";
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!("Error building project from issues_master: {:?}", e);
-    };
-
-    Ok(())
+    exec_counted(conn, query, "Error building project from issues_master").await
 }
 
-pub async fn sum_budget_to_project(pool: &mysql_async::Pool) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
+// The money a project's issues hold in the budget ledger: allocated, approved or paid,
+// in the reporting currency. A project with money in a currency that has no rate is
+// skipped and keeps its last total, so one missing rate doesn't fail the merge.
+pub async fn sum_budget_to_project<Q: Queryable>(conn: &mut Q) -> GosimResult<StepStats> {
+    let unrated = projects_without_rates(conn, BUDGET_ACCOUNTS, reporting_currency()).await?;
+    for (project_id, e) in &unrated {
+        log::warn!("Skipping the budget sum of {}: {}", project_id, e);
+    }
 
-    let query = r"
+    let accounts = vec!["?"; BUDGET_ACCOUNTS.len()].join(", ");
+    let skipped = if unrated.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "WHERE p.project_id NOT IN ({})",
+            vec!["?"; unrated.len()].join(", ")
+        )
+    };
+    let query = format!(
+        r"
     UPDATE projects p
-    JOIN (
-        SELECT project_id, SUM(issue_budget) AS total_budget
-        FROM issues_master
-        GROUP BY project_id
+    LEFT JOIN (
+        SELECT e.project_id, SUM({amount}) AS total_budget
+        FROM ledger_entries e
+        WHERE e.account IN ({accounts})
+        GROUP BY e.project_id
     ) AS summed_budgets ON p.project_id = summed_budgets.project_id
-    SET p.total_budget_allocated = summed_budgets.total_budget;";
+    SET p.total_budget_allocated = summed_budgets.total_budget
+    {skipped};",
+        amount = converted_amount_sql(reporting_currency())
+    );
+    let params = BUDGET_ACCOUNTS
+        .iter()
+        .map(|a| Value::from(*a))
+        .chain(
+            unrated
+                .iter()
+                .map(|(project_id, _)| Value::from(project_id)),
+        )
+        .collect();
+
+    let result = conn
+        .exec_iter(query, Params::Positional(params))
+        .await
+        .map_err(|e| {
+            log::error!("Error summing total_budget_allocated: {:?}", e);
+            e
+        })?;
+    let affected_rows = result.affected_rows();
+    result.drop_result().await?;
+
+    Ok(StepStats {
+        rows_written: affected_rows as i32,
+        rows_skipped: unrated.len() as i32,
+        ..Default::default()
+    })
+}
 
-    if let Err(e) = conn.query_drop(query).await {
-        log::error!("Error summing total_budget_allocated: {:?}", e);
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Currency;
+    use crate::db_ledger::fund_campaign;
+    use crate::test_db;
+
+    async fn total_budget(conn: &mut mysql_async::Conn, project_id: &str) -> Option<i32> {
+        conn.exec_first(
+            "SELECT total_budget_allocated FROM projects WHERE project_id = :project_id",
+            params! { "project_id" => project_id },
+        )
+        .await
+        .unwrap()
+        .flatten()
+    }
 
-    Ok(())
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_project_without_a_rate_is_skipped_and_the_rest_summed() {
+        let pool = test_db::pool().await;
+        let mut conn = pool.get_conn().await.unwrap();
+        // no other test loads CNY rates, this one needs there to be none
+        conn.query_drop("DELETE FROM exchange_rates WHERE currency = 'CNY'")
+            .await
+            .unwrap();
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        fund_campaign(&pool, &campaign_id, 1000, Currency::Cny, "test", None)
+            .await
+            .unwrap();
+
+        let rated = format!("{}-1", campaign_id);
+        let unrated = format!("{}-2", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &rated).await;
+        test_db::insert_issue(&pool, &campaign_id, &unrated).await;
+        let rated_project = format!("https://github.com/{}/rated", campaign_id);
+        let unrated_project = format!("https://github.com/{}/unrated", campaign_id);
+        for (issue_id, project_id) in [(&rated, &rated_project), (&unrated, &unrated_project)] {
+            conn.exec_drop(
+                "UPDATE issues_master SET project_id = :project_id WHERE issue_id = :issue_id",
+                params! { "issue_id" => issue_id, "project_id" => project_id },
+            )
+            .await
+            .unwrap();
+            conn.exec_drop(
+                "INSERT INTO projects (project_id, total_budget_allocated) VALUES (:project_id, 7)",
+                params! { "project_id" => project_id },
+            )
+            .await
+            .unwrap();
+        }
+        let usd = test_db::started_transaction(&pool).await;
+        test_db::allocate(usd, &rated, 60).await.unwrap();
+        test_db::unchecked_allocation(&pool, &unrated, 50, Currency::Cny).await;
+
+        let stats = sum_budget_to_project(&mut conn).await.unwrap();
+
+        assert!(stats.rows_skipped >= 1, "{:?}", stats);
+        assert_eq!(total_budget(&mut conn, &rated_project).await, Some(60));
+        assert_eq!(total_budget(&mut conn, &unrated_project).await, Some(7));
+    }
 }
@@ -0,0 +1,946 @@
+use crate::currency::{reporting_currency, Currency, Rates};
+use crate::db_caps::budget_room;
+use crate::db_query::page_window;
+use crate::db_rates::{check_rate, converted_amount_sql};
+use crate::error::{Error, GosimResult};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+// Budget money as double-entry transactions. The entries of a transaction sum to
+// zero, so money only moves between accounts:
+//
+//   funding -> available            fund: a campaign's budget
+//   available -> allocated          allocate: an approved issue budget
+//   allocated -> approved           approve: the issue was concluded
+//   allocated/approved -> available release: a decline or a lowered budget
+//   approved -> paid                payout: a payout batch was sent
+//
+// funding is the only account that goes negative. Transactions are never changed,
+// a mistake is undone by a reversal transaction with the entries negated. Every
+// account is kept per currency: a transaction's entries are in one currency, and
+// an issue's budget is allocated from what is available in the currency it's in.
+
+pub const DEFAULT_CAMPAIGN: &str = "gosim";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Account {
+    Funding,
+    Available,
+    Allocated,
+    Approved,
+    Paid,
+}
+
+impl Account {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Account::Funding => "funding",
+            Account::Available => "available",
+            Account::Allocated => "allocated",
+            Account::Approved => "approved",
+            Account::Paid => "paid",
+        }
+    }
+
+    // allocated, approved and paid are kept per issue, the others per campaign
+    pub fn per_issue(&self) -> bool {
+        matches!(self, Account::Allocated | Account::Approved | Account::Paid)
+    }
+
+    fn from_db(s: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(s.to_string())).unwrap_or(Account::Funding)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxnKind {
+    Fund,
+    Allocate,
+    Approve,
+    Release,
+    Payout,
+    Reversal,
+}
+
+impl TxnKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxnKind::Fund => "fund",
+            TxnKind::Allocate => "allocate",
+            TxnKind::Approve => "approve",
+            TxnKind::Release => "release",
+            TxnKind::Payout => "payout",
+            TxnKind::Reversal => "reversal",
+        }
+    }
+
+    fn from_db(s: &str) -> Self {
+        serde_json::from_value(serde_json::Value::String(s.to_string())).unwrap_or(TxnKind::Fund)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Posting {
+    pub account: Account,
+    pub amount: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LedgerTxn {
+    pub txn_id: u64,
+    pub campaign_id: String,
+    pub kind: TxnKind,
+    pub project_id: Option<String>,
+    pub issue_id: Option<String>,
+    pub reverses_txn_id: Option<u64>,
+    pub actor: String,
+    pub memo: Option<String>,
+    pub created_at: String,
+    #[serde(default)]
+    pub currency: Currency,
+    pub postings: Vec<Posting>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct IssueBalance {
+    pub allocated: i64,
+    pub approved: i64,
+    pub paid: i64,
+}
+
+// A campaign's money in one currency.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CampaignBalance {
+    pub campaign_id: String,
+    #[serde(default)]
+    pub currency: Currency,
+    pub funded: i64,
+    pub available: i64,
+    pub allocated: i64,
+    pub approved: i64,
+    pub paid: i64,
+}
+
+impl CampaignBalance {
+    // (total budget, allocated, balance), the shape of count_budget_by_status
+    pub fn running_budget(&self) -> (i32, i32, i32) {
+        (
+            self.funded as i32,
+            (self.allocated + self.approved + self.paid) as i32,
+            self.available as i32,
+        )
+    }
+}
+
+// What an admin action on an issue does to its budget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IssueLedgerOp {
+    // hold this much for the issue, allocating or releasing the difference
+    Allocate(i64),
+    // approve what is allocated, allocating the issue budget first if nothing is
+    Approve,
+    // give back everything allocated or approved
+    Release,
+    // pay out what is approved
+    Payout,
+}
+
+fn posting(account: Account, amount: i64) -> Posting {
+    Posting { account, amount }
+}
+
+fn release(allocated: i64, approved: i64) -> (TxnKind, Vec<Posting>) {
+    (
+        TxnKind::Release,
+        vec![
+            posting(Account::Allocated, -allocated),
+            posting(Account::Approved, -approved),
+            posting(Account::Available, allocated + approved),
+        ]
+        .into_iter()
+        .filter(|p| p.amount != 0)
+        .collect(),
+    )
+}
+
+// The transactions `op` takes, given what the issue holds now.
+pub fn issue_postings(
+    op: IssueLedgerOp,
+    balance: IssueBalance,
+    issue_budget: Option<i32>,
+) -> Vec<(TxnKind, Vec<Posting>)> {
+    let mut txns = Vec::new();
+    match op {
+        IssueLedgerOp::Allocate(target) => {
+            let held = balance.allocated + balance.approved;
+            if target > held {
+                txns.push((
+                    TxnKind::Allocate,
+                    vec![
+                        posting(Account::Available, held - target),
+                        posting(Account::Allocated, target - held),
+                    ],
+                ));
+            } else if target < held {
+                // allocated money goes back before approved money does
+                let from_allocated = balance.allocated.min(held - target);
+                txns.push(release(from_allocated, held - target - from_allocated));
+            }
+        }
+        IssueLedgerOp::Approve => {
+            let mut allocated = balance.allocated;
+            let budget = issue_budget.unwrap_or(0) as i64;
+            if allocated == 0 && balance.approved == 0 && budget > 0 {
+                txns.extend(issue_postings(
+                    IssueLedgerOp::Allocate(budget),
+                    balance,
+                    issue_budget,
+                ));
+                allocated = budget;
+            }
+            if allocated > 0 {
+                txns.push((
+                    TxnKind::Approve,
+                    vec![
+                        posting(Account::Allocated, -allocated),
+                        posting(Account::Approved, allocated),
+                    ],
+                ));
+            }
+        }
+        IssueLedgerOp::Release => {
+            if balance.allocated + balance.approved != 0 {
+                txns.push(release(balance.allocated, balance.approved));
+            }
+        }
+        IssueLedgerOp::Payout => {
+            if balance.approved > 0 {
+                txns.push((
+                    TxnKind::Payout,
+                    vec![
+                        posting(Account::Approved, -balance.approved),
+                        posting(Account::Paid, balance.approved),
+                    ],
+                ));
+            }
+        }
+    }
+    txns
+}
+
+// The budget to check against the caps, when `op` gives the issue a budget.
+pub fn capped_budget(
+    op: IssueLedgerOp,
+    txns: &[(TxnKind, Vec<Posting>)],
+    issue_budget: Option<i32>,
+) -> Option<i64> {
+    match op {
+        IssueLedgerOp::Allocate(target) => Some(target),
+        _ if txns.iter().any(|(kind, _)| *kind == TxnKind::Allocate) => {
+            Some(issue_budget.unwrap_or(0) as i64)
+        }
+        _ => None,
+    }
+}
+
+pub fn check_balanced(postings: &[Posting]) -> GosimResult<()> {
+    if postings.is_empty() {
+        return Err(Error::Validation(String::from(
+            "a ledger transaction needs entries",
+        )));
+    }
+    let sum: i64 = postings.iter().map(|p| p.amount).sum();
+    if sum != 0 {
+        return Err(Error::Validation(format!(
+            "ledger entries must sum to zero, these sum to {}",
+            sum
+        )));
+    }
+    Ok(())
+}
+
+pub fn overspend(account: Account, scope: &str, balance: i64) -> Error {
+    Error::Validation(format!(
+        "overspend: {} for {} would be {}",
+        account.as_str(),
+        scope,
+        balance
+    ))
+}
+
+async fn lock_campaign(tx: &mut Transaction<'_>, campaign_id: &str) -> GosimResult<()> {
+    let campaign: Option<String> = tx
+        .exec_first(
+            "SELECT campaign_id FROM campaigns WHERE campaign_id = :campaign_id FOR UPDATE",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+    match campaign {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound(format!("Campaign {}", campaign_id))),
+    }
+}
+
+// What the ledger needs of an issue.
+pub struct LedgerIssue {
+    pub campaign_id: String,
+    pub project_id: String,
+    pub issue_budget: Option<i32>,
+    pub currency: Currency,
+}
+
+// Locks the issue's row and then its campaign. Anything that books entries for the
+// issue calls this before reading the ledger, so two bookings on one campaign
+// queue up here instead of both checking the same balance.
+pub async fn lock_issue_campaign(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+) -> GosimResult<LedgerIssue> {
+    let issue: Option<(String, String, Option<i32>, String)> = tx
+        .exec_first(
+            r"SELECT campaign_id, project_id, issue_budget, issue_budget_currency
+              FROM issues_master WHERE issue_id = :issue_id FOR UPDATE",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    let (campaign_id, project_id, issue_budget, currency) =
+        issue.ok_or_else(|| Error::NotFound(format!("Issue {}", issue_id)))?;
+    lock_campaign(tx, &campaign_id).await?;
+
+    Ok(LedgerIssue {
+        campaign_id,
+        project_id,
+        issue_budget,
+        currency: Currency::from_db(&currency),
+    })
+}
+
+// Books `txn` inside the caller's transaction and checks that no account other
+// than funding ends up negative. On an error the caller's transaction must not be
+// committed, dropping it rolls the entries back.
+pub async fn post_transaction(tx: &mut Transaction<'_>, txn: &LedgerTxn) -> GosimResult<u64> {
+    check_balanced(&txn.postings)?;
+    if txn.postings.iter().any(|p| p.account.per_issue()) && txn.issue_id.is_none() {
+        return Err(Error::Validation(String::from(
+            "issue accounts need an issue_id",
+        )));
+    }
+
+    // One writer per campaign at a time. The transaction's snapshot may predate
+    // entries committed while it waited for the lock, so the balance checks below
+    // are locking reads, which see the latest committed entries.
+    lock_campaign(tx, &txn.campaign_id).await?;
+
+    tx.exec_drop(
+        r"INSERT INTO ledger_transactions
+            (campaign_id, kind, project_id, issue_id, reverses_txn_id, actor, memo, created_at)
+          VALUES
+            (:campaign_id, :kind, :project_id, :issue_id, :reverses_txn_id, :actor, :memo, NOW())",
+        params! {
+            "campaign_id" => &txn.campaign_id,
+            "kind" => txn.kind.as_str(),
+            "project_id" => &txn.project_id,
+            "issue_id" => &txn.issue_id,
+            "reverses_txn_id" => txn.reverses_txn_id,
+            "actor" => &txn.actor,
+            "memo" => &txn.memo,
+        },
+    )
+    .await?;
+    let txn_id = tx
+        .last_insert_id()
+        .ok_or_else(|| Error::Validation(String::from("no txn_id for the ledger transaction")))?;
+
+    tx.exec_batch(
+        r"INSERT INTO ledger_entries
+            (txn_id, campaign_id, account, project_id, issue_id, amount, currency)
+          VALUES (:txn_id, :campaign_id, :account, :project_id, :issue_id, :amount, :currency)",
+        txn.postings.iter().map(|p| {
+            let (project_id, issue_id) = match p.account.per_issue() {
+                true => (txn.project_id.as_deref(), txn.issue_id.as_deref()),
+                false => (None, None),
+            };
+            params! {
+                "txn_id" => txn_id,
+                "campaign_id" => &txn.campaign_id,
+                "account" => p.account.as_str(),
+                "project_id" => project_id,
+                "issue_id" => issue_id,
+                "amount" => p.amount,
+                "currency" => txn.currency.as_str(),
+            }
+        }),
+    )
+    .await?;
+
+    let touched: BTreeSet<Account> = txn
+        .postings
+        .iter()
+        .map(|p| p.account)
+        .filter(|a| *a != Account::Funding)
+        .collect();
+    for account in touched {
+        let issue_id = txn.issue_id.as_deref().filter(|_| account.per_issue());
+        let balance: i64 = tx
+            .exec_first(
+                r"SELECT CAST(COALESCE(SUM(amount), 0) AS SIGNED) FROM ledger_entries
+                  WHERE campaign_id = :campaign_id AND account = :account
+                    AND currency = :currency AND (:issue_id IS NULL OR issue_id = :issue_id)
+                  FOR SHARE",
+                params! {
+                    "campaign_id" => &txn.campaign_id,
+                    "account" => account.as_str(),
+                    "currency" => txn.currency.as_str(),
+                    "issue_id" => issue_id,
+                },
+            )
+            .await?
+            .unwrap_or(0);
+        if balance < 0 {
+            let scope = format!("{} {}", issue_id.unwrap_or(&txn.campaign_id), txn.currency);
+            return Err(overspend(account, &scope, balance));
+        }
+    }
+
+    Ok(txn_id)
+}
+
+// A locking read, so inside a transaction it sees the latest committed entries
+// rather than the transaction's snapshot.
+pub async fn issue_balance<Q: Queryable>(
+    conn: &mut Q,
+    issue_id: &str,
+) -> GosimResult<IssueBalance> {
+    let rows: Vec<(String, i64)> = conn
+        .exec(
+            r"SELECT account, CAST(SUM(amount) AS SIGNED) FROM ledger_entries
+              WHERE issue_id = :issue_id GROUP BY account FOR SHARE",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    let mut balance = IssueBalance::default();
+    for (account, amount) in rows {
+        match Account::from_db(&account) {
+            Account::Allocated => balance.allocated = amount,
+            Account::Approved => balance.approved = amount,
+            Account::Paid => balance.paid = amount,
+            _ => {}
+        }
+    }
+    Ok(balance)
+}
+
+// The project's total_budget_allocated, as its issues hold it in the ledger, in the
+// reporting currency, after money in `currency` moved. Only that currency needs a
+// rate, money of the project in another one without a rate is left out.
+pub async fn refresh_project_budget<Q: Queryable>(
+    conn: &mut Q,
+    project_id: &str,
+    currency: Currency,
+) -> GosimResult<()> {
+    check_rate(conn, currency, reporting_currency()).await?;
+    conn.exec_drop(
+        format!(
+            r"UPDATE projects SET total_budget_allocated = (
+                SELECT SUM({}) FROM ledger_entries e
+                WHERE e.project_id = :project_id
+                  AND e.account IN ('allocated', 'approved', 'paid')
+              )
+              WHERE project_id = :project_id",
+            converted_amount_sql(reporting_currency())
+        ),
+        params! { "project_id" => project_id },
+    )
+    .await?;
+    Ok(())
+}
+
+// Books what `op` does to the issue's budget, with `memo` on every transaction.
+pub async fn apply_issue_op(
+    tx: &mut Transaction<'_>,
+    issue_id: &str,
+    op: IssueLedgerOp,
+    actor: &str,
+    memo: Option<&str>,
+) -> GosimResult<()> {
+    let LedgerIssue {
+        campaign_id,
+        project_id,
+        issue_budget,
+        currency,
+    } = lock_issue_campaign(tx, issue_id).await?;
+
+    let balance = issue_balance(tx, issue_id).await?;
+    let txns = issue_postings(op, balance, issue_budget);
+    if txns.is_empty() {
+        return Ok(());
+    }
+    if let Some(target) = capped_budget(op, &txns, issue_budget) {
+        // the campaign is locked and the cap sums are locking reads, so another
+        // approval in the campaign either committed before this check or waits
+        budget_room(tx, issue_id).await?.check(target)?;
+    }
+    for (kind, postings) in txns {
+        let txn = LedgerTxn {
+            txn_id: 0,
+            campaign_id: campaign_id.clone(),
+            kind,
+            project_id: Some(project_id.clone()),
+            issue_id: Some(issue_id.to_string()),
+            reverses_txn_id: None,
+            actor: actor.to_string(),
+            memo: memo.map(String::from),
+            created_at: String::new(),
+            currency,
+            postings,
+        };
+        post_transaction(tx, &txn).await?;
+    }
+    refresh_project_budget(tx, &project_id, currency).await
+}
+
+// Adds `amount` in `currency` to a campaign's budget, creating the campaign on its
+// first funding. A negative amount takes budget away, as long as it is still
+// available.
+pub async fn fund_campaign(
+    pool: &Pool,
+    campaign_id: &str,
+    amount: i64,
+    currency: Currency,
+    actor: &str,
+    memo: Option<&str>,
+) -> GosimResult<u64> {
+    if amount == 0 {
+        return Err(Error::Validation(String::from(
+            "funding amount must not be zero",
+        )));
+    }
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    tx.exec_drop(
+        r"INSERT IGNORE INTO campaigns (campaign_id, campaign_name, created_at)
+          VALUES (:campaign_id, :campaign_id, NOW())",
+        params! { "campaign_id" => campaign_id },
+    )
+    .await?;
+    let txn = LedgerTxn {
+        txn_id: 0,
+        campaign_id: campaign_id.to_string(),
+        kind: TxnKind::Fund,
+        project_id: None,
+        issue_id: None,
+        reverses_txn_id: None,
+        actor: actor.to_string(),
+        memo: memo.map(String::from),
+        created_at: String::new(),
+        currency,
+        postings: vec![
+            posting(Account::Funding, -amount),
+            posting(Account::Available, amount),
+        ],
+    };
+    let txn_id = post_transaction(&mut tx, &txn).await?;
+    tx.commit().await?;
+
+    Ok(txn_id)
+}
+
+// The reversal of `txn`: the same accounts with the amounts negated.
+pub fn reversal_of(txn: &LedgerTxn, actor: &str, memo: Option<&str>) -> GosimResult<LedgerTxn> {
+    if txn.kind == TxnKind::Reversal {
+        return Err(Error::Validation(format!(
+            "transaction {} is a reversal, it can't be reversed",
+            txn.txn_id
+        )));
+    }
+    Ok(LedgerTxn {
+        txn_id: 0,
+        kind: TxnKind::Reversal,
+        reverses_txn_id: Some(txn.txn_id),
+        actor: actor.to_string(),
+        memo: memo.map(String::from),
+        created_at: String::new(),
+        postings: txn
+            .postings
+            .iter()
+            .map(|p| posting(p.account, -p.amount))
+            .collect(),
+        ..txn.clone()
+    })
+}
+
+// Undoes a transaction. The issue row keeps its budget, only the money moves back.
+pub async fn reverse_transaction(
+    pool: &Pool,
+    txn_id: u64,
+    actor: &str,
+    memo: Option<&str>,
+) -> GosimResult<u64> {
+    let txn = get_transaction(pool, txn_id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Ledger transaction {}", txn_id)))?;
+    let reversal = reversal_of(&txn, actor, memo)?;
+
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let reversal_id = post_transaction(&mut tx, &reversal)
+        .await
+        .map_err(|e| match e {
+            Error::Duplicate(_) => {
+                Error::Duplicate(format!("transaction {} is already reversed", txn_id))
+            }
+            e => e,
+        })?;
+    if let Some(project_id) = &txn.project_id {
+        refresh_project_budget(&mut tx, project_id, txn.currency).await?;
+    }
+    tx.commit().await?;
+
+    Ok(reversal_id)
+}
+
+const TXN_COLUMNS: &str = r"txn_id, campaign_id, kind, project_id, issue_id, reverses_txn_id,
+    actor, memo, DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at";
+
+fn txn_from_row(row: &Row) -> LedgerTxn {
+    LedgerTxn {
+        txn_id: row.get("txn_id").unwrap_or_default(),
+        campaign_id: row.get("campaign_id").unwrap_or_default(),
+        kind: TxnKind::from_db(&row.get::<String, _>("kind").unwrap_or_default()),
+        project_id: row.get::<Option<String>, _>("project_id").unwrap_or(None),
+        issue_id: row.get::<Option<String>, _>("issue_id").unwrap_or(None),
+        reverses_txn_id: row.get::<Option<u64>, _>("reverses_txn_id").unwrap_or(None),
+        actor: row.get("actor").unwrap_or_default(),
+        memo: row.get::<Option<String>, _>("memo").unwrap_or(None),
+        created_at: row.get("created_at").unwrap_or_default(),
+        currency: Currency::Usd,
+        postings: Vec::new(),
+    }
+}
+
+// Loads the entries of all `txns` in one read.
+async fn with_postings(conn: &mut Conn, mut txns: Vec<LedgerTxn>) -> GosimResult<Vec<LedgerTxn>> {
+    if txns.is_empty() {
+        return Ok(txns);
+    }
+    let placeholders = vec!["?"; txns.len()].join(", ");
+    let entries: Vec<(u64, String, i64, String)> = conn
+        .exec(
+            format!(
+                r"SELECT txn_id, account, amount, currency FROM ledger_entries
+                  WHERE txn_id IN ({placeholders}) ORDER BY entry_id"
+            ),
+            Params::Positional(txns.iter().map(|t| Value::from(t.txn_id)).collect()),
+        )
+        .await?;
+
+    let mut by_txn: HashMap<u64, &mut LedgerTxn> = txns.iter_mut().map(|t| (t.txn_id, t)).collect();
+    for (txn_id, account, amount, currency) in entries {
+        if let Some(txn) = by_txn.get_mut(&txn_id) {
+            if txn.postings.is_empty() {
+                txn.currency = Currency::from_db(&currency);
+            }
+            txn.postings
+                .push(posting(Account::from_db(&account), amount));
+        }
+    }
+    Ok(txns)
+}
+
+pub async fn get_transaction(pool: &Pool, txn_id: u64) -> GosimResult<Option<LedgerTxn>> {
+    let mut conn = pool.get_conn().await?;
+
+    let row: Option<Row> = conn
+        .exec_first(
+            format!(
+                "SELECT {} FROM ledger_transactions WHERE txn_id = :txn_id",
+                TXN_COLUMNS
+            ),
+            params! { "txn_id" => txn_id },
+        )
+        .await?;
+    let txns = with_postings(&mut conn, row.iter().map(txn_from_row).collect()).await?;
+    Ok(txns.into_iter().next())
+}
+
+// A page of every transaction, or of the ones of a single issue, oldest first.
+pub async fn list_transactions(
+    pool: &Pool,
+    issue_id: Option<&str>,
+    page: usize,
+    page_size: usize,
+) -> GosimResult<Vec<LedgerTxn>> {
+    let mut conn = pool.get_conn().await?;
+    let (offset, limit) = page_window(page, page_size);
+
+    let rows: Vec<Row> = conn
+        .exec(
+            format!(
+                "SELECT {} FROM ledger_transactions
+                 WHERE :issue_id IS NULL OR issue_id = :issue_id ORDER BY txn_id
+                 LIMIT :limit OFFSET :offset",
+                TXN_COLUMNS
+            ),
+            params! {
+                "issue_id" => issue_id,
+                "limit" => limit,
+                "offset" => offset,
+            },
+        )
+        .await?;
+    with_postings(&mut conn, rows.iter().map(txn_from_row).collect()).await
+}
+
+pub async fn campaign_balances(pool: &Pool) -> GosimResult<Vec<CampaignBalance>> {
+    let mut conn = pool.get_conn().await?;
+
+    let rows: Vec<Row> = conn
+        .query(
+            r"SELECT c.campaign_id, COALESCE(e.currency, 'USD') AS currency,
+                CAST(-COALESCE(SUM(CASE WHEN e.account = 'funding' THEN e.amount END), 0) AS SIGNED) AS funded,
+                CAST(COALESCE(SUM(CASE WHEN e.account = 'available' THEN e.amount END), 0) AS SIGNED) AS available,
+                CAST(COALESCE(SUM(CASE WHEN e.account = 'allocated' THEN e.amount END), 0) AS SIGNED) AS allocated,
+                CAST(COALESCE(SUM(CASE WHEN e.account = 'approved' THEN e.amount END), 0) AS SIGNED) AS approved,
+                CAST(COALESCE(SUM(CASE WHEN e.account = 'paid' THEN e.amount END), 0) AS SIGNED) AS paid
+              FROM campaigns c
+              LEFT JOIN ledger_entries e ON e.campaign_id = c.campaign_id
+              GROUP BY c.campaign_id, e.currency
+              ORDER BY c.campaign_id, e.currency",
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| CampaignBalance {
+            campaign_id: row.get("campaign_id").unwrap_or_default(),
+            currency: Currency::from_db(&row.get::<String, _>("currency").unwrap_or_default()),
+            funded: row.get("funded").unwrap_or_default(),
+            available: row.get("available").unwrap_or_default(),
+            allocated: row.get("allocated").unwrap_or_default(),
+            approved: row.get("approved").unwrap_or_default(),
+            paid: row.get("paid").unwrap_or_default(),
+        })
+        .collect())
+}
+
+// All campaigns and currencies added up in `currency`, at the given rates.
+pub fn total_balance(
+    balances: &[CampaignBalance],
+    rates: &Rates,
+    currency: Currency,
+) -> GosimResult<CampaignBalance> {
+    let mut total = CampaignBalance {
+        currency,
+        ..Default::default()
+    };
+    for b in balances {
+        let convert = |amount| rates.convert(amount, b.currency, currency);
+        total.funded += convert(b.funded)?;
+        total.available += convert(b.available)?;
+        total.allocated += convert(b.allocated)?;
+        total.approved += convert(b.approved)?;
+        total.paid += convert(b.paid)?;
+    }
+    Ok(total)
+}
+
+// total_balance for reads that shouldn't fail on a missing rate: a balance whose
+// currency can't be converted is logged and left out of the totals.
+pub fn convertible_balance(
+    balances: &[CampaignBalance],
+    rates: &Rates,
+    currency: Currency,
+) -> CampaignBalance {
+    let convertible: Vec<CampaignBalance> = balances
+        .iter()
+        .filter(|b| match rates.convert(1, b.currency, currency) {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!(
+                    "Leaving the {} money of campaign {} out of the totals: {}",
+                    b.currency,
+                    b.campaign_id,
+                    e
+                );
+                false
+            }
+        })
+        .cloned()
+        .collect();
+
+    // every currency left has a rate, so nothing fails to convert
+    total_balance(&convertible, rates, currency).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::ExchangeRate;
+    use crate::db_audit::AuditInfo;
+    use crate::db_manipulate::conclude_issue_in_db;
+    use crate::test_db;
+
+    fn balance(campaign_id: &str, currency: Currency, funded: i64) -> CampaignBalance {
+        CampaignBalance {
+            campaign_id: campaign_id.to_string(),
+            currency,
+            funded,
+            available: funded,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unrated_balances_are_left_out_of_the_listed_totals() {
+        let rates = Rates::snapshot(
+            &[ExchangeRate {
+                rate_date: String::from("2023-10-01"),
+                currency: Currency::Eur,
+                rate: 2.0,
+            }],
+            None,
+        );
+        let balances = [
+            balance("usd", Currency::Usd, 100),
+            balance("eur", Currency::Eur, 10),
+            balance("cny", Currency::Cny, 1000),
+        ];
+
+        assert!(total_balance(&balances, &rates, Currency::Usd).is_err());
+        let listed = convertible_balance(&balances, &rates, Currency::Usd);
+        assert_eq!(listed.running_budget(), (120, 0, 120));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn concurrent_approvals_cannot_overspend() {
+        let pool = test_db::pool().await;
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        fund_campaign(&pool, &campaign_id, 100, Currency::Usd, "test", None)
+            .await
+            .unwrap();
+        let issue_ids = [format!("{}-1", campaign_id), format!("{}-2", campaign_id)];
+        for issue_id in &issue_ids {
+            test_db::insert_issue(&pool, &campaign_id, issue_id).await;
+        }
+
+        // both transactions read before either books, so neither snapshot has
+        // the other's allocation
+        let first = test_db::started_transaction(&pool).await;
+        let second = test_db::started_transaction(&pool).await;
+        let (first, second) = tokio::join!(
+            test_db::allocate(first, &issue_ids[0], 60),
+            test_db::allocate(second, &issue_ids[1], 60)
+        );
+
+        assert!(first.is_ok() != second.is_ok(), "{:?} {:?}", first, second);
+        let available = campaign_balances(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|b| b.campaign_id == campaign_id)
+            .unwrap()
+            .available;
+        assert_eq!(available, 40);
+    }
+
+    // Pays out `amount` of the issue's approved money in `tx` and commits it,
+    // booked as given rather than from what the issue holds.
+    async fn pay_out(mut tx: Transaction<'_>, issue_id: &str, amount: i64) -> GosimResult<()> {
+        let issue = lock_issue_campaign(&mut tx, issue_id).await?;
+        let txn = LedgerTxn {
+            txn_id: 0,
+            campaign_id: issue.campaign_id,
+            kind: TxnKind::Payout,
+            project_id: Some(issue.project_id),
+            issue_id: Some(issue_id.to_string()),
+            reverses_txn_id: None,
+            actor: String::from("test"),
+            memo: None,
+            created_at: String::new(),
+            currency: issue.currency,
+            postings: vec![
+                posting(Account::Approved, -amount),
+                posting(Account::Paid, amount),
+            ],
+        };
+        post_transaction(&mut tx, &txn).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn concurrent_payouts_cannot_pay_an_issue_twice() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        let issue_id = test_db::approved_issue(&pool, &campaign_id, 1, 100, &["sam"]).await;
+        let audit = AuditInfo::new(Some(String::from("test")), None);
+        conclude_issue_in_db(&pool, &issue_id, &audit)
+            .await
+            .unwrap();
+
+        // both snapshots still have the 100 approved, only the locking read of the
+        // second sees that the first paid it
+        let first = test_db::started_transaction(&pool).await;
+        let second = test_db::started_transaction(&pool).await;
+        let (first, second) = tokio::join!(
+            pay_out(first, &issue_id, 100),
+            pay_out(second, &issue_id, 100)
+        );
+
+        assert!(first.is_ok() != second.is_ok(), "{:?} {:?}", first, second);
+        let refused = first.and(second).unwrap_err();
+        assert!(
+            matches!(&refused, Error::Validation(m) if m.starts_with("overspend: approved")),
+            "{:?}",
+            refused
+        );
+        let mut conn = pool.get_conn().await.unwrap();
+        let balance = issue_balance(&mut conn, &issue_id).await.unwrap();
+        assert_eq!((balance.approved, balance.paid), (0, 100));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn an_unrated_currency_only_blocks_its_own_issues() {
+        let pool = test_db::pool().await;
+        let mut conn = pool.get_conn().await.unwrap();
+        // no other test loads CNY rates, this one needs there to be none
+        conn.query_drop("DELETE FROM exchange_rates WHERE currency = 'CNY'")
+            .await
+            .unwrap();
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        for currency in [Currency::Usd, Currency::Cny] {
+            fund_campaign(&pool, &campaign_id, 1000, currency, "test", None)
+                .await
+                .unwrap();
+        }
+        let issue_ids: Vec<String> = (1..=3).map(|n| format!("{}-{}", campaign_id, n)).collect();
+        for issue_id in &issue_ids {
+            test_db::insert_issue(&pool, &campaign_id, issue_id).await;
+        }
+
+        // CNY money in the project from before its rate went missing
+        test_db::unchecked_allocation(&pool, &issue_ids[0], 50, Currency::Cny).await;
+
+        // a USD issue of the same project needs no rate, and with no caps set the
+        // project's CNY money needs none either
+        let usd = test_db::started_transaction(&pool).await;
+        test_db::allocate(usd, &issue_ids[1], 60).await.unwrap();
+
+        conn.exec_drop(
+            "UPDATE issues_master SET issue_budget_currency = 'CNY' WHERE issue_id = :issue_id",
+            params! { "issue_id" => &issue_ids[2] },
+        )
+        .await
+        .unwrap();
+        let cny = test_db::started_transaction(&pool).await;
+        let refused = test_db::allocate(cny, &issue_ids[2], 60).await.unwrap_err();
+        assert!(matches!(refused, Error::Validation(_)), "{:?}", refused);
+    }
+}
@@ -0,0 +1,184 @@
+use mysql_async::prelude::*;
+use mysql_async::*;
+
+// Shared by the scheduled runners and the manual triggers in tester_hook.
+pub const PIPELINE_LOCK: &str = "pipeline";
+pub const PIPELINE_LEASE_SECS: u64 = 30 * 60;
+
+// Lease on a row in run_locks, held until released or until expires_at passes.
+pub struct RunLock<'a> {
+    pool: &'a Pool,
+    pub lock_name: String,
+    pub holder: String,
+    lease_secs: u64,
+}
+
+impl<'a> RunLock<'a> {
+    // Returns None when another holder has a lease that hasn't expired yet.
+    pub async fn acquire(
+        pool: &'a Pool,
+        lock_name: &str,
+        holder_kind: &str,
+        lease_secs: u64,
+    ) -> anyhow::Result<Option<RunLock<'a>>> {
+        let mut conn = pool.get_conn().await?;
+        let holder = format!("{}-{:08x}", holder_kind, rand::random::<u32>());
+
+        // expires_at has to be assigned last, the IF() checks read the old value
+        conn.exec_drop(
+            r"INSERT INTO run_locks (lock_name, holder, acquired_at, expires_at)
+              VALUES (:lock_name, :holder, NOW(), NOW() + INTERVAL :lease_secs SECOND)
+              ON DUPLICATE KEY UPDATE
+                  holder = IF(expires_at < NOW(), VALUES(holder), holder),
+                  acquired_at = IF(expires_at < NOW(), VALUES(acquired_at), acquired_at),
+                  expires_at = IF(expires_at < NOW(), VALUES(expires_at), expires_at)",
+            params! {
+                "lock_name" => lock_name,
+                "holder" => &holder,
+                "lease_secs" => lease_secs,
+            },
+        )
+        .await?;
+
+        let current: Option<String> = conn
+            .exec_first(
+                "SELECT holder FROM run_locks WHERE lock_name = :lock_name",
+                params! {
+                    "lock_name" => lock_name,
+                },
+            )
+            .await?;
+
+        if current.as_deref() != Some(holder.as_str()) {
+            log::warn!(
+                "Lock {} is held by {}, skipping",
+                lock_name,
+                current.unwrap_or_default()
+            );
+            return Ok(None);
+        }
+
+        log::info!("Lock {} acquired by {}", lock_name, holder);
+        Ok(Some(RunLock {
+            pool,
+            lock_name: lock_name.to_string(),
+            holder,
+            lease_secs,
+        }))
+    }
+
+    pub async fn renew(&self) -> anyhow::Result<()> {
+        let mut conn = self.pool.get_conn().await?;
+
+        conn.exec_drop(
+            r"UPDATE run_locks
+              SET expires_at = NOW() + INTERVAL :lease_secs SECOND
+              WHERE lock_name = :lock_name AND holder = :holder",
+            params! {
+                "lock_name" => &self.lock_name,
+                "holder" => &self.holder,
+                "lease_secs" => self.lease_secs,
+            },
+        )
+        .await?;
+
+        // affected_rows() is 0 when expires_at didn't change, e.g. a renew in the
+        // same second as the acquire, so ask who holds the lock instead
+        let current: Option<String> = conn
+            .exec_first(
+                "SELECT holder FROM run_locks WHERE lock_name = :lock_name",
+                params! {
+                    "lock_name" => &self.lock_name,
+                },
+            )
+            .await?;
+
+        if current.as_deref() != Some(self.holder.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Lock {} is no longer held by {}",
+                self.lock_name,
+                self.holder
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn release(self) {
+        let res = async {
+            let mut conn = self.pool.get_conn().await?;
+            conn.exec_drop(
+                "DELETE FROM run_locks WHERE lock_name = :lock_name AND holder = :holder",
+                params! {
+                    "lock_name" => &self.lock_name,
+                    "holder" => &self.holder,
+                },
+            )
+            .await?;
+            anyhow::Ok(())
+        }
+        .await;
+
+        match res {
+            Ok(()) => log::info!("Lock {} released by {}", self.lock_name, self.holder),
+            Err(e) => log::error!("Error releasing lock {}: {:?}", self.lock_name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_db;
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn renew_right_after_acquire() {
+        let pool = test_db::pool().await;
+        let lock_name = format!("test-renew-{:08x}", rand::random::<u32>());
+
+        let lock = RunLock::acquire(&pool, &lock_name, "test", 60)
+            .await
+            .unwrap()
+            .expect("lock is free");
+        lock.renew().await.unwrap();
+        lock.renew().await.unwrap();
+
+        assert!(RunLock::acquire(&pool, &lock_name, "other", 60)
+            .await
+            .unwrap()
+            .is_none());
+        lock.release().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn renew_fails_once_taken_over() {
+        let pool = test_db::pool().await;
+        let lock_name = format!("test-renew-{:08x}", rand::random::<u32>());
+
+        let lock = RunLock::acquire(&pool, &lock_name, "test", 60)
+            .await
+            .unwrap()
+            .expect("lock is free");
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.exec_drop(
+            "UPDATE run_locks SET holder = 'other' WHERE lock_name = :lock_name",
+            params! {
+                "lock_name" => &lock_name,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(lock.renew().await.is_err());
+        conn.exec_drop(
+            "DELETE FROM run_locks WHERE lock_name = :lock_name",
+            params! {
+                "lock_name" => &lock_name,
+            },
+        )
+        .await
+        .unwrap();
+    }
+}
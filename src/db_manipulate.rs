@@ -1,9 +1,22 @@
-use std::collections::HashMap;
-
+use crate::currency::{reporting_currency, Currency};
+use crate::db_approval::{
+    agreeing_votes, check_reviewer, discard_votes, issue_required_votes, issue_votes, record_vote,
+    ApprovalVote, BudgetVote,
+};
+use crate::db_audit::{
+    audited_update, audited_update_in, issue_state_for_update, record_action, AuditInfo,
+};
+use crate::db_caps::{budget_room, budget_rooms, project_headroom_column};
+use crate::db_ledger::{
+    campaign_balances, convertible_balance, lock_issue_campaign, total_balance, IssueLedgerOp,
+};
 use crate::db_populate::*;
+use crate::db_query::*;
+use crate::db_rates::{check_ledger_rates, rates_as_of};
+use crate::error::{Error, GosimResult};
 use crate::issue_tracker::IssueOpen;
-use crate::TOTAL_BUDGET;
-use anyhow::anyhow;
+use crate::review_state::{ReviewAction, ReviewState};
+use chrono::{Duration, Utc};
 use mysql_async::prelude::*;
 use mysql_async::Row;
 use mysql_async::*;
@@ -19,56 +32,97 @@ pub struct IssueSubset {
     pub main_language: String,
     pub repo_stars: i32,
     pub issue_budget: Option<i32>,
+    #[serde(default)]
+    pub issue_budget_currency: Currency,
     pub running_budget: (i32, i32, i32),
     pub issue_stats: (i32, i32, i32, i32),
     pub issue_status: Option<String>,
     pub review_status: String,
     #[serde(default = "default_value")]
     pub issue_budget_approved: bool,
+    #[serde(default)]
+    pub filtered_count: i32,
 }
 
 fn default_value() -> bool {
     false
 }
 
+// issue_id, project_id, project_logo, issue_title, main_language, repo_stars,
+// issue_budget, issue_budget_currency, issue_creator, issue_status, review_status,
+// issue_budget_approved
+type IssueSubsetRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    i32,
+    Option<i32>,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<bool>,
+);
+
+// project_id, project_logo, repo_stars, main_language, project_description,
+// issues_list, total_budget_allocated, budget_headroom
+type ProjectRow = (
+    String,
+    Option<String>,
+    i32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<i32>,
+    Option<i32>,
+);
+
 pub async fn batch_decline_issues_in_db(
     pool: &Pool,
     issue_ids: Vec<String>,
-) -> anyhow::Result<(), String> {
-    let mut conn = pool.get_conn().await.expect("Error getting connection");
-    let mut failed_ids = Vec::new();
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let mut missing_ids = Vec::new();
+    let mut rejected = Vec::new();
     for issue_id in issue_ids {
-        if let Err(e) = conn
-            .exec_drop(
-                r"UPDATE issues_master SET review_status = 'decline',
-                date_declined = NOW() 
-                 WHERE issue_id = :issue_id",
-                params! {
-                    "issue_id" => &issue_id
-                },
-            )
-            .await
-        {
-            failed_ids.push(issue_id);
-            log::error!("Error batch decline issues: {:?}", e);
+        let declined = audited_update(
+            pool,
+            &issue_id,
+            ReviewAction::Decline,
+            audit,
+            Some((DECLINE_QUERY, params! { "issue_id" => &issue_id })),
+            Some(IssueLedgerOp::Release),
+        )
+        .await;
+
+        match declined {
+            Ok(true) => {}
+            Ok(false) => missing_ids.push(issue_id),
+            Err(Error::Transition(msg)) => rejected.push(format!("{}: {}", issue_id, msg)),
+            Err(e) => {
+                log::error!("Error batch decline issues: {:?}", e);
+                return Err(e);
+            }
         }
     }
 
-    if failed_ids.is_empty() {
-        Ok(())
+    if !missing_ids.is_empty() {
+        Err(Error::NotFound(format!("Issues {}", missing_ids.join(","))))
+    } else if !rejected.is_empty() {
+        Err(Error::Transition(rejected.join("; ")))
     } else {
-        Err(failed_ids.join(","))
+        Ok(())
     }
 }
 
-pub async fn count_issues_by_status(pool: &Pool) -> anyhow::Result<(i32, i32, i32, i32)> {
+pub async fn count_issues_by_status(pool: &Pool) -> GosimResult<(i32, i32, i32, i32)> {
     let mut conn = pool.get_conn().await?;
-    let counts_query = format!(
-        "SELECT
+    let counts_query = "SELECT
             (SELECT COUNT(*) FROM issues_master) as total_count,
             (SELECT COUNT(*) FROM issues_master WHERE review_status = 'approve') as approve_count,
-            (SELECT COUNT(*) FROM issues_master WHERE review_status = 'decline') as decline_count"
-    );
+            (SELECT COUNT(*) FROM issues_master WHERE review_status = 'decline') as decline_count";
 
     let counts_rows: Vec<mysql_async::Row> = conn.query(counts_query).await?;
     let (total_count, approve_count, decline_count): (i32, i32, i32) = counts_rows
@@ -88,91 +142,58 @@ pub async fn count_issues_by_status(pool: &Pool) -> anyhow::Result<(i32, i32, i3
     Ok((total_count, queue_count, approve_count, decline_count))
 }
 
-pub async fn count_budget_by_status(pool: &Pool) -> anyhow::Result<(i32, i32, i32)> {
-    let mut conn = pool.get_conn().await?;
-    let counts_query =
-        "SELECT SUM(total_budget_allocated) as total_budget_allocated FROM projects;";
-
-    let total_budget_allocated: i32 = conn
-        .query_first(counts_query)
-        .await?
-        .unwrap_or(None) // Handle the case where no rows are returned
-        .unwrap_or(0);
+// (funded, allocated, available) over every campaign, from the budget ledger, in
+// the reporting currency at the latest exchange rates.
+pub async fn count_budget_by_status(pool: &Pool) -> GosimResult<(i32, i32, i32)> {
+    let balances = campaign_balances(pool).await?;
+    let rates = rates_as_of(&mut pool.get_conn().await?, None).await?;
 
-    let budget_balance = TOTAL_BUDGET - total_budget_allocated;
-
-    Ok((TOTAL_BUDGET, total_budget_allocated, budget_balance))
-}
-
-fn build_query_clause(filters: Vec<&str>) -> String {
-    let schema_array = [
-        ("repo_stars", "repo_stars DESC"),
-        ("issue_title", "issue_title ASC"),
-        ("main_language", "main_language ASC"),
-        ("issue_creator", "issue_creator ASC"),
-        ("issue_budget", "issue_budget DESC"),
-        ("issue_assignees", "issue_assignees ASC"),
-        ("date_issue_assigned", "date_issue_assigned ASC"),
-    ];
-
-    let special_conditions = [
-        ("main_language", "LENGTH(main_language) > 0"),
-        ("issue_assignees", "issue_assignees IS NOT NULL"),
-        ("queue", "review_status = 'queue'"),
-        ("approve", "review_status = 'approve'"),
-        ("decline", "review_status = 'decline'"),
-    ];
-
-    let schema_map: HashMap<&str, &str> = schema_array.into_iter().collect();
-    let condition_map: HashMap<&str, &str> = special_conditions.into_iter().collect();
-
-    let mut where_clause = String::new();
-    let mut order_bys = Vec::new();
-
-    for &filter in &filters {
-        if let Some(&condition) = condition_map.get(filter) {
-            where_clause = format!("WHERE {}", condition);
-        } else if let Some(&order_by) = schema_map.get(filter) {
-            order_bys.push(order_by);
-        }
-    }
+    Ok(total_balance(&balances, &rates, reporting_currency())?.running_budget())
+}
 
-    let order_by_clause = if order_bys.is_empty() {
-        String::new()
-    } else {
-        format!("ORDER BY {}", order_bys.join(", "))
-    };
+// count_budget_by_status for the issue listings. A read shouldn't fail on a
+// missing rate, so the money without one is left out of the totals.
+async fn listed_budget_by_status(pool: &Pool) -> GosimResult<(i32, i32, i32)> {
+    let balances = campaign_balances(pool).await?;
+    let rates = rates_as_of(&mut pool.get_conn().await?, None).await?;
 
-    format!("{} {}", where_clause, order_by_clause)
-        .trim()
-        .to_string()
+    Ok(convertible_balance(&balances, &rates, reporting_currency()).running_budget())
 }
 
 pub async fn list_issues_by_multi(
     pool: &Pool,
-    filters: Vec<&str>,
+    issue_query: &IssueQuery,
     page: usize,
     page_size: usize,
-) -> Result<Vec<IssueOut>> {
+) -> GosimResult<Vec<IssueOut>> {
     let mut conn = pool.get_conn().await?;
 
-    let (total_budget, total_budget_allocated, budget_balance) = count_budget_by_status(&pool)
-        .await
-        .expect("budget counting failure");
+    let (total_budget, total_budget_allocated, budget_balance) =
+        listed_budget_by_status(pool).await?;
 
-    let offset = (page - 1) * page_size;
-
-    let filter_str = build_query_clause(filters);
-
-    let query = format!(
-        "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master {} LIMIT {} OFFSET {}",
-        filter_str, page_size, offset
+    let select = SelectQuery::new(
+        "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_description, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved, review_state, issue_budget_currency FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)",
+    )
+    .issue_query(issue_query)?
+    .page(page, page_size);
+    let (count_query, count_params) = select.build_count(
+        "SELECT COUNT(*) FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)",
     );
+    let filtered_count: i32 = conn
+        .exec_first(count_query, count_params)
+        .await?
+        .unwrap_or(0);
+    let (query, params) = select.build();
 
-    let rows: Vec<mysql_async::Row> = conn.query(query).await?;
-    let (total_count, queue_count, approve_count, decline_count) = count_issues_by_status(&pool)
-        .await
-        .expect("failed to get issue stats");
+    let rows: Vec<mysql_async::Row> = conn.exec(query, params).await?;
+    let (total_count, queue_count, approve_count, decline_count) =
+        count_issues_by_status(pool).await?;
+
+    let issue_ids: Vec<String> = rows
+        .iter()
+        .map(|row| row.get("issue_id").unwrap_or_default())
+        .collect();
+    let rooms = budget_rooms(&mut conn, &issue_ids).await?;
 
     let mut issues = Vec::new();
     for row in rows {
@@ -199,9 +220,26 @@ pub async fn list_issues_by_multi(
                 .unwrap_or_default(),
             running_budget: (total_budget, total_budget_allocated, budget_balance),
             issue_stats: (total_count, queue_count, approve_count, decline_count),
+            filtered_count,
+            budget_headroom: None,
+            review_state: row
+                .get::<String, _>("review_state")
+                .unwrap_or_default()
+                .parse()?,
+            issue_budget_currency: Currency::from_db(
+                &row.get::<String, _>("issue_budget_currency")
+                    .unwrap_or_default(),
+            ),
         };
-
-        issues.push(issue);
+        let budget_headroom = rooms
+            .get(&issue.issue_id)
+            .and_then(|room| room.headroom().ok())
+            .map(|room| room as i32);
+
+        issues.push(IssueOut {
+            budget_headroom,
+            ..issue
+        });
     }
 
     Ok(issues)
@@ -212,34 +250,49 @@ pub async fn list_issues_by_single(
     list_by: Option<&str>,
     page: usize,
     page_size: usize,
-) -> Result<Vec<IssueSubset>> {
+) -> GosimResult<Vec<IssueSubset>> {
     let mut conn = pool.get_conn().await?;
-    let offset = (page - 1) * page_size;
 
-    let filter_str = match list_by {
-        None => String::new(),
-        Some(list_by) => {
-            let list_by_str = list_by.to_string();
-            build_query_clause(vec![&list_by_str])
-        }
-    };
+    let names: Vec<&str> = list_by.into_iter().collect();
+    let select = SelectQuery::new(
+        "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_budget_currency, issue_creator, issue_status, review_status, issue_budget_approved FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)",
+    )
+    .issue_query(&IssueQuery::from_names(&names)?)?
+    .page(page, page_size);
+    let (count_query, count_params) = select.build_count(
+        "SELECT COUNT(*) FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id)",
+    );
+    let filtered_count: i32 = conn
+        .exec_first(count_query, count_params)
+        .await?
+        .unwrap_or(0);
+    let (query, params) = select.build();
 
-    log::info!("filter_str: {:?}", filter_str);
+    log::info!("query: {:?}", query);
 
-    let (total_budget, total_budget_allocated, budget_balance) = count_budget_by_status(&pool)
-        .await
-        .expect("budget counting failure");
+    let (total_budget, total_budget_allocated, budget_balance) =
+        listed_budget_by_status(pool).await?;
 
-    let (total_count, queue_count, approve_count, decline_count) = count_issues_by_status(&pool)
-        .await
-        .expect("failed to get issue stats");
+    let (total_count, queue_count, approve_count, decline_count) =
+        count_issues_by_status(pool).await?;
     let issues: Vec<IssueSubset> = conn
-        .query_map(
-            format!(
-                "SELECT issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget,issue_creator, issue_status, review_status, issue_budget_approved FROM issues_master {} LIMIT {} OFFSET {}",
-                filter_str, page_size, offset
-            ),
-            |(issue_id, project_id, project_logo, issue_title, main_language, repo_stars, issue_budget, issue_creator, issue_status, review_status, issue_budget_approved): (String, String, String, String, String, i32, Option<i32>, String, Option<String>, Option<String>, Option<bool>)| {
+        .exec_map(
+            query,
+            params,
+            |(
+                issue_id,
+                project_id,
+                project_logo,
+                issue_title,
+                main_language,
+                repo_stars,
+                issue_budget,
+                issue_budget_currency,
+                issue_creator,
+                issue_status,
+                review_status,
+                issue_budget_approved,
+            ): IssueSubsetRow| {
                 IssueSubset {
                     issue_id,
                     project_id,
@@ -248,12 +301,14 @@ pub async fn list_issues_by_single(
                     main_language,
                     repo_stars,
                     issue_budget,
+                    issue_budget_currency: Currency::from_db(&issue_budget_currency),
                     issue_creator,
                     issue_status,
                     review_status: review_status.unwrap_or_default(),
                     issue_budget_approved: issue_budget_approved.unwrap_or_default(),
                     running_budget: (total_budget, total_budget_allocated, budget_balance),
                     issue_stats: (total_count, queue_count, approve_count, decline_count),
+                    filtered_count,
                 }
             },
         )
@@ -263,7 +318,7 @@ pub async fn list_issues_by_single(
 
 // "SELECT project_id FROM projects WHERE project_logo is NULL ORDER BY project_id LIMIT :limit OFFSET :offset",
 
-pub async fn get_projects_as_repo_list(pool: &Pool, page: u32) -> Result<String> {
+pub async fn get_projects_as_repo_list(pool: &Pool, page: u32) -> GosimResult<String> {
     let page_size = 30u32;
     let mut conn = pool.get_conn().await?;
     let offset = (page - 1) * page_size;
@@ -278,39 +333,38 @@ pub async fn get_projects_as_repo_list(pool: &Pool, page: u32) -> Result<String>
         )
         .await?;
 
+    Ok(repo_list_query(project_ids))
+}
+
+// GitHub search query for the given project urls, e.g. "repo:owner/name ... fork:true"
+pub fn repo_list_query(project_ids: Vec<String>) -> String {
     let res = project_ids
         .into_iter()
-        .map(|x| {
-            let owner_repo = x.replace("https://github.com/", "repo:");
-
-            owner_repo
-        })
+        .map(|x| x.replace("https://github.com/", "repo:"))
         .collect::<Vec<String>>();
 
     let mut out = res.join(" ");
     out.push_str(" fork:true");
-    Ok(out)
+    out
 }
 
-pub async fn get_issues_open_from_master(
-    pool: &Pool,
-    page: u32,
-) -> Result<Vec<IssueOpen>> {
+pub async fn get_issues_open_from_master(pool: &Pool, page: u32) -> GosimResult<Vec<IssueOpen>> {
     let page_size = 30u32;
     let offset = (page - 1) * page_size;
     let mut conn = pool.get_conn().await?;
 
-    let query = format!(
-        "SELECT issue_title, issue_id, issue_creator, issue_description, project_id FROM issues_master 
+    let query = r"SELECT issue_title, issue_id, issue_creator, issue_description, project_id FROM issues_master 
         WHERE issue_id NOT IN (SELECT issue_or_project_id FROM issues_repos_summarized WHERE issue_or_project_summary IS NOT NULL) 
         ORDER BY issue_id ASC
-        LIMIT {} OFFSET {}",
-        page_size, offset
-    );
+        LIMIT :limit OFFSET :offset";
 
     let out: Vec<IssueOpen> = conn
-        .query_map(
+        .exec_map(
             query,
+            params! {
+                "limit" => page_size,
+                "offset" => offset,
+            },
             |(issue_title, issue_id, issue_creator, issue_description, project_id): (
                 String,
                 String,
@@ -324,6 +378,7 @@ pub async fn get_issues_open_from_master(
                 issue_budget: 0,
                 issue_description,
                 project_id,
+                ..Default::default()
             },
         )
         .await?;
@@ -333,66 +388,34 @@ pub async fn get_issues_open_from_master(
 
 pub async fn list_projects_by(
     pool: &Pool,
-    list_by: Option<&str>,
+    project_query: &ProjectQuery,
     page: usize,
     page_size: usize,
-) -> Result<Vec<ProjectOut>> {
+) -> GosimResult<Vec<ProjectOut>> {
     let mut conn = pool.get_conn().await?;
-    let offset = (page - 1) * page_size;
-
-    let schema_array = [
-        ("issues_count", "ORDER BY JSON_LENGTH(fp.issues_list) DESC"),
-        (
-            "total_budget_allocated",
-            "ORDER BY fp.total_budget_allocated DESC",
-        ),
-        ("repo_stars", "ORDER BY fp.repo_stars DESC"),
-        (
-            "main_language",
-            "WHERE LENGTH(fp.main_language) > 0 ORDER BY fp.main_language ASC",
-        ),
-    ];
-
-    let schema_map: HashMap<&str, &str> = schema_array.into_iter().collect();
+    // a read shouldn't fail on a missing rate, but the headroom leaves that money out
+    if let Err(e) = check_ledger_rates(&mut conn, None, reporting_currency()).await {
+        log::warn!("Project budget_headroom is incomplete: {}", e);
+    }
 
-    let filter_str = match list_by {
-        None => &"",
-        Some(list_by) => schema_map.get(list_by).unwrap_or(&""),
-    };
+    let base_query = format!(
+        "SELECT project_id, project_logo, repo_stars, main_language, project_description, issues_list, total_budget_allocated, {} FROM projects",
+        project_headroom_column()
+    );
+    let select = SelectQuery::new(&base_query)
+        .project_query(project_query)?
+        .page(page, page_size);
+    let (count_query, count_params) = select.build_count("SELECT COUNT(*) FROM projects");
+    let total_count: i32 = conn
+        .exec_first(count_query, count_params)
+        .await?
+        .unwrap_or(0);
+    let (query, params) = select.build();
 
     let projects: Vec<ProjectOut> = conn
-        .query_map(
-            format!(
-                "WITH FilteredProjects AS (
-                SELECT 
-                    project_id, 
-                    project_logo, 
-                    repo_stars, 
-                    main_language, 
-                    project_description, 
-                    issues_list,   
-                    total_budget_allocated
-                FROM 
-                    projects
-            ),
-            TotalCount AS (
-                SELECT COUNT(*) AS total_count FROM FilteredProjects
-            )
-            SELECT 
-                fp.project_id, 
-                fp.project_logo, 
-                fp.repo_stars, 
-                fp.main_language, 
-                fp.project_description, 
-                fp.issues_list,   
-                fp.total_budget_allocated,
-                tc.total_count
-            FROM 
-                FilteredProjects fp, TotalCount tc
-                            {}
-            LIMIT {} OFFSET {}",
-                filter_str, page_size, offset
-            ),
+        .exec_map(
+            query,
+            params,
             |(
                 project_id,
                 project_logo,
@@ -401,17 +424,8 @@ pub async fn list_projects_by(
                 project_description,
                 issues_list,
                 total_budget_allocated,
-                total_count,
-            ): (
-                String,
-                Option<String>,
-                i32,
-                Option<String>,
-                Option<String>,
-                Option<String>,
-                Option<i32>,
-                i32,
-            )| {
+                budget_headroom,
+            ): ProjectRow| {
                 ProjectOut {
                     project_id,
                     project_logo,
@@ -422,6 +436,7 @@ pub async fn list_projects_by(
                         .map_or(Some(Vec::new()), |s| serde_json::from_str(&s).ok()),
                     total_budget_allocated,
                     total_count,
+                    budget_headroom,
                 }
             },
         )
@@ -440,37 +455,41 @@ pub struct IssueAndComments {
     pub issue_creator: String,
     pub issue_description: String,
     pub issue_budget: Option<i32>,
+    #[serde(default)]
+    pub issue_budget_currency: Currency,
     pub issue_assignees: Option<String>, // or a more specific type if you know the structure of the JSON
     pub issue_linked_pr: Option<String>,
     pub issue_status: Option<String>,
     pub review_status: String,
     pub issue_budget_approved: bool,
+    #[serde(default)]
+    pub review_state: ReviewState,
+    // budget votes waiting for quorum
+    #[serde(default)]
+    pub pending_votes: Vec<ApprovalVote>,
     pub issue_comments: Option<Vec<(String, String)>>,
 }
 
 pub async fn get_issue_w_comments_by_id(
     pool: &Pool,
     issue_id: &str,
-) -> anyhow::Result<IssueAndComments> {
+) -> GosimResult<IssueAndComments> {
     let mut conn = pool.get_conn().await?;
 
-    let issue_query = format!(
-        "SELECT issue_id, project_id, main_language, repo_stars, issue_title, issue_creator, issue_description, issue_budget, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved FROM issues_master WHERE issue_id = '{}'",
-        issue_id
-    );
+    let issue_query = r"SELECT issue_id, project_id, main_language, repo_stars, issue_title, issue_creator, issue_description, issue_budget, issue_assignees, issue_linked_pr, issue_status, review_status, issue_budget_approved, review_state, issue_budget_currency FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id) WHERE issue_id = :issue_id";
 
-    let comments_query = format!(
-        "SELECT comment_creator, comment_body FROM issues_comment WHERE issue_id = '{}' ORDER BY comment_date",
-        issue_id
-    );
+    let comments_query = r"SELECT comment_creator, comment_body FROM issues_comment WHERE issue_id = :issue_id ORDER BY comment_date";
 
     // Fetch the issue
-    let issue_rows: Vec<mysql_async::Row> = conn.query(issue_query).await?;
-    let issue_row = issue_rows
-        .first()
-        .ok_or_else(|| anyhow!("No issue found with the provided issue_id: {}", issue_id))
-        .map_err(|_e| anyhow::anyhow!("discard mysql error: {_e}"))?;
-    // .map_err(|e| mysql_async::Error::DriverError(mysql_async::DriverError::Other(format!("Failed to fetch issue: {}", e))))?;
+    let issue_rows: Vec<mysql_async::Row> = conn
+        .exec(issue_query, params! { "issue_id" => issue_id })
+        .await?;
+    let issue_row = issue_rows.first().ok_or_else(|| {
+        Error::NotFound(format!(
+            "No issue found with the provided issue_id: {}",
+            issue_id
+        ))
+    })?;
 
     let issue = IssueOut {
         issue_id: issue_row.get("issue_id").unwrap_or_default(),
@@ -499,10 +518,24 @@ pub async fn get_issue_w_comments_by_id(
             .unwrap_or_default(),
         running_budget: (99999, 99999, 99999),
         issue_stats: (99999, 99999, 99999, 99999),
+        filtered_count: 1,
+        budget_headroom: None,
+        review_state: issue_row
+            .get::<String, _>("review_state")
+            .unwrap_or_default()
+            .parse()?,
+        issue_budget_currency: Currency::from_db(
+            &issue_row
+                .get::<String, _>("issue_budget_currency")
+                .unwrap_or_default(),
+        ),
     };
+    let pending_votes = issue_votes(&mut conn, issue_id).await?;
 
     // Fetch the comments
-    let comments_rows: Vec<mysql_async::Row> = conn.query(comments_query).await?;
+    let comments_rows: Vec<mysql_async::Row> = conn
+        .exec(comments_query, params! { "issue_id" => issue_id })
+        .await?;
     let comments: Vec<(String, String)> = comments_rows
         .into_iter()
         .map(|row| {
@@ -522,11 +555,14 @@ pub async fn get_issue_w_comments_by_id(
         issue_creator: issue.issue_creator,
         issue_description: issue.issue_description,
         issue_budget: issue.issue_budget,
+        issue_budget_currency: issue.issue_budget_currency,
         issue_assignees: issue.issue_assignees,
         issue_linked_pr: issue.issue_linked_pr,
         issue_status: issue.issue_status,
         review_status: issue.review_status,
         issue_budget_approved: issue.issue_budget_approved,
+        review_state: issue.review_state,
+        pending_votes,
         issue_comments: if comments.is_empty() {
             None
         } else {
@@ -538,7 +574,7 @@ pub async fn get_issue_w_comments_by_id(
 pub async fn get_comments_by_issue_id(
     pool: &Pool,
     issue_id: &str,
-) -> anyhow::Result<Vec<(String, String)>> {
+) -> GosimResult<Vec<(String, String)>> {
     let mut conn = pool.get_conn().await?;
 
     let query_comments = r"SELECT comment_creator, comment_body FROM issues_comment WHERE issue_id = :issue_id ORDER BY comment_date";
@@ -559,43 +595,79 @@ pub async fn get_comments_by_issue_id(
                     .map(|(creator, body): (String, String)| (creator, body))
                     .collect::<Vec<(String, String)>>())
             } else {
-                Err(anyhow::anyhow!("Error no comments found by issue_id"))
+                Err(Error::NotFound(format!("Comments for issue {}", issue_id)))
             }
         }
         Err(e) => {
             log::error!("Error getting comments by issue_id: {:?}", e);
-            Err(anyhow::anyhow!(
-                "Error getting comments found by issue_id: {:?}",
-                e
-            ))
+            Err(e.into())
         }
     }
 }
-pub async fn get_issue_ids_with_budget(pool: &Pool) -> Result<Vec<String>> {
+// The comments note_issues posts, each is sent at most once per issue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteKind {
+    BudgetAllocated,
+    Declined,
+    DistributeFund,
+    NoPr,
+}
+
+impl NoteKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoteKind::BudgetAllocated => "budget_allocated",
+            NoteKind::Declined => "declined",
+            NoteKind::DistributeFund => "distribute_fund",
+            NoteKind::NoPr => "no_pr",
+        }
+    }
+}
+
+const NOT_SENT: &str =
+    "NOT EXISTS (SELECT 1 FROM sent_notes sn WHERE sn.issue_id = im.issue_id AND sn.kind = :kind)";
+
+pub async fn record_note_sent(pool: &Pool, issue_id: &str, kind: NoteKind) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
-    // let one_hour_ago = (Utc::now() - Duration::try_hours(1).unwrap())
-    //     .naive_utc()
-    //     .format("%Y-%m-%d %H:%M:%S")
-    //     .to_string();
-    let one_hour_ago = "2023-10-06 13:04:00".to_string();
+    conn.exec_drop(
+        "INSERT IGNORE INTO sent_notes (issue_id, kind, sent_at) VALUES (:issue_id, :kind, NOW())",
+        params! {
+            "issue_id" => issue_id,
+            "kind" => kind.as_str(),
+        },
+    )
+    .await?;
+    Ok(())
+}
 
-    let selected_rows: Vec<String> = conn
+pub async fn get_issue_ids_with_budget(pool: &Pool) -> GosimResult<Vec<(String, i32, Currency)>> {
+    let mut conn = pool.get_conn().await?;
+    let one_hour_ago = (Utc::now() - Duration::hours(1))
+        .naive_utc()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+
+    let selected_rows: Vec<(String, i32, Currency)> = conn
         .exec_map(
-            "SELECT issue_id FROM issues_master WHERE issue_budget > 0 AND review_status='approve' AND date_issue_assigned > :one_hour_ago",
+            format!("SELECT issue_id, issue_budget, issue_budget_currency FROM issues_master im WHERE issue_budget > 0 AND review_status='approve' AND date_issue_assigned > :one_hour_ago AND {}", NOT_SENT),
                         params! {
-                "one_hour_ago" => &one_hour_ago
+                "one_hour_ago" => &one_hour_ago,
+                "kind" => NoteKind::BudgetAllocated.as_str(),
+            },
+            |(issue_id, issue_budget, currency): (String, i32, String)| {
+                (issue_id, issue_budget, Currency::from_db(&currency))
             },
-            |issue_id| issue_id,
         )
         .await?;
     Ok(selected_rows)
 }
 
-pub async fn get_issue_ids_declined(pool: &Pool) -> Result<Vec<String>> {
+pub async fn get_issue_ids_declined(pool: &Pool) -> GosimResult<Vec<String>> {
     let mut conn = pool.get_conn().await?;
     let selected_rows: Vec<String> = conn
-        .query_map(
-            "select issue_id from issues_master where review_status='decline' limit 5;",
+        .exec_map(
+            format!("select issue_id from issues_master im where review_status='decline' AND {} limit 5;", NOT_SENT),
+            params! { "kind" => NoteKind::Declined.as_str() },
             |issue_id| issue_id,
         )
         .await?;
@@ -604,181 +676,319 @@ pub async fn get_issue_ids_declined(pool: &Pool) -> Result<Vec<String>> {
 
 pub async fn get_issue_ids_distribute_fund(
     pool: &Pool,
-) -> Result<Vec<(Option<String>, String, i32)>> {
+) -> GosimResult<Vec<(Option<String>, String, i32, Currency)>> {
     let mut conn = pool.get_conn().await?;
-    let selected_rows: Vec<(Option<String>, String, i32)> = conn
-        .query_map(
-            "SELECT issue_assignees, issue_id, issue_budget FROM issues_master WHERE issue_budget_approved=1 LIMIT 5",
-            |(issue_assignees, issue_id, issue_budget): (Option<String>, Option<String>, Option<i32>)| {
-                let issue_assignee = issue_assignees.and_then(|s| s.split(",").next().map(String::from));
-                (issue_assignee, issue_id.unwrap_or_default(), issue_budget.unwrap_or(0))
+    let selected_rows: Vec<(Option<String>, String, i32, Currency)> = conn
+        .exec_map(
+            // the earliest assignee, the one who was assigned before closing if any
+            format!(
+                r"SELECT (
+                SELECT ia.login FROM issue_assignees ia
+                WHERE ia.issue_id = im.issue_id
+                ORDER BY ia.assigned_at IS NULL, ia.assigned_at, ia.login
+                LIMIT 1
+              ) AS issue_assignee, im.issue_id, im.issue_budget, im.issue_budget_currency
+              FROM issues_master im WHERE im.issue_budget_approved=1 AND {} LIMIT 5",
+                NOT_SENT
+            ),
+            params! { "kind" => NoteKind::DistributeFund.as_str() },
+            |(issue_assignee, issue_id, issue_budget, currency): (
+                Option<String>,
+                Option<String>,
+                Option<i32>,
+                String,
+            )| {
+                (
+                    issue_assignee,
+                    issue_id.unwrap_or_default(),
+                    issue_budget.unwrap_or(0),
+                    Currency::from_db(&currency),
+                )
             },
         )
         .await?;
     Ok(selected_rows)
 }
-pub async fn get_issue_ids_one_month_no_activity(pool: &Pool) -> Result<Vec<String>> {
+pub async fn get_issue_ids_one_month_no_activity(pool: &Pool) -> GosimResult<Vec<String>> {
     let mut conn = pool.get_conn().await?;
-    // let _one_month_ago =
-    //     (Utc::now() - Duration::try_days(30).unwrap().to_std().unwrap()).naive_utc();
-    // let _formatted_one_month_ago = _one_month_ago.format("%Y-%m-%d %H:%M:%S").to_string();
-    let formatted_one_month_ago = "2023-10-06 13:04:00".to_string();
+    let formatted_one_month_ago = (Utc::now() - Duration::days(30))
+        .naive_utc()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
 
     let selected_rows: Vec<String> = conn.exec_map(
-        "SELECT issue_id FROM issues_master WHERE date_issue_assigned < :formatted_one_month_ago AND issue_linked_pr IS NULL",
+        format!("SELECT issue_id FROM issues_master im WHERE date_issue_assigned < :one_month_ago AND issue_linked_pr IS NULL AND {}", NOT_SENT),
         params! {
             "one_month_ago" => formatted_one_month_ago,
+            "kind" => NoteKind::NoPr.as_str(),
         },
         |issue_id| issue_id,
     ).await?;
     Ok(selected_rows)
 }
 
-pub async fn assign_issue_budget_in_db(
+// Records the reviewer's vote for a budget, and approves the issue with it once as
+// many reviewers agree on it as its tier needs. A vote the review state or the
+// caps would reject is refused right away.
+pub async fn vote_issue_budget_in_db(
     pool: &mysql_async::Pool,
     issue_id: &str,
     issue_budget: i64,
-) -> anyhow::Result<(), String> {
-    let mut conn = pool.get_conn().await.expect("failed to get mysql pool");
-    let exists_query = r"SELECT 1 FROM issues_master WHERE issue_id = :issue_id";
-    let exists: Option<u8> = conn
-        .exec_first(
-            exists_query,
-            params! {
-                "issue_id" => issue_id,
-            },
+    audit: &AuditInfo,
+) -> GosimResult<BudgetVote> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    let state = match issue_state_for_update(&mut tx, issue_id).await? {
+        Some(state) => state,
+        None => {
+            tx.rollback().await?;
+            return Err(Error::NotFound(format!(
+                "Issue with ID {} doesn't exist",
+                issue_id
+            )));
+        }
+    };
+    state.review_state.next(ReviewAction::Approve)?;
+    // before the cap and quorum reads, so a concurrent approval in the campaign
+    // has committed or waits for this one
+    lock_issue_campaign(&mut tx, issue_id).await?;
+    budget_room(&mut tx, issue_id).await?.check(issue_budget)?;
+    let required_votes = issue_required_votes(&mut tx, issue_id, issue_budget).await?;
+    check_reviewer(audit, required_votes)?;
+
+    record_vote(&mut tx, issue_id, issue_budget, audit).await?;
+    let votes = agreeing_votes(&issue_votes(&mut tx, issue_id).await?, issue_budget);
+    let approved = votes >= required_votes;
+    if approved {
+        let update_query = r"UPDATE issues_master 
+                     SET issue_budget = :issue_budget, 
+                         date_approved = NOW() 
+                     WHERE issue_id = :issue_id";
+
+        audited_update_in(
+            &mut tx,
+            issue_id,
+            ReviewAction::Approve,
+            audit,
+            Some((
+                update_query,
+                params! {
+                    "issue_id" => issue_id,
+                    "issue_budget" => issue_budget,
+                },
+            )),
+            Some(IssueLedgerOp::Allocate(issue_budget)),
         )
         .await
-        .map_err(|e| e.to_string())?;
-
-    if exists.is_none() {
-        return Err(format!("Issue with ID {} doesn't exist", issue_id));
+        .map_err(|e| {
+            log::error!("Error assigning issue budget: {:?}", e);
+            e
+        })?;
+        discard_votes(&mut tx, issue_id).await?;
+    } else {
+        record_action(&mut tx, issue_id, "vote_budget", &state, &state, audit).await?;
     }
+    tx.commit().await?;
+
+    Ok(BudgetVote {
+        issue_id: issue_id.to_string(),
+        issue_budget,
+        votes,
+        required_votes,
+        approved,
+    })
+}
 
-    let update_query = r"UPDATE issues_master 
-                     SET issue_budget = :issue_budget, 
-                         review_status = 'approve',
-                         date_approved = NOW() 
-                     WHERE issue_id = :issue_id";
+const DECLINE_QUERY: &str = r"UPDATE issues_master 
+                  SET issue_budget = null, 
+                      date_declined = NOW() 
+                  WHERE issue_id = :issue_id";
 
-    conn.exec_drop(
-        update_query,
-        params! {
-            "issue_id" => issue_id,
-            "issue_budget" => issue_budget,
-        },
+const CONCLUDE_QUERY: &str = r"UPDATE issues_master 
+                  SET date_budget_approved = NOW()
+                  WHERE issue_id = :issue_id";
+
+pub async fn decline_issue_in_db(
+    pool: &mysql_async::Pool,
+    issue_id: &str,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let found = audited_update(
+        pool,
+        issue_id,
+        ReviewAction::Decline,
+        audit,
+        Some((DECLINE_QUERY, params! { "issue_id" => issue_id })),
+        Some(IssueLedgerOp::Release),
     )
     .await
     .map_err(|e| {
-        log::error!("Error assigning issue budget: {:?}", e);
-        format!("Error assigning issue budget: {:?}", e)
+        log::error!("Error decline issue: {:?}", e);
+        e
     })?;
 
+    if !found {
+        return Err(Error::NotFound(format!(
+            "Issue with ID {} doesn't exist",
+            issue_id
+        )));
+    }
     Ok(())
 }
 
-pub async fn decline_issue_in_db(pool: &mysql_async::Pool, issue_id: &str) -> Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"UPDATE issues_master 
-                  SET issue_budget = null, 
-                      review_status = 'decline'
-                  WHERE issue_id = :issue_id";
-
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_id" => issue_id,
-            },
-        )
-        .await
-    {
-        log::error!("Error decline issue: {:?}", e);
-    };
-
-    Ok(())
+// For the batch updates that aren't audited by an admin. An unknown issue_id is
+// skipped.
+fn system_audit() -> AuditInfo {
+    AuditInfo::new(Some(String::from("system")), None)
 }
 
 pub async fn decline_issues_batch_in_db(
     pool: &mysql_async::Pool,
     issue_ids: Vec<&str>,
-) -> Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"UPDATE issues_master 
-                  SET issue_budget = null, 
-                      review_status = 'decline',
-                      date_declined = NOW() 
-                  WHERE issue_id = :issue_id";
-
+) -> GosimResult<()> {
+    let audit = system_audit();
     for issue_id in issue_ids {
-        if let Err(e) = conn
-            .exec_drop(
-                query,
-                params! {
-                    "issue_id" => issue_id,
-                },
-            )
-            .await
-        {
+        let found = audited_update(
+            pool,
+            issue_id,
+            ReviewAction::Decline,
+            &audit,
+            Some((DECLINE_QUERY, params! { "issue_id" => issue_id })),
+            Some(IssueLedgerOp::Release),
+        )
+        .await
+        .map_err(|e| {
             log::error!("Error batch decline issues: {:?}", e);
-        };
+            e
+        })?;
+        if !found {
+            log::warn!("Skipping unknown issue {}", issue_id);
+        }
     }
 
     Ok(())
 }
 
-pub async fn conclude_issue_in_db(pool: &mysql_async::Pool, issue_id: &str) -> Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"UPDATE issues_master 
-                  SET issue_budget_approved = True
-                  WHERE issue_id = :issue_id";
-
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_id" => issue_id,
-            },
-        )
-        .await
-    {
+pub async fn conclude_issue_in_db(
+    pool: &mysql_async::Pool,
+    issue_id: &str,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let found = audited_update(
+        pool,
+        issue_id,
+        ReviewAction::Conclude,
+        audit,
+        Some((CONCLUDE_QUERY, params! { "issue_id" => issue_id })),
+        Some(IssueLedgerOp::Approve),
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error concluding issue: {:?}", e);
-    };
+        e
+    })?;
 
+    if !found {
+        return Err(Error::NotFound(format!(
+            "Issue with ID {} doesn't exist",
+            issue_id
+        )));
+    }
     Ok(())
 }
 
 pub async fn conclude_issues_batch_in_db(
     pool: &mysql_async::Pool,
     issue_ids: Vec<&str>,
-) -> Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let query = r"UPDATE issues_master 
-                  SET issue_budget_approved = True
-                  WHERE issue_id = :issue_id";
-
+) -> GosimResult<()> {
+    let audit = system_audit();
     for issue_id in issue_ids {
-        if let Err(e) = conn
-            .exec_drop(
-                query,
-                params! {
-                    "issue_id" => issue_id,
-                },
-            )
-            .await
-        {
+        let found = audited_update(
+            pool,
+            issue_id,
+            ReviewAction::Conclude,
+            &audit,
+            Some((CONCLUDE_QUERY, params! { "issue_id" => issue_id })),
+            Some(IssueLedgerOp::Approve),
+        )
+        .await
+        .map_err(|e| {
             log::error!("Error concluding issues batch: {:?}", e);
-        };
+            e
+        })?;
+        if !found {
+            log::warn!("Skipping unknown issue {}", issue_id);
+        }
     }
 
     Ok(())
 }
 
-// pub async fn search_by_keyword_tags(tags_to_search: Vec<String>) -> anyhow::Result<Vec<String>> {
+// What the actions without a writer of their own do to the budget.
+fn review_ledger_op(action: ReviewAction) -> Option<IssueLedgerOp> {
+    match action {
+        ReviewAction::Decline | ReviewAction::Withdraw | ReviewAction::Expire => {
+            Some(IssueLedgerOp::Release)
+        }
+        ReviewAction::Conclude => Some(IssueLedgerOp::Approve),
+        _ => None,
+    }
+}
+
+// Takes an admin's review action on the issue. Approvals need a budget and go
+// through vote_issue_budget_in_db, payments through db_payout, and changes to an
+// approved budget through db_adjust.
+pub async fn review_issue_in_db(
+    pool: &mysql_async::Pool,
+    issue_id: &str,
+    action: ReviewAction,
+    audit: &AuditInfo,
+) -> GosimResult<()> {
+    let update = match action {
+        ReviewAction::Approve => {
+            return Err(Error::Validation(String::from(
+                "approving an issue needs a budget",
+            )))
+        }
+        ReviewAction::Pay => {
+            return Err(Error::Validation(String::from(
+                "issues are paid through payout batches",
+            )))
+        }
+        ReviewAction::Adjust | ReviewAction::Clawback => {
+            return Err(Error::Validation(String::from(
+                "approved budgets are changed through db_adjust",
+            )))
+        }
+        ReviewAction::Decline => Some(DECLINE_QUERY),
+        ReviewAction::Conclude => Some(CONCLUDE_QUERY),
+        _ => None,
+    };
+
+    let found = audited_update(
+        pool,
+        issue_id,
+        action,
+        audit,
+        update.map(|query| (query, params! { "issue_id" => issue_id })),
+        review_ledger_op(action),
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Error reviewing issue: {:?}", e);
+        e
+    })?;
+
+    if !found {
+        return Err(Error::NotFound(format!(
+            "Issue with ID {} doesn't exist",
+            issue_id
+        )));
+    }
+    Ok(())
+}
+
+// pub async fn search_by_keyword_tags(tags_to_search: Vec<String>) -> GosimResult<Vec<String>> {
 //     let mut conn = pool.get_conn().await?;
 
 //     let query = r"select issue_or_project_id, keyword_tags from issues_repos_summarized
@@ -801,7 +1011,7 @@ pub async fn conclude_issues_batch_in_db(
 //     Ok(())
 // }
 
-// pub async fn search_by_keyword_tags(pool: Pool, tags_to_search: Vec<String>) -> Result<Vec<String>> {
+// pub async fn search_by_keyword_tags(pool: Pool, tags_to_search: Vec<String>) -> GosimResult<Vec<String>> {
 //     let mut conn = pool.get_conn().await?;
 //     let mut results = Vec::new();
 //     let mut unique_ids = std::collections::HashSet::new();
@@ -829,7 +1039,7 @@ pub async fn conclude_issues_batch_in_db(
 pub async fn search_by_keyword_tags(
     pool: Pool,
     tags_to_search: Vec<String>,
-) -> Result<Vec<String>> {
+) -> GosimResult<Vec<String>> {
     let mut conn = pool.get_conn().await?;
     let mut results = Vec::new();
     let mut unique_ids = std::collections::HashSet::new();
@@ -848,7 +1058,9 @@ pub async fn search_by_keyword_tags(
         .await?;
 
     for row in rows {
-        let issue_id: String = row.get("issue_or_project_id").unwrap();
+        let Some(issue_id) = row.get::<String, _>("issue_or_project_id") else {
+            continue;
+        };
         if unique_ids.insert(issue_id.clone()) {
             results.push(issue_id);
         }
@@ -856,3 +1068,158 @@ pub async fn search_by_keyword_tags(
 
     Ok(results)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_approval::set_tier;
+    use crate::db_audit::list_issue_history;
+    use crate::db_ledger::{fund_campaign, issue_balance};
+    use crate::test_db;
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn issues_are_listed_while_a_rate_is_missing() {
+        let pool = test_db::pool().await;
+        let mut conn = pool.get_conn().await.unwrap();
+        // no other test loads CNY rates, this one needs there to be none
+        conn.query_drop("DELETE FROM exchange_rates WHERE currency = 'CNY'")
+            .await
+            .unwrap();
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        fund_campaign(&pool, &campaign_id, 1000, Currency::Cny, "test", None)
+            .await
+            .unwrap();
+        let issue_id = format!("{}-1", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &issue_id).await;
+
+        // the totals can't be converted as a whole
+        let refused = count_budget_by_status(&pool).await.unwrap_err();
+        assert!(matches!(refused, Error::Validation(_)), "{:?}", refused);
+
+        let query = IssueQuery {
+            filter: Some(IssueFilter::Project(format!(
+                "https://github.com/{}/test",
+                campaign_id
+            ))),
+            ..Default::default()
+        };
+        let issues = list_issues_by_multi(&pool, &query, 1, 10).await.unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].issue_id, issue_id);
+        let subsets = list_issues_by_single(&pool, None, 1, 10).await.unwrap();
+        assert!(!subsets.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_budget_is_approved_once_its_quorum_agrees() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        set_tier(&pool, &campaign_id, 100, Some(2)).await.unwrap();
+        let issue_id = format!("{}-1", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &issue_id).await;
+        let reviewer = |login: &str| AuditInfo::new(Some(login.to_string()), None);
+
+        let first = vote_issue_budget_in_db(&pool, &issue_id, 200, &reviewer("Rita"))
+            .await
+            .unwrap();
+        assert_eq!((first.votes, first.required_votes), (1, 2));
+        assert!(!first.approved);
+
+        // the same reviewer under another spelling, and a vote naming nobody
+        let again = vote_issue_budget_in_db(&pool, &issue_id, 200, &reviewer(" RITA "))
+            .await
+            .unwrap_err();
+        assert!(matches!(again, Error::Duplicate(_)), "{:?}", again);
+        let anonymous = vote_issue_budget_in_db(&pool, &issue_id, 200, &AuditInfo::new(None, None))
+            .await
+            .unwrap_err();
+        assert!(matches!(anonymous, Error::Validation(_)), "{:?}", anonymous);
+
+        // a vote for another budget doesn't agree with Rita's
+        let other = vote_issue_budget_in_db(&pool, &issue_id, 150, &reviewer("sam"))
+            .await
+            .unwrap();
+        assert_eq!((other.votes, other.approved), (1, false));
+        assert_eq!(
+            test_db::issue_review(&pool, &issue_id).await,
+            (String::from("queued"), None)
+        );
+
+        let agreed = vote_issue_budget_in_db(&pool, &issue_id, 200, &reviewer("sam"))
+            .await
+            .unwrap();
+        assert_eq!((agreed.votes, agreed.approved), (2, true));
+        assert_eq!(
+            test_db::issue_review(&pool, &issue_id).await,
+            (String::from("approved"), Some(200))
+        );
+
+        let mut conn = pool.get_conn().await.unwrap();
+        assert_eq!(
+            issue_balance(&mut conn, &issue_id).await.unwrap().allocated,
+            200
+        );
+        assert!(issue_votes(&mut conn, &issue_id).await.unwrap().is_empty());
+        let actions: Vec<String> = list_issue_history(&pool, &issue_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|action| action.action)
+            .collect();
+        assert_eq!(actions, ["vote_budget", "vote_budget", "approve_budget"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_budget_below_every_tier_needs_one_vote() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        set_tier(&pool, &campaign_id, 100, Some(2)).await.unwrap();
+        let issue_id = format!("{}-1", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &issue_id).await;
+
+        let vote = vote_issue_budget_in_db(&pool, &issue_id, 50, &AuditInfo::new(None, None))
+            .await
+            .unwrap();
+        assert_eq!(
+            (vote.votes, vote.required_votes, vote.approved),
+            (1, 1, true)
+        );
+        assert_eq!(
+            test_db::issue_review(&pool, &issue_id).await,
+            (String::from("approved"), Some(50))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn an_unreadable_review_state_fails_the_update() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        let issue_id = format!("{}-1", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &issue_id).await;
+        // outside strict mode an ENUM column takes a value it doesn't list as ''
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.query_drop("SET SESSION sql_mode = ''").await.unwrap();
+        conn.exec_drop(
+            "UPDATE issues_master SET review_state = 'lost' WHERE issue_id = :issue_id",
+            params! { "issue_id" => &issue_id },
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let audit = AuditInfo::new(Some(String::from("rita")), None);
+        let refused = review_issue_in_db(&pool, &issue_id, ReviewAction::Decline, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Validation(_)), "{:?}", refused);
+        assert_eq!(test_db::issue_review(&pool, &issue_id).await.0, "");
+        assert!(list_issue_history(&pool, &issue_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,565 @@
+use crate::error::{Error, GosimResult};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+pub struct Migration {
+    pub version: &'static str,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Applied in this order, new files go at the end with a later version.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: "20240308202145",
+        name: "gosim",
+        sql: include_str!("../migrations/20240308202145_gosim.sql"),
+    },
+    Migration {
+        version: "20261018090000",
+        name: "pipeline_runs",
+        sql: include_str!("../migrations/20261018090000_pipeline_runs.sql"),
+    },
+    Migration {
+        version: "20261018090100",
+        name: "run_locks",
+        sql: include_str!("../migrations/20261018090100_run_locks.sql"),
+    },
+    Migration {
+        version: "20261018090200",
+        name: "issue_labels",
+        sql: include_str!("../migrations/20261018090200_issue_labels.sql"),
+    },
+    Migration {
+        version: "20261018090300",
+        name: "issue_assignees",
+        sql: include_str!("../migrations/20261018090300_issue_assignees.sql"),
+    },
+    Migration {
+        version: "20261018090400",
+        name: "admin_actions",
+        sql: include_str!("../migrations/20261018090400_admin_actions.sql"),
+    },
+    Migration {
+        version: "20261018090500",
+        name: "snapshots",
+        sql: include_str!("../migrations/20261018090500_snapshots.sql"),
+    },
+    Migration {
+        version: "20261018090600",
+        name: "issue_imports",
+        sql: include_str!("../migrations/20261018090600_issue_imports.sql"),
+    },
+    Migration {
+        version: "20261018090700",
+        name: "budget_ledger",
+        sql: include_str!("../migrations/20261018090700_budget_ledger.sql"),
+    },
+    Migration {
+        version: "20261018090800",
+        name: "budget_caps",
+        sql: include_str!("../migrations/20261018090800_budget_caps.sql"),
+    },
+    Migration {
+        version: "20261018090900",
+        name: "review_states",
+        sql: include_str!("../migrations/20261018090900_review_states.sql"),
+    },
+    Migration {
+        version: "20261018091000",
+        name: "approval_votes",
+        sql: include_str!("../migrations/20261018091000_approval_votes.sql"),
+    },
+    Migration {
+        version: "20261018091100",
+        name: "payouts",
+        sql: include_str!("../migrations/20261018091100_payouts.sql"),
+    },
+    Migration {
+        version: "20261018091200",
+        name: "currencies",
+        sql: include_str!("../migrations/20261018091200_currencies.sql"),
+    },
+    Migration {
+        version: "20261018091300",
+        name: "risk_flags",
+        sql: include_str!("../migrations/20261018091300_risk_flags.sql"),
+    },
+    Migration {
+        version: "20261018091400",
+        name: "rolled_back_steps",
+        sql: include_str!("../migrations/20261018091400_rolled_back_steps.sql"),
+    },
+    Migration {
+        version: "20261018091500",
+        name: "sent_notes",
+        sql: include_str!("../migrations/20261018091500_sent_notes.sql"),
+    },
+    Migration {
+        version: "20261018091600",
+        name: "skipped_rows",
+        sql: include_str!("../migrations/20261018091600_skipped_rows.sql"),
+    },
+];
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // Splits on the ';' outside quotes once the comments are gone. `--` only
+    // starts a comment at the start of a line or after whitespace, so
+    // `a--b` and quoted dashes stay in the statement.
+    fn statements(&self) -> Vec<String> {
+        let mut statements = Vec::new();
+        let mut statement = String::new();
+        let mut quote: Option<char> = None;
+        let mut chars = self.sql.chars().peekable();
+        while let Some(c) = chars.next() {
+            match quote {
+                Some(q) => {
+                    statement.push(c);
+                    if c == '\\' && q != '`' {
+                        statement.extend(chars.next());
+                    } else if c == q {
+                        quote = None;
+                    }
+                }
+                None if c == '-'
+                    && chars.peek() == Some(&'-')
+                    && statement.chars().last().is_none_or(char::is_whitespace) =>
+                {
+                    while chars.next_if(|&c| c != '\n').is_some() {}
+                }
+                None if c == ';' => statements.push(std::mem::take(&mut statement)),
+                None => {
+                    if matches!(c, '\'' | '"' | '`') {
+                        quote = Some(c);
+                    }
+                    statement.push(c);
+                }
+            }
+        }
+        statements.push(statement);
+
+        statements
+            .into_iter()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    // the file changed after it was applied
+    Modified { applied_checksum: String },
+}
+
+impl MigrationState {
+    // Compares the file against the checksums in schema_migrations, by version.
+    fn of(migration: &Migration, applied: &HashMap<String, String>) -> MigrationState {
+        match applied.get(migration.version) {
+            None => MigrationState::Pending,
+            Some(checksum) if *checksum == migration.checksum() => MigrationState::Applied,
+            Some(checksum) => MigrationState::Modified {
+                applied_checksum: checksum.clone(),
+            },
+        }
+    }
+}
+
+// Held while migrating, so two servers starting at once don't both apply the
+// same files. A named lock rather than db_lock, run_locks is itself a migration.
+const MIGRATE_LOCK: &str = "gosim_migrate";
+const MIGRATE_LOCK_WAIT_SECS: u32 = 60;
+
+async fn ensure_migrations_table(conn: &mut Conn) -> GosimResult<()> {
+    conn.query_drop(
+        r"CREATE TABLE IF NOT EXISTS schema_migrations (
+            version VARCHAR(14) PRIMARY KEY,
+            name VARCHAR(100) NOT NULL,
+            checksum CHAR(64) NOT NULL,
+            applied_at DATETIME NOT NULL
+        ) DEFAULT CHARSET=utf8mb4 COLLATE utf8mb4_unicode_ci",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn applied_checksums(conn: &mut Conn) -> GosimResult<HashMap<String, String>> {
+    let rows: Vec<(String, String)> = conn
+        .query("SELECT version, checksum FROM schema_migrations")
+        .await?;
+    Ok(rows.into_iter().collect())
+}
+
+async fn record_migration(conn: &mut Conn, migration: &Migration) -> GosimResult<()> {
+    conn.exec_drop(
+        r"INSERT INTO schema_migrations (version, name, checksum, applied_at)
+          VALUES (:version, :name, :checksum, NOW())",
+        params! {
+            "version" => migration.version,
+            "name" => migration.name,
+            "checksum" => migration.checksum(),
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+// Databases created by hand before schema_migrations existed already have the
+// first migration's tables, that one gets recorded instead of run.
+async fn adopt_existing_schema(conn: &mut Conn) -> GosimResult<()> {
+    let has_projects: Option<u32> = conn
+        .query_first(
+            r"SELECT 1 FROM information_schema.tables
+              WHERE table_schema = DATABASE() AND table_name = 'projects'",
+        )
+        .await?;
+
+    if has_projects.is_some() {
+        log::info!(
+            "Existing schema found, recording {}_{} as applied",
+            MIGRATIONS[0].version,
+            MIGRATIONS[0].name
+        );
+        record_migration(conn, &MIGRATIONS[0]).await?;
+    }
+    Ok(())
+}
+
+async fn status_on(conn: &mut Conn) -> GosimResult<Vec<(&'static Migration, MigrationState)>> {
+    ensure_migrations_table(conn).await?;
+    let applied = applied_checksums(conn).await?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| (migration, MigrationState::of(migration, &applied)))
+        .collect())
+}
+
+pub async fn migration_status(
+    pool: &Pool,
+) -> GosimResult<Vec<(&'static Migration, MigrationState)>> {
+    let mut conn = pool.get_conn().await?;
+    status_on(&mut conn).await
+}
+
+// Applies pending migrations in order, returns the versions it ran. Refuses to
+// touch anything when an applied migration no longer matches its file. The
+// status is read under the lock, a run that waited finds the files applied.
+pub async fn run_migrations(pool: &Pool) -> GosimResult<Vec<&'static str>> {
+    let mut conn = pool.get_conn().await?;
+    let locked: Option<Option<i32>> = conn
+        .exec_first(
+            "SELECT GET_LOCK(:name, :wait)",
+            params! { "name" => MIGRATE_LOCK, "wait" => MIGRATE_LOCK_WAIT_SECS },
+        )
+        .await?;
+    if locked.flatten() != Some(1) {
+        return Err(Error::Validation(format!(
+            "another `gosim migrate` held the {} lock for {}s",
+            MIGRATE_LOCK, MIGRATE_LOCK_WAIT_SECS
+        )));
+    }
+
+    let applied = apply_pending(&mut conn).await;
+    // the lock is per connection, release it before the connection goes back to the pool
+    if let Err(e) = conn
+        .exec_drop("DO RELEASE_LOCK(:name)", params! { "name" => MIGRATE_LOCK })
+        .await
+    {
+        log::error!("Error releasing the {} lock: {:?}", MIGRATE_LOCK, e);
+    }
+    applied
+}
+
+async fn apply_pending(conn: &mut Conn) -> GosimResult<Vec<&'static str>> {
+    ensure_migrations_table(conn).await?;
+    if applied_checksums(conn).await?.is_empty() {
+        adopt_existing_schema(conn).await?;
+    }
+
+    let status = status_on(conn).await?;
+    if let Some((migration, _)) = status
+        .iter()
+        .find(|(_, state)| matches!(state, MigrationState::Modified { .. }))
+    {
+        return Err(Error::Validation(format!(
+            "migration {}_{} was changed after it was applied",
+            migration.version, migration.name
+        )));
+    }
+
+    let mut applied = Vec::new();
+    for (migration, state) in status {
+        if state != MigrationState::Pending {
+            continue;
+        }
+
+        log::info!(
+            "Applying migration {}_{}",
+            migration.version,
+            migration.name
+        );
+        // MySQL commits DDL implicitly, a failure leaves the earlier statements in place
+        for statement in migration.statements() {
+            conn.query_drop(statement).await.map_err(|e| {
+                log::error!(
+                    "Error applying migration {}_{}: {:?}",
+                    migration.version,
+                    migration.name,
+                    e
+                );
+                e
+            })?;
+        }
+        record_migration(conn, migration).await?;
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+// Called before pipeline runs and at server startup, so nothing writes to a
+// schema older than the code expects.
+pub async fn ensure_schema_current(pool: &Pool) -> GosimResult<()> {
+    let outdated: Vec<String> = migration_status(pool)
+        .await?
+        .into_iter()
+        .filter(|(_, state)| !matches!(state, MigrationState::Applied))
+        .map(|(migration, state)| {
+            let label = match state {
+                MigrationState::Pending => "pending",
+                _ => "modified",
+            };
+            format!("{}_{} ({})", migration.version, migration.name, label)
+        })
+        .collect();
+
+    if outdated.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Validation(format!(
+            "database schema is out of date, run `gosim migrate`: {}",
+            outdated.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_db;
+
+    fn migration(sql: &'static str) -> Migration {
+        Migration {
+            version: "20990101000000",
+            name: "test",
+            sql,
+        }
+    }
+
+    #[test]
+    fn statements_split_outside_quotes_and_comments() {
+        let sql = "-- leading comment; not a statement\n\
+                   CREATE TABLE a (id INT); -- trailing; comment\n\
+                   INSERT INTO a VALUES ('x;y', 'it''s -- here', \"q\\\"; -- \");\n\
+                   UPDATE a SET id = id--1;\n\
+                   \n  ;  ;\n\
+                   SELECT `odd--name;` FROM a";
+        assert_eq!(
+            migration(sql).statements(),
+            [
+                "CREATE TABLE a (id INT)",
+                "INSERT INTO a VALUES ('x;y', 'it''s -- here', \"q\\\"; -- \")",
+                "UPDATE a SET id = id--1",
+                "SELECT `odd--name;` FROM a",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_migration_file_splits_into_statements() {
+        for migration in MIGRATIONS {
+            let statements = migration.statements();
+            assert!(!statements.is_empty(), "{}", migration.name);
+            assert!(
+                statements.iter().all(|s| !s.starts_with("--")),
+                "{}",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn checksum_is_the_sha256_of_the_file() {
+        assert_eq!(
+            migration("").checksum(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            migration("abc").checksum(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(
+            migration("SELECT 1;").checksum(),
+            migration("SELECT 1; ").checksum()
+        );
+    }
+
+    #[test]
+    fn changed_files_are_detected_by_checksum() {
+        let applied_as = migration("CREATE TABLE a (id INT);");
+        let edited = migration("CREATE TABLE a (id BIGINT);");
+        let applied = HashMap::from([(applied_as.version.to_string(), applied_as.checksum())]);
+
+        assert_eq!(
+            MigrationState::of(&applied_as, &applied),
+            MigrationState::Applied
+        );
+        assert_eq!(
+            MigrationState::of(&edited, &applied),
+            MigrationState::Modified {
+                applied_checksum: applied_as.checksum()
+            }
+        );
+        assert_eq!(
+            MigrationState::of(&edited, &HashMap::new()),
+            MigrationState::Pending
+        );
+    }
+
+    fn assignee_statements() -> Vec<String> {
+        MIGRATIONS
+            .iter()
+            .find(|m| m.name == "issue_assignees")
+            .unwrap()
+            .statements()
+    }
+
+    // The backfill runs against temporary tables shadowing issues_master (with its
+    // old JSON column) and issue_assignees on one connection. The rows it splits
+    // out then go to the real table, read back through the real view.
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn assignee_backfill_splits_the_json_and_the_view_rebuilds_it() {
+        let pool = test_db::pool().await;
+        let prefix = format!("test-{:08x}", rand::random::<u32>());
+        let id = |n: u32| format!("{}/issues/{}", prefix, n);
+        let old_lists = [
+            (id(1), Some(r#"["alice", "bob"]"#)),
+            (id(2), Some(r#"["carol"]"#)),
+            (id(3), Some(r#"["dave", "dave", null]"#)),
+            (id(4), Some("[]")),
+            (id(5), None),
+        ];
+
+        let statements = assignee_statements();
+        let create_table =
+            statements[0].replacen("CREATE TABLE IF NOT EXISTS", "CREATE TEMPORARY TABLE", 1);
+        let backfill = &statements[1];
+        let mut shadowed = pool.get_conn().await.unwrap();
+        shadowed
+            .query_drop(
+                r"CREATE TEMPORARY TABLE issues_master (
+                    issue_id VARCHAR(255) PRIMARY KEY,
+                    issue_assignees JSON,
+                    date_issue_assigned DATETIME
+                )",
+            )
+            .await
+            .unwrap();
+        shadowed.query_drop(create_table).await.unwrap();
+        for (issue_id, assignees) in &old_lists {
+            shadowed
+                .exec_drop(
+                    r"INSERT INTO issues_master (issue_id, issue_assignees, date_issue_assigned)
+                      VALUES (:issue_id, :issue_assignees, '2023-10-02 09:00:00')",
+                    params! { "issue_id" => issue_id, "issue_assignees" => assignees },
+                )
+                .await
+                .unwrap();
+        }
+        shadowed.query_drop(backfill).await.unwrap();
+        let rows: Vec<(String, String, String)> = shadowed
+            .query(
+                r"SELECT issue_id, login, source FROM issue_assignees
+                  ORDER BY issue_id, login",
+            )
+            .await
+            .unwrap();
+        drop(shadowed);
+
+        let split: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|(issue_id, login, source)| {
+                assert_eq!(source, "backfill");
+                (issue_id.as_str(), login.as_str())
+            })
+            .collect();
+        assert_eq!(
+            split,
+            [
+                (id(1).as_str(), "alice"),
+                (id(1).as_str(), "bob"),
+                (id(2).as_str(), "carol"),
+                (id(3).as_str(), "dave"),
+            ]
+        );
+
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.exec_batch(
+            r"INSERT INTO issue_assignees (issue_id, login, source, assigned_at)
+              VALUES (:issue_id, :login, 'backfill', '2023-10-02 09:00:00')",
+            split.iter().map(|(issue_id, login)| {
+                params! { "issue_id" => issue_id, "login" => login }
+            }),
+        )
+        .await
+        .unwrap();
+        let lists: Vec<(String, String)> = conn
+            .exec(
+                r"SELECT issue_id, issue_assignees FROM issue_assignee_lists
+                  WHERE issue_id LIKE :prefix ORDER BY issue_id",
+                params! { "prefix" => format!("{}/%", prefix) },
+            )
+            .await
+            .unwrap();
+        conn.exec_drop(
+            "DELETE FROM issue_assignees WHERE issue_id LIKE :prefix",
+            params! { "prefix" => format!("{}/%", prefix) },
+        )
+        .await
+        .unwrap();
+
+        // the same logins as the old column, once each and without nulls. An issue
+        // without any has no row, so the API's LEFT JOIN gives NULL as before for
+        // NULL, and NULL rather than [] for an empty list
+        let lists: Vec<(String, Vec<String>)> = lists
+            .into_iter()
+            .map(|(issue_id, json)| {
+                let mut logins: Vec<String> = serde_json::from_str(&json).unwrap();
+                logins.sort();
+                (issue_id, logins)
+            })
+            .collect();
+        let logins = |logins: &[&str]| logins.iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            lists,
+            [
+                (id(1), logins(&["alice", "bob"])),
+                (id(2), logins(&["carol"])),
+                (id(3), logins(&["dave"])),
+            ]
+        );
+    }
+}
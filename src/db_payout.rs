@@ -0,0 +1,855 @@
+use crate::csv_util::{csv_records, ImportFormat};
+use crate::currency::Currency;
+use crate::db_audit::{audited_update_in, AuditInfo};
+use crate::db_caps::get_caps;
+use crate::db_export::{csv_field, ExportValue};
+use crate::db_ledger::{issue_balance, IssueLedgerOp};
+use crate::error::{Error, GosimResult};
+use crate::review_state::{ReviewAction, ReviewState};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::str::FromStr;
+
+// Paying concluded issues:
+//
+//   draft       create_batch collects the concluded issues that aren't in a batch,
+//               and splits each one's approved budget between its assignees
+//   sent        mark_sent, once the payout file went to the payment provider. The
+//               issues are paid, in the ledger and in their review state
+//   reconciled  reconcile, once every entry is in the provider's confirmation
+//               file with the amount it was sent with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    #[default]
+    Draft,
+    Sent,
+    Reconciled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    #[default]
+    Pending,
+    Sent,
+    Confirmed,
+    // confirmed with another amount
+    Mismatch,
+}
+
+impl BatchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BatchStatus::Draft => "draft",
+            BatchStatus::Sent => "sent",
+            BatchStatus::Reconciled => "reconciled",
+        }
+    }
+}
+
+impl EntryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryStatus::Pending => "pending",
+            EntryStatus::Sent => "sent",
+            EntryStatus::Confirmed => "confirmed",
+            EntryStatus::Mismatch => "mismatch",
+        }
+    }
+}
+
+fn from_db<T: serde::de::DeserializeOwned + Default>(s: &str) -> T {
+    serde_json::from_value(serde_json::Value::String(s.to_string())).unwrap_or_default()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PayoutEntry {
+    pub entry_id: u64,
+    pub issue_id: String,
+    pub project_id: String,
+    pub payee: String,
+    pub amount: i64,
+    // the currency of the issue's budget
+    pub currency: Currency,
+    pub status: EntryStatus,
+    pub reference: Option<String>,
+    pub confirmed_amount: Option<i64>,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PayoutBatch {
+    pub batch_id: u64,
+    pub campaign_id: String,
+    pub status: BatchStatus,
+    pub created_by: String,
+    pub created_at: String,
+    pub sent_at: Option<String>,
+    pub reconciled_at: Option<String>,
+    pub entries: Vec<PayoutEntry>,
+}
+
+impl PayoutBatch {
+    // What the batch pays in each currency.
+    pub fn totals(&self) -> BTreeMap<Currency, i64> {
+        let mut totals = BTreeMap::new();
+        for entry in &self.entries {
+            *totals.entry(entry.currency).or_insert(0) += entry.amount;
+        }
+        totals
+    }
+}
+
+// A line of the payment provider's confirmation file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PayoutConfirmation {
+    #[serde(alias = "issue_url")]
+    pub issue_id: String,
+    #[serde(alias = "login")]
+    pub payee: String,
+    pub amount: i64,
+    // when given, it must be the currency the entry was sent in
+    #[serde(default)]
+    pub currency: Option<Currency>,
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Finding {
+    Confirmed,
+    AmountMismatch,
+    // sent, but not in the confirmation file
+    Missing,
+    // in the confirmation file, but not in the batch
+    Unexpected,
+    // in the confirmation file more than once
+    Duplicate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReconcileLine {
+    pub issue_id: String,
+    pub payee: String,
+    pub finding: Finding,
+    pub expected: Option<i64>,
+    pub confirmed: Option<i64>,
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReconcileReport {
+    pub batch_id: u64,
+    pub confirmed: usize,
+    // everything that needs a look, confirmed lines are left out
+    pub flagged: Vec<ReconcileLine>,
+    pub reconciled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayoutFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl FromStr for PayoutFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::Validation(format!("unknown payout format '{}'", s)))
+    }
+}
+
+// An issue's budget split evenly between its payees, in the order given. What
+// doesn't divide evenly goes to the first payees, one each.
+pub fn split_payout(amount: i64, payees: &[String]) -> Vec<(String, i64)> {
+    if payees.is_empty() {
+        return Vec::new();
+    }
+    let count = payees.len() as i64;
+    payees
+        .iter()
+        .enumerate()
+        .map(|(i, payee)| {
+            let extra = ((i as i64) < amount % count) as i64;
+            (payee.clone(), amount / count + extra)
+        })
+        .collect()
+}
+
+// Matches the confirmations to the entries by issue and payee. Entries confirmed
+// by an earlier file aren't reported missing.
+pub fn reconcile_entries(
+    entries: &[PayoutEntry],
+    confirmations: &[PayoutConfirmation],
+) -> Vec<ReconcileLine> {
+    let mut lines = Vec::new();
+    let mut seen = BTreeSet::new();
+    for confirmation in confirmations {
+        let key = (confirmation.issue_id.as_str(), confirmation.payee.as_str());
+        let entry = entries
+            .iter()
+            .find(|e| (e.issue_id.as_str(), e.payee.as_str()) == key);
+        let finding = match entry {
+            _ if !seen.insert(key) => Finding::Duplicate,
+            None => Finding::Unexpected,
+            Some(entry)
+                if entry.amount == confirmation.amount
+                    && confirmation.currency.is_none_or(|c| c == entry.currency) =>
+            {
+                Finding::Confirmed
+            }
+            Some(_) => Finding::AmountMismatch,
+        };
+        lines.push(ReconcileLine {
+            issue_id: confirmation.issue_id.clone(),
+            payee: confirmation.payee.clone(),
+            finding,
+            expected: entry.map(|e| e.amount),
+            confirmed: Some(confirmation.amount),
+            reference: confirmation.reference.clone(),
+        });
+    }
+
+    for entry in entries {
+        let key = (entry.issue_id.as_str(), entry.payee.as_str());
+        if !seen.contains(&key) && entry.status != EntryStatus::Confirmed {
+            lines.push(ReconcileLine {
+                issue_id: entry.issue_id.clone(),
+                payee: entry.payee.clone(),
+                finding: Finding::Missing,
+                expected: Some(entry.amount),
+                confirmed: None,
+                reference: None,
+            });
+        }
+    }
+    lines
+}
+
+// A JSON array of PayoutConfirmation objects, or CSV with a header naming
+// issue_id (or issue_url), payee (or login), amount and optionally currency and
+// reference.
+pub fn parse_confirmations(
+    text: &str,
+    format: ImportFormat,
+) -> GosimResult<Vec<PayoutConfirmation>> {
+    if format == ImportFormat::Json {
+        return serde_json::from_str(text)
+            .map_err(|e| Error::Validation(format!("invalid confirmation file: {}", e)));
+    }
+
+    let mut records = csv_records(text).into_iter();
+    let header = records
+        .next()
+        .ok_or_else(|| Error::Validation(String::from("confirmation file is empty")))?;
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.contains(&h.trim().to_lowercase().as_str()))
+    };
+    let missing =
+        |name: &str| Error::Validation(format!("confirmation file has no {} column", name));
+    let issue_column = column(&["issue_id", "issue_url"]).ok_or_else(|| missing("issue_id"))?;
+    let payee_column = column(&["payee", "login"]).ok_or_else(|| missing("payee"))?;
+    let amount_column = column(&["amount"]).ok_or_else(|| missing("amount"))?;
+    let currency_column = column(&["currency"]);
+    let reference_column = column(&["reference"]);
+
+    let mut confirmations = Vec::new();
+    for (i, record) in records.enumerate() {
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .map(|f| f.trim())
+                .filter(|f| !f.is_empty())
+        };
+        let amount = field(Some(amount_column)).unwrap_or_default();
+        confirmations.push(PayoutConfirmation {
+            issue_id: field(Some(issue_column)).unwrap_or_default().to_string(),
+            payee: field(Some(payee_column)).unwrap_or_default().to_string(),
+            amount: amount.parse().map_err(|_| {
+                Error::Validation(format!("row {}: invalid amount '{}'", i + 1, amount))
+            })?,
+            currency: field(currency_column)
+                .map(|currency| {
+                    currency
+                        .parse()
+                        .map_err(|e| Error::Validation(format!("row {}: {}", i + 1, e)))
+                })
+                .transpose()?,
+            reference: field(reference_column).map(String::from),
+        });
+    }
+    Ok(confirmations)
+}
+
+// The file for the payment provider: CSV with one line per entry, or a JSON
+// object with the batch and its entries.
+pub fn write_payout_file<W: Write>(
+    batch: &PayoutBatch,
+    format: PayoutFormat,
+    mut out: W,
+) -> GosimResult<()> {
+    let write_error = |e: std::io::Error| Error::Export(e.to_string());
+    match format {
+        PayoutFormat::Csv => {
+            writeln!(
+                out,
+                "batch_id,entry_id,issue_id,project_id,payee,amount,currency"
+            )
+            .map_err(write_error)?;
+            for entry in &batch.entries {
+                let fields = [
+                    ExportValue::Int(batch.batch_id as i64),
+                    ExportValue::Int(entry.entry_id as i64),
+                    ExportValue::Text(entry.issue_id.clone()),
+                    ExportValue::Text(entry.project_id.clone()),
+                    ExportValue::Text(entry.payee.clone()),
+                    ExportValue::Int(entry.amount),
+                    ExportValue::Text(entry.currency.to_string()),
+                ];
+                let line: Vec<String> = fields.iter().map(csv_field).collect();
+                writeln!(out, "{}", line.join(",")).map_err(write_error)?;
+            }
+        }
+        PayoutFormat::Json => {
+            let file = serde_json::json!({
+                "batch_id": batch.batch_id,
+                "campaign_id": batch.campaign_id,
+                "created_at": batch.created_at,
+                "totals": batch.totals(),
+                "entries": batch.entries.iter().map(|entry| serde_json::json!({
+                    "entry_id": entry.entry_id,
+                    "issue_id": entry.issue_id,
+                    "project_id": entry.project_id,
+                    "payee": entry.payee,
+                    "amount": entry.amount,
+                    "currency": entry.currency,
+                })).collect::<Vec<_>>(),
+            });
+            serde_json::to_writer_pretty(&mut out, &file)
+                .map_err(|e| Error::Export(e.to_string()))?;
+            writeln!(out).map_err(write_error)?;
+        }
+    }
+    out.flush().map_err(write_error)
+}
+
+// The issue's assignees, earliest first as in get_issue_ids_distribute_fund.
+async fn payees<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<Vec<String>> {
+    let payees: Vec<String> = conn
+        .exec(
+            r"SELECT login FROM issue_assignees WHERE issue_id = :issue_id
+              GROUP BY login
+              ORDER BY MIN(assigned_at) IS NULL, MIN(assigned_at), login",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    Ok(payees)
+}
+
+// Collects the campaign's concluded issues that aren't in a batch yet. Issues
+// without an assignee or an approved balance are returned apart, to be looked at.
+pub async fn create_batch(
+    pool: &Pool,
+    campaign_id: &str,
+    actor: &str,
+) -> GosimResult<(PayoutBatch, Vec<String>)> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    get_caps(&mut tx, campaign_id).await?;
+
+    let issues: Vec<(String, String, String)> = tx
+        .exec(
+            r"SELECT im.issue_id, im.project_id, im.issue_budget_currency FROM issues_master im
+              WHERE im.campaign_id = :campaign_id AND im.review_state = :review_state
+                AND NOT EXISTS (SELECT 1 FROM payout_entries pe WHERE pe.issue_id = im.issue_id)
+              ORDER BY im.issue_id
+              FOR UPDATE",
+            params! {
+                "campaign_id" => campaign_id,
+                "review_state" => ReviewState::Concluded.as_str(),
+            },
+        )
+        .await?;
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for (issue_id, project_id, currency) in issues {
+        let payees = payees(&mut tx, &issue_id).await?;
+        let balance = issue_balance(&mut tx, &issue_id).await?;
+        if payees.is_empty() || balance.approved <= 0 {
+            skipped.push(issue_id);
+            continue;
+        }
+        for (payee, amount) in split_payout(balance.approved, &payees) {
+            entries.push(PayoutEntry {
+                issue_id: issue_id.clone(),
+                project_id: project_id.clone(),
+                payee,
+                amount,
+                currency: Currency::from_db(&currency),
+                ..Default::default()
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Err(Error::Validation(format!(
+            "campaign {} has no concluded issues to pay out",
+            campaign_id
+        )));
+    }
+
+    tx.exec_drop(
+        r"INSERT INTO payout_batches (campaign_id, status, created_by, created_at)
+          VALUES (:campaign_id, 'draft', :created_by, NOW())",
+        params! { "campaign_id" => campaign_id, "created_by" => actor },
+    )
+    .await?;
+    let batch_id = tx
+        .last_insert_id()
+        .ok_or_else(|| Error::Validation(String::from("payout batch got no id")))?;
+    tx.exec_batch(
+        r"INSERT INTO payout_entries
+            (batch_id, issue_id, project_id, payee, amount, currency, status)
+          VALUES (:batch_id, :issue_id, :project_id, :payee, :amount, :currency, 'pending')",
+        entries.iter().map(|entry| {
+            params! {
+                "batch_id" => batch_id,
+                "issue_id" => &entry.issue_id,
+                "project_id" => &entry.project_id,
+                "payee" => &entry.payee,
+                "amount" => entry.amount,
+                "currency" => entry.currency.as_str(),
+            }
+        }),
+    )
+    .await?;
+
+    let batch = get_batch(&mut tx, batch_id).await?;
+    tx.commit().await?;
+    Ok((batch, skipped))
+}
+
+pub async fn get_batch<Q: Queryable>(conn: &mut Q, batch_id: u64) -> GosimResult<PayoutBatch> {
+    let row: Option<Row> = conn
+        .exec_first(
+            r"SELECT batch_id, campaign_id, status, created_by,
+                DATE_FORMAT(created_at, '%Y-%m-%d %H:%i:%s') AS created_at,
+                DATE_FORMAT(sent_at, '%Y-%m-%d %H:%i:%s') AS sent_at,
+                DATE_FORMAT(reconciled_at, '%Y-%m-%d %H:%i:%s') AS reconciled_at
+              FROM payout_batches WHERE batch_id = :batch_id",
+            params! { "batch_id" => batch_id },
+        )
+        .await?;
+    let row = row.ok_or_else(|| Error::NotFound(format!("Payout batch {}", batch_id)))?;
+
+    let entries: Vec<Row> = conn
+        .exec(
+            r"SELECT entry_id, issue_id, project_id, payee, amount, currency, status,
+                reference, confirmed_amount, note
+              FROM payout_entries WHERE batch_id = :batch_id ORDER BY entry_id",
+            params! { "batch_id" => batch_id },
+        )
+        .await?;
+
+    Ok(PayoutBatch {
+        batch_id,
+        campaign_id: row.get("campaign_id").unwrap_or_default(),
+        status: from_db(&row.get::<String, _>("status").unwrap_or_default()),
+        created_by: row.get("created_by").unwrap_or_default(),
+        created_at: row.get("created_at").unwrap_or_default(),
+        sent_at: row.get::<Option<String>, _>("sent_at").unwrap_or(None),
+        reconciled_at: row
+            .get::<Option<String>, _>("reconciled_at")
+            .unwrap_or(None),
+        entries: entries
+            .iter()
+            .map(|row| PayoutEntry {
+                entry_id: row.get("entry_id").unwrap_or_default(),
+                issue_id: row.get("issue_id").unwrap_or_default(),
+                project_id: row.get("project_id").unwrap_or_default(),
+                payee: row.get("payee").unwrap_or_default(),
+                amount: row.get("amount").unwrap_or_default(),
+                currency: Currency::from_db(&row.get::<String, _>("currency").unwrap_or_default()),
+                status: from_db(&row.get::<String, _>("status").unwrap_or_default()),
+                reference: row.get::<Option<String>, _>("reference").unwrap_or(None),
+                confirmed_amount: row
+                    .get::<Option<i64>, _>("confirmed_amount")
+                    .unwrap_or(None),
+                note: row.get::<Option<String>, _>("note").unwrap_or(None),
+            })
+            .collect(),
+    })
+}
+
+// The batches without their entries, newest first.
+pub async fn list_batches(pool: &Pool) -> GosimResult<Vec<PayoutBatch>> {
+    let mut conn = pool.get_conn().await?;
+
+    let batch_ids: Vec<u64> = conn
+        .query("SELECT batch_id FROM payout_batches ORDER BY batch_id DESC")
+        .await?;
+    let mut batches = Vec::new();
+    for batch_id in batch_ids {
+        let batch = get_batch(&mut conn, batch_id).await?;
+        batches.push(PayoutBatch {
+            entries: Vec::new(),
+            ..batch
+        });
+    }
+    Ok(batches)
+}
+
+async fn lock_batch(
+    tx: &mut Transaction<'_>,
+    batch_id: u64,
+    status: BatchStatus,
+) -> GosimResult<PayoutBatch> {
+    tx.exec_drop(
+        "SELECT batch_id FROM payout_batches WHERE batch_id = :batch_id FOR UPDATE",
+        params! { "batch_id" => batch_id },
+    )
+    .await?;
+    let batch = get_batch(tx, batch_id).await?;
+    if batch.status != status {
+        return Err(Error::Validation(format!(
+            "payout batch {} is {}, not {}",
+            batch_id,
+            batch.status.as_str(),
+            status.as_str()
+        )));
+    }
+    Ok(batch)
+}
+
+// Records that the payout file was sent: the entries are sent, and every issue in
+// the batch is paid in the ledger and moves to the paid review state.
+pub async fn mark_sent(pool: &Pool, batch_id: u64, audit: &AuditInfo) -> GosimResult<PayoutBatch> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let batch = lock_batch(&mut tx, batch_id, BatchStatus::Draft).await?;
+
+    let issue_ids: BTreeSet<&str> = batch.entries.iter().map(|e| e.issue_id.as_str()).collect();
+    for issue_id in issue_ids {
+        audited_update_in(
+            &mut tx,
+            issue_id,
+            ReviewAction::Pay,
+            audit,
+            None,
+            Some(IssueLedgerOp::Payout),
+        )
+        .await?;
+    }
+    tx.exec_drop(
+        "UPDATE payout_entries SET status = 'sent' WHERE batch_id = :batch_id",
+        params! { "batch_id" => batch_id },
+    )
+    .await?;
+    tx.exec_drop(
+        r"UPDATE payout_batches SET status = 'sent', sent_at = NOW()
+          WHERE batch_id = :batch_id",
+        params! { "batch_id" => batch_id },
+    )
+    .await?;
+
+    let batch = get_batch(&mut tx, batch_id).await?;
+    tx.commit().await?;
+    Ok(batch)
+}
+
+// Drops a batch that wasn't sent, its issues go into the next one.
+pub async fn cancel_batch(pool: &Pool, batch_id: u64) -> GosimResult<()> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    lock_batch(&mut tx, batch_id, BatchStatus::Draft).await?;
+
+    for query in [
+        "DELETE FROM payout_entries WHERE batch_id = :batch_id",
+        "DELETE FROM payout_batches WHERE batch_id = :batch_id",
+    ] {
+        tx.exec_drop(query, params! { "batch_id" => batch_id })
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Checks a sent batch against the confirmation file. Matching entries are
+// confirmed, the rest is flagged; the batch is reconciled once nothing is.
+// A corrected file can be reconciled again.
+pub async fn reconcile(
+    pool: &Pool,
+    batch_id: u64,
+    confirmations: &[PayoutConfirmation],
+) -> GosimResult<ReconcileReport> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let batch = lock_batch(&mut tx, batch_id, BatchStatus::Sent).await?;
+
+    let lines = reconcile_entries(&batch.entries, confirmations);
+    for line in &lines {
+        let (status, note) = match line.finding {
+            Finding::Confirmed => (EntryStatus::Confirmed, None),
+            Finding::AmountMismatch => (
+                EntryStatus::Mismatch,
+                Some(format!(
+                    "confirmed {} of {}",
+                    line.confirmed.unwrap_or_default(),
+                    line.expected.unwrap_or_default()
+                )),
+            ),
+            Finding::Missing => (
+                EntryStatus::Sent,
+                Some(String::from("not in the confirmation file")),
+            ),
+            Finding::Unexpected | Finding::Duplicate => continue,
+        };
+        tx.exec_drop(
+            r"UPDATE payout_entries SET status = :status, confirmed_amount = :confirmed_amount,
+                reference = COALESCE(:reference, reference), note = :note
+              WHERE batch_id = :batch_id AND issue_id = :issue_id AND payee = :payee",
+            params! {
+                "batch_id" => batch_id,
+                "issue_id" => &line.issue_id,
+                "payee" => &line.payee,
+                "status" => status.as_str(),
+                "confirmed_amount" => line.confirmed,
+                "reference" => &line.reference,
+                "note" => note,
+            },
+        )
+        .await?;
+    }
+
+    let flagged: Vec<ReconcileLine> = lines
+        .iter()
+        .filter(|line| line.finding != Finding::Confirmed)
+        .cloned()
+        .collect();
+    let reconciled = flagged.is_empty();
+    if reconciled {
+        tx.exec_drop(
+            r"UPDATE payout_batches SET status = 'reconciled', reconciled_at = NOW()
+              WHERE batch_id = :batch_id",
+            params! { "batch_id" => batch_id },
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(ReconcileReport {
+        batch_id,
+        confirmed: lines.len() - flagged.len(),
+        flagged,
+        reconciled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_manipulate::conclude_issue_in_db;
+    use crate::test_db;
+
+    const ISSUE: &str = "https://github.com/acme/widgets/issues/1";
+
+    fn logins(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn entry(payee: &str, amount: i64, status: EntryStatus) -> PayoutEntry {
+        PayoutEntry {
+            issue_id: ISSUE.to_string(),
+            payee: payee.to_string(),
+            amount,
+            status,
+            ..Default::default()
+        }
+    }
+
+    fn confirmation(payee: &str, amount: i64) -> PayoutConfirmation {
+        PayoutConfirmation {
+            issue_id: ISSUE.to_string(),
+            payee: payee.to_string(),
+            amount,
+            ..Default::default()
+        }
+    }
+
+    fn findings(lines: &[ReconcileLine]) -> Vec<(&str, Finding)> {
+        lines
+            .iter()
+            .map(|l| (l.payee.as_str(), l.finding))
+            .collect()
+    }
+
+    #[test]
+    fn a_single_payee_gets_everything() {
+        assert_eq!(
+            split_payout(100, &logins(&["rita"])),
+            vec![(String::from("rita"), 100)]
+        );
+        assert!(split_payout(100, &[]).is_empty());
+    }
+
+    #[test]
+    fn the_remainder_goes_to_the_first_payees() {
+        let split = split_payout(100, &logins(&["rita", "bob", "carol"]));
+        assert_eq!(
+            split,
+            vec![
+                (String::from("rita"), 34),
+                (String::from("bob"), 33),
+                (String::from("carol"), 33),
+            ]
+        );
+
+        for (amount, count) in [(0, 3), (1, 2), (2, 3), (99, 4), (101, 7)] {
+            let payees: Vec<String> = (0..count).map(|i| format!("p{}", i)).collect();
+            let amounts: Vec<i64> = split_payout(amount, &payees)
+                .into_iter()
+                .map(|(_, a)| a)
+                .collect();
+            assert_eq!(
+                amounts.iter().sum::<i64>(),
+                amount,
+                "{} / {}",
+                amount,
+                count
+            );
+            assert!(amounts.windows(2).all(|w| w[0] >= w[1] && w[0] - w[1] <= 1));
+        }
+    }
+
+    #[test]
+    fn matching_confirmations_are_confirmed() {
+        let entries = vec![
+            entry("rita", 34, EntryStatus::Sent),
+            entry("bob", 33, EntryStatus::Sent),
+        ];
+        let lines = reconcile_entries(
+            &entries,
+            &[confirmation("bob", 33), confirmation("rita", 34)],
+        );
+        assert_eq!(
+            findings(&lines),
+            vec![("bob", Finding::Confirmed), ("rita", Finding::Confirmed)]
+        );
+        assert_eq!(lines[1].expected, Some(34));
+    }
+
+    #[test]
+    fn mismatched_entries_are_flagged() {
+        let entries = vec![
+            entry("rita", 34, EntryStatus::Sent),
+            entry("bob", 33, EntryStatus::Sent),
+            entry("carol", 33, EntryStatus::Sent),
+            entry("dave", 10, EntryStatus::Confirmed),
+        ];
+        let mut in_euro = confirmation("carol", 33);
+        in_euro.currency = Some(Currency::Eur);
+        let lines = reconcile_entries(
+            &entries,
+            &[
+                confirmation("rita", 30),
+                confirmation("mallory", 33),
+                in_euro,
+                confirmation("rita", 34),
+            ],
+        );
+        assert_eq!(
+            findings(&lines),
+            vec![
+                ("rita", Finding::AmountMismatch),
+                ("mallory", Finding::Unexpected),
+                ("carol", Finding::AmountMismatch),
+                ("rita", Finding::Duplicate),
+                // dave was confirmed by an earlier file
+                ("bob", Finding::Missing),
+            ]
+        );
+        assert_eq!(
+            (lines[0].expected, lines[0].confirmed),
+            (Some(34), Some(30))
+        );
+        assert_eq!((lines[1].expected, lines[1].confirmed), (None, Some(33)));
+        assert_eq!((lines[4].expected, lines[4].confirmed), (Some(33), None));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_batch_is_created_sent_and_reconciled() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        let audit = AuditInfo::new(Some(String::from("rita")), None);
+        let paid = test_db::approved_issue(&pool, &campaign_id, 1, 101, &["sam", "tess"]).await;
+        let unassigned = test_db::approved_issue(&pool, &campaign_id, 2, 50, &[]).await;
+        for issue_id in [&paid, &unassigned] {
+            conclude_issue_in_db(&pool, issue_id, &audit).await.unwrap();
+        }
+
+        let (batch, skipped) = create_batch(&pool, &campaign_id, "rita").await.unwrap();
+        assert_eq!(skipped, vec![unassigned.clone()]);
+        assert_eq!(batch.status, BatchStatus::Draft);
+        let split: Vec<(&str, i64)> = batch
+            .entries
+            .iter()
+            .map(|e| (e.payee.as_str(), e.amount))
+            .collect();
+        assert_eq!(split, [("sam", 51), ("tess", 50)]);
+        // the batched issue isn't collected again
+        let refused = create_batch(&pool, &campaign_id, "rita").await.unwrap_err();
+        assert!(matches!(refused, Error::Validation(_)), "{:?}", refused);
+
+        let sent = mark_sent(&pool, batch.batch_id, &audit).await.unwrap();
+        assert_eq!(sent.status, BatchStatus::Sent);
+        assert!(sent.entries.iter().all(|e| e.status == EntryStatus::Sent));
+        assert_eq!(test_db::issue_review(&pool, &paid).await.0, "paid");
+        let mut conn = pool.get_conn().await.unwrap();
+        let balance = issue_balance(&mut conn, &paid).await.unwrap();
+        assert_eq!((balance.approved, balance.paid), (0, 101));
+        let resent = mark_sent(&pool, batch.batch_id, &audit).await.unwrap_err();
+        assert!(matches!(resent, Error::Validation(_)), "{:?}", resent);
+
+        let confirmation = |payee: &str, amount| PayoutConfirmation {
+            issue_id: paid.clone(),
+            payee: payee.to_string(),
+            amount,
+            ..Default::default()
+        };
+        let report = reconcile(
+            &pool,
+            batch.batch_id,
+            &[confirmation("sam", 51), confirmation("tess", 40)],
+        )
+        .await
+        .unwrap();
+        assert!(!report.reconciled);
+        assert_eq!(report.confirmed, 1);
+        assert_eq!(
+            findings(&report.flagged),
+            [("tess", Finding::AmountMismatch)]
+        );
+        let checked = get_batch(&mut conn, batch.batch_id).await.unwrap();
+        assert_eq!(checked.status, BatchStatus::Sent);
+        let statuses: Vec<EntryStatus> = checked.entries.iter().map(|e| e.status).collect();
+        assert_eq!(statuses, [EntryStatus::Confirmed, EntryStatus::Mismatch]);
+
+        // the corrected file only needs the entry that was off
+        let report = reconcile(&pool, batch.batch_id, &[confirmation("tess", 50)])
+            .await
+            .unwrap();
+        assert!(report.reconciled, "{:?}", report);
+        let reconciled = get_batch(&mut conn, batch.batch_id).await.unwrap();
+        assert_eq!(reconciled.status, BatchStatus::Reconciled);
+        let again = reconcile(&pool, batch.batch_id, &[]).await.unwrap_err();
+        assert!(matches!(again, Error::Validation(_)), "{:?}", again);
+    }
+}
@@ -1,6 +1,9 @@
+use crate::currency::Currency;
+use crate::error::{Error, GosimResult};
 use crate::issue_tracker::*;
 use crate::llm_utils::parse_summary_and_keywords;
 use crate::llm_utils_together::*;
+use crate::review_state::ReviewState;
 use dotenv::dotenv;
 use mysql_async::prelude::*;
 use mysql_async::*;
@@ -26,6 +29,16 @@ pub struct IssueOut {
     pub issue_budget_approved: bool,
     pub running_budget: (i32, i32, i32),
     pub issue_stats: (i32, i32, i32, i32),
+    // issues matching the list filter, across all pages
+    #[serde(default)]
+    pub filtered_count: i32,
+    // the highest budget the caps and the available money allow for the issue now
+    #[serde(default)]
+    pub budget_headroom: Option<i32>,
+    #[serde(default)]
+    pub review_state: ReviewState,
+    #[serde(default)]
+    pub issue_budget_currency: Currency,
 }
 
 fn default_value() -> bool {
@@ -49,7 +62,11 @@ pub struct ProjectOut {
     pub project_description: Option<String>,
     pub issues_list: Option<Vec<String>>,
     pub total_budget_allocated: Option<i32>,
+    // projects matching the list filter, across all pages
     pub total_count: i32,
+    // what the project's issues can still be given under its cap, None when uncapped
+    #[serde(default)]
+    pub budget_headroom: Option<i32>,
 }
 
 pub async fn get_pool() -> Pool {
@@ -65,124 +82,125 @@ pub async fn get_pool() -> Pool {
     Pool::new(builder.pool_opts(pool_opts))
 }
 
-pub async fn project_exists(pool: &mysql_async::Pool, project_id: &str) -> anyhow::Result<bool> {
+pub async fn project_exists(pool: &mysql_async::Pool, project_id: &str) -> GosimResult<bool> {
     let mut conn = pool.get_conn().await?;
     let result: Option<u32> = conn
-        .query_first(format!(
-            "SELECT 1 FROM projects WHERE project_id = '{}'",
-            project_id
-        ))
+        .exec_first(
+            "SELECT 1 FROM projects WHERE project_id = :project_id",
+            params! { "project_id" => project_id },
+        )
         .await?;
 
     match result {
         Some(_) => Ok(true),
         None => {
             log::error!("Project not found");
-            Err(anyhow::Error::msg("Project not found"))
+            Err(Error::NotFound(format!("Project {}", project_id)))
         }
     }
 }
 
-pub async fn fill_project_w_repo_data(pool: &Pool, repo_data: RepoData) -> anyhow::Result<()> {
-    let mut conn = pool.get_conn().await?;
-
-    let project_description = if !repo_data.repo_description.is_empty() {
+pub fn project_description(repo_data: &RepoData) -> String {
+    if !repo_data.repo_description.is_empty() {
         repo_data.repo_description.clone()
     } else if !repo_data.repo_readme.is_empty() {
         repo_data.repo_readme.chars().take(1000).collect::<String>()
     } else {
         String::from("No description available")
-    };
+    }
+}
 
-    if   let Err(e) = conn
-        .exec_drop(
-            r"INSERT INTO projects (project_id, project_logo, main_language, repo_stars, project_description)
+pub async fn fill_project_w_repo_data(pool: &Pool, repo_data: RepoData) -> GosimResult<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let project_description = project_description(&repo_data);
+
+    conn.exec_drop(
+        r"INSERT INTO projects (project_id, project_logo, main_language, repo_stars, project_description)
         VALUES (:project_id, :project_logo, :main_language, :repo_stars, :project_description)
         ON DUPLICATE KEY UPDATE
         project_logo = VALUES(project_logo),
         main_language = VALUES(main_language),
         repo_stars = VALUES(repo_stars),
         project_description = VALUES(project_description);",
-            params! {
-                "project_id" => &repo_data.project_id,
-                "project_logo" => &repo_data.project_logo,
-                "main_language" => &repo_data.main_language,
-                "repo_stars" => repo_data.repo_stars,
-                "project_description" => project_description,
-            },
-        )
-        .await
-
-    {
-            log::error!("Failed to fill project with repo data: {:?}", e);
-    }
+        params! {
+            "project_id" => &repo_data.project_id,
+            "project_logo" => &repo_data.project_logo,
+            "main_language" => &repo_data.main_language,
+            "repo_stars" => repo_data.repo_stars,
+            "project_description" => project_description,
+        },
+    )
+    .await
+    .map_err(|e| {
+        log::error!("Failed to fill project with repo data: {:?}", e);
+        e
+    })?;
 
     Ok(())
 }
 
-pub async fn issue_exists(pool: &mysql_async::Pool, issue_id: &str) -> anyhow::Result<bool> {
+pub async fn issue_exists(pool: &mysql_async::Pool, issue_id: &str) -> GosimResult<bool> {
     let mut conn = pool.get_conn().await?;
     let result: Option<u32> = conn
-        .query_first(format!(
-            "SELECT 1 FROM issues WHERE issue_id = '{}'",
-            issue_id
-        ))
+        .exec_first(
+            "SELECT 1 FROM issues_master WHERE issue_id = :issue_id",
+            params! { "issue_id" => issue_id },
+        )
         .await?;
 
     match result {
         Some(_) => Ok(true),
         None => {
             log::error!("Issue not found");
-            Err(anyhow::Error::msg("Issue not found"))
+            Err(Error::NotFound(format!("Issue {}", issue_id)))
         }
     }
 }
 
-pub async fn pull_request_exists(pool: &mysql_async::Pool, pull_id: &str) -> anyhow::Result<bool> {
+pub async fn pull_request_exists(pool: &mysql_async::Pool, pull_id: &str) -> GosimResult<bool> {
     let mut conn = pool.get_conn().await?;
     let result: Option<u32> = conn
-        .query_first(format!(
-            "SELECT 1 FROM pull_requests WHERE pull_id = '{}'",
-            pull_id
-        ))
+        .exec_first(
+            "SELECT 1 FROM pull_requests WHERE pull_id = :pull_id",
+            params! { "pull_id" => pull_id },
+        )
         .await?;
 
     match result {
         Some(_) => Ok(true),
         None => {
             log::error!("Pull request not found");
-            Err(anyhow::Error::msg("Pull request not found"))
+            Err(Error::NotFound(format!("Pull request {}", pull_id)))
         }
     }
 }
 
-pub async fn add_issues_open(pool: &Pool, issue: &IssueOpen) -> Result<()> {
+pub async fn add_issues_open(pool: &Pool, issue: &IssueOpen) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_creator, issue_budget, issue_description)
-                  VALUES (:issue_id, :project_id, :issue_title, :issue_creator, :issue_budget, :issue_description)";
-
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_id" => &issue.issue_id,
-                "project_id" => &issue.project_id,
-                "issue_title" => &issue.issue_title,
-                "issue_creator" => &issue.issue_creator,
-                "issue_budget" => &issue.issue_budget,
-                "issue_description" => &issue.issue_description,
-            },
-        )
-        .await
-    {
-        // log::error!("Error add issues_open: {:?}", e);
-    };
+    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_creator, issue_budget, issue_budget_currency, issue_description, issue_labels)
+                  VALUES (:issue_id, :project_id, :issue_title, :issue_creator, :issue_budget, :issue_budget_currency, :issue_description, :issue_labels)";
+
+    conn.exec_drop(
+        query,
+        params! {
+            "issue_id" => &issue.issue_id,
+            "project_id" => &issue.project_id,
+            "issue_title" => &issue.issue_title,
+            "issue_creator" => &issue.issue_creator,
+            "issue_budget" => &issue.issue_budget,
+            "issue_budget_currency" => issue.issue_budget_currency.as_str(),
+            "issue_description" => &issue.issue_description,
+            "issue_labels" => json!(issue.issue_labels).to_string(),
+        },
+    )
+    .await?;
 
     Ok(())
 }
 
-pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> Result<()> {
+pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
     let query = r"INSERT INTO issues_comment (issue_id, comment_creator, comment_date, comment_body)
@@ -205,69 +223,69 @@ pub async fn add_issues_comment(pool: &Pool, issue: IssueComment) -> Result<()>
         )
         .await
     {
-        if let mysql_async::Error::Server(server_error) = &e {
-            if server_error.code == 23000 {
-                log::info!("Skipping duplicate comment: {:?}", issue);
-                return Ok(());
-            }
+        let e = Error::from(e);
+        match &e {
+            Error::Duplicate(_) => log::info!("Skipping duplicate comment: {:?}", issue),
+            _ => log::error!("Error add issues_comment: {:?}", e),
         }
-        log::error!("Error add issues_comment: {:?}", e);
-        return Err(e.into());
+        return Err(e);
     }
 
     Ok(())
 }
-pub async fn add_issues_open_batch(pool: &Pool, issues: Vec<IssueOpen>) -> Result<()> {
+pub async fn add_issues_open_batch(pool: &Pool, issues: Vec<IssueOpen>) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_budget, issue_description)
-                  VALUES (:issue_id, :project_id, :issue_title, :issue_budget, :issue_description)";
+    let query = r"INSERT INTO issues_open (issue_id, project_id, issue_title, issue_budget, issue_budget_currency, issue_description)
+                  VALUES (:issue_id, :project_id, :issue_title, :issue_budget, :issue_budget_currency, :issue_description)";
 
-    if let Err(e) = query
+    query
         .with(issues.iter().map(|issue| {
             params! {
                 "issue_id" => &issue.issue_id,
                 "project_id" => &issue.project_id,
                 "issue_title" => &issue.issue_title,
                 "issue_budget" => &issue.issue_budget,
+                "issue_budget_currency" => issue.issue_budget_currency.as_str(),
                 "issue_description" => &issue.issue_description,
             }
         }))
         .batch(&mut conn)
         .await
-    {
-        log::error!("Error add issues_open in batch: {:?}", e);
-    };
+        .map_err(|e| {
+            log::error!("Error add issues_open in batch: {:?}", e);
+            e
+        })?;
 
     Ok(())
 }
 
-pub async fn add_issues_closed(pool: &Pool, issue: IssueClosed) -> Result<()> {
+pub async fn add_issues_closed(pool: &Pool, issue: IssueClosed) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
-    let issue_assignees_json: Value = json!(issue.issue_assignees).into();
+    let issue_assignees_json: Value = json!(issue.issue_assignees);
 
     let query = r"INSERT INTO issues_closed (issue_id, issue_assignees, issue_linked_pr)
                   VALUES (:issue_id, :issue_assignees, :issue_linked_pr)";
 
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_id" => &issue.issue_id,
-                "issue_assignees" => &issue_assignees_json,
-                "issue_linked_pr" => issue.issue_linked_pr.as_deref(),
-            },
-        )
-        .await
-    {
+    conn.exec_drop(
+        query,
+        params! {
+            "issue_id" => &issue.issue_id,
+            "issue_assignees" => &issue_assignees_json,
+            "issue_linked_pr" => issue.issue_linked_pr.as_deref(),
+        },
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error add issues_closed: {:?}", e);
-    };
+        e
+    })?;
 
     Ok(())
 }
 
-pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) -> Result<()> {
+pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
     let issue_assignee = if issue_assigned.issue_assignee.is_empty() {
@@ -279,40 +297,40 @@ pub async fn add_issues_assigned(pool: &Pool, issue_assigned: IssueAssigned) ->
     let query = r"INSERT INTO issues_assigned (issue_id, issue_assignee, date_assigned)
                   VALUES (:issue_id, :issue_assignee, :date_assigned)";
 
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_id" => &issue_assigned.issue_id,
-                "issue_assignee" => &issue_assignee,
-                "date_assigned" => &issue_assigned.date_assigned,
-            },
-        )
-        .await
-    {
+    conn.exec_drop(
+        query,
+        params! {
+            "issue_id" => &issue_assigned.issue_id,
+            "issue_assignee" => &issue_assignee,
+            "date_assigned" => &issue_assigned.date_assigned,
+        },
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error add issues_assigned: {:?}", e);
-    };
+        e
+    })?;
 
     Ok(())
 }
 
-pub async fn mark_id_indexed(pool: &Pool, issue_or_project_id: &str) -> Result<()> {
+pub async fn mark_id_indexed(pool: &Pool, issue_or_project_id: &str) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
     let query = r"UPDATE issues_repos_summarized
     SET indexed=1 WHERE issue_or_project_id = :issue_or_project_id";
 
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_or_project_id" => &issue_or_project_id,
-            },
-        )
-        .await
-    {
+    conn.exec_drop(
+        query,
+        params! {
+            "issue_or_project_id" => &issue_or_project_id,
+        },
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error marking issue_or_project_id: {:?}", e);
-    };
+        e
+    })?;
 
     Ok(())
 }
@@ -322,7 +340,7 @@ pub async fn add_or_update_summary_and_id(
     issue_or_project_id: &str,
     issue_or_project_summary: &str,
     keyword_tags: Vec<String>,
-) -> Result<()> {
+) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
     let keyword_tags_json_str = json!(keyword_tags).to_string();
 
@@ -331,53 +349,55 @@ pub async fn add_or_update_summary_and_id(
     ON DUPLICATE KEY UPDATE
     keyword_tags = :keyword_tags_json_str;";
 
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "issue_or_project_id" => &issue_or_project_id,
-                "issue_or_project_summary" => &issue_or_project_summary,
-                "keyword_tags_json_str" => &keyword_tags_json_str,
-            },
-        )
-        .await
-    {
-        // Log the error if the query fails
+    conn.exec_drop(
+        query,
+        params! {
+            "issue_or_project_id" => &issue_or_project_id,
+            "issue_or_project_summary" => &issue_or_project_summary,
+            "keyword_tags_json_str" => &keyword_tags_json_str,
+        },
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error adding or updating issue_or_project_id: {:?}", e);
-        return Err(e);
-    };
+        e
+    })?;
 
     Ok(())
 }
 
 
-pub async fn add_pull_request(pool: &Pool, pull: OuterPull) -> Result<()> {
+pub async fn add_pull_request(pool: &Pool, pull: OuterPull) -> GosimResult<()> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"INSERT INTO pull_requests (pull_id, pull_title, pull_author, project_id, date_merged)
-                  VALUES (:pull_id, :pull_title, :pull_author, :project_id, :date_merged)";
-
-    if let Err(e) = conn
-        .exec_drop(
-            query,
-            params! {
-                "pull_id" => &pull.pull_id,
-                "pull_title" => &pull.pull_title,
-                "pull_author" => pull.pull_author.as_deref(),
-                "project_id" => &pull.project_id,
-                "date_merged" => pull.merged_at,
-            },
-        )
-        .await
-    {
+    let query = r"INSERT INTO pull_requests (pull_id, pull_title, pull_author, project_id, date_merged, merged_by, additions, deletions, changed_files, author_created_at)
+                  VALUES (:pull_id, :pull_title, :pull_author, :project_id, :date_merged, :merged_by, :additions, :deletions, :changed_files, :author_created_at)";
+
+    conn.exec_drop(
+        query,
+        params! {
+            "pull_id" => &pull.pull_id,
+            "pull_title" => &pull.pull_title,
+            "pull_author" => pull.pull_author.as_deref(),
+            "project_id" => &pull.project_id,
+            "date_merged" => pull.merged_at,
+            "merged_by" => pull.merged_by,
+            "additions" => pull.additions,
+            "deletions" => pull.deletions,
+            "changed_files" => pull.changed_files,
+            "author_created_at" => pull.author_created_at,
+        },
+    )
+    .await
+    .map_err(|e| {
         log::error!("Error add pull_request: {:?}", e);
-    };
+        e
+    })?;
 
     Ok(())
 }
 
-pub async fn get_issues_repos_from_db() -> Result<Vec<(String, String)>> {
-    let pool = get_pool().await;
+pub async fn get_issues_repos_from_db(pool: &Pool) -> GosimResult<Vec<(String, String)>> {
     let mut conn = pool.get_conn().await?;
 
     let query = r"SELECT issue_or_project_id, issue_or_project_summary FROM issues_repos_summarized WHERE indexed=0 limit 50";
@@ -394,11 +414,12 @@ pub async fn get_issues_repos_from_db() -> Result<Vec<(String, String)>> {
     Ok(entries)
 }
 
-pub async fn get_issues_from_db() -> Result<Vec<(String, String, String, Option<String>)>> {
-    let pool = get_pool().await;
+pub async fn get_issues_from_db(
+    pool: &Pool,
+) -> GosimResult<Vec<(String, String, String, Option<String>)>> {
     let mut conn = pool.get_conn().await?;
 
-    let query = r"SELECT issue_id, issue_title, issue_description, issue_assignees FROM issues_master WHERE issue_id not in (SELECT issue_or_project_id FROM issues_repos_summarized) limit 50";
+    let query = r"SELECT issue_id, issue_title, issue_description, issue_assignees FROM issues_master LEFT JOIN issue_assignee_lists USING (issue_id) WHERE issue_id not in (SELECT issue_or_project_id FROM issues_repos_summarized) limit 50";
 
     let issues: Vec<(String, String, String, Option<String>)> = conn
         .query_map(
@@ -415,7 +436,7 @@ pub async fn get_issues_from_db() -> Result<Vec<(String, String, String, Option<
     Ok(issues)
 }
 
-pub async fn summarize_issue_add_in_db(pool: &Pool, issue: &IssueOpen) -> anyhow::Result<()> {
+pub async fn summarize_issue_add_in_db(pool: &Pool, issue: &IssueOpen) -> GosimResult<()> {
     let issue_clone = issue.clone();
     let issue_title = issue_clone.issue_title;
     let issue_id = issue_clone.issue_id;
@@ -452,12 +473,10 @@ pub async fn summarize_issue_add_in_db(pool: &Pool, issue: &IssueOpen) -> anyhow
 
     let (summary, keyword_tags) = parse_summary_and_keywords(&generated_summary);
     // log::info!("{}, {:?}", issue_id, keyword_tags.clone());
-    let _ = add_or_update_summary_and_id(&pool, &issue_id, &summary, keyword_tags).await;
-
-    Ok(())
+    add_or_update_summary_and_id(pool, &issue_id, &summary, keyword_tags).await
 }
 
-pub async fn summarize_project_add_in_db(pool: &Pool, repo_data: RepoData) -> anyhow::Result<()> {
+pub async fn summarize_project_add_in_db(pool: &Pool, repo_data: RepoData) -> GosimResult<()> {
     let parts: Vec<&str> = repo_data.project_id.split('/').collect();
     let owner = parts[3].to_string();
     let repo = parts[4].to_string();
@@ -508,7 +527,5 @@ pub async fn summarize_project_add_in_db(pool: &Pool, repo_data: RepoData) -> an
     let (summary, keyword_tags) = parse_summary_and_keywords(&generated_summary);
     //  log::info!("keywords: {:?}", &keyword_tags);
 
-    let _ =
-        add_or_update_summary_and_id(&pool, &repo_data.project_id, &summary, keyword_tags).await;
-    Ok(())
+    add_or_update_summary_and_id(pool, &repo_data.project_id, &summary, keyword_tags).await
 }
@@ -0,0 +1,649 @@
+use crate::error::{Error, GosimResult};
+use chrono::NaiveDate;
+use mysql_async::{Params, Value};
+use serde::{Deserialize, Serialize};
+
+// Named conditions, kept for the list_by / filter_strs names the frontend sends.
+// Only the fragments in these tables and generated placeholders are ever put in
+// SQL text, anything a caller supplies is looked up here or bound as a parameter.
+pub const ISSUE_CONDITIONS: &[(&str, &str)] = &[
+    ("main_language", "LENGTH(main_language) > 0"),
+    ("issue_assignees", "issue_assignees IS NOT NULL"),
+    ("queue", "review_status = 'queue'"),
+    ("approve", "review_status = 'approve'"),
+    ("decline", "review_status = 'decline'"),
+];
+
+// (name, column, descending unless asked otherwise)
+pub const ISSUE_SORTS: &[(&str, &str, bool)] = &[
+    ("repo_stars", "repo_stars", true),
+    ("issue_title", "issue_title", false),
+    ("main_language", "main_language", false),
+    ("issue_creator", "issue_creator", false),
+    ("issue_budget", "issue_budget", true),
+    ("issue_assignees", "issue_assignees", false),
+    ("date_issue_assigned", "date_issue_assigned", false),
+];
+
+pub const PROJECT_CONDITIONS: &[(&str, &str)] = &[("main_language", "LENGTH(main_language) > 0")];
+
+pub const PROJECT_SORTS: &[(&str, &str, bool)] = &[
+    ("issues_count", "JSON_LENGTH(issues_list)", true),
+    ("total_budget_allocated", "total_budget_allocated", true),
+    ("repo_stars", "repo_stars", true),
+    ("main_language", "main_language", false),
+];
+
+const REVIEW_STATUSES: &[&str] = &["queue", "approve", "decline"];
+
+// Keeps one request from building an arbitrarily large statement.
+const MAX_FILTER_VALUES: usize = 64;
+const MAX_FILTER_TERMS: usize = 256;
+const MAX_SORT_KEYS: usize = 8;
+// Largest page a list or export asks for at once, bigger page sizes are clamped.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+// (offset, limit) of a 1-based page. The offset saturates, so a huge page number
+// reads past the last row instead of overflowing.
+pub fn page_window(page: usize, page_size: usize) -> (usize, usize) {
+    let limit = page_size.min(MAX_PAGE_SIZE);
+    ((page.max(1) - 1).saturating_mul(limit), limit)
+}
+
+pub fn lookup(table: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, fragment)| *fragment)
+}
+
+pub fn lookup_sort(
+    table: &[(&str, &'static str, bool)],
+    key: &str,
+) -> Option<(&'static str, bool)> {
+    table
+        .iter()
+        .find(|(name, _, _)| *name == key)
+        .map(|(_, column, desc)| (*column, *desc))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NumRange {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+// Inclusive YYYY-MM-DD bounds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DateRange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+// e.g. {"and": [{"review_status": "queue"}, {"or": [{"language": "Rust"}, {"language": "Go"}]}]}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueFilter {
+    And(Vec<IssueFilter>),
+    Or(Vec<IssueFilter>),
+    Preset(String),
+    ReviewStatus(String),
+    Project(String),
+    Language(String),
+    Assignee(String),
+    Label(String),
+    Budget(NumRange),
+    DateAssigned(DateRange),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectFilter {
+    And(Vec<ProjectFilter>),
+    Or(Vec<ProjectFilter>),
+    Preset(String),
+    Language(String),
+    Stars(NumRange),
+    Budget(NumRange),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Without an order the column's usual direction applies, e.g. repo_stars DESC.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SortKey {
+    pub field: String,
+    #[serde(default)]
+    pub order: Option<SortOrder>,
+}
+
+impl SortKey {
+    pub fn is_desc(&self, default_desc: bool) -> bool {
+        self.order.map_or(default_desc, |o| o == SortOrder::Desc)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IssueQuery {
+    #[serde(default)]
+    pub filter: Option<IssueFilter>,
+    #[serde(default)]
+    pub sort: Vec<SortKey>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectQuery {
+    #[serde(default)]
+    pub filter: Option<ProjectFilter>,
+    #[serde(default)]
+    pub sort: Vec<SortKey>,
+}
+
+impl IssueQuery {
+    // The list_by / filter_strs names: every condition applies, sorts in order.
+    pub fn from_names(names: &[&str]) -> GosimResult<IssueQuery> {
+        let mut conditions = Vec::new();
+        let mut sort = Vec::new();
+        for &name in names {
+            if lookup(ISSUE_CONDITIONS, name).is_some() {
+                conditions.push(IssueFilter::Preset(name.to_string()));
+            } else if lookup_sort(ISSUE_SORTS, name).is_some() {
+                sort.push(SortKey {
+                    field: name.to_string(),
+                    order: None,
+                });
+            } else {
+                return Err(Error::Validation(format!("unknown filter '{}'", name)));
+            }
+        }
+
+        Ok(IssueQuery {
+            filter: (!conditions.is_empty()).then_some(IssueFilter::And(conditions)),
+            sort,
+        })
+    }
+
+    pub fn and(self, other: IssueQuery) -> IssueQuery {
+        let filter = match (self.filter, other.filter) {
+            (Some(a), Some(b)) => Some(IssueFilter::And(vec![a, b])),
+            (a, b) => a.or(b),
+        };
+        IssueQuery {
+            filter,
+            sort: self.sort.into_iter().chain(other.sort).collect(),
+        }
+    }
+
+    // The checks the SQL builder makes, for stores that don't build SQL.
+    pub fn validate(&self) -> GosimResult<()> {
+        SelectQuery::new("").issue_query(self).map(|_| ())
+    }
+}
+
+impl ProjectQuery {
+    // list_by names one condition or sort, "main_language" is both.
+    pub fn from_list_by(list_by: Option<&str>) -> GosimResult<ProjectQuery> {
+        let list_by = match list_by {
+            Some(list_by) => list_by,
+            None => return Ok(ProjectQuery::default()),
+        };
+        let is_condition = lookup(PROJECT_CONDITIONS, list_by).is_some();
+        let is_sort = lookup_sort(PROJECT_SORTS, list_by).is_some();
+        if !is_condition && !is_sort {
+            return Err(Error::Validation(format!("unknown list_by '{}'", list_by)));
+        }
+
+        let mut query = ProjectQuery::default();
+        if is_condition {
+            query.filter = Some(ProjectFilter::Preset(list_by.to_string()));
+        }
+        if is_sort {
+            query.sort.push(SortKey {
+                field: list_by.to_string(),
+                order: None,
+            });
+        }
+        Ok(query)
+    }
+
+    pub fn validate(&self) -> GosimResult<()> {
+        SelectQuery::new("").project_query(self).map(|_| ())
+    }
+}
+
+pub fn parse_date(date: &str) -> GosimResult<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| Error::Validation(format!("invalid date '{}', expected YYYY-MM-DD", date)))
+}
+
+// SELECT with whitelisted WHERE/ORDER BY fragments and bound values. The SQL text
+// is put together from &'static fragments and generated placeholder names only.
+pub struct SelectQuery {
+    select: String,
+    conditions: Vec<String>,
+    order_bys: Vec<(&'static str, bool)>,
+    params: Vec<(String, Value)>,
+    // (offset, limit)
+    page: Option<(usize, usize)>,
+    // filter nodes seen, values or not
+    terms: usize,
+}
+
+impl SelectQuery {
+    pub fn new(select: &str) -> Self {
+        SelectQuery {
+            select: select.to_string(),
+            conditions: Vec::new(),
+            order_bys: Vec::new(),
+            params: Vec::new(),
+            page: None,
+            terms: 0,
+        }
+    }
+
+    pub fn filter(mut self, condition: &'static str) -> Self {
+        self.conditions.push(condition.to_string());
+        self
+    }
+
+    pub fn order_by(mut self, column: &'static str, desc: bool) -> Self {
+        self.order_bys.push((column, desc));
+        self
+    }
+
+    pub fn page(mut self, page: usize, page_size: usize) -> Self {
+        self.page = Some(page_window(page, page_size));
+        self
+    }
+
+    pub fn issue_query(mut self, query: &IssueQuery) -> GosimResult<Self> {
+        if let Some(filter) = &query.filter {
+            let condition = self.issue_condition(filter)?;
+            self.conditions.push(condition);
+        }
+        for key in &query.sort {
+            let (column, default_desc) = lookup_sort(ISSUE_SORTS, &key.field)
+                .ok_or_else(|| Error::Validation(format!("unknown sort '{}'", key.field)))?;
+            self = self.order_by(column, key.is_desc(default_desc));
+        }
+        self.check_size()
+    }
+
+    pub fn project_query(mut self, query: &ProjectQuery) -> GosimResult<Self> {
+        if let Some(filter) = &query.filter {
+            let condition = self.project_condition(filter)?;
+            self.conditions.push(condition);
+        }
+        for key in &query.sort {
+            let (column, default_desc) = lookup_sort(PROJECT_SORTS, &key.field)
+                .ok_or_else(|| Error::Validation(format!("unknown sort '{}'", key.field)))?;
+            self = self.order_by(column, key.is_desc(default_desc));
+        }
+        self.check_size()
+    }
+
+    fn check_size(self) -> GosimResult<Self> {
+        if self.params.len() > MAX_FILTER_VALUES {
+            return Err(Error::Validation(format!(
+                "filter has more than {} values",
+                MAX_FILTER_VALUES
+            )));
+        }
+        if self.terms > MAX_FILTER_TERMS {
+            return Err(Error::Validation(format!(
+                "filter has more than {} terms",
+                MAX_FILTER_TERMS
+            )));
+        }
+        if self.order_bys.len() > MAX_SORT_KEYS {
+            return Err(Error::Validation(format!(
+                "more than {} sort keys",
+                MAX_SORT_KEYS
+            )));
+        }
+        Ok(self)
+    }
+
+    fn bind(&mut self, value: impl Into<Value>) -> String {
+        let name = format!("p{}", self.params.len());
+        let placeholder = format!(":{}", name);
+        self.params.push((name, value.into()));
+        placeholder
+    }
+
+    fn issue_condition(&mut self, filter: &IssueFilter) -> GosimResult<String> {
+        self.terms += 1;
+        Ok(match filter {
+            IssueFilter::And(filters) => {
+                let parts = filters
+                    .iter()
+                    .map(|f| self.issue_condition(f))
+                    .collect::<GosimResult<Vec<_>>>()?;
+                join_conditions(parts, " AND ", "TRUE")
+            }
+            IssueFilter::Or(filters) => {
+                let parts = filters
+                    .iter()
+                    .map(|f| self.issue_condition(f))
+                    .collect::<GosimResult<Vec<_>>>()?;
+                join_conditions(parts, " OR ", "FALSE")
+            }
+            IssueFilter::Preset(name) => lookup(ISSUE_CONDITIONS, name)
+                .ok_or_else(|| Error::Validation(format!("unknown filter '{}'", name)))?
+                .to_string(),
+            IssueFilter::ReviewStatus(status) => {
+                if !REVIEW_STATUSES.contains(&status.as_str()) {
+                    return Err(Error::Validation(format!(
+                        "unknown review_status '{}'",
+                        status
+                    )));
+                }
+                format!("review_status = {}", self.bind(status.as_str()))
+            }
+            IssueFilter::Project(project_id) => {
+                format!("project_id = {}", self.bind(project_id.as_str()))
+            }
+            IssueFilter::Language(language) => {
+                format!("main_language = {}", self.bind(language.as_str()))
+            }
+            IssueFilter::Assignee(login) => format!(
+                "JSON_CONTAINS(issue_assignees, JSON_QUOTE({}))",
+                self.bind(login.as_str())
+            ),
+            IssueFilter::Label(label) => format!(
+                "JSON_CONTAINS(issue_labels, JSON_QUOTE({}))",
+                self.bind(label.as_str())
+            ),
+            IssueFilter::Budget(range) => self.range_condition("issue_budget", range)?,
+            IssueFilter::DateAssigned(range) => {
+                self.date_condition("date_issue_assigned", range)?
+            }
+        })
+    }
+
+    fn project_condition(&mut self, filter: &ProjectFilter) -> GosimResult<String> {
+        self.terms += 1;
+        Ok(match filter {
+            ProjectFilter::And(filters) => {
+                let parts = filters
+                    .iter()
+                    .map(|f| self.project_condition(f))
+                    .collect::<GosimResult<Vec<_>>>()?;
+                join_conditions(parts, " AND ", "TRUE")
+            }
+            ProjectFilter::Or(filters) => {
+                let parts = filters
+                    .iter()
+                    .map(|f| self.project_condition(f))
+                    .collect::<GosimResult<Vec<_>>>()?;
+                join_conditions(parts, " OR ", "FALSE")
+            }
+            ProjectFilter::Preset(name) => lookup(PROJECT_CONDITIONS, name)
+                .ok_or_else(|| Error::Validation(format!("unknown filter '{}'", name)))?
+                .to_string(),
+            ProjectFilter::Language(language) => {
+                format!("main_language = {}", self.bind(language.as_str()))
+            }
+            ProjectFilter::Stars(range) => self.range_condition("repo_stars", range)?,
+            ProjectFilter::Budget(range) => {
+                self.range_condition("total_budget_allocated", range)?
+            }
+        })
+    }
+
+    fn range_condition(&mut self, column: &'static str, range: &NumRange) -> GosimResult<String> {
+        check_range(column, range)?;
+
+        let mut parts = Vec::new();
+        if let Some(min) = range.min {
+            parts.push(format!("{} >= {}", column, self.bind(min)));
+        }
+        if let Some(max) = range.max {
+            parts.push(format!("{} <= {}", column, self.bind(max)));
+        }
+        Ok(join_conditions(parts, " AND ", "TRUE"))
+    }
+
+    fn date_condition(&mut self, column: &'static str, range: &DateRange) -> GosimResult<String> {
+        let (from, to) = parse_date_range(column, range)?;
+
+        let mut parts = Vec::new();
+        if let Some(from) = from {
+            let from = from.format("%Y-%m-%d 00:00:00").to_string();
+            parts.push(format!("{} >= {}", column, self.bind(from)));
+        }
+        // `to` is inclusive, so compare against the start of the next day
+        if let Some(to) = to.and_then(|to| to.succ_opt()) {
+            let to = to.format("%Y-%m-%d 00:00:00").to_string();
+            parts.push(format!("{} < {}", column, self.bind(to)));
+        }
+        Ok(join_conditions(parts, " AND ", "TRUE"))
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    // COUNT over the same conditions, without sorting or paging.
+    pub fn build_count(&self, count_select: &'static str) -> (String, Params) {
+        let query = format!("{}{}", count_select, self.where_clause());
+        (query, to_params(self.params.clone()))
+    }
+
+    pub fn build(self) -> (String, Params) {
+        let mut query = format!("{}{}", self.select, self.where_clause());
+        if !self.order_bys.is_empty() {
+            let order_bys: Vec<String> = self
+                .order_bys
+                .iter()
+                .map(|(column, desc)| format!("{} {}", column, if *desc { "DESC" } else { "ASC" }))
+                .collect();
+            query.push_str(" ORDER BY ");
+            query.push_str(&order_bys.join(", "));
+        }
+
+        let mut params = self.params;
+        if let Some((offset, limit)) = self.page {
+            query.push_str(" LIMIT :limit OFFSET :offset");
+            params.push((String::from("limit"), Value::from(limit as u64)));
+            params.push((String::from("offset"), Value::from(offset as u64)));
+        }
+        (query, to_params(params))
+    }
+}
+
+pub fn check_range(column: &str, range: &NumRange) -> GosimResult<()> {
+    match (range.min, range.max) {
+        (None, None) => Err(Error::Validation(format!(
+            "{} range needs min or max",
+            column
+        ))),
+        (Some(min), Some(max)) if min > max => Err(Error::Validation(format!(
+            "{} range has min {} above max {}",
+            column, min, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_date_range(
+    column: &str,
+    range: &DateRange,
+) -> GosimResult<(Option<NaiveDate>, Option<NaiveDate>)> {
+    let from = range.from.as_deref().map(parse_date).transpose()?;
+    let to = range.to.as_deref().map(parse_date).transpose()?;
+    match (from, to) {
+        (None, None) => Err(Error::Validation(format!(
+            "{} range needs from or to",
+            column
+        ))),
+        (Some(from), Some(to)) if from > to => Err(Error::Validation(format!(
+            "{} range starts after it ends",
+            column
+        ))),
+        _ => Ok((from, to)),
+    }
+}
+
+fn join_conditions(parts: Vec<String>, separator: &str, empty: &str) -> String {
+    if parts.is_empty() {
+        empty.to_string()
+    } else {
+        format!("({})", parts.join(separator))
+    }
+}
+
+fn to_params(params: Vec<(String, Value)>) -> Params {
+    if params.is_empty() {
+        Params::Empty
+    } else {
+        Params::from(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn named(params: Params) -> HashMap<String, Value> {
+        match params {
+            Params::Empty => HashMap::new(),
+            Params::Named(params) => params
+                .into_iter()
+                .map(|(name, value)| (String::from_utf8(name).unwrap(), value))
+                .collect(),
+            Params::Positional(_) => panic!("positional params"),
+        }
+    }
+
+    #[test]
+    fn build_without_filter_sort_or_page() {
+        let (query, params) = SelectQuery::new("SELECT * FROM projects").build();
+        assert_eq!(query, "SELECT * FROM projects");
+        assert!(named(params).is_empty());
+    }
+
+    #[test]
+    fn build_binds_filter_values() {
+        let query = IssueQuery {
+            filter: Some(IssueFilter::And(vec![
+                IssueFilter::Preset(String::from("issue_assignees")),
+                IssueFilter::Or(vec![
+                    IssueFilter::Language(String::from("Rust' OR 1=1 --")),
+                    IssueFilter::Language(String::from("Go")),
+                ]),
+                IssueFilter::Budget(NumRange {
+                    min: Some(10),
+                    max: None,
+                }),
+            ])),
+            sort: vec![
+                SortKey {
+                    field: String::from("repo_stars"),
+                    order: None,
+                },
+                SortKey {
+                    field: String::from("issue_title"),
+                    order: Some(SortOrder::Desc),
+                },
+            ],
+        };
+        let (sql, params) = SelectQuery::new("SELECT * FROM issues_master")
+            .issue_query(&query)
+            .unwrap()
+            .page(2, 20)
+            .build();
+
+        assert_eq!(
+            sql,
+            "SELECT * FROM issues_master WHERE (issue_assignees IS NOT NULL AND (main_language = :p0 OR main_language = :p1) AND (issue_budget >= :p2)) ORDER BY repo_stars DESC, issue_title DESC LIMIT :limit OFFSET :offset"
+        );
+        let params = named(params);
+        assert_eq!(params.len(), 5);
+        assert_eq!(params["p0"], Value::from("Rust' OR 1=1 --"));
+        assert_eq!(params["p1"], Value::from("Go"));
+        assert_eq!(params["p2"], Value::from(10i64));
+        assert_eq!(params["limit"], Value::from(20u64));
+        assert_eq!(params["offset"], Value::from(20u64));
+    }
+
+    #[test]
+    fn build_count_skips_sort_and_page() {
+        let query = ProjectQuery {
+            filter: Some(ProjectFilter::Stars(NumRange {
+                min: None,
+                max: Some(500),
+            })),
+            sort: vec![SortKey {
+                field: String::from("issues_count"),
+                order: Some(SortOrder::Asc),
+            }],
+        };
+        let select = SelectQuery::new("SELECT * FROM projects")
+            .project_query(&query)
+            .unwrap()
+            .page(3, 10);
+
+        let (sql, params) = select.build_count("SELECT COUNT(*) FROM projects");
+        assert_eq!(
+            sql,
+            "SELECT COUNT(*) FROM projects WHERE (repo_stars <= :p0)"
+        );
+        assert_eq!(named(params).len(), 1);
+
+        let (sql, _) = select.build();
+        assert!(sql.ends_with("ORDER BY JSON_LENGTH(issues_list) ASC LIMIT :limit OFFSET :offset"));
+    }
+
+    #[test]
+    fn page_size_is_clamped_and_offset_saturates() {
+        let (_, params) = SelectQuery::new("SELECT * FROM projects")
+            .page(usize::MAX, usize::MAX)
+            .build();
+        let params = named(params);
+        assert_eq!(params["limit"], Value::from(MAX_PAGE_SIZE as u64));
+        assert_eq!(params["offset"], Value::from(usize::MAX as u64));
+
+        assert_eq!(page_window(0, 10), (0, 10));
+        assert_eq!(page_window(1, 10), (0, 10));
+        assert_eq!(page_window(4, 10), (30, 10));
+        assert_eq!(
+            page_window(2, MAX_PAGE_SIZE + 1),
+            (MAX_PAGE_SIZE, MAX_PAGE_SIZE)
+        );
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        let sort = IssueQuery {
+            filter: None,
+            sort: vec![SortKey {
+                field: String::from("repo_stars; DROP TABLE projects"),
+                order: None,
+            }],
+        };
+        assert!(sort.validate().is_err());
+        let status = IssueQuery {
+            filter: Some(IssueFilter::ReviewStatus(String::from("queue' --"))),
+            sort: Vec::new(),
+        };
+        assert!(status.validate().is_err());
+        assert!(IssueQuery::from_names(&["queue", "nope"]).is_err());
+        assert!(ProjectQuery::from_list_by(Some("issue_budget")).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::currency::{validate_rate, Currency, ExchangeRate, Rates};
+use crate::error::{Error, GosimResult};
+use chrono::NaiveDate;
+use mysql_async::prelude::*;
+use mysql_async::*;
+
+// Stores the rates of a rates file. A rate loaded again for the same day and
+// currency replaces the earlier one.
+pub async fn load_rates(pool: &Pool, rates: &[ExchangeRate]) -> GosimResult<usize> {
+    for rate in rates {
+        validate_rate(rate)?;
+    }
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_batch(
+        r"INSERT INTO exchange_rates (rate_date, currency, rate)
+          VALUES (:rate_date, :currency, :rate)
+          ON DUPLICATE KEY UPDATE rate = VALUES(rate)",
+        rates.iter().map(|rate| {
+            params! {
+                "rate_date" => &rate.rate_date,
+                "currency" => rate.currency.as_str(),
+                "rate" => rate.rate,
+            }
+        }),
+    )
+    .await?;
+
+    Ok(rates.len())
+}
+
+// Every rate up to `as_of`, oldest first.
+pub async fn list_rates<Q: Queryable>(
+    conn: &mut Q,
+    as_of: Option<NaiveDate>,
+) -> GosimResult<Vec<ExchangeRate>> {
+    let rows: Vec<(String, String, f64)> = conn
+        .exec(
+            r"SELECT DATE_FORMAT(rate_date, '%Y-%m-%d'), currency, CAST(rate AS DOUBLE)
+              FROM exchange_rates
+              WHERE :as_of IS NULL OR rate_date <= :as_of
+              ORDER BY rate_date, currency",
+            params! { "as_of" => as_of.map(|d| d.format("%Y-%m-%d").to_string()) },
+        )
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(rate_date, currency, rate)| ExchangeRate {
+            rate_date,
+            currency: Currency::from_db(&currency),
+            rate,
+        })
+        .collect())
+}
+
+// The rates in effect at the end of `as_of`, or the latest ones.
+pub async fn rates_as_of<Q: Queryable>(
+    conn: &mut Q,
+    as_of: Option<NaiveDate>,
+) -> GosimResult<Rates> {
+    let rates = list_rates(conn, as_of).await?;
+    let as_of = as_of.map(|d| d.format("%Y-%m-%d").to_string());
+
+    Ok(Rates::snapshot(&rates, as_of.as_deref()))
+}
+
+fn usd_value_sql(currency: &str) -> String {
+    format!(
+        r"CASE WHEN {currency} = 'USD' THEN 1 ELSE (
+            SELECT r.rate FROM exchange_rates r WHERE r.currency = {currency}
+            ORDER BY r.rate_date DESC LIMIT 1) END",
+        currency = currency
+    )
+}
+
+// The amount of ledger entry `e` in `currency`, at the latest rates, for sums in
+// SQL. It is NULL when there's no rate for the entry's currency, so a sum would
+// leave the entry out: run check_ledger_rates or check_rate first, they fail the
+// way Rates::convert does.
+pub fn converted_amount_sql(currency: Currency) -> String {
+    let target = format!("'{}'", currency.as_str());
+    format!(
+        r"CASE WHEN e.currency = {target} THEN e.amount
+            ELSE ROUND(e.amount * ({from}) / ({to})) END",
+        target = target,
+        from = usd_value_sql("e.currency"),
+        to = usd_value_sql(&target)
+    )
+}
+
+// Errors when converted_amount_sql would drop ledger money of the project, or of
+// every project, because its currency or `currency` has no rate.
+pub async fn check_ledger_rates<Q: Queryable>(
+    conn: &mut Q,
+    project_id: Option<&str>,
+    currency: Currency,
+) -> GosimResult<()> {
+    let currencies: Vec<String> = conn
+        .exec(
+            r"SELECT DISTINCT e.currency FROM ledger_entries e
+              WHERE e.project_id IS NOT NULL
+                AND (:project_id IS NULL OR e.project_id = :project_id)",
+            params! { "project_id" => project_id },
+        )
+        .await?;
+    let rates = rates_as_of(conn, None).await?;
+
+    for from in currencies {
+        rates.convert(1, Currency::from_db(&from), currency)?;
+    }
+    Ok(())
+}
+
+// The projects whose ledger money in `accounts` converted_amount_sql would drop,
+// each with the error Rates::convert gives for its first currency without a rate.
+pub async fn projects_without_rates<Q: Queryable>(
+    conn: &mut Q,
+    accounts: &[&str],
+    currency: Currency,
+) -> GosimResult<Vec<(String, Error)>> {
+    if accounts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = vec!["?"; accounts.len()].join(", ");
+    let project_currencies: Vec<(String, String)> = conn
+        .exec(
+            format!(
+                r"SELECT DISTINCT e.project_id, e.currency FROM ledger_entries e
+                  WHERE e.project_id IS NOT NULL AND e.account IN ({placeholders})
+                  ORDER BY e.project_id, e.currency"
+            ),
+            Params::Positional(accounts.iter().map(|a| Value::from(*a)).collect()),
+        )
+        .await?;
+    let rates = rates_as_of(conn, None).await?;
+
+    let mut missing: Vec<(String, Error)> = Vec::new();
+    for (project_id, from) in project_currencies {
+        if missing.last().is_some_and(|(p, _)| *p == project_id) {
+            continue;
+        }
+        if let Err(e) = rates.convert(1, Currency::from_db(&from), currency) {
+            missing.push((project_id, e));
+        }
+    }
+    Ok(missing)
+}
+
+// Errors when money in `from` can't be converted into `to` at the latest rates,
+// for writes that only add money in `from`.
+pub async fn check_rate<Q: Queryable>(
+    conn: &mut Q,
+    from: Currency,
+    to: Currency,
+) -> GosimResult<()> {
+    if from == to {
+        return Ok(());
+    }
+    rates_as_of(conn, None).await?.convert(1, from, to)?;
+    Ok(())
+}
@@ -0,0 +1,528 @@
+use crate::currency::{reporting_currency, Currency, Rates};
+use crate::db_ledger::Account;
+use crate::db_rates::rates_as_of;
+use crate::error::{Error, GosimResult};
+use crate::review_state::ReviewState;
+use crate::END_DATE;
+use chrono::{NaiveDate, Utc};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Budget reporting from the ledger: how committed and paid money grew day by day,
+// where the campaign is headed by its end, and which projects and languages the
+// money went to. Everything is in the reporting currency at the latest rates, so
+// the days compare.
+
+// One ledger entry, with the day of its transaction and what its issue is in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReportEntry {
+    // %Y-%m-%d
+    pub date: String,
+    pub account: Option<Account>,
+    pub currency: Currency,
+    pub amount: i64,
+    pub issue_id: Option<String>,
+    pub project_id: Option<String>,
+    pub main_language: Option<String>,
+}
+
+// The running totals at the end of a day. Committed is what is allocated,
+// approved or paid.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BurnDownPoint {
+    pub date: String,
+    pub funded: i64,
+    pub committed: i64,
+    pub paid: i64,
+    // funded but not committed yet
+    pub remaining: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetForecast {
+    pub campaign_end: String,
+    pub days_left: i64,
+    // committed money per day since the first allocation
+    pub commit_rate: f64,
+    pub queued_issues: i64,
+    // what the queued issues ask for
+    pub queued_budget: i64,
+    // approved issues out of approved and declined ones, None before any decision
+    pub approval_rate: Option<f64>,
+    // committed plus commit_rate for the days left
+    pub pace_projection: i64,
+    // committed plus the queued budget at the approval rate
+    pub queue_projection: i64,
+    // the larger of the two projections
+    pub projected_spend: i64,
+    pub projected_balance: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetBreakdown {
+    pub key: String,
+    pub issues: i64,
+    pub committed: i64,
+    pub paid: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BudgetReport {
+    // None for every campaign
+    pub campaign_id: Option<String>,
+    pub currency: Currency,
+    pub funded: i64,
+    pub available: i64,
+    pub committed: i64,
+    pub paid: i64,
+    pub burn_down: Vec<BurnDownPoint>,
+    pub forecast: BudgetForecast,
+    pub by_project: Vec<BudgetBreakdown>,
+    pub by_language: Vec<BudgetBreakdown>,
+}
+
+// What the report reads from the issues: the queued budgets and how many issues
+// are in each review state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReviewFacts {
+    pub queued: Vec<(i64, Currency)>,
+    pub states: Vec<(ReviewState, i64)>,
+}
+
+// The day the campaign ends, END_DATE.
+pub fn campaign_end() -> NaiveDate {
+    NaiveDate::parse_from_str(END_DATE, "%Y-%m-%d").unwrap_or_else(|_| Utc::now().date_naive())
+}
+
+fn is_committed(account: Option<Account>) -> bool {
+    matches!(
+        account,
+        Some(Account::Allocated | Account::Approved | Account::Paid)
+    )
+}
+
+fn breakdown(
+    entries: &[(ReportEntry, i64)],
+    key: impl Fn(&ReportEntry) -> Option<String>,
+) -> Vec<BudgetBreakdown> {
+    // (key, issue_id) -> (committed, paid)
+    let mut issues: BTreeMap<(String, &str), (i64, i64)> = BTreeMap::new();
+    for (entry, amount) in entries {
+        let (key, issue_id) = match (key(entry), &entry.issue_id) {
+            (Some(key), Some(issue_id)) if is_committed(entry.account) => (key, issue_id),
+            _ => continue,
+        };
+        let issue = issues.entry((key, issue_id.as_str())).or_default();
+        issue.0 += amount;
+        if entry.account == Some(Account::Paid) {
+            issue.1 += amount;
+        }
+    }
+
+    // an issue whose budget was released no longer counts
+    let mut groups: BTreeMap<String, BudgetBreakdown> = BTreeMap::new();
+    for ((key, _), (committed, paid)) in issues {
+        if committed == 0 && paid == 0 {
+            continue;
+        }
+        let group = groups
+            .entry(key.clone())
+            .or_insert_with(|| BudgetBreakdown {
+                key,
+                ..Default::default()
+            });
+        group.issues += 1;
+        group.committed += committed;
+        group.paid += paid;
+    }
+
+    let mut rows: Vec<BudgetBreakdown> = groups.into_values().collect();
+    rows.sort_by(|a, b| b.committed.cmp(&a.committed).then(a.key.cmp(&b.key)));
+    rows
+}
+
+// Builds the report from `entries` in time order, converted into `currency` at
+// `rates`. It fails like the running budget does when a currency has no rate.
+pub fn build_report(
+    campaign_id: Option<&str>,
+    entries: &[ReportEntry],
+    review: &ReviewFacts,
+    rates: &Rates,
+    currency: Currency,
+    today: NaiveDate,
+    end: NaiveDate,
+) -> GosimResult<BudgetReport> {
+    let mut converted = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let amount = rates.convert(entry.amount, entry.currency, currency)?;
+        converted.push((entry.clone(), amount));
+    }
+
+    let mut report = BudgetReport {
+        campaign_id: campaign_id.map(String::from),
+        currency,
+        ..Default::default()
+    };
+    let mut first_allocation: Option<NaiveDate> = None;
+    for (entry, amount) in &converted {
+        match entry.account {
+            Some(Account::Funding) => report.funded -= amount,
+            Some(Account::Available) => report.available += amount,
+            Some(Account::Paid) => {
+                report.committed += amount;
+                report.paid += amount;
+            }
+            Some(Account::Allocated | Account::Approved) => report.committed += amount,
+            None => {}
+        }
+        if entry.account == Some(Account::Allocated) && first_allocation.is_none() {
+            first_allocation = NaiveDate::parse_from_str(&entry.date, "%Y-%m-%d").ok();
+        }
+
+        let point = BurnDownPoint {
+            date: entry.date.clone(),
+            funded: report.funded,
+            committed: report.committed,
+            paid: report.paid,
+            remaining: report.funded - report.committed,
+        };
+        match report.burn_down.last_mut() {
+            Some(last) if last.date == point.date => *last = point,
+            _ => report.burn_down.push(point),
+        }
+    }
+
+    // after the end the rate is what it was over the whole campaign
+    let until = today.min(end);
+    let commit_rate = match first_allocation {
+        Some(first) if first <= until => {
+            report.committed as f64 / ((until - first).num_days() + 1) as f64
+        }
+        _ => 0.0,
+    };
+    let days_left = (end - today).num_days().max(0);
+
+    let mut queued_budget = 0;
+    for (issue_budget, issue_currency) in &review.queued {
+        queued_budget += rates.convert(*issue_budget, *issue_currency, currency)?;
+    }
+    let count = |wanted: &dyn Fn(ReviewState) -> bool| -> i64 {
+        review
+            .states
+            .iter()
+            .filter(|(state, _)| wanted(*state))
+            .map(|(_, count)| count)
+            .sum()
+    };
+    let approved = count(&|state| state != ReviewState::Queued && !state.is_closed());
+    let declined = count(&|state| state == ReviewState::Declined);
+    let approval_rate =
+        (approved + declined > 0).then(|| approved as f64 / (approved + declined) as f64);
+
+    let pace_projection = report.committed + (commit_rate * days_left as f64).round() as i64;
+    // without a decision yet the whole queue is counted
+    let queue_projection =
+        report.committed + (queued_budget as f64 * approval_rate.unwrap_or(1.0)).round() as i64;
+    let projected_spend = pace_projection.max(queue_projection);
+
+    report.forecast = BudgetForecast {
+        campaign_end: end.format("%Y-%m-%d").to_string(),
+        days_left,
+        commit_rate,
+        queued_issues: review.queued.len() as i64,
+        queued_budget,
+        approval_rate,
+        pace_projection,
+        queue_projection,
+        projected_spend,
+        projected_balance: report.funded - projected_spend,
+    };
+    report.by_project = breakdown(&converted, |entry| entry.project_id.clone());
+    report.by_language = breakdown(&converted, |entry| {
+        entry
+            .issue_id
+            .as_ref()
+            .map(|_| match entry.main_language.as_deref().map(str::trim) {
+                Some(language) if !language.is_empty() => language.to_string(),
+                _ => String::from("unknown"),
+            })
+    });
+
+    Ok(report)
+}
+
+pub async fn budget_report(pool: &Pool, campaign_id: Option<&str>) -> GosimResult<BudgetReport> {
+    let mut conn = pool.get_conn().await?;
+
+    if let Some(campaign_id) = campaign_id {
+        let exists: Option<String> = conn
+            .exec_first(
+                "SELECT campaign_id FROM campaigns WHERE campaign_id = :campaign_id",
+                params! { "campaign_id" => campaign_id },
+            )
+            .await?;
+        if exists.is_none() {
+            return Err(Error::NotFound(format!("Campaign {}", campaign_id)));
+        }
+    }
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT DATE_FORMAT(t.created_at, '%Y-%m-%d') AS date, e.account, e.currency,
+                e.amount, t.issue_id, COALESCE(t.project_id, im.project_id) AS project_id,
+                im.main_language
+              FROM ledger_entries e
+              JOIN ledger_transactions t ON t.txn_id = e.txn_id
+              LEFT JOIN issues_master im ON im.issue_id = t.issue_id
+              WHERE :campaign_id IS NULL OR e.campaign_id = :campaign_id
+              ORDER BY t.created_at, e.txn_id, e.entry_id",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+    let entries: Vec<ReportEntry> = rows
+        .iter()
+        .map(|row| ReportEntry {
+            date: row.get("date").unwrap_or_default(),
+            account: serde_json::from_value(serde_json::Value::String(
+                row.get("account").unwrap_or_default(),
+            ))
+            .ok(),
+            currency: Currency::from_db(&row.get::<String, _>("currency").unwrap_or_default()),
+            amount: row.get("amount").unwrap_or_default(),
+            issue_id: row.get("issue_id").unwrap_or(None),
+            project_id: row.get("project_id").unwrap_or(None),
+            main_language: row.get("main_language").unwrap_or(None),
+        })
+        .collect();
+
+    let queued: Vec<(i64, String)> = conn
+        .exec(
+            r"SELECT issue_budget, issue_budget_currency FROM issues_master
+              WHERE review_state = 'queued' AND issue_budget > 0
+                AND (:campaign_id IS NULL OR campaign_id = :campaign_id)",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+    let states: Vec<(String, i64)> = conn
+        .exec(
+            r"SELECT review_state, COUNT(*) FROM issues_master
+              WHERE :campaign_id IS NULL OR campaign_id = :campaign_id
+              GROUP BY review_state",
+            params! { "campaign_id" => campaign_id },
+        )
+        .await?;
+    let review = ReviewFacts {
+        queued: queued
+            .into_iter()
+            .map(|(budget, currency)| (budget, Currency::from_db(&currency)))
+            .collect(),
+        states: states
+            .into_iter()
+            .map(|(state, count)| Ok((state.parse()?, count)))
+            .collect::<GosimResult<_>>()?,
+    };
+
+    let rates = rates_as_of(&mut conn, None).await?;
+    build_report(
+        campaign_id,
+        &entries,
+        &review,
+        &rates,
+        reporting_currency(),
+        Utc::now().date_naive(),
+        campaign_end(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::ExchangeRate;
+
+    fn day(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    fn rates() -> Rates {
+        Rates::snapshot(
+            &[ExchangeRate {
+                rate_date: String::from("2023-10-01"),
+                currency: Currency::Eur,
+                rate: 1.1,
+            }],
+            None,
+        )
+    }
+
+    // One posting of a transaction, on issue `n` of project `n` when it has one.
+    fn entry(
+        date: &str,
+        account: Account,
+        amount: i64,
+        currency: Currency,
+        issue: Option<u32>,
+    ) -> ReportEntry {
+        ReportEntry {
+            date: date.to_string(),
+            account: Some(account),
+            currency,
+            amount,
+            issue_id: issue.map(|n| format!("https://github.com/o/r{n}/issues/{n}")),
+            project_id: issue.map(|n| format!("https://github.com/o/r{n}")),
+            main_language: issue.filter(|&n| n == 1).map(|_| String::from("Rust")),
+        }
+    }
+
+    // Funds 1000 USD, allocates 300 USD to issue 1 and 100 EUR to issue 2,
+    // releases issue 1's budget and approves and pays issue 2's.
+    fn entries() -> Vec<ReportEntry> {
+        use Account::*;
+        use Currency::*;
+        vec![
+            entry("2023-10-01", Funding, -1000, Usd, None),
+            entry("2023-10-01", Available, 1000, Usd, None),
+            entry("2023-10-02", Available, -300, Usd, Some(1)),
+            entry("2023-10-02", Allocated, 300, Usd, Some(1)),
+            entry("2023-10-03", Available, -100, Eur, Some(2)),
+            entry("2023-10-03", Allocated, 100, Eur, Some(2)),
+            entry("2023-10-04", Allocated, -300, Usd, Some(1)),
+            entry("2023-10-04", Available, 300, Usd, Some(1)),
+            entry("2023-10-04", Allocated, -100, Eur, Some(2)),
+            entry("2023-10-04", Approved, 100, Eur, Some(2)),
+            entry("2023-10-05", Approved, -100, Eur, Some(2)),
+            entry("2023-10-05", Paid, 100, Eur, Some(2)),
+        ]
+    }
+
+    fn review() -> ReviewFacts {
+        ReviewFacts {
+            queued: vec![(200, Currency::Usd), (200, Currency::Eur)],
+            states: vec![
+                (ReviewState::Queued, 2),
+                (ReviewState::Approved, 2),
+                (ReviewState::InProgress, 1),
+                (ReviewState::Declined, 1),
+                (ReviewState::Withdrawn, 1),
+            ],
+        }
+    }
+
+    fn report(review: &ReviewFacts) -> BudgetReport {
+        build_report(
+            Some("gosim"),
+            &entries(),
+            review,
+            &rates(),
+            Currency::Usd,
+            day("2023-10-06"),
+            day("2023-10-14"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn burn_down_counts_funding_as_positive_and_releases_as_available() {
+        let report = report(&review());
+        assert_eq!(
+            (
+                report.funded,
+                report.available,
+                report.committed,
+                report.paid
+            ),
+            (1000, 890, 110, 110)
+        );
+
+        let points: Vec<(&str, i64, i64, i64, i64)> = report
+            .burn_down
+            .iter()
+            .map(|p| (p.date.as_str(), p.funded, p.committed, p.paid, p.remaining))
+            .collect();
+        assert_eq!(
+            points,
+            [
+                ("2023-10-01", 1000, 0, 0, 1000),
+                ("2023-10-02", 1000, 300, 0, 700),
+                ("2023-10-03", 1000, 410, 0, 590),
+                // the release takes issue 1's 300 back, the approval moves none
+                ("2023-10-04", 1000, 110, 0, 890),
+                ("2023-10-05", 1000, 110, 110, 890),
+            ]
+        );
+    }
+
+    #[test]
+    fn forecast_projects_the_pace_and_the_queue() {
+        let forecast = report(&review()).forecast;
+        assert_eq!(forecast.days_left, 8);
+        // 110 committed over the 5 days since the first allocation
+        assert_eq!(forecast.commit_rate, 22.0);
+        assert_eq!(forecast.pace_projection, 110 + 176);
+        assert_eq!(forecast.queued_issues, 2);
+        assert_eq!(forecast.queued_budget, 200 + 220);
+        // approved, in progress and declined decide, withdrawn doesn't
+        assert_eq!(forecast.approval_rate, Some(0.75));
+        assert_eq!(forecast.queue_projection, 110 + 315);
+        assert_eq!(forecast.projected_spend, 425);
+        assert_eq!(forecast.projected_balance, 1000 - 425);
+    }
+
+    #[test]
+    fn forecast_counts_the_whole_queue_before_any_decision() {
+        let undecided = ReviewFacts {
+            states: vec![(ReviewState::Queued, 2)],
+            ..review()
+        };
+        let forecast = report(&undecided).forecast;
+        assert_eq!(forecast.approval_rate, None);
+        assert_eq!(forecast.queue_projection, 110 + 420);
+    }
+
+    #[test]
+    fn breakdowns_leave_out_released_budgets() {
+        let report = report(&review());
+        assert_eq!(
+            report.by_project,
+            [BudgetBreakdown {
+                key: String::from("https://github.com/o/r2"),
+                issues: 1,
+                committed: 110,
+                paid: 110,
+            }]
+        );
+        assert_eq!(report.by_language.len(), 1);
+        assert_eq!(report.by_language[0].key, "unknown");
+    }
+
+    #[test]
+    fn a_currency_without_a_rate_fails_the_report() {
+        let mut with_cny = entries();
+        with_cny.push(entry(
+            "2023-10-05",
+            Account::Allocated,
+            50,
+            Currency::Cny,
+            Some(3),
+        ));
+        let build = |entries: &[ReportEntry], review: &ReviewFacts| {
+            build_report(
+                None,
+                entries,
+                review,
+                &rates(),
+                Currency::Usd,
+                day("2023-10-06"),
+                day("2023-10-14"),
+            )
+        };
+        assert!(build(&with_cny, &review()).is_err());
+
+        let queued_in_cny = ReviewFacts {
+            queued: vec![(10, Currency::Cny)],
+            ..review()
+        };
+        assert!(build(&entries(), &queued_in_cny).is_err());
+    }
+}
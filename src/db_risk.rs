@@ -0,0 +1,637 @@
+use crate::error::{Error, GosimResult};
+use crate::review_state::{ReviewAction, ReviewState};
+use chrono::{Duration, NaiveDateTime, Utc};
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+// The checks the join phase runs on issues that can still be concluded. Each one
+// that fires raises a flag worth some points, and an issue whose undismissed
+// flags add up to the risk threshold can't be concluded until an admin dismisses
+// enough of them. Flags stay once raised, the pull requests they were read from
+// are purged after the join.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskFlag {
+    // the pull request's author merged it
+    SelfMerged,
+    // the pull request's author was never assigned, or never commented
+    NoEngagement,
+    // fewer than TRIVIAL_DIFF_LINES lines changed
+    TrivialDiff,
+    // an assignee holds more than MAX_OPEN_CLAIMS issues at once
+    ManyClaims,
+    // the pull request's author signed up less than NEW_ACCOUNT_DAYS before
+    NewAccount,
+    // the issue's creator owns the repo and is assigned to the issue
+    OwnIssue,
+}
+
+pub const TRIVIAL_DIFF_LINES: i64 = 5;
+pub const MAX_OPEN_CLAIMS: usize = 3;
+pub const NEW_ACCOUNT_DAYS: i64 = 30;
+pub const DEFAULT_RISK_THRESHOLD: i32 = 50;
+
+impl RiskFlag {
+    pub const ALL: [RiskFlag; 6] = [
+        RiskFlag::SelfMerged,
+        RiskFlag::NoEngagement,
+        RiskFlag::TrivialDiff,
+        RiskFlag::ManyClaims,
+        RiskFlag::NewAccount,
+        RiskFlag::OwnIssue,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RiskFlag::SelfMerged => "self_merged",
+            RiskFlag::NoEngagement => "no_engagement",
+            RiskFlag::TrivialDiff => "trivial_diff",
+            RiskFlag::ManyClaims => "many_claims",
+            RiskFlag::NewAccount => "new_account",
+            RiskFlag::OwnIssue => "own_issue",
+        }
+    }
+
+    pub fn points(&self) -> i32 {
+        match self {
+            RiskFlag::SelfMerged | RiskFlag::OwnIssue => 40,
+            RiskFlag::NoEngagement => 25,
+            RiskFlag::TrivialDiff | RiskFlag::NewAccount => 20,
+            RiskFlag::ManyClaims => 15,
+        }
+    }
+}
+
+impl fmt::Display for RiskFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RiskFlag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        RiskFlag::ALL
+            .into_iter()
+            .find(|flag| flag.as_str() == s)
+            .ok_or_else(|| Error::Validation(format!("unknown risk flag '{}'", s)))
+    }
+}
+
+// The score that blocks conclusion, RISK_THRESHOLD or 50.
+pub fn risk_threshold() -> i32 {
+    std::env::var("RISK_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_RISK_THRESHOLD)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IssueRiskFlag {
+    pub issue_id: String,
+    pub flag: RiskFlag,
+    pub points: i32,
+    pub detail: String,
+    pub flagged_at: String,
+    pub dismissed_by: Option<String>,
+    pub dismissed_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IssueRisk {
+    pub issue_id: String,
+    // the points of the undismissed flags
+    pub risk_score: i32,
+    pub threshold: i32,
+    pub blocked: bool,
+    pub flags: Vec<IssueRiskFlag>,
+}
+
+// What the checks know about the pull request that closed an issue.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PullFacts {
+    pub pull_id: String,
+    pub pull_author: Option<String>,
+    pub merged_by: Option<String>,
+    pub additions: Option<i64>,
+    pub deletions: Option<i64>,
+    pub changed_files: Option<i64>,
+    // %Y-%m-%d %H:%M:%S
+    pub author_created_at: Option<String>,
+    pub merged_at: Option<String>,
+}
+
+// What the checks know about an issue that can still be concluded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RiskFacts {
+    pub issue_id: String,
+    pub issue_creator: String,
+    pub project_id: String,
+    pub assignees: BTreeSet<String>,
+    pub commenters: BTreeSet<String>,
+    pub pull: Option<PullFacts>,
+}
+
+// The owner in https://github.com/<owner>/<repo>.
+fn repo_owner(project_id: &str) -> Option<&str> {
+    project_id.trim_end_matches('/').rsplit('/').nth(1)
+}
+
+fn parse_time(time: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok()
+}
+
+// The flags `issues` raise, as (issue_id, flag, detail). Claims are counted over
+// all of `issues`, so they should be every issue that can still be concluded.
+pub fn assess(issues: &[RiskFacts]) -> Vec<(String, RiskFlag, String)> {
+    let mut claims: BTreeMap<&str, usize> = BTreeMap::new();
+    for issue in issues {
+        for login in &issue.assignees {
+            *claims.entry(login.as_str()).or_default() += 1;
+        }
+    }
+
+    let mut flags = Vec::new();
+    for issue in issues {
+        let mut raise = |flag: RiskFlag, detail: String| {
+            flags.push((issue.issue_id.clone(), flag, detail));
+        };
+
+        if let Some(owner) = repo_owner(&issue.project_id) {
+            if owner.eq_ignore_ascii_case(&issue.issue_creator)
+                && issue.assignees.contains(&issue.issue_creator)
+            {
+                raise(
+                    RiskFlag::OwnIssue,
+                    format!("{} owns the repo and is assigned to their own issue", owner),
+                );
+            }
+        }
+
+        if let Some((login, count)) = issue
+            .assignees
+            .iter()
+            .map(|login| (login, claims[login.as_str()]))
+            .find(|(_, count)| *count > MAX_OPEN_CLAIMS)
+        {
+            raise(
+                RiskFlag::ManyClaims,
+                format!("{} holds {} issues at once", login, count),
+            );
+        }
+
+        let pull = match &issue.pull {
+            Some(pull) => pull,
+            None => continue,
+        };
+        if let Some(author) = &pull.pull_author {
+            if pull.merged_by.as_ref() == Some(author) {
+                raise(
+                    RiskFlag::SelfMerged,
+                    format!("{} merged their own pull request {}", author, pull.pull_id),
+                );
+            }
+            let missing = match (
+                issue.assignees.contains(author),
+                issue.commenters.contains(author),
+            ) {
+                (true, true) => None,
+                (false, true) => Some("was never assigned"),
+                (true, false) => Some("never commented"),
+                (false, false) => Some("was never assigned and never commented"),
+            };
+            if let Some(missing) = missing {
+                raise(
+                    RiskFlag::NoEngagement,
+                    format!("{} {} before {}", author, missing, pull.pull_id),
+                );
+            }
+            let merged_at = pull
+                .merged_at
+                .as_deref()
+                .and_then(parse_time)
+                .unwrap_or_else(|| Utc::now().naive_utc());
+            if let Some(created_at) = pull.author_created_at.as_deref().and_then(parse_time) {
+                if merged_at - created_at < Duration::days(NEW_ACCOUNT_DAYS) {
+                    raise(
+                        RiskFlag::NewAccount,
+                        format!(
+                            "{} signed up on {}, {} days before the merge",
+                            author,
+                            created_at.format("%Y-%m-%d"),
+                            (merged_at - created_at).num_days()
+                        ),
+                    );
+                }
+            }
+        }
+        if let (Some(additions), Some(deletions)) = (pull.additions, pull.deletions) {
+            if additions + deletions < TRIVIAL_DIFF_LINES || pull.changed_files == Some(0) {
+                raise(
+                    RiskFlag::TrivialDiff,
+                    format!(
+                        "{} changes {} lines in {} files",
+                        pull.pull_id,
+                        additions + deletions,
+                        pull.changed_files.unwrap_or_default()
+                    ),
+                );
+            }
+        }
+    }
+    flags
+}
+
+// Conclusion is blocked at the threshold, the message names the flags to look at.
+pub fn check_risk(risk: &IssueRisk) -> GosimResult<()> {
+    if !risk.blocked {
+        return Ok(());
+    }
+    let flags = risk
+        .flags
+        .iter()
+        .filter(|flag| flag.dismissed_at.is_none())
+        .map(|flag| flag.flag.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(Error::Risk(format!(
+        "issue {} scores {} ({}), conclusions are blocked at {}",
+        risk.issue_id, risk.risk_score, flags, risk.threshold
+    )))
+}
+
+pub fn issue_risk_of(issue_id: &str, flags: Vec<IssueRiskFlag>) -> IssueRisk {
+    let risk_score = flags
+        .iter()
+        .filter(|flag| flag.dismissed_at.is_none())
+        .map(|flag| flag.points)
+        .sum();
+    let threshold = risk_threshold();
+    IssueRisk {
+        issue_id: issue_id.to_string(),
+        risk_score,
+        threshold,
+        blocked: risk_score >= threshold,
+        flags,
+    }
+}
+
+fn state_list(states: &[ReviewState]) -> String {
+    states
+        .iter()
+        .map(|state| format!("'{}'", state.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn risk_facts<Q: Queryable>(conn: &mut Q) -> GosimResult<Vec<RiskFacts>> {
+    let states = state_list(&ReviewState::sources(ReviewAction::Conclude));
+
+    let rows: Vec<Row> = conn
+        .query(format!(
+            r"SELECT im.issue_id, im.issue_creator, im.project_id, pr.pull_id, pr.pull_author,
+                pr.merged_by, pr.additions, pr.deletions, pr.changed_files,
+                DATE_FORMAT(pr.author_created_at, '%Y-%m-%d %H:%i:%s') AS author_created_at,
+                DATE_FORMAT(pr.date_merged, '%Y-%m-%d %H:%i:%s') AS merged_at
+              FROM issues_master im
+              LEFT JOIN pull_requests pr ON pr.pull_id = im.issue_linked_pr
+              WHERE im.review_state IN ({states})
+              ORDER BY im.issue_id",
+            states = states
+        ))
+        .await?;
+    let assignees: Vec<(String, String)> = conn
+        .query(format!(
+            r"SELECT DISTINCT ia.issue_id, ia.login FROM issue_assignees ia
+              JOIN issues_master im ON im.issue_id = ia.issue_id
+              WHERE im.review_state IN ({states})",
+            states = states
+        ))
+        .await?;
+    let commenters: Vec<(String, String)> = conn
+        .query(format!(
+            r"SELECT DISTINCT ic.issue_id, ic.comment_creator FROM issues_comment ic
+              JOIN issues_master im ON im.issue_id = ic.issue_id
+              WHERE im.review_state IN ({states})",
+            states = states
+        ))
+        .await?;
+
+    let mut issues: BTreeMap<String, RiskFacts> = rows
+        .iter()
+        .map(|row| {
+            let issue_id: String = row.get("issue_id").unwrap_or_default();
+            let pull = row
+                .get::<Option<String>, _>("pull_id")
+                .unwrap_or(None)
+                .map(|pull_id| PullFacts {
+                    pull_id,
+                    pull_author: row.get("pull_author").unwrap_or(None),
+                    merged_by: row.get("merged_by").unwrap_or(None),
+                    additions: row.get("additions").unwrap_or(None),
+                    deletions: row.get("deletions").unwrap_or(None),
+                    changed_files: row.get("changed_files").unwrap_or(None),
+                    author_created_at: row.get("author_created_at").unwrap_or(None),
+                    merged_at: row.get("merged_at").unwrap_or(None),
+                });
+            let facts = RiskFacts {
+                issue_id: issue_id.clone(),
+                issue_creator: row.get("issue_creator").unwrap_or_default(),
+                project_id: row.get("project_id").unwrap_or_default(),
+                pull,
+                ..Default::default()
+            };
+            (issue_id, facts)
+        })
+        .collect();
+    for (issue_id, login) in assignees {
+        if let Some(issue) = issues.get_mut(&issue_id) {
+            issue.assignees.insert(login);
+        }
+    }
+    for (issue_id, login) in commenters {
+        if let Some(issue) = issues.get_mut(&issue_id) {
+            issue.commenters.insert(login);
+        }
+    }
+
+    Ok(issues.into_values().collect())
+}
+
+// Sets issues_master.risk_score from the undismissed flags, of one issue or all.
+async fn refresh_scores<Q: Queryable>(conn: &mut Q, issue_id: Option<&str>) -> GosimResult<()> {
+    conn.exec_drop(
+        r"UPDATE issues_master im
+          LEFT JOIN (
+              SELECT issue_id, SUM(points) AS score FROM issue_risk_flags
+              WHERE dismissed_at IS NULL GROUP BY issue_id
+          ) f ON f.issue_id = im.issue_id
+          SET im.risk_score = COALESCE(f.score, 0)
+          WHERE :issue_id IS NULL OR im.issue_id = :issue_id",
+        params! { "issue_id" => issue_id },
+    )
+    .await?;
+
+    Ok(())
+}
+
+// The join step: runs the checks and records the flags they raise. A flag raised
+// again keeps its dismissal. Returns the number of flags raised.
+pub async fn score_risks<Q: Queryable>(conn: &mut Q) -> GosimResult<u64> {
+    let issues = risk_facts(conn).await?;
+    let flags = assess(&issues);
+
+    conn.exec_batch(
+        r"INSERT INTO issue_risk_flags (issue_id, flag, points, detail, flagged_at)
+          VALUES (:issue_id, :flag, :points, :detail, NOW())
+          ON DUPLICATE KEY UPDATE points = VALUES(points), detail = VALUES(detail)",
+        flags.iter().map(|(issue_id, flag, detail)| {
+            params! {
+                "issue_id" => issue_id,
+                "flag" => flag.as_str(),
+                "points" => flag.points(),
+                "detail" => detail,
+            }
+        }),
+    )
+    .await?;
+    refresh_scores(conn, None).await?;
+
+    Ok(flags.len() as u64)
+}
+
+pub async fn issue_risk<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<IssueRisk> {
+    let exists: Option<String> = conn
+        .exec_first(
+            "SELECT issue_id FROM issues_master WHERE issue_id = :issue_id",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+    if exists.is_none() {
+        return Err(Error::NotFound(format!("Issue {}", issue_id)));
+    }
+
+    let rows: Vec<Row> = conn
+        .exec(
+            r"SELECT issue_id, flag, points, detail,
+                DATE_FORMAT(flagged_at, '%Y-%m-%d %H:%i:%s') AS flagged_at, dismissed_by,
+                DATE_FORMAT(dismissed_at, '%Y-%m-%d %H:%i:%s') AS dismissed_at
+              FROM issue_risk_flags WHERE issue_id = :issue_id ORDER BY points DESC, flag",
+            params! { "issue_id" => issue_id },
+        )
+        .await?;
+
+    let flags = rows
+        .iter()
+        .filter_map(|row| {
+            let flag: String = row.get("flag").unwrap_or_default();
+            Some(IssueRiskFlag {
+                issue_id: row.get("issue_id").unwrap_or_default(),
+                flag: flag.parse().ok()?,
+                points: row.get("points").unwrap_or_default(),
+                detail: row.get("detail").unwrap_or_default(),
+                flagged_at: row.get("flagged_at").unwrap_or_default(),
+                dismissed_by: row.get("dismissed_by").unwrap_or(None),
+                dismissed_at: row.get("dismissed_at").unwrap_or(None),
+            })
+        })
+        .collect();
+
+    Ok(issue_risk_of(issue_id, flags))
+}
+
+// Issues scoring at least `min_score`, highest first.
+pub async fn list_risky_issues(pool: &Pool, min_score: i32) -> GosimResult<Vec<IssueRisk>> {
+    let mut conn = pool.get_conn().await?;
+
+    let issue_ids: Vec<String> = conn
+        .exec(
+            r"SELECT issue_id FROM issues_master
+              WHERE risk_score >= :min_score AND risk_score > 0
+              ORDER BY risk_score DESC, issue_id",
+            params! { "min_score" => min_score },
+        )
+        .await?;
+
+    let mut risks = Vec::new();
+    for issue_id in issue_ids {
+        risks.push(issue_risk(&mut conn, &issue_id).await?);
+    }
+    Ok(risks)
+}
+
+// Called before an issue is concluded.
+pub async fn check_issue_risk<Q: Queryable>(conn: &mut Q, issue_id: &str) -> GosimResult<()> {
+    let risk = issue_risk(conn, issue_id).await?;
+    check_risk(&risk)
+}
+
+// Dismissing a flag takes its points off the issue's score, for good.
+pub async fn dismiss_flag(
+    pool: &Pool,
+    issue_id: &str,
+    flag: RiskFlag,
+    actor: &str,
+) -> GosimResult<IssueRisk> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    tx.exec_drop(
+        r"UPDATE issue_risk_flags SET dismissed_by = :actor, dismissed_at = NOW()
+          WHERE issue_id = :issue_id AND flag = :flag AND dismissed_at IS NULL",
+        params! {
+            "issue_id" => issue_id,
+            "flag" => flag.as_str(),
+            "actor" => actor,
+        },
+    )
+    .await?;
+    if tx.affected_rows() == 0 {
+        return Err(Error::NotFound(format!(
+            "Undismissed {} flag on issue {}",
+            flag, issue_id
+        )));
+    }
+    refresh_scores(&mut tx, Some(issue_id)).await?;
+    let risk = issue_risk(&mut tx, issue_id).await?;
+    tx.commit().await?;
+
+    Ok(risk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_audit::{list_issue_history, AuditInfo};
+    use crate::db_ledger::issue_balance;
+    use crate::db_manipulate::conclude_issue_in_db;
+    use crate::db_populate::add_pull_request;
+    use crate::issue_tracker::OuterPull;
+    use crate::test_db;
+
+    fn logins(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn issue(assignees: &[&str], commenters: &[&str]) -> RiskFacts {
+        RiskFacts {
+            issue_id: String::from("https://github.com/acme/widgets/issues/1"),
+            issue_creator: String::from("carol"),
+            project_id: String::from("https://github.com/acme/widgets"),
+            assignees: logins(assignees),
+            commenters: logins(commenters),
+            pull: Some(PullFacts {
+                pull_id: String::from("https://github.com/acme/widgets/pull/2"),
+                pull_author: Some(String::from("rita")),
+                merged_by: Some(String::from("carol")),
+                additions: Some(40),
+                deletions: Some(2),
+                changed_files: Some(3),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn no_engagement(issue: RiskFacts) -> Option<String> {
+        assess(&[issue])
+            .into_iter()
+            .find(|(_, flag, _)| *flag == RiskFlag::NoEngagement)
+            .map(|(_, _, detail)| detail)
+    }
+
+    #[test]
+    fn engaged_authors_are_not_flagged() {
+        assert_eq!(no_engagement(issue(&["rita"], &["rita", "carol"])), None);
+        assert!(assess(&[issue(&["rita"], &["rita"])]).is_empty());
+    }
+
+    #[test]
+    fn either_missing_signal_raises_no_engagement() {
+        let pull = "https://github.com/acme/widgets/pull/2";
+        assert_eq!(
+            no_engagement(issue(&["bob"], &["rita"])),
+            Some(format!("rita was never assigned before {}", pull))
+        );
+        assert_eq!(
+            no_engagement(issue(&["rita"], &["carol"])),
+            Some(format!("rita never commented before {}", pull))
+        );
+        assert_eq!(
+            no_engagement(issue(&[], &[])),
+            Some(format!(
+                "rita was never assigned and never commented before {}",
+                pull
+            ))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_risky_issue_is_concluded_once_its_flags_are_dismissed() {
+        let pool = test_db::pool().await;
+        let campaign_id = test_db::funded_campaign(&pool, 1000).await;
+        // a login of its own, so no other test's claims count towards many_claims
+        let login = format!("{}-dev", campaign_id);
+        let issue_id = test_db::approved_issue(&pool, &campaign_id, 1, 100, &[&login]).await;
+        let pull_id = format!("{}-pull", campaign_id);
+        add_pull_request(
+            &pool,
+            OuterPull {
+                pull_id: pull_id.clone(),
+                pull_title: String::from("test"),
+                pull_author: Some(login.clone()),
+                project_id: format!("https://github.com/{}/test", campaign_id),
+                merged_at: String::from("2023-10-20 12:00:00"),
+                merged_by: Some(login.clone()),
+                additions: Some(40),
+                deletions: Some(2),
+                changed_files: Some(3),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.exec_drop(
+            "UPDATE issues_master SET issue_linked_pr = :pull_id WHERE issue_id = :issue_id",
+            params! { "pull_id" => &pull_id, "issue_id" => &issue_id },
+        )
+        .await
+        .unwrap();
+
+        // the author merged their own pull request and never commented
+        score_risks(&mut conn).await.unwrap();
+        let risk = issue_risk(&mut conn, &issue_id).await.unwrap();
+        assert_eq!(risk.risk_score, 65);
+        assert!(risk.blocked);
+
+        let audit = AuditInfo::new(Some(String::from("rita")), None);
+        let refused = conclude_issue_in_db(&pool, &issue_id, &audit)
+            .await
+            .unwrap_err();
+        assert!(matches!(refused, Error::Risk(_)), "{:?}", refused);
+        assert_eq!(
+            test_db::issue_review(&pool, &issue_id).await,
+            (String::from("approved"), Some(100))
+        );
+        let balance = issue_balance(&mut conn, &issue_id).await.unwrap();
+        assert_eq!((balance.allocated, balance.approved), (100, 0));
+        let actions = list_issue_history(&pool, &issue_id).await.unwrap();
+        assert_eq!(actions.len(), 1);
+
+        let risk = dismiss_flag(&pool, &issue_id, RiskFlag::SelfMerged, "rita")
+            .await
+            .unwrap();
+        assert_eq!(risk.risk_score, 25);
+        conclude_issue_in_db(&pool, &issue_id, &audit)
+            .await
+            .unwrap();
+        assert_eq!(test_db::issue_review(&pool, &issue_id).await.0, "concluded");
+        let balance = issue_balance(&mut conn, &issue_id).await.unwrap();
+        assert_eq!((balance.allocated, balance.approved), (0, 100));
+    }
+}
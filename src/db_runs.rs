@@ -0,0 +1,623 @@
+use crate::db_lock::*;
+use crate::db_migrate::ensure_schema_current;
+use crate::error::{ErrorAction, GosimResult};
+use crate::issue_tracker::get_rate_limit;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Mutex;
+
+const MAX_WRITE_ATTEMPTS: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StepStats {
+    pub rows_fetched: i32,
+    pub rows_written: i32,
+    // rows left out for a reason that doesn't fail the step, see ErrorAction::Skip
+    pub rows_skipped: i32,
+}
+
+impl StepStats {
+    pub fn written(rows_written: u64) -> Self {
+        StepStats {
+            rows_fetched: 0,
+            rows_written: rows_written as i32,
+            rows_skipped: 0,
+        }
+    }
+
+    pub fn add(&mut self, other: StepStats) {
+        self.rows_fetched += other.rows_fetched;
+        self.rows_written += other.rows_written;
+        self.rows_skipped += other.rows_skipped;
+    }
+
+    // Counts a successful row write. Failed writes are retried, skipped or abort
+    // the step depending on `Error::action`.
+    pub async fn record<F, Fut>(&mut self, mut write: F) -> anyhow::Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = GosimResult<()>>,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match write().await {
+                Ok(()) => {
+                    self.rows_written += 1;
+                    return Ok(());
+                }
+                Err(e) => match e.action() {
+                    ErrorAction::Retry if attempts < MAX_WRITE_ATTEMPTS => {
+                        log::warn!("Retrying write after: {}", e);
+                    }
+                    ErrorAction::Skip => {
+                        log::info!("Skipping write: {}", e);
+                        self.rows_skipped += 1;
+                        return Ok(());
+                    }
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PipelineStepOut {
+    pub step_name: String,
+    pub date_started: String,
+    pub date_finished: Option<String>,
+    pub step_status: String,
+    pub rows_fetched: i32,
+    pub rows_written: i32,
+    pub rows_skipped: i32,
+    pub github_points_spent: i32,
+    pub error_text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PipelineRunOut {
+    pub run_id: u64,
+    pub run_kind: String,
+    pub date_started: String,
+    pub date_finished: Option<String>,
+    pub run_status: String,
+    pub rows_fetched: i32,
+    pub rows_written: i32,
+    pub rows_skipped: i32,
+    pub github_points_spent: i32,
+    pub error_text: Option<String>,
+    pub steps: Vec<PipelineStepOut>,
+}
+
+// One row in pipeline_runs, steps executed through `step` are recorded against it.
+pub struct PipelineRun<'a> {
+    pool: &'a Pool,
+    pub run_id: u64,
+    lock: Option<RunLock<'a>>,
+    // tx_step successes waiting for their transaction to commit or roll back
+    unsettled: Mutex<Vec<(u64, StepStats)>>,
+}
+
+impl<'a> PipelineRun<'a> {
+    pub async fn start(pool: &'a Pool, run_kind: &str) -> anyhow::Result<PipelineRun<'a>> {
+        let mut conn = pool.get_conn().await?;
+
+        conn.exec_drop(
+            r"INSERT INTO pipeline_runs (run_kind, date_started, run_status)
+              VALUES (:run_kind, NOW(), 'running')",
+            params! {
+                "run_kind" => run_kind,
+            },
+        )
+        .await?;
+
+        let run_id = conn
+            .last_insert_id()
+            .ok_or_else(|| anyhow::anyhow!("No run_id returned for pipeline run"))?;
+        log::info!("Pipeline run {} ({}) started", run_id, run_kind);
+
+        Ok(PipelineRun {
+            pool,
+            run_id,
+            lock: None,
+            unsettled: Mutex::new(Vec::new()),
+        })
+    }
+
+    // Same as `start`, but only when no other run holds the pipeline lock.
+    // The lease is renewed before every step and released by `finish`.
+    pub async fn start_locked(
+        pool: &'a Pool,
+        run_kind: &str,
+    ) -> anyhow::Result<Option<PipelineRun<'a>>> {
+        ensure_schema_current(pool).await?;
+
+        let lock =
+            match RunLock::acquire(pool, PIPELINE_LOCK, run_kind, PIPELINE_LEASE_SECS).await? {
+                Some(lock) => lock,
+                None => return Ok(None),
+            };
+
+        match PipelineRun::start(pool, run_kind).await {
+            Ok(mut run) => {
+                run.lock = Some(lock);
+                Ok(Some(run))
+            }
+            Err(e) => {
+                lock.release().await;
+                Err(e)
+            }
+        }
+    }
+
+    // For steps that only touch the database.
+    pub async fn step<Fut, E>(&self, step_name: &str, step: Fut) -> anyhow::Result<StepStats>
+    where
+        Fut: Future<Output = std::result::Result<StepStats, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let step_id = self.begin_step(step_name).await?;
+        let result = step.await.map_err(Into::into);
+        self.end_step(step_name, step_id, &result, 0).await;
+
+        result
+    }
+
+    // For steps that fetch from GitHub, records the GraphQL points they spent.
+    pub async fn github_step<Fut, E>(&self, step_name: &str, step: Fut) -> anyhow::Result<StepStats>
+    where
+        Fut: Future<Output = std::result::Result<StepStats, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let step_id = self.begin_step(step_name).await?;
+        let points_before = get_rate_limit().await.ok();
+
+        let result = step.await.map_err(Into::into);
+
+        let points_after = get_rate_limit().await.ok();
+        let github_points_spent = match (points_before, points_after) {
+            (Some(before), Some(after)) if before >= after => before - after,
+            _ => 0,
+        };
+        self.end_step(step_name, step_id, &result, github_points_spent)
+            .await;
+
+        result
+    }
+
+    // For steps inside an open transaction. A step that succeeds stays 'running'
+    // until `settle` records whether the transaction committed.
+    pub async fn tx_step<Fut, E>(&self, step_name: &str, step: Fut) -> anyhow::Result<StepStats>
+    where
+        Fut: Future<Output = std::result::Result<StepStats, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let step_id = self.begin_step(step_name).await?;
+        let result = step.await.map_err(Into::into);
+
+        match &result {
+            Ok(stats) => {
+                log::info!("Step {} done, awaiting commit: {:?}", step_name, stats);
+                self.unsettled
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push((step_id, stats.clone()));
+            }
+            Err(_) => self.end_step(step_name, step_id, &result, 0).await,
+        }
+
+        result
+    }
+
+    // Marks the tx_step successes since the last call as committed, or as
+    // rolled back with nothing written.
+    pub async fn settle(&self, committed: bool) {
+        let steps: Vec<(u64, StepStats)> = self
+            .unsettled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain(..)
+            .collect();
+
+        for (step_id, stats) in steps {
+            if let Err(e) = settle_pipeline_step(self.pool, step_id, &stats, committed).await {
+                log::error!("Error settling pipeline step {}: {:?}", step_id, e);
+            }
+        }
+    }
+
+    async fn begin_step(&self, step_name: &str) -> anyhow::Result<u64> {
+        if let Some(lock) = &self.lock {
+            lock.renew().await?;
+        }
+
+        start_pipeline_step(self.pool, self.run_id, step_name).await
+    }
+
+    async fn end_step(
+        &self,
+        step_name: &str,
+        step_id: u64,
+        result: &anyhow::Result<StepStats>,
+        github_points_spent: i32,
+    ) {
+        if let Err(e) = finish_pipeline_step(self.pool, step_id, result, github_points_spent).await
+        {
+            log::error!("Error recording pipeline step {}: {:?}", step_name, e);
+        }
+
+        match result {
+            Ok(stats) => log::info!("Step {} done: {:?}", step_name, stats),
+            Err(e) => log::error!("Step {} failed: {:?}", step_name, e),
+        }
+    }
+
+    pub async fn finish(self, result: &anyhow::Result<()>) {
+        let error_text = result.as_ref().err().map(|e| e.to_string());
+
+        if let Err(e) = finish_pipeline_run(self.pool, self.run_id, error_text.as_deref()).await {
+            log::error!(
+                "Error recording end of pipeline run {}: {:?}",
+                self.run_id,
+                e
+            );
+        }
+
+        if let Some(lock) = self.lock {
+            lock.release().await;
+        }
+    }
+}
+
+async fn start_pipeline_step(pool: &Pool, run_id: u64, step_name: &str) -> anyhow::Result<u64> {
+    let mut conn = pool.get_conn().await?;
+
+    conn.exec_drop(
+        r"INSERT INTO pipeline_steps (run_id, step_name, date_started, step_status)
+          VALUES (:run_id, :step_name, NOW(), 'running')",
+        params! {
+            "run_id" => run_id,
+            "step_name" => step_name,
+        },
+    )
+    .await?;
+
+    conn.last_insert_id()
+        .ok_or_else(|| anyhow::anyhow!("No step_id returned for pipeline step"))
+}
+
+async fn finish_pipeline_step(
+    pool: &Pool,
+    step_id: u64,
+    result: &anyhow::Result<StepStats>,
+    github_points_spent: i32,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let (stats, step_status, error_text) = match result {
+        Ok(stats) => (stats.clone(), "success", None),
+        Err(e) => (StepStats::default(), "failed", Some(e.to_string())),
+    };
+
+    conn.exec_drop(
+        r"UPDATE pipeline_steps
+          SET date_finished = NOW(),
+              step_status = :step_status,
+              rows_fetched = :rows_fetched,
+              rows_written = :rows_written,
+              rows_skipped = :rows_skipped,
+              github_points_spent = :github_points_spent,
+              error_text = :error_text
+          WHERE step_id = :step_id",
+        params! {
+            "step_id" => step_id,
+            "step_status" => step_status,
+            "rows_fetched" => stats.rows_fetched,
+            "rows_written" => stats.rows_written,
+            "rows_skipped" => stats.rows_skipped,
+            "github_points_spent" => github_points_spent,
+            "error_text" => error_text,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn settle_pipeline_step(
+    pool: &Pool,
+    step_id: u64,
+    stats: &StepStats,
+    committed: bool,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let (step_status, rows_written, rows_skipped) = if committed {
+        ("success", stats.rows_written, stats.rows_skipped)
+    } else {
+        ("rolled_back", 0, 0)
+    };
+
+    conn.exec_drop(
+        r"UPDATE pipeline_steps
+          SET date_finished = NOW(),
+              step_status = :step_status,
+              rows_fetched = :rows_fetched,
+              rows_written = :rows_written,
+              rows_skipped = :rows_skipped
+          WHERE step_id = :step_id",
+        params! {
+            "step_id" => step_id,
+            "step_status" => step_status,
+            "rows_fetched" => stats.rows_fetched,
+            "rows_written" => rows_written,
+            "rows_skipped" => rows_skipped,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn finish_pipeline_run(
+    pool: &Pool,
+    run_id: u64,
+    error_text: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get_conn().await?;
+
+    let run_status = if error_text.is_none() {
+        "success"
+    } else {
+        "failed"
+    };
+
+    conn.exec_drop(
+        r"UPDATE pipeline_runs
+          SET date_finished = NOW(),
+              run_status = :run_status,
+              error_text = :error_text,
+              rows_fetched = (SELECT COALESCE(SUM(rows_fetched), 0) FROM pipeline_steps WHERE run_id = :run_id),
+              rows_written = (SELECT COALESCE(SUM(rows_written), 0) FROM pipeline_steps WHERE run_id = :run_id),
+              rows_skipped = (SELECT COALESCE(SUM(rows_skipped), 0) FROM pipeline_steps WHERE run_id = :run_id),
+              github_points_spent = (SELECT COALESCE(SUM(github_points_spent), 0) FROM pipeline_steps WHERE run_id = :run_id)
+          WHERE run_id = :run_id",
+        params! {
+            "run_id" => run_id,
+            "run_status" => run_status,
+            "error_text" => error_text,
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_recent_runs(pool: &Pool, limit: usize) -> GosimResult<Vec<PipelineRunOut>> {
+    let mut conn = pool.get_conn().await?;
+
+    let runs_query = r"SELECT run_id, run_kind,
+        DATE_FORMAT(date_started, '%Y-%m-%d %H:%i:%s') AS date_started,
+        DATE_FORMAT(date_finished, '%Y-%m-%d %H:%i:%s') AS date_finished,
+        run_status, rows_fetched, rows_written, rows_skipped, github_points_spent, error_text
+        FROM pipeline_runs ORDER BY run_id DESC LIMIT :limit";
+
+    let rows: Vec<Row> = conn
+        .exec(
+            runs_query,
+            params! {
+                "limit" => limit,
+            },
+        )
+        .await?;
+
+    let mut runs: Vec<PipelineRunOut> = rows
+        .into_iter()
+        .map(|row| PipelineRunOut {
+            run_id: row.get("run_id").unwrap_or_default(),
+            run_kind: row.get("run_kind").unwrap_or_default(),
+            date_started: row.get("date_started").unwrap_or_default(),
+            date_finished: row
+                .get::<Option<String>, _>("date_finished")
+                .unwrap_or(None),
+            run_status: row
+                .get::<Option<String>, _>("run_status")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            rows_fetched: row.get("rows_fetched").unwrap_or_default(),
+            rows_written: row.get("rows_written").unwrap_or_default(),
+            rows_skipped: row.get("rows_skipped").unwrap_or_default(),
+            github_points_spent: row.get("github_points_spent").unwrap_or_default(),
+            error_text: row.get::<Option<String>, _>("error_text").unwrap_or(None),
+            steps: Vec::new(),
+        })
+        .collect();
+
+    let steps_query = r"SELECT run_id, step_name,
+        DATE_FORMAT(date_started, '%Y-%m-%d %H:%i:%s') AS date_started,
+        DATE_FORMAT(date_finished, '%Y-%m-%d %H:%i:%s') AS date_finished,
+        step_status, rows_fetched, rows_written, rows_skipped, github_points_spent, error_text
+        FROM pipeline_steps
+        WHERE run_id IN (
+            SELECT run_id FROM (SELECT run_id FROM pipeline_runs ORDER BY run_id DESC LIMIT :limit) AS recent
+        )
+        ORDER BY step_id ASC";
+
+    let step_rows: Vec<Row> = conn
+        .exec(
+            steps_query,
+            params! {
+                "limit" => limit,
+            },
+        )
+        .await?;
+
+    for row in step_rows {
+        let run_id: u64 = row.get("run_id").unwrap_or_default();
+        let step = PipelineStepOut {
+            step_name: row.get("step_name").unwrap_or_default(),
+            date_started: row.get("date_started").unwrap_or_default(),
+            date_finished: row
+                .get::<Option<String>, _>("date_finished")
+                .unwrap_or(None),
+            step_status: row
+                .get::<Option<String>, _>("step_status")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            rows_fetched: row.get("rows_fetched").unwrap_or_default(),
+            rows_written: row.get("rows_written").unwrap_or_default(),
+            rows_skipped: row.get("rows_skipped").unwrap_or_default(),
+            github_points_spent: row.get("github_points_spent").unwrap_or_default(),
+            error_text: row.get::<Option<String>, _>("error_text").unwrap_or(None),
+        };
+
+        if let Some(run) = runs.iter_mut().find(|r| r.run_id == run_id) {
+            run.steps.push(step);
+        }
+    }
+
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::test_db;
+    use std::cell::Cell;
+
+    // A write that fails with `errors` in turn, then succeeds.
+    async fn write_after(stats: &mut StepStats, errors: Vec<Error>) -> (anyhow::Result<()>, u32) {
+        let errors = Mutex::new(errors.into_iter());
+        let calls = Cell::new(0);
+        let result = stats
+            .record(|| {
+                calls.set(calls.get() + 1);
+                let next = errors.lock().unwrap().next();
+                async move { next.map_or(Ok(()), Err) }
+            })
+            .await;
+        (result, calls.get())
+    }
+
+    #[tokio::test]
+    async fn a_retried_write_counts_once_it_succeeds() {
+        let mut stats = StepStats::default();
+        let errors = vec![Error::GitHub("502".into()), Error::GitHub("502".into())];
+        let (result, calls) = write_after(&mut stats, errors).await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+        assert_eq!(stats.rows_written, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_the_last_attempt() {
+        let mut stats = StepStats::default();
+        let errors = (0..MAX_WRITE_ATTEMPTS)
+            .map(|_| Error::GitHub("502".into()))
+            .collect();
+        let (result, calls) = write_after(&mut stats, errors).await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, MAX_WRITE_ATTEMPTS);
+        assert_eq!(stats.rows_written, 0);
+    }
+
+    #[tokio::test]
+    async fn a_skipped_write_is_not_counted_and_not_retried() {
+        let mut stats = StepStats::default();
+        let (result, calls) = write_after(&mut stats, vec![Error::Duplicate("seen".into())]).await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls, 1);
+        assert_eq!(stats.rows_written, 0);
+        assert_eq!(stats.rows_skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn an_aborting_write_fails_the_step_at_once() {
+        let mut stats = StepStats::default();
+        let (result, calls) =
+            write_after(&mut stats, vec![Error::Unauthorized("token".into())]).await;
+
+        let e = result.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Error>(),
+            Some(Error::Unauthorized(_))
+        ));
+        assert_eq!(calls, 1);
+        assert_eq!(stats.rows_written, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_run_records_its_steps_and_fails_with_an_aborted_one() {
+        let pool = test_db::pool().await;
+        let run = PipelineRun::start(&pool, "test").await.unwrap();
+
+        let written = run
+            .step("writes", async {
+                let mut stats = StepStats::default();
+                write_after(&mut stats, vec![Error::GitHub("502".into())])
+                    .await
+                    .0?;
+                write_after(&mut stats, vec![Error::Duplicate("seen".into())])
+                    .await
+                    .0?;
+                anyhow::Ok(stats)
+            })
+            .await
+            .unwrap();
+        assert_eq!(written.rows_written, 1);
+
+        let aborted = run
+            .step("aborts", async {
+                let mut stats = StepStats::default();
+                write_after(&mut stats, vec![Error::Unauthorized("token".into())])
+                    .await
+                    .0?;
+                anyhow::Ok(stats)
+            })
+            .await;
+        let result = aborted.map(|_| ());
+        assert!(result.is_err());
+        let run_id = run.run_id;
+        run.finish(&result).await;
+
+        let mut conn = pool.get_conn().await.unwrap();
+        let steps: Vec<(String, String, i32, Option<String>)> = conn
+            .exec(
+                r"SELECT step_name, step_status, rows_written, error_text
+                  FROM pipeline_steps WHERE run_id = :run_id ORDER BY step_id",
+                params! { "run_id" => run_id },
+            )
+            .await
+            .unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(
+            (steps[0].0.as_str(), steps[0].1.as_str(), steps[0].2),
+            ("writes", "success", 1)
+        );
+        assert_eq!(
+            (steps[1].0.as_str(), steps[1].1.as_str()),
+            ("aborts", "failed")
+        );
+        assert!(steps[1].3.is_some());
+
+        let run_row: Option<(String, i32, Option<String>)> = conn
+            .exec_first(
+                "SELECT run_status, rows_written, error_text FROM pipeline_runs WHERE run_id = :run_id",
+                params! { "run_id" => run_id },
+            )
+            .await
+            .unwrap();
+        let (run_status, rows_written, error_text) = run_row.unwrap();
+        assert_eq!(run_status, "failed");
+        assert_eq!(rows_written, 1);
+        assert!(error_text.is_some());
+    }
+}
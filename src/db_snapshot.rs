@@ -0,0 +1,359 @@
+use crate::currency::{reporting_currency, Currency};
+use crate::db_rates::rates_as_of;
+use crate::error::{Error, GosimResult};
+use chrono::NaiveDate;
+use mysql_async::prelude::*;
+use mysql_async::*;
+use serde::{Deserialize, Serialize};
+
+// Snapshots only store changes: a row is written when an issue or project differs
+// from its latest earlier row, so the state on a date is the latest row on or
+// before it.
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IssueSnapshot {
+    pub snapshot_date: String,
+    pub issue_id: String,
+    pub project_id: String,
+    pub review_status: String,
+    pub issue_budget: Option<i32>,
+    pub issue_budget_approved: bool,
+    pub issue_assignees: Option<String>,
+    pub issue_linked_pr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProjectSnapshot {
+    pub snapshot_date: String,
+    pub project_id: String,
+    pub repo_stars: i32,
+    pub total_budget_allocated: Option<i32>,
+    pub issues_count: i32,
+}
+
+const LATEST_ISSUE_ROWS: &str = r"
+    SELECT s.* FROM issue_snapshots s
+    JOIN (
+        SELECT issue_id, MAX(snapshot_date) AS snapshot_date
+        FROM issue_snapshots WHERE snapshot_date <= :as_of GROUP BY issue_id
+    ) latest USING (issue_id, snapshot_date)";
+
+const LATEST_PROJECT_ROWS: &str = r"
+    SELECT s.* FROM project_snapshots s
+    JOIN (
+        SELECT project_id, MAX(snapshot_date) AS snapshot_date
+        FROM project_snapshots WHERE snapshot_date <= :as_of GROUP BY project_id
+    ) latest USING (project_id, snapshot_date)";
+
+fn date_param(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+// Writes the rows for `snapshot_date` that changed since the previous snapshot,
+// replacing any taken earlier the same day. Returns (issue rows, project rows).
+pub async fn take_snapshot(pool: &Pool, snapshot_date: NaiveDate) -> GosimResult<(u64, u64)> {
+    let date = date_param(snapshot_date);
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+
+    // an older date would be compared against rows that come after it
+    let latest: Option<Option<String>> = tx
+        .query_first(
+            r"SELECT DATE_FORMAT(GREATEST(
+                COALESCE((SELECT MAX(snapshot_date) FROM issue_snapshots), '1000-01-01'),
+                COALESCE((SELECT MAX(snapshot_date) FROM project_snapshots), '1000-01-01')
+              ), '%Y-%m-%d')",
+        )
+        .await?;
+    if let Some(latest) = latest.flatten().filter(|latest| *latest > date) {
+        tx.rollback().await?;
+        return Err(Error::Validation(format!(
+            "cannot snapshot {}, a snapshot for {} already exists",
+            date, latest
+        )));
+    }
+
+    tx.exec_drop(
+        "DELETE FROM issue_snapshots WHERE snapshot_date = :snapshot_date",
+        params! { "snapshot_date" => &date },
+    )
+    .await?;
+    tx.exec_drop(
+        "DELETE FROM project_snapshots WHERE snapshot_date = :snapshot_date",
+        params! { "snapshot_date" => &date },
+    )
+    .await?;
+
+    tx.exec_drop(
+        r"INSERT INTO issue_snapshots (snapshot_date, issue_id, project_id, review_status,
+            issue_budget, issue_budget_approved, issue_assignees, issue_linked_pr)
+          SELECT :snapshot_date, im.issue_id, im.project_id, im.review_status, im.issue_budget,
+            im.issue_budget_approved, ial.issue_assignees, im.issue_linked_pr
+          FROM issues_master im
+          LEFT JOIN issue_assignee_lists ial ON ial.issue_id = im.issue_id
+          LEFT JOIN issue_snapshots prev ON prev.issue_id = im.issue_id
+            AND prev.snapshot_date = (
+                SELECT MAX(s.snapshot_date) FROM issue_snapshots s
+                WHERE s.issue_id = im.issue_id AND s.snapshot_date < :snapshot_date
+            )
+          WHERE prev.issue_id IS NULL
+            OR NOT (im.project_id <=> prev.project_id
+                AND im.review_status <=> prev.review_status
+                AND im.issue_budget <=> prev.issue_budget
+                AND im.issue_budget_approved <=> prev.issue_budget_approved
+                AND ial.issue_assignees <=> prev.issue_assignees
+                AND im.issue_linked_pr <=> prev.issue_linked_pr)",
+        params! { "snapshot_date" => &date },
+    )
+    .await?;
+    let issue_rows = tx.affected_rows();
+
+    tx.exec_drop(
+        r"INSERT INTO project_snapshots (snapshot_date, project_id, repo_stars,
+            total_budget_allocated, issues_count)
+          SELECT :snapshot_date, p.project_id, p.repo_stars, p.total_budget_allocated,
+            COALESCE(JSON_LENGTH(p.issues_list), 0)
+          FROM projects p
+          LEFT JOIN project_snapshots prev ON prev.project_id = p.project_id
+            AND prev.snapshot_date = (
+                SELECT MAX(s.snapshot_date) FROM project_snapshots s
+                WHERE s.project_id = p.project_id AND s.snapshot_date < :snapshot_date
+            )
+          WHERE prev.project_id IS NULL
+            OR NOT (p.repo_stars <=> prev.repo_stars
+                AND p.total_budget_allocated <=> prev.total_budget_allocated
+                AND COALESCE(JSON_LENGTH(p.issues_list), 0) <=> prev.issues_count)",
+        params! { "snapshot_date" => &date },
+    )
+    .await?;
+    let project_rows = tx.affected_rows();
+
+    tx.commit().await?;
+
+    Ok((issue_rows, project_rows))
+}
+
+pub async fn issues_as_of(pool: &Pool, as_of: NaiveDate) -> GosimResult<Vec<IssueSnapshot>> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = format!(
+        "SELECT issue_id, project_id, review_status, issue_budget, issue_budget_approved,
+            issue_assignees, issue_linked_pr,
+            DATE_FORMAT(snapshot_date, '%Y-%m-%d') AS snapshot_date
+         FROM ({}) AS as_of ORDER BY issue_id",
+        LATEST_ISSUE_ROWS
+    );
+    let rows: Vec<Row> = conn
+        .exec(query, params! { "as_of" => date_param(as_of) })
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| IssueSnapshot {
+            snapshot_date: row.get("snapshot_date").unwrap_or_default(),
+            issue_id: row.get("issue_id").unwrap_or_default(),
+            project_id: row.get("project_id").unwrap_or_default(),
+            review_status: row
+                .get::<Option<String>, _>("review_status")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            issue_budget: row.get::<Option<i32>, _>("issue_budget").unwrap_or(None),
+            issue_budget_approved: row
+                .get::<Option<bool>, _>("issue_budget_approved")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            issue_assignees: row
+                .get::<Option<String>, _>("issue_assignees")
+                .unwrap_or(None),
+            issue_linked_pr: row
+                .get::<Option<String>, _>("issue_linked_pr")
+                .unwrap_or(None),
+        })
+        .collect())
+}
+
+pub async fn projects_as_of(pool: &Pool, as_of: NaiveDate) -> GosimResult<Vec<ProjectSnapshot>> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = format!(
+        "SELECT project_id, repo_stars, total_budget_allocated, issues_count,
+            DATE_FORMAT(snapshot_date, '%Y-%m-%d') AS snapshot_date
+         FROM ({}) AS as_of ORDER BY project_id",
+        LATEST_PROJECT_ROWS
+    );
+    let rows: Vec<Row> = conn
+        .exec(query, params! { "as_of" => date_param(as_of) })
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| ProjectSnapshot {
+            snapshot_date: row.get("snapshot_date").unwrap_or_default(),
+            project_id: row.get("project_id").unwrap_or_default(),
+            repo_stars: row
+                .get::<Option<i32>, _>("repo_stars")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+            total_budget_allocated: row
+                .get::<Option<i32>, _>("total_budget_allocated")
+                .unwrap_or(None),
+            issues_count: row
+                .get::<Option<i32>, _>("issues_count")
+                .unwrap_or(None)
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+// Same shape as count_issues_by_status, for the catalog on `as_of`.
+pub async fn count_issues_as_of(
+    pool: &Pool,
+    as_of: NaiveDate,
+) -> GosimResult<(i32, i32, i32, i32)> {
+    let mut conn = pool.get_conn().await?;
+
+    let query = format!(
+        "SELECT COUNT(*) AS total_count,
+            COALESCE(SUM(review_status = 'approve'), 0) AS approve_count,
+            COALESCE(SUM(review_status = 'decline'), 0) AS decline_count
+         FROM ({}) AS as_of",
+        LATEST_ISSUE_ROWS
+    );
+    let row: Option<(i32, i32, i32)> = conn
+        .exec_first(query, params! { "as_of" => date_param(as_of) })
+        .await?;
+    let (total_count, approve_count, decline_count) = row.unwrap_or((0, 0, 0));
+    let queue_count = total_count - (approve_count + decline_count);
+
+    Ok((total_count, queue_count, approve_count, decline_count))
+}
+
+// Same shape as count_budget_by_status, for the end of `as_of` and at the exchange
+// rates of that day. The ledger is append-only, so this reads it rather than the
+// project snapshots.
+pub async fn count_budget_as_of(pool: &Pool, as_of: NaiveDate) -> GosimResult<(i32, i32, i32)> {
+    let mut conn = pool.get_conn().await?;
+
+    let rows: Vec<(String, i64, i64, i64)> = conn
+        .exec(
+            r"SELECT e.currency,
+                CAST(-COALESCE(SUM(CASE WHEN e.account = 'funding' THEN e.amount END), 0) AS SIGNED),
+                CAST(COALESCE(SUM(CASE WHEN e.account IN ('allocated', 'approved', 'paid')
+                    THEN e.amount END), 0) AS SIGNED),
+                CAST(COALESCE(SUM(CASE WHEN e.account = 'available' THEN e.amount END), 0) AS SIGNED)
+              FROM ledger_entries e
+              JOIN ledger_transactions t ON t.txn_id = e.txn_id
+              WHERE t.created_at < :as_of + INTERVAL 1 DAY
+              GROUP BY e.currency",
+            params! { "as_of" => date_param(as_of) },
+        )
+        .await?;
+    let rates = rates_as_of(&mut conn, Some(as_of)).await?;
+    let currency = reporting_currency();
+
+    let mut totals = (0, 0, 0);
+    for (from, funded, allocated, available) in rows {
+        let from = Currency::from_db(&from);
+        totals.0 += rates.convert(funded, from, currency)?;
+        totals.1 += rates.convert(allocated, from, currency)?;
+        totals.2 += rates.convert(available, from, currency)?;
+    }
+    Ok((totals.0 as i32, totals.1 as i32, totals.2 as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_db;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    async fn insert_snapshot(pool: &Pool, snapshot_date: &str, issue_id: &str, status: &str) {
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.exec_drop(
+            r"INSERT INTO issue_snapshots
+                (snapshot_date, issue_id, project_id, review_status, issue_budget_approved)
+              VALUES (:snapshot_date, :issue_id, 'test', :status, FALSE)",
+            params! {
+                "snapshot_date" => snapshot_date,
+                "issue_id" => issue_id,
+                "status" => status,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn snapshot_of(pool: &Pool, as_of: &str, issue_id: &str) -> Option<IssueSnapshot> {
+        issues_as_of(pool, date(as_of))
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|snapshot| snapshot.issue_id == issue_id)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn as_of_reads_the_latest_snapshot_on_or_before_the_date() {
+        let pool = test_db::pool().await;
+        let issue_id = format!("test-{:08x}", rand::random::<u32>());
+        insert_snapshot(&pool, "2001-03-01", &issue_id, "queue").await;
+        insert_snapshot(&pool, "2001-03-10", &issue_id, "approve").await;
+
+        // nothing before the first row
+        assert!(snapshot_of(&pool, "2001-02-28", &issue_id).await.is_none());
+
+        let on_first = snapshot_of(&pool, "2001-03-01", &issue_id).await.unwrap();
+        assert_eq!(on_first.snapshot_date, "2001-03-01");
+        assert_eq!(on_first.review_status, "queue");
+
+        let between = snapshot_of(&pool, "2001-03-09", &issue_id).await.unwrap();
+        assert_eq!(between.snapshot_date, "2001-03-01");
+
+        let on_second = snapshot_of(&pool, "2001-03-10", &issue_id).await.unwrap();
+        assert_eq!(on_second.review_status, "approve");
+        let after = snapshot_of(&pool, "2001-06-01", &issue_id).await.unwrap();
+        assert_eq!(after.snapshot_date, "2001-03-10");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn rerunning_a_day_replaces_its_rows() {
+        let pool = test_db::pool().await;
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        let issue_id = format!("{}-1", campaign_id);
+        test_db::insert_issue(&pool, &campaign_id, &issue_id).await;
+        // the last day there is, so no other test's snapshot comes after it
+        let day = "9999-12-31";
+
+        take_snapshot(&pool, date(day)).await.unwrap();
+        assert!(snapshot_of(&pool, day, &issue_id).await.is_some());
+
+        // the same day again keeps a row even though nothing changed since
+        take_snapshot(&pool, date(day)).await.unwrap();
+        let unchanged = snapshot_of(&pool, day, &issue_id).await.unwrap();
+        assert_eq!(unchanged.snapshot_date, day);
+
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.exec_drop(
+            "UPDATE issues_master SET review_status = 'decline' WHERE issue_id = :issue_id",
+            params! { "issue_id" => &issue_id },
+        )
+        .await
+        .unwrap();
+        take_snapshot(&pool, date(day)).await.unwrap();
+        let rows: Vec<String> = conn
+            .exec(
+                "SELECT review_status FROM issue_snapshots WHERE issue_id = :issue_id",
+                params! { "issue_id" => &issue_id },
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows, vec!["decline".to_string()]);
+
+        // an earlier day would be compared against the rows after it
+        let refused = take_snapshot(&pool, date("9999-12-30")).await.unwrap_err();
+        assert!(matches!(refused, Error::Validation(_)), "{:?}", refused);
+    }
+}
@@ -0,0 +1,404 @@
+use crate::db_adjust::{self, BudgetChange};
+use crate::db_approval::BudgetVote;
+use crate::db_audit::{self, AdminAction, AuditInfo, StateChange};
+use crate::db_export::{self, ExportRequest, ExportValue};
+use crate::db_join;
+use crate::db_ledger::{self, CampaignBalance, LedgerTxn};
+use crate::db_manipulate::{self, IssueAndComments, IssueSubset};
+use crate::db_populate::{self, IssueOut, ProjectOut};
+use crate::db_query::{IssueQuery, ProjectQuery};
+use crate::db_report::{self, BudgetReport};
+use crate::db_risk;
+use crate::db_runs::{self, PipelineRunOut};
+use crate::error::GosimResult;
+use crate::issue_tracker::*;
+use crate::review_state::ReviewAction;
+use async_trait::async_trait;
+use mysql_async::Pool;
+
+// The database operations the pipeline and the backend handlers depend on, run
+// against MySQL by Pool. The tests in tests/ go through it as well.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // staging tables filled from GitHub
+    async fn project_exists(&self, project_id: &str) -> GosimResult<bool>;
+    async fn issue_exists(&self, issue_id: &str) -> GosimResult<bool>;
+    async fn pull_request_exists(&self, pull_id: &str) -> GosimResult<bool>;
+    async fn add_issues_open(&self, issue: &IssueOpen) -> GosimResult<()>;
+    async fn add_issues_comment(&self, issue: IssueComment) -> GosimResult<()>;
+    async fn add_issues_closed(&self, issue: IssueClosed) -> GosimResult<()>;
+    async fn add_issues_assigned(&self, issue_assigned: IssueAssigned) -> GosimResult<()>;
+    async fn add_pull_request(&self, pull: OuterPull) -> GosimResult<()>;
+    async fn fill_project_w_repo_data(&self, repo_data: RepoData) -> GosimResult<()>;
+    async fn add_or_update_summary_and_id(
+        &self,
+        issue_or_project_id: &str,
+        issue_or_project_summary: &str,
+        keyword_tags: Vec<String>,
+    ) -> GosimResult<()>;
+    async fn mark_id_indexed(&self, issue_or_project_id: &str) -> GosimResult<()>;
+    async fn get_issues_repos_from_db(&self) -> GosimResult<Vec<(String, String)>>;
+    async fn search_by_keyword_tags(&self, tags_to_search: Vec<String>)
+        -> GosimResult<Vec<String>>;
+
+    // join and cleanup, each returns the number of affected rows. On Pool every
+    // call runs on its own connection. the_runner doesn't go through these, it
+    // calls db_join on the connection of its transaction.
+    async fn open_master(&self) -> GosimResult<u64>;
+    async fn assigned_master(&self) -> GosimResult<u64>;
+    async fn closed_master(&self) -> GosimResult<u64>;
+    async fn advance_review_states(&self) -> GosimResult<u64>;
+    async fn score_risks(&self) -> GosimResult<u64>;
+    async fn master_project(&self) -> GosimResult<u64>;
+    async fn sum_budget_to_project(&self) -> GosimResult<u64>;
+    async fn project_master_back_sync(&self) -> GosimResult<u64>;
+    async fn remove_pull_by_issued_linked_pr(&self) -> GosimResult<u64>;
+    async fn delete_issues_open_assigned_closed(&self) -> GosimResult<u64>;
+
+    // reads behind the backend routes
+    async fn count_issues_by_status(&self) -> GosimResult<(i32, i32, i32, i32)>;
+    async fn count_budget_by_status(&self) -> GosimResult<(i32, i32, i32)>;
+    async fn list_issues_by_single(
+        &self,
+        list_by: Option<&str>,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<IssueSubset>>;
+    async fn list_issues_by_multi(
+        &self,
+        query: &IssueQuery,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<IssueOut>>;
+    async fn list_projects_by(
+        &self,
+        query: &ProjectQuery,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<ProjectOut>>;
+    async fn get_issue_w_comments_by_id(&self, issue_id: &str) -> GosimResult<IssueAndComments>;
+    async fn get_projects_as_repo_list(&self, page: u32) -> GosimResult<String>;
+    async fn get_issues_open_from_master(&self, page: u32) -> GosimResult<Vec<IssueOpen>>;
+
+    // review actions, each one is recorded in admin_actions. A budget is approved
+    // once enough reviewers have voted for it.
+    async fn vote_issue_budget_in_db(
+        &self,
+        issue_id: &str,
+        issue_budget: i64,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetVote>;
+    async fn decline_issue_in_db(&self, issue_id: &str, audit: &AuditInfo) -> GosimResult<()>;
+    async fn batch_decline_issues_in_db(
+        &self,
+        issue_ids: Vec<String>,
+        audit: &AuditInfo,
+    ) -> GosimResult<()>;
+    async fn conclude_issue_in_db(&self, issue_id: &str, audit: &AuditInfo) -> GosimResult<()>;
+    async fn review_issue_in_db(
+        &self,
+        issue_id: &str,
+        action: ReviewAction,
+        audit: &AuditInfo,
+    ) -> GosimResult<()>;
+    // changes to approved budgets, booked and recorded like the review actions
+    async fn adjust_budget_in_db(
+        &self,
+        issue_id: &str,
+        issue_budget: i64,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetChange>;
+    async fn move_budget_in_db(
+        &self,
+        from_issue_id: &str,
+        to_issue_id: &str,
+        amount: Option<i64>,
+        audit: &AuditInfo,
+    ) -> GosimResult<Vec<BudgetChange>>;
+    async fn clawback_budget_in_db(
+        &self,
+        issue_id: &str,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetChange>;
+    async fn list_issue_history(&self, issue_id: &str) -> GosimResult<Vec<AdminAction>>;
+    async fn list_state_changes(&self, issue_id: &str) -> GosimResult<Vec<StateChange>>;
+
+    // the budget ledger, a page of every transaction or of one issue's, and each
+    // campaign's balances
+    async fn list_ledger_transactions(
+        &self,
+        issue_id: Option<&str>,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<LedgerTxn>>;
+    async fn campaign_balances(&self) -> GosimResult<Vec<CampaignBalance>>;
+    // burn-down, forecast and breakdowns of one campaign or all of them
+    async fn budget_report(&self, campaign_id: Option<&str>) -> GosimResult<BudgetReport>;
+
+    // one page of an export, columns in the order of ExportRequest::table.columns()
+    async fn export_page(
+        &self,
+        request: &ExportRequest,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<Vec<ExportValue>>>;
+
+    async fn list_recent_runs(&self, limit: usize) -> GosimResult<Vec<PipelineRunOut>>;
+}
+
+#[async_trait]
+impl Storage for Pool {
+    async fn project_exists(&self, project_id: &str) -> GosimResult<bool> {
+        db_populate::project_exists(self, project_id).await
+    }
+
+    async fn issue_exists(&self, issue_id: &str) -> GosimResult<bool> {
+        db_populate::issue_exists(self, issue_id).await
+    }
+
+    async fn pull_request_exists(&self, pull_id: &str) -> GosimResult<bool> {
+        db_populate::pull_request_exists(self, pull_id).await
+    }
+
+    async fn add_issues_open(&self, issue: &IssueOpen) -> GosimResult<()> {
+        db_populate::add_issues_open(self, issue).await
+    }
+
+    async fn add_issues_comment(&self, issue: IssueComment) -> GosimResult<()> {
+        db_populate::add_issues_comment(self, issue).await
+    }
+
+    async fn add_issues_closed(&self, issue: IssueClosed) -> GosimResult<()> {
+        db_populate::add_issues_closed(self, issue).await
+    }
+
+    async fn add_issues_assigned(&self, issue_assigned: IssueAssigned) -> GosimResult<()> {
+        db_populate::add_issues_assigned(self, issue_assigned).await
+    }
+
+    async fn add_pull_request(&self, pull: OuterPull) -> GosimResult<()> {
+        db_populate::add_pull_request(self, pull).await
+    }
+
+    async fn fill_project_w_repo_data(&self, repo_data: RepoData) -> GosimResult<()> {
+        db_populate::fill_project_w_repo_data(self, repo_data).await
+    }
+
+    async fn add_or_update_summary_and_id(
+        &self,
+        issue_or_project_id: &str,
+        issue_or_project_summary: &str,
+        keyword_tags: Vec<String>,
+    ) -> GosimResult<()> {
+        db_populate::add_or_update_summary_and_id(
+            self,
+            issue_or_project_id,
+            issue_or_project_summary,
+            keyword_tags,
+        )
+        .await
+    }
+
+    async fn mark_id_indexed(&self, issue_or_project_id: &str) -> GosimResult<()> {
+        db_populate::mark_id_indexed(self, issue_or_project_id).await
+    }
+
+    async fn get_issues_repos_from_db(&self) -> GosimResult<Vec<(String, String)>> {
+        db_populate::get_issues_repos_from_db(self).await
+    }
+
+    async fn search_by_keyword_tags(
+        &self,
+        tags_to_search: Vec<String>,
+    ) -> GosimResult<Vec<String>> {
+        db_manipulate::search_by_keyword_tags(self.clone(), tags_to_search).await
+    }
+
+    async fn open_master(&self) -> GosimResult<u64> {
+        db_join::open_master(&mut self.get_conn().await?).await
+    }
+
+    async fn assigned_master(&self) -> GosimResult<u64> {
+        db_join::assigned_master(&mut self.get_conn().await?).await
+    }
+
+    async fn closed_master(&self) -> GosimResult<u64> {
+        db_join::closed_master(&mut self.get_conn().await?).await
+    }
+
+    async fn advance_review_states(&self) -> GosimResult<u64> {
+        db_join::advance_review_states(&mut self.get_conn().await?).await
+    }
+
+    async fn score_risks(&self) -> GosimResult<u64> {
+        db_risk::score_risks(&mut self.get_conn().await?).await
+    }
+
+    async fn master_project(&self) -> GosimResult<u64> {
+        db_join::master_project(&mut self.get_conn().await?).await
+    }
+
+    async fn sum_budget_to_project(&self) -> GosimResult<u64> {
+        db_join::sum_budget_to_project(&mut self.get_conn().await?)
+            .await
+            .map(|stats| stats.rows_written as u64)
+    }
+
+    async fn project_master_back_sync(&self) -> GosimResult<u64> {
+        db_join::project_master_back_sync(&mut self.get_conn().await?).await
+    }
+
+    async fn remove_pull_by_issued_linked_pr(&self) -> GosimResult<u64> {
+        db_join::remove_pull_by_issued_linked_pr(&mut self.get_conn().await?).await
+    }
+
+    async fn delete_issues_open_assigned_closed(&self) -> GosimResult<u64> {
+        db_join::delete_issues_open_assigned_closed(&mut self.get_conn().await?).await
+    }
+
+    async fn count_issues_by_status(&self) -> GosimResult<(i32, i32, i32, i32)> {
+        db_manipulate::count_issues_by_status(self).await
+    }
+
+    async fn count_budget_by_status(&self) -> GosimResult<(i32, i32, i32)> {
+        db_manipulate::count_budget_by_status(self).await
+    }
+
+    async fn list_issues_by_single(
+        &self,
+        list_by: Option<&str>,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<IssueSubset>> {
+        db_manipulate::list_issues_by_single(self, list_by, page, page_size).await
+    }
+
+    async fn list_issues_by_multi(
+        &self,
+        query: &IssueQuery,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<IssueOut>> {
+        db_manipulate::list_issues_by_multi(self, query, page, page_size).await
+    }
+
+    async fn list_projects_by(
+        &self,
+        query: &ProjectQuery,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<ProjectOut>> {
+        db_manipulate::list_projects_by(self, query, page, page_size).await
+    }
+
+    async fn get_issue_w_comments_by_id(&self, issue_id: &str) -> GosimResult<IssueAndComments> {
+        db_manipulate::get_issue_w_comments_by_id(self, issue_id).await
+    }
+
+    async fn get_projects_as_repo_list(&self, page: u32) -> GosimResult<String> {
+        db_manipulate::get_projects_as_repo_list(self, page).await
+    }
+
+    async fn get_issues_open_from_master(&self, page: u32) -> GosimResult<Vec<IssueOpen>> {
+        db_manipulate::get_issues_open_from_master(self, page).await
+    }
+
+    async fn vote_issue_budget_in_db(
+        &self,
+        issue_id: &str,
+        issue_budget: i64,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetVote> {
+        db_manipulate::vote_issue_budget_in_db(self, issue_id, issue_budget, audit).await
+    }
+
+    async fn decline_issue_in_db(&self, issue_id: &str, audit: &AuditInfo) -> GosimResult<()> {
+        db_manipulate::decline_issue_in_db(self, issue_id, audit).await
+    }
+
+    async fn batch_decline_issues_in_db(
+        &self,
+        issue_ids: Vec<String>,
+        audit: &AuditInfo,
+    ) -> GosimResult<()> {
+        db_manipulate::batch_decline_issues_in_db(self, issue_ids, audit).await
+    }
+
+    async fn conclude_issue_in_db(&self, issue_id: &str, audit: &AuditInfo) -> GosimResult<()> {
+        db_manipulate::conclude_issue_in_db(self, issue_id, audit).await
+    }
+
+    async fn review_issue_in_db(
+        &self,
+        issue_id: &str,
+        action: ReviewAction,
+        audit: &AuditInfo,
+    ) -> GosimResult<()> {
+        db_manipulate::review_issue_in_db(self, issue_id, action, audit).await
+    }
+
+    async fn adjust_budget_in_db(
+        &self,
+        issue_id: &str,
+        issue_budget: i64,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetChange> {
+        db_adjust::adjust_budget(self, issue_id, issue_budget, audit).await
+    }
+
+    async fn move_budget_in_db(
+        &self,
+        from_issue_id: &str,
+        to_issue_id: &str,
+        amount: Option<i64>,
+        audit: &AuditInfo,
+    ) -> GosimResult<Vec<BudgetChange>> {
+        db_adjust::move_budget(self, from_issue_id, to_issue_id, amount, audit).await
+    }
+
+    async fn clawback_budget_in_db(
+        &self,
+        issue_id: &str,
+        audit: &AuditInfo,
+    ) -> GosimResult<BudgetChange> {
+        db_adjust::clawback_budget(self, issue_id, audit).await
+    }
+
+    async fn list_issue_history(&self, issue_id: &str) -> GosimResult<Vec<AdminAction>> {
+        db_audit::list_issue_history(self, issue_id).await
+    }
+
+    async fn list_state_changes(&self, issue_id: &str) -> GosimResult<Vec<StateChange>> {
+        db_audit::list_state_changes(self, issue_id).await
+    }
+
+    async fn list_ledger_transactions(
+        &self,
+        issue_id: Option<&str>,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<LedgerTxn>> {
+        db_ledger::list_transactions(self, issue_id, page, page_size).await
+    }
+
+    async fn campaign_balances(&self) -> GosimResult<Vec<CampaignBalance>> {
+        db_ledger::campaign_balances(self).await
+    }
+
+    async fn budget_report(&self, campaign_id: Option<&str>) -> GosimResult<BudgetReport> {
+        db_report::budget_report(self, campaign_id).await
+    }
+
+    async fn export_page(
+        &self,
+        request: &ExportRequest,
+        page: usize,
+        page_size: usize,
+    ) -> GosimResult<Vec<Vec<ExportValue>>> {
+        db_export::export_page(self, request, page, page_size).await
+    }
+
+    async fn list_recent_runs(&self, limit: usize) -> GosimResult<Vec<PipelineRunOut>> {
+        db_runs::list_recent_runs(self, limit).await
+    }
+}
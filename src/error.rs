@@ -0,0 +1,101 @@
+use std::fmt;
+
+pub type GosimResult<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    GitHub(String),
+    RateLimited(String),
+    Db(mysql_async::Error),
+    Duplicate(String),
+    Llm(String),
+    VectorStore(String),
+    NotFound(String),
+    Validation(String),
+    // writing an export to its output failed
+    Export(String),
+    // an issue budget over a project, contributor or issue cap
+    BudgetCap(String),
+    // a review action the issue's state doesn't allow
+    Transition(String),
+    // concluding an issue whose risk flags add up to the threshold
+    Risk(String),
+    // an admin action without a known reviewer token
+    Unauthorized(String),
+}
+
+// What a runner should do with a failed item or step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    Retry,
+    Skip,
+    Abort,
+}
+
+impl Error {
+    pub fn action(&self) -> ErrorAction {
+        match self {
+            Error::Duplicate(_) | Error::NotFound(_) | Error::Validation(_) => ErrorAction::Skip,
+            Error::BudgetCap(_) | Error::Transition(_) | Error::Risk(_) => ErrorAction::Skip,
+            Error::Unauthorized(_) => ErrorAction::Abort,
+            Error::Llm(_) | Error::VectorStore(_) => ErrorAction::Skip,
+            Error::GitHub(_) => ErrorAction::Retry,
+            // dropped connections are worth another try, server side errors are not
+            Error::Db(mysql_async::Error::Io(_)) => ErrorAction::Retry,
+            Error::Db(_) | Error::RateLimited(_) | Error::Export(_) => ErrorAction::Abort,
+        }
+    }
+
+    pub fn status_code(&self) -> u16 {
+        match self {
+            Error::NotFound(_) => 404,
+            Error::Validation(_) => 400,
+            Error::Unauthorized(_) => 401,
+            Error::Duplicate(_) | Error::Transition(_) => 409,
+            Error::BudgetCap(_) | Error::Risk(_) => 422,
+            Error::RateLimited(_) => 429,
+            Error::GitHub(_) | Error::Llm(_) | Error::VectorStore(_) => 502,
+            Error::Db(_) | Error::Export(_) => 500,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::GitHub(msg) => write!(f, "GitHub error: {}", msg),
+            Error::RateLimited(msg) => write!(f, "GitHub rate limit hit: {}", msg),
+            Error::Db(e) => write!(f, "Database error: {}", e),
+            Error::Duplicate(msg) => write!(f, "Duplicate entry: {}", msg),
+            Error::Llm(msg) => write!(f, "LLM error: {}", msg),
+            Error::VectorStore(msg) => write!(f, "Vector store error: {}", msg),
+            Error::NotFound(msg) => write!(f, "Not found: {}", msg),
+            Error::Validation(msg) => write!(f, "Invalid input: {}", msg),
+            Error::Export(msg) => write!(f, "Export error: {}", msg),
+            Error::BudgetCap(msg) => write!(f, "Budget cap exceeded: {}", msg),
+            Error::Transition(msg) => write!(f, "Illegal review transition: {}", msg),
+            Error::Risk(msg) => write!(f, "Risk threshold reached: {}", msg),
+            Error::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Db(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<mysql_async::Error> for Error {
+    fn from(e: mysql_async::Error) -> Self {
+        match e {
+            mysql_async::Error::Server(ref server_error) if server_error.code == 1062 => {
+                Error::Duplicate(server_error.message.clone())
+            }
+            e => Error::Db(e),
+        }
+    }
+}
@@ -1,9 +1,8 @@
+use crate::budget_parser::extract_budget;
+use crate::currency::Currency;
+use crate::error::{Error, GosimResult};
 use anyhow::anyhow;
 use chrono::{DateTime, Duration, ParseError, Utc};
-use http_req::{
-    request::{Method, Request},
-    uri::Uri,
-};
 use serde::{Deserialize, Serialize};
 use std::env;
 
@@ -12,93 +11,140 @@ fn convert_datetime(merged_at: &str) -> Result<String, ParseError> {
     Ok(datetime.format("%Y-%m-%d %H:%M:%S").to_string())
 }
 
-pub async fn github_http_get(url: &str, token: &str) -> anyhow::Result<Vec<u8>> {
-    let mut writer = Vec::new();
-    let url = Uri::try_from(url).unwrap();
-
-    match Request::new(&url)
-        .method(Method::GET)
-        .header("User-Agent", "flows-network connector")
-        .header("Content-Type", "application/json")
-        .header("Authorization", &format!("Bearer {}", token))
-        .header("CONNECTION", "close")
-        .send(&mut writer)
-    {
-        Ok(res) => {
-            if !res.status_code().is_success() {
-                log::error!("Github http error {:?}", res.status_code());
-                return Err(anyhow::anyhow!("Github http error {:?}", res.status_code()));
-            }
-            Ok(writer)
-        }
-        Err(_e) => {
-            log::error!("Error getting response from Github: {:?}", _e);
-            Err(anyhow::anyhow!(_e))
-        }
+// GitHub answers an exhausted rate limit with 403 or 429 and says so in the body.
+fn github_status_error(status: u16, body: &[u8]) -> Error {
+    let body = String::from_utf8_lossy(body);
+    if status == 429 || (status == 403 && body.to_lowercase().contains("rate limit")) {
+        Error::RateLimited(format!("Github http error {}", status))
+    } else {
+        Error::GitHub(format!("Github http error {}", status))
     }
 }
 
-pub async fn github_http_post(url: &str, body: &str) -> anyhow::Result<Vec<u8>> {
-    let token = env::var("GITHUB_TOKEN").expect("github_token is required");
-    let mut writer = Vec::new();
+fn github_token() -> GosimResult<String> {
+    env::var("GITHUB_TOKEN").map_err(|_| Error::GitHub("GITHUB_TOKEN is not set".to_string()))
+}
 
-    let uri = Uri::try_from(url).expect("failed to parse url");
+pub async fn github_http_get(url: &str, token: &str) -> GosimResult<Vec<u8>> {
+    github_send(url, token, None).await
+}
 
+pub async fn github_http_post(url: &str, body: &str) -> GosimResult<Vec<u8>> {
+    let token = github_token()?;
     let body = serde_json::json!({"body": body});
 
-    match Request::new(&uri)
-        .method(Method::POST)
+    github_send(url, &token, Some(body.to_string())).await
+}
+
+pub async fn github_http_post_gql(query: &str) -> GosimResult<Vec<u8>> {
+    let token = github_token()?;
+    let query = serde_json::json!({"query": query});
+
+    github_send(
+        "https://api.github.com/graphql",
+        &token,
+        Some(query.to_string()),
+    )
+    .await
+}
+
+// POSTs when there is a body, GETs otherwise.
+#[cfg(feature = "wasi")]
+async fn github_send(url: &str, token: &str, body: Option<String>) -> GosimResult<Vec<u8>> {
+    use http_req::{
+        request::{Method, Request},
+        uri::Uri,
+    };
+
+    let mut writer = Vec::new();
+    let uri = Uri::try_from(url).map_err(|e| Error::GitHub(e.to_string()))?;
+    let body = body.map(String::into_bytes);
+
+    let mut request = Request::new(&uri);
+    request
         .header("User-Agent", "flows-network connector")
         .header("Content-Type", "application/json")
         .header("Accept", "application/json")
         .header("Authorization", &format!("Bearer {}", token))
-        .header("Content-Length", &body.to_string().len())
-        .body(&body.to_string().into_bytes())
-        .send(&mut writer)
-    {
+        .header("CONNECTION", "close");
+    match &body {
+        Some(body) => request
+            .method(Method::POST)
+            .header("Content-Length", &body.len())
+            .body(body),
+        None => request.method(Method::GET),
+    };
+
+    match request.send(&mut writer) {
         Ok(res) => {
             if !res.status_code().is_success() {
                 log::error!("Github http error {:?}", res.status_code());
-                return Err(anyhow::anyhow!("Github http error {:?}", res.status_code()));
+                return Err(github_status_error(res.status_code().into(), &writer));
             }
             Ok(writer)
         }
         Err(_e) => {
             log::error!("Error getting response from Github: {:?}", _e);
-            Err(anyhow::anyhow!(_e))
+            Err(Error::GitHub(_e.to_string()))
         }
     }
 }
 
-pub async fn github_http_post_gql(query: &str) -> anyhow::Result<Vec<u8>> {
-    let token = env::var("GITHUB_TOKEN").expect("github_token is required");
-    let base_url = Uri::try_from("https://api.github.com/graphql").unwrap();
-    let mut writer = Vec::new();
+#[cfg(not(feature = "wasi"))]
+async fn github_send(url: &str, token: &str, body: Option<String>) -> GosimResult<Vec<u8>> {
+    let client = reqwest::Client::new();
+    let request = match body {
+        Some(body) => client.post(url).body(body),
+        None => client.get(url),
+    };
 
-    let query = serde_json::json!({"query": query});
-    match Request::new(&base_url)
-        .method(Method::POST)
+    let res = request
         .header("User-Agent", "flows-network connector")
         .header("Content-Type", "application/json")
-        .header("Authorization", &format!("Bearer {}", token))
-        .header("Content-Length", &query.to_string().len())
-        .body(&query.to_string().into_bytes())
-        .send(&mut writer)
-    {
-        Ok(res) => {
-            if !res.status_code().is_success() {
-                log::error!("Github http error {:?}", res.status_code());
-                return Err(anyhow::anyhow!("Github http error {:?}", res.status_code()));
-            }
-            Ok(writer)
-        }
-        Err(_e) => {
-            log::error!("Error getting response from Github: {:?}", _e);
-            Err(anyhow::anyhow!(_e))
-        }
+        .header("Accept", "application/json")
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| {
+            log::error!("Error getting response from Github: {:?}", e);
+            Error::GitHub(e.to_string())
+        })?;
+
+    let status = res.status();
+    let writer = res
+        .bytes()
+        .await
+        .map_err(|e| Error::GitHub(e.to_string()))?;
+    if !status.is_success() {
+        log::error!("Github http error {:?}", status);
+        return Err(github_status_error(status.as_u16(), &writer));
+    }
+    Ok(writer.to_vec())
+}
+
+// issue_id is the html url, e.g. https://github.com/owner/repo/issues/1
+pub fn issue_url_parts(issue_id: &str) -> GosimResult<(&str, &str, u64)> {
+    let parts: Vec<&str> = issue_id.trim_end_matches('/').split('/').collect();
+    match parts.as_slice() {
+        [_, _, _, owner, repo, "issues", number] => number
+            .parse::<u64>()
+            .map(|number| (*owner, *repo, number))
+            .map_err(|_| Error::Validation(format!("Not an issue url: {}", issue_id))),
+        _ => Err(Error::Validation(format!("Not an issue url: {}", issue_id))),
     }
 }
 
+pub async fn comment_on_issue(issue_id: &str, comment: &str) -> GosimResult<()> {
+    let (owner, repo, number) = issue_url_parts(issue_id)?;
+    let url = format!(
+        "https://api.github.com/repos/{}/{}/issues/{}/comments",
+        owner, repo, number
+    );
+
+    github_http_post(&url, comment).await?;
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct RepoData {
     pub project_id: String,
@@ -216,7 +262,7 @@ pub async fn search_repos_in_batch(query: &str) -> anyhow::Result<Vec<RepoData>>
                         main_language: repo
                             .languages
                             .and_then(|l| {
-                                l.nodes.and_then(|n| n.get(0).and_then(|l| l.name.clone()))
+                                l.nodes.and_then(|n| n.first().and_then(|l| l.name.clone()))
                             })
                             .unwrap_or_default(),
                         repo_stars: repo.stargazers.and_then(|s| s.totalCount).unwrap_or(0),
@@ -382,6 +428,10 @@ pub struct IssueOpen {
     pub issue_budget: i32,         // url of an issue
     pub issue_description: String, // description of the issue, could be truncated body text
     pub project_id: String,        // url of the repo
+    #[serde(default)]
+    pub issue_labels: Vec<String>,
+    #[serde(default)]
+    pub issue_budget_currency: Currency,
 }
 
 pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
@@ -416,6 +466,7 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
         url: String,
         body: Option<String>,
         author: Option<Author>,
+        labels: Option<Labels>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -423,6 +474,16 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
         login: Option<String>,
     }
 
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Labels {
+        nodes: Option<Vec<Label>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Label {
+        name: Option<String>,
+    }
+
     let mut all_issues = Vec::new();
     let mut after_cursor: Option<String> = None;
 
@@ -440,6 +501,11 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
                             author {{
                                 login
                             }}
+                            labels(first: 10) {{
+                                nodes {{
+                                    name
+                                }}
+                            }}
                         }}
                     }}
                     pageInfo {{
@@ -489,7 +555,15 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
                             .as_ref()
                             .and_then(|author| author.login.clone())
                             .unwrap_or_default();
-                        let issue_budget = extract_budget(&issue_description);
+                        let issue_labels: Vec<String> = issue
+                            .labels
+                            .and_then(|labels| labels.nodes)
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter_map(|label| label.name)
+                            .collect();
+                        let (issue_budget, issue_budget_currency) =
+                            extract_budget(&issue_description, &issue_labels);
                         all_issues.push(IssueOpen {
                             issue_title: issue.title,
                             issue_id: issue.url, // Assuming issue.url is the issue_id
@@ -497,6 +571,8 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
                             issue_description,
                             issue_budget,
                             project_id,
+                            issue_labels,
+                            issue_budget_currency,
                         });
                     }
                 }
@@ -515,6 +591,118 @@ pub async fn search_issues_open(query: &str) -> anyhow::Result<Vec<IssueOpen>> {
     Ok(all_issues)
 }
 
+// One issue by url, shaped like the rows search_issues_open stages.
+pub async fn get_issue_open(issue_id: &str) -> GosimResult<IssueOpen> {
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct GraphQLResponse {
+        data: Option<Data>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Data {
+        repository: Option<Repository>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Repository {
+        issue: Option<Issue>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Issue {
+        title: String,
+        url: String,
+        body: Option<String>,
+        author: Option<Author>,
+        labels: Option<Labels>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Author {
+        login: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Labels {
+        nodes: Option<Vec<Label>>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Default, Debug)]
+    struct Label {
+        name: Option<String>,
+    }
+
+    let (owner, repo, number) = issue_url_parts(issue_id)?;
+    let query_str = format!(
+        r#"
+        query {{
+            repository(owner: "{}", name: "{}") {{
+                issue(number: {}) {{
+                    title
+                    url
+                    body
+                    author {{
+                        login
+                    }}
+                    labels(first: 10) {{
+                        nodes {{
+                            name
+                        }}
+                    }}
+                }}
+            }}
+        }}
+        "#,
+        owner.replace("\"", ""),
+        repo.replace("\"", ""),
+        number,
+    );
+
+    let response_body = github_http_post_gql(&query_str).await?;
+    let response: GraphQLResponse = serde_json::from_slice(&response_body)
+        .map_err(|e| Error::GitHub(format!("Failed to deserialize response: {}", e)))?;
+
+    let issue = response
+        .data
+        .and_then(|data| data.repository)
+        .and_then(|repository| repository.issue)
+        .ok_or_else(|| Error::NotFound(format!("GitHub issue {}", issue_id)))?;
+
+    let issue_description = issue
+        .body
+        .unwrap_or_default()
+        .chars()
+        .take(8000)
+        .collect::<String>();
+    let project_id = issue
+        .url
+        .rsplitn(3, '/')
+        .nth(2)
+        .unwrap_or("wrong_project_id")
+        .to_string();
+    let issue_labels: Vec<String> = issue
+        .labels
+        .and_then(|labels| labels.nodes)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|label| label.name)
+        .collect();
+    let (issue_budget, issue_budget_currency) = extract_budget(&issue_description, &issue_labels);
+    Ok(IssueOpen {
+        issue_title: issue.title,
+        issue_id: issue.url,
+        issue_creator: issue
+            .author
+            .and_then(|author| author.login)
+            .unwrap_or_default(),
+        issue_budget,
+        issue_description,
+        project_id,
+        issue_labels,
+        issue_budget_currency,
+    })
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct IssueComment {
     pub issue_id: String,        // url of an issue
@@ -631,10 +819,12 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
                         if let Some(comments) = &issue.comments {
                             if let Some(nodes) = &comments.nodes {
                                 for comment in nodes {
-                                    if let Some(updated_at) = &comment.updatedAt {
-                                        let updated_at = DateTime::parse_from_rfc3339(updated_at)
-                                            .unwrap()
-                                            .with_timezone(&Utc);
+                                    if let Some(Ok(updated_at)) = comment
+                                        .updatedAt
+                                        .as_deref()
+                                        .map(DateTime::parse_from_rfc3339)
+                                    {
+                                        let updated_at = updated_at.with_timezone(&Utc);
                                         if updated_at > last_hour {
                                             let comment_creator = comment
                                                 .author
@@ -675,20 +865,6 @@ pub async fn search_issues_comment(query: &str) -> anyhow::Result<Vec<IssueComme
     Ok(all_issues)
 }
 
-pub fn extract_budget(body: &str) -> i32 {
-    let re = regex::Regex::new(r"(?i)budget:?\s*(\d{2,3})").unwrap();
-    for cap in re.captures_iter(body) {
-        if let Some(match_) = cap.get(1) {
-            if let Ok(b) = match_.as_str().parse::<i32>() {
-                if b >= 10 && b <= 999 {
-                    return b; // Returns the first match found
-                }
-            }
-        }
-    }
-    0
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct IssueClosed {
     pub issue_id: String, // url of an issue
@@ -878,9 +1054,9 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
                                         .map_or((None, None, None, None), |nodes| {
                                             nodes
                                                 .iter()
-                                                .filter_map(|event| {
+                                                .map(|event| {
                                                     if let Some(closer) = &event.closer {
-                                                        Some((
+                                                        (
                                                             event.stateReason.clone(),
                                                             closer.url.clone(),
                                                             closer.title.clone(),
@@ -888,9 +1064,9 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
                                                                 .author
                                                                 .as_ref()
                                                                 .map(|author| author.login.clone()),
-                                                        ))
+                                                        )
                                                     } else {
-                                                        Some((None, None, None, None))
+                                                        (None, None, None, None)
                                                     }
                                                 })
                                                 .next()
@@ -905,7 +1081,7 @@ pub async fn search_issues_closed(query: &str) -> anyhow::Result<Vec<IssueClosed
                         };
 
                         all_issues.push(IssueClosed {
-                            issue_id: issue_id,
+                            issue_id,
                             issue_assignees,
                             issue_linked_pr: close_pull_request,
                         });
@@ -932,6 +1108,17 @@ pub struct OuterPull {
     pub pull_author: Option<String>,
     pub project_id: String,
     pub merged_at: String,
+    #[serde(default)]
+    pub merged_by: Option<String>,
+    #[serde(default)]
+    pub additions: Option<i64>,
+    #[serde(default)]
+    pub deletions: Option<i64>,
+    #[serde(default)]
+    pub changed_files: Option<i64>,
+    // when the author signed up, %Y-%m-%d %H:%M:%S
+    #[serde(default)]
+    pub author_created_at: Option<String>,
 }
 
 pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>> {
@@ -962,11 +1149,18 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
         labels: Option<Labels>,
         reviews: Option<Reviews>,
         mergedAt: Option<String>,
+        mergedBy: Option<Author>,
+        additions: Option<i64>,
+        deletions: Option<i64>,
+        changedFiles: Option<i64>,
     }
 
+    #[allow(non_snake_case)]
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
     struct Author {
         login: Option<String>,
+        // only users have one, bots don't
+        createdAt: Option<String>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Default, Debug)]
@@ -1012,6 +1206,9 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                             url
                             author {{
                                 login
+                                ... on User {{
+                                    createdAt
+                                }}
                             }}
                             labels(first: 10) {{
                                 nodes {{
@@ -1027,6 +1224,12 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                                 }}
                             }}
                             mergedAt
+                            mergedBy {{
+                                login
+                            }}
+                            additions
+                            deletions
+                            changedFiles
                         }}
                     }}
                     pageInfo {{
@@ -1064,6 +1267,11 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                         let pull_title = node.title.clone().unwrap_or_default();
                         let pull_author =
                             node.author.as_ref().and_then(|author| author.login.clone());
+                        let author_created_at = node
+                            .author
+                            .as_ref()
+                            .and_then(|author| author.createdAt.as_deref())
+                            .and_then(|created_at| convert_datetime(created_at).ok());
                         let merged_at = node.mergedAt.unwrap_or_default();
                         let merged_at = convert_datetime(&merged_at).unwrap_or_default();
 
@@ -1073,6 +1281,11 @@ pub async fn search_pull_requests(query: &str) -> anyhow::Result<Vec<OuterPull>>
                             pull_author,
                             project_id,
                             merged_at,
+                            merged_by: node.mergedBy.and_then(|author| author.login),
+                            additions: node.additions,
+                            deletions: node.deletions,
+                            changed_files: node.changedFiles,
+                            author_created_at,
                         });
                     }
 
@@ -1240,7 +1453,7 @@ pub async fn get_rate_limit() -> anyhow::Result<i32> {
         }
     "#;
 
-    let response_body = github_http_post_gql(&query_str)
+    let response_body = github_http_post_gql(query_str)
         .await
         .map_err(|e| anyhow!("Failed to post GraphQL query: {}", e))?;
 
@@ -1,17 +1,45 @@
+pub mod backend_api;
+pub mod budget_parser;
+pub mod csv_util;
+pub mod currency;
+pub mod db_adjust;
+pub mod db_approval;
+pub mod db_audit;
+pub mod db_caps;
+pub mod db_export;
+pub mod db_import;
 pub mod db_join;
+pub mod db_ledger;
+pub mod db_lock;
 pub mod db_manipulate;
+pub mod db_migrate;
+pub mod db_payout;
 pub mod db_populate;
+pub mod db_query;
+pub mod db_rates;
+pub mod db_report;
+pub mod db_risk;
+pub mod db_runs;
+pub mod db_snapshot;
+pub mod db_storage;
+pub mod error;
 pub mod issue_bot;
 pub mod issue_tracker;
 pub mod llm_utils;
 pub mod llm_utils_together;
+pub mod review_state;
+pub mod reviewers;
+#[cfg(test)]
+mod test_db;
 pub mod the_paced_runner;
 pub mod the_runner;
+#[cfg_attr(not(feature = "wasi"), path = "vector_search_native.rs")]
 pub mod vector_search;
 // pub mod issue_paced_tracker;
 use chrono::{NaiveDate, Timelike, Utc};
 use lazy_static::lazy_static;
 
+// the default campaign's initial funding, budgets are read from the ledger
 pub static TOTAL_BUDGET: i32 = 50_000;
 pub static ISSUE_LABEL: &str = "hacktoberfest";
 pub static PR_LABEL: &str = "hacktoberfest-accepted";
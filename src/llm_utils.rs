@@ -1,9 +1,14 @@
+#[cfg(feature = "wasi")]
+use crate::error::{Error, GosimResult};
+#[cfg(feature = "wasi")]
 use openai_flows::{
     chat::{ChatModel, ChatOptions},
     OpenAIFlows,
 };
 use regex::Regex;
 
+// The chat helpers go through flows.network, natively use llm_utils_together.
+#[cfg(feature = "wasi")]
 pub async fn chain_of_chat(
     sys_prompt_1: &str,
     usr_prompt_1: &str,
@@ -11,7 +16,7 @@ pub async fn chain_of_chat(
     gen_len_1: u16,
     usr_prompt_2: &str,
     gen_len_2: u16,
-) -> anyhow::Result<String> {
+) -> GosimResult<String> {
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(2);
 
@@ -45,21 +50,22 @@ pub async fn chain_of_chat(
                     return Ok(r.choice);
                 }
                 Err(_e) => {
-                    return Err(anyhow::anyhow!("openai generation error, step 2: {_e}"));
+                    return Err(Error::Llm(format!("openai generation error, step 2: {_e}")));
                 }
             }
         }
         Err(_e) => {
-            return Err(anyhow::anyhow!("openai generation error, step 1: {_e}"));
+            return Err(Error::Llm(format!("openai generation error, step 1: {_e}")));
         }
     }
 }
 
+#[cfg(feature = "wasi")]
 pub async fn chat_inner_async(
     system_prompt: &str,
     user_input: &str,
     max_token: u16,
-) -> anyhow::Result<String> {
+) -> GosimResult<String> {
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(2);
 
@@ -80,7 +86,7 @@ pub async fn chat_inner_async(
             return Ok(r.choice);
         }
         Err(_e) => {
-            return Err(anyhow::anyhow!("openai generation error, inner: {_e}"));
+            return Err(Error::Llm(format!("openai generation error, inner: {_e}")));
         }
     }
 }
@@ -122,8 +128,7 @@ pub fn extract_summary_from_answer(input: &str) -> String {
         lines
             .iter()
             .skip(1)
-            .skip_while(|&&line| line.trim().is_empty())
-            .next() // Get the first element after skipping empty lines
+            .find(|&&line| !line.trim().is_empty()) // Get the first non-empty line after the first
             .map(|line| line.to_string()) // Convert &str to String
             .unwrap_or_else(|| "No summary located".to_string()) // Provide a default message
     }
@@ -1,3 +1,4 @@
+use crate::error::{Error, GosimResult};
 use reqwest::{
     header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
     ClientBuilder,
@@ -39,7 +40,13 @@ pub async fn chat_inner_async(
     system_prompt: &str,
     input: &str,
     max_token: u16,
-) -> anyhow::Result<String> {
+) -> GosimResult<String> {
+    chat_together(system_prompt, input, max_token)
+        .await
+        .map_err(|e| Error::Llm(format!("together generation error: {}", e)))
+}
+
+async fn chat_together(system_prompt: &str, input: &str, max_token: u16) -> anyhow::Result<String> {
     let mut headers = HeaderMap::new();
     let api_key = std::env::var("TOGETHER_API_KEY")?;
     // let api_key = std::env::var("AZURE_API_TOKEN")?;
@@ -48,7 +55,6 @@ pub async fn chat_inner_async(
     headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
     headers.insert(USER_AGENT, HeaderValue::from_static("MyClient/1.0.0"));
     headers.insert(AUTHORIZATION, HeaderValue::from_str(&bearer_token)?);
-    let input_head = input.chars().take(150).collect::<String>();
 
     let messages = serde_json::json!([
         {"role": "system", "content": system_prompt},
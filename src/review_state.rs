@@ -0,0 +1,297 @@
+use crate::error::{Error, GosimResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Where an issue is in its review, stored in issues_master.review_state. Every
+// change goes through ReviewState::next, and is recorded in issue_state_changes
+// with its time.
+//
+//   queued -> approved -> in_progress -> pr_linked -> concluded -> paid
+//
+// An issue that isn't concluded yet can be declined or withdrawn, and one without
+// a PR can expire. A budget that was approved can be adjusted, or clawed back
+// until the issue is concluded, which expires the issue. Declined, withdrawn and
+// expired issues can be reopened as queued, and only get a budget again from there.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewState {
+    #[default]
+    Queued,
+    Approved,
+    InProgress,
+    PrLinked,
+    Concluded,
+    Paid,
+    Declined,
+    Withdrawn,
+    Expired,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewAction {
+    // give the issue a budget, or change the budget of an approved issue
+    Approve,
+    Decline,
+    // someone was assigned to the issue
+    Assign,
+    // a pull request closed the issue
+    LinkPr,
+    // the budget is approved for payout
+    Conclude,
+    Pay,
+    Withdraw,
+    Expire,
+    Reopen,
+    // change an approved budget, the state stays
+    Adjust,
+    // take an approved budget back
+    Clawback,
+}
+
+impl ReviewState {
+    pub const ALL: [ReviewState; 9] = [
+        ReviewState::Queued,
+        ReviewState::Approved,
+        ReviewState::InProgress,
+        ReviewState::PrLinked,
+        ReviewState::Concluded,
+        ReviewState::Paid,
+        ReviewState::Declined,
+        ReviewState::Withdrawn,
+        ReviewState::Expired,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewState::Queued => "queued",
+            ReviewState::Approved => "approved",
+            ReviewState::InProgress => "in_progress",
+            ReviewState::PrLinked => "pr_linked",
+            ReviewState::Concluded => "concluded",
+            ReviewState::Paid => "paid",
+            ReviewState::Declined => "declined",
+            ReviewState::Withdrawn => "withdrawn",
+            ReviewState::Expired => "expired",
+        }
+    }
+
+    // The older review_status column, kept for the filters and the counts.
+    pub fn review_status(&self) -> &'static str {
+        match self {
+            ReviewState::Queued => "queue",
+            ReviewState::Declined | ReviewState::Withdrawn | ReviewState::Expired => "decline",
+            _ => "approve",
+        }
+    }
+
+    // issue_budget_approved, the budget can be paid out
+    pub fn budget_approved(&self) -> bool {
+        matches!(self, ReviewState::Concluded | ReviewState::Paid)
+    }
+
+    // The state `action` leads to, or an error when it isn't allowed from here.
+    pub fn next(self, action: ReviewAction) -> GosimResult<ReviewState> {
+        use ReviewAction as A;
+        use ReviewState as S;
+
+        let next = match (action, self) {
+            (A::Approve, S::Queued | S::Approved) => Some(S::Approved),
+            // a new budget doesn't set back work that has started
+            (A::Approve, S::InProgress | S::PrLinked) => Some(self),
+            (A::Decline, S::Queued | S::Approved | S::InProgress | S::PrLinked | S::Declined) => {
+                Some(S::Declined)
+            }
+            (A::Assign, S::Approved) => Some(S::InProgress),
+            (A::LinkPr, S::Approved | S::InProgress) => Some(S::PrLinked),
+            (A::Conclude, S::Approved | S::InProgress | S::PrLinked) => Some(S::Concluded),
+            (A::Pay, S::Concluded) => Some(S::Paid),
+            (A::Withdraw, S::Queued | S::Approved | S::InProgress | S::PrLinked) => {
+                Some(S::Withdrawn)
+            }
+            (A::Expire, S::Queued | S::Approved | S::InProgress) => Some(S::Expired),
+            (A::Reopen, S::Declined | S::Withdrawn | S::Expired) => Some(S::Queued),
+            (A::Adjust, S::Approved | S::InProgress | S::PrLinked | S::Concluded) => Some(self),
+            (A::Clawback, S::Approved | S::InProgress | S::PrLinked) => Some(S::Expired),
+            _ => None,
+        };
+        next.ok_or_else(|| {
+            Error::Transition(format!(
+                "cannot {} an issue that is {}",
+                action.as_str(),
+                self.as_str()
+            ))
+        })
+    }
+
+    // Declined, withdrawn or expired: the review is over until the issue is reopened.
+    pub fn is_closed(&self) -> bool {
+        matches!(
+            self,
+            ReviewState::Declined | ReviewState::Withdrawn | ReviewState::Expired
+        )
+    }
+
+    // The states `action` can be taken from, for updating many issues in SQL.
+    pub fn sources(action: ReviewAction) -> Vec<ReviewState> {
+        ReviewState::ALL
+            .into_iter()
+            .filter(|state| state.next(action).is_ok())
+            .collect()
+    }
+}
+
+impl ReviewAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReviewAction::Approve => "approve",
+            ReviewAction::Decline => "decline",
+            ReviewAction::Assign => "assign",
+            ReviewAction::LinkPr => "link_pr",
+            ReviewAction::Conclude => "conclude",
+            ReviewAction::Pay => "pay",
+            ReviewAction::Withdraw => "withdraw",
+            ReviewAction::Expire => "expire",
+            ReviewAction::Reopen => "reopen",
+            ReviewAction::Adjust => "adjust",
+            ReviewAction::Clawback => "clawback",
+        }
+    }
+
+    // the admin_actions name, which predates the state machine for approvals
+    pub fn audit_name(&self) -> &'static str {
+        match self {
+            ReviewAction::Approve => "approve_budget",
+            ReviewAction::Adjust => "adjust_budget",
+            action => action.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for ReviewState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ReviewState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::Validation(format!("unknown review state '{}'", s)))
+    }
+}
+
+impl FromStr for ReviewAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> GosimResult<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| Error::Validation(format!("unknown review action '{}'", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ReviewAction as A;
+    use ReviewState as S;
+
+    // Every (state, action) pair that is allowed, and where it leads. Anything
+    // missing here has to be refused.
+    const TRANSITIONS: &[(ReviewState, ReviewAction, ReviewState)] = &[
+        (S::Queued, A::Approve, S::Approved),
+        (S::Queued, A::Decline, S::Declined),
+        (S::Queued, A::Withdraw, S::Withdrawn),
+        (S::Queued, A::Expire, S::Expired),
+        (S::Approved, A::Approve, S::Approved),
+        (S::Approved, A::Decline, S::Declined),
+        (S::Approved, A::Assign, S::InProgress),
+        (S::Approved, A::LinkPr, S::PrLinked),
+        (S::Approved, A::Conclude, S::Concluded),
+        (S::Approved, A::Withdraw, S::Withdrawn),
+        (S::Approved, A::Expire, S::Expired),
+        (S::Approved, A::Adjust, S::Approved),
+        (S::Approved, A::Clawback, S::Expired),
+        (S::InProgress, A::Approve, S::InProgress),
+        (S::InProgress, A::Decline, S::Declined),
+        (S::InProgress, A::LinkPr, S::PrLinked),
+        (S::InProgress, A::Conclude, S::Concluded),
+        (S::InProgress, A::Withdraw, S::Withdrawn),
+        (S::InProgress, A::Expire, S::Expired),
+        (S::InProgress, A::Adjust, S::InProgress),
+        (S::InProgress, A::Clawback, S::Expired),
+        (S::PrLinked, A::Approve, S::PrLinked),
+        (S::PrLinked, A::Decline, S::Declined),
+        (S::PrLinked, A::Conclude, S::Concluded),
+        (S::PrLinked, A::Withdraw, S::Withdrawn),
+        (S::PrLinked, A::Adjust, S::PrLinked),
+        (S::PrLinked, A::Clawback, S::Expired),
+        (S::Concluded, A::Pay, S::Paid),
+        (S::Concluded, A::Adjust, S::Concluded),
+        (S::Declined, A::Decline, S::Declined),
+        (S::Declined, A::Reopen, S::Queued),
+        (S::Withdrawn, A::Reopen, S::Queued),
+        (S::Expired, A::Reopen, S::Queued),
+    ];
+
+    const ACTIONS: [ReviewAction; 11] = [
+        A::Approve,
+        A::Decline,
+        A::Assign,
+        A::LinkPr,
+        A::Conclude,
+        A::Pay,
+        A::Withdraw,
+        A::Expire,
+        A::Reopen,
+        A::Adjust,
+        A::Clawback,
+    ];
+
+    #[test]
+    fn next_follows_the_transition_table() {
+        for state in ReviewState::ALL {
+            for action in ACTIONS {
+                let expected = TRANSITIONS
+                    .iter()
+                    .find(|(from, by, _)| *from == state && *by == action)
+                    .map(|(_, _, to)| *to);
+                match (state.next(action), expected) {
+                    (Ok(to), Some(expected)) => {
+                        assert_eq!(to, expected, "{} from {}", action.as_str(), state)
+                    }
+                    (Err(Error::Transition(_)), None) => {}
+                    (result, expected) => panic!(
+                        "{} from {}: got {:?}, expected {:?}",
+                        action.as_str(),
+                        state,
+                        result,
+                        expected
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn declined_needs_reopen_before_approve() {
+        assert!(S::Declined.next(A::Approve).is_err());
+        let reopened = S::Declined.next(A::Reopen).unwrap();
+        assert_eq!(reopened.next(A::Approve).unwrap(), S::Approved);
+        assert!(!ReviewState::sources(A::Approve).contains(&S::Declined));
+    }
+
+    #[test]
+    fn states_round_trip_through_their_names() {
+        for state in ReviewState::ALL {
+            assert_eq!(state.as_str().parse::<ReviewState>().unwrap(), state);
+        }
+        for action in ACTIONS {
+            assert_eq!(action.as_str().parse::<ReviewAction>().unwrap(), action);
+        }
+        assert!("approve".parse::<ReviewState>().is_err());
+    }
+}
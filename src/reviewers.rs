@@ -0,0 +1,102 @@
+use crate::db_audit::normalize_login;
+use crate::error::{Error, GosimResult};
+use std::collections::HashMap;
+
+// The reviewers who may take admin actions through the backend routes, from
+// GOSIM_REVIEWERS: comma separated `login:token` pairs. A request names its
+// reviewer with an `Authorization: Bearer <token>` header, and the action is
+// recorded under the login the token belongs to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reviewers {
+    // token -> normalized login
+    logins: HashMap<String, String>,
+}
+
+impl Reviewers {
+    pub fn parse(config: &str) -> GosimResult<Reviewers> {
+        let mut logins = HashMap::new();
+        for pair in config.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (login, token) = pair
+                .split_once(':')
+                .map(|(login, token)| (normalize_login(login), token.trim()))
+                .filter(|(login, token)| !login.is_empty() && !token.is_empty())
+                .ok_or_else(|| {
+                    Error::Validation(String::from("reviewers must be given as login:token pairs"))
+                })?;
+            if logins.insert(token.to_string(), login).is_some() {
+                return Err(Error::Validation(String::from(
+                    "two reviewers share a token",
+                )));
+            }
+        }
+        Ok(Reviewers { logins })
+    }
+
+    // Without GOSIM_REVIEWERS, or with one that doesn't parse, nobody is let in.
+    pub fn from_env() -> Reviewers {
+        let config = std::env::var("GOSIM_REVIEWERS").unwrap_or_default();
+        Reviewers::parse(&config).unwrap_or_else(|e| {
+            log::error!("GOSIM_REVIEWERS is ignored: {}", e);
+            Reviewers::default()
+        })
+    }
+
+    // The login of the reviewer whose token the request carries.
+    pub fn authenticate(&self, headers: &[(String, String)]) -> GosimResult<String> {
+        let token = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .and_then(|(_, value)| value.trim().strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or_else(|| Error::Unauthorized(String::from("a reviewer token is required")))?;
+        self.logins
+            .get(token)
+            .cloned()
+            .ok_or_else(|| Error::Unauthorized(String::from("unknown reviewer token")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(token: &str) -> Vec<(String, String)> {
+        vec![(String::from("Authorization"), format!("Bearer {}", token))]
+    }
+
+    #[test]
+    fn tokens_map_to_normalized_logins() {
+        let reviewers = Reviewers::parse(" Rita :t1, bob:t2,").unwrap();
+        assert_eq!(reviewers.authenticate(&bearer("t1")).unwrap(), "rita");
+        assert_eq!(reviewers.authenticate(&bearer("t2")).unwrap(), "bob");
+
+        let lowercase = vec![(String::from("authorization"), String::from("Bearer t2"))];
+        assert_eq!(reviewers.authenticate(&lowercase).unwrap(), "bob");
+    }
+
+    #[test]
+    fn missing_or_unknown_tokens_are_refused() {
+        let reviewers = Reviewers::parse("rita:t1").unwrap();
+        for headers in [
+            Vec::new(),
+            bearer("t2"),
+            bearer(""),
+            vec![(String::from("Authorization"), String::from("t1"))],
+        ] {
+            assert!(matches!(
+                reviewers.authenticate(&headers),
+                Err(Error::Unauthorized(_))
+            ));
+        }
+        assert!(Reviewers::default().authenticate(&bearer("t1")).is_err());
+    }
+
+    #[test]
+    fn bad_config_is_rejected() {
+        assert!(Reviewers::parse("rita").is_err());
+        assert!(Reviewers::parse("rita:").is_err());
+        assert!(Reviewers::parse(":t1").is_err());
+        assert!(Reviewers::parse("rita:t1,bob:t1").is_err());
+        assert_eq!(Reviewers::parse("").unwrap(), Reviewers::default());
+    }
+}
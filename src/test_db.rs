@@ -0,0 +1,147 @@
+use crate::currency::Currency;
+use crate::db_audit::AuditInfo;
+use crate::db_ledger::{
+    apply_issue_op, fund_campaign, issue_postings, lock_issue_campaign, post_transaction,
+    IssueBalance, IssueLedgerOp, LedgerTxn,
+};
+use crate::db_manipulate::vote_issue_budget_in_db;
+use crate::db_migrate::run_migrations;
+use crate::error::GosimResult;
+use mysql_async::prelude::*;
+use mysql_async::*;
+
+// A migrated database for the tests that need MySQL, from TEST_DATABASE_URL.
+// Those tests are #[ignore]d, `cargo test -- --ignored` runs them.
+pub async fn pool() -> Pool {
+    let url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set for the MySQL tests");
+    let pool = Pool::new(Opts::from_url(&url).expect("invalid TEST_DATABASE_URL"));
+    run_migrations(&pool)
+        .await
+        .expect("migrations failed on the test database");
+
+    pool
+}
+
+// A new campaign, funded with `amount` USD.
+pub async fn funded_campaign(pool: &Pool, amount: i64) -> String {
+    let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+    fund_campaign(pool, &campaign_id, amount, Currency::Usd, "test", None)
+        .await
+        .unwrap();
+    campaign_id
+}
+
+// An issue in `campaign_id`, in a project of its own.
+pub async fn insert_issue(pool: &Pool, campaign_id: &str, issue_id: &str) {
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.exec_drop(
+        r"INSERT INTO issues_master
+            (issue_id, project_id, issue_title, issue_creator, issue_description, campaign_id)
+          VALUES (:issue_id, :project_id, 'test', 'test', 'test', :campaign_id)",
+        params! {
+            "issue_id" => issue_id,
+            "project_id" => format!("https://github.com/{}/test", campaign_id),
+            "campaign_id" => campaign_id,
+        },
+    )
+    .await
+    .unwrap();
+}
+
+// Issue `n` of the campaign, assigned to `assignees` in that order and approved
+// with `budget` by one vote.
+pub async fn approved_issue(
+    pool: &Pool,
+    campaign_id: &str,
+    n: u32,
+    budget: i64,
+    assignees: &[&str],
+) -> String {
+    let issue_id = format!("{}-{}", campaign_id, n);
+    insert_issue(pool, campaign_id, &issue_id).await;
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.exec_batch(
+        r"INSERT INTO issue_assignees (issue_id, login, source, assigned_at)
+          VALUES (:issue_id, :login, 'assigned', NOW() - INTERVAL :rank DAY)",
+        assignees.iter().enumerate().map(|(i, login)| {
+            params! {
+                "issue_id" => &issue_id,
+                "login" => login,
+                "rank" => assignees.len() - i,
+            }
+        }),
+    )
+    .await
+    .unwrap();
+    let audit = AuditInfo::new(Some(String::from("test")), None);
+    vote_issue_budget_in_db(pool, &issue_id, budget, &audit)
+        .await
+        .unwrap();
+
+    issue_id
+}
+
+// A transaction that has already taken its snapshot.
+pub async fn started_transaction(pool: &Pool) -> Transaction<'static> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await.unwrap();
+    let _: Option<i64> = tx
+        .query_first("SELECT COUNT(*) FROM ledger_entries")
+        .await
+        .unwrap();
+    tx
+}
+
+// Allocates `amount` to the issue in `tx` and commits it.
+pub async fn allocate(mut tx: Transaction<'_>, issue_id: &str, amount: i64) -> GosimResult<()> {
+    apply_issue_op(
+        &mut tx,
+        issue_id,
+        IssueLedgerOp::Allocate(amount),
+        "test",
+        None,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Allocates `amount` in `currency` to the issue straight in the ledger, without the
+// rate and cap checks of apply_issue_op, like money booked before a rate went missing.
+pub async fn unchecked_allocation(pool: &Pool, issue_id: &str, amount: i64, currency: Currency) {
+    let mut tx = started_transaction(pool).await;
+    let issue = lock_issue_campaign(&mut tx, issue_id).await.unwrap();
+    for (kind, postings) in issue_postings(
+        IssueLedgerOp::Allocate(amount),
+        IssueBalance::default(),
+        None,
+    ) {
+        let txn = LedgerTxn {
+            txn_id: 0,
+            campaign_id: issue.campaign_id.clone(),
+            kind,
+            project_id: Some(issue.project_id.clone()),
+            issue_id: Some(issue_id.to_string()),
+            reverses_txn_id: None,
+            actor: String::from("test"),
+            memo: None,
+            created_at: String::new(),
+            currency,
+            postings,
+        };
+        post_transaction(&mut tx, &txn).await.unwrap();
+    }
+    tx.commit().await.unwrap();
+}
+
+// The issue's review_state and issue_budget.
+pub async fn issue_review(pool: &Pool, issue_id: &str) -> (String, Option<i32>) {
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.exec_first(
+        "SELECT review_state, issue_budget FROM issues_master WHERE issue_id = :issue_id",
+        params! { "issue_id" => issue_id },
+    )
+    .await
+    .unwrap()
+    .expect("issue is in issues_master")
+}
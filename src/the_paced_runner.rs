@@ -1,9 +1,12 @@
-use crate::{db_join::*, db_manipulate::*, db_populate::*, issue_tracker::*, vector_search::*};
+use crate::the_runner::merge_and_purge_ops;
+use crate::{db_manipulate::*, db_populate::*, db_runs::*, issue_tracker::*, vector_search::*};
 use crate::{ISSUE_LABEL, NEXT_HOUR, PR_LABEL, START_DATE, THIS_HOUR};
 
 use anyhow::Ok;
 use mysql_async::Pool;
 
+// the issue branches are kept apart so the commented alternatives below can be swapped in
+#[allow(clippy::too_many_arguments, clippy::if_same_then_else)]
 pub fn inner_query_1_hour(
     start_date: &str,
     start_hour: &str,
@@ -40,42 +43,46 @@ pub fn inner_query_1_hour(
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
-    let _ = popuate_dbs_save_issues_open(pool).await?;
-
-    let _ = open_master(pool).await?;
-
-    let _ = popuate_dbs_save_issues_assigned(pool).await?;
-
-    let _ = assigned_master(pool).await?;
-
-    let _ = popuate_dbs_save_issues_closed(pool).await?;
-
-    let _ = closed_master(pool).await?;
-
-    let _ = popuate_dbs_fill_projects(pool).await?;
-
-    let _ = master_project(&pool).await?;
-
-    let _ = popuate_dbs_save_pull_requests(pool).await?;
-
-    let _ = project_master_back_sync(&pool).await?;
-
-    let _ = populate_vector_db(pool).await?;
-
-    let _ = popuate_dbs_save_issues_comment(pool).await?;
+    let run = match PipelineRun::start_locked(pool, "paced").await? {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let result = run_hourly_steps(pool, &run).await;
+    run.finish(&result).await;
 
-    let _ = sum_budget_to_project(&pool).await?;
+    result
+}
 
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
-    let _ = delete_issues_open_assigned_closed(&pool).await?;
+// The staging tables are filled first and then merged and purged in one
+// transaction, as the_runner does, so a failed step leaves no half-merged run.
+async fn run_hourly_steps(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    run.github_step("save_issues_open", popuate_dbs_save_issues_open(pool))
+        .await?;
+    run.github_step(
+        "save_issues_assigned",
+        popuate_dbs_save_issues_assigned(pool),
+    )
+    .await?;
+    run.github_step("save_issues_closed", popuate_dbs_save_issues_closed(pool))
+        .await?;
+    run.github_step("save_pull_requests", popuate_dbs_save_pull_requests(pool))
+        .await?;
+    run.github_step("save_issues_comment", popuate_dbs_save_issues_comment(pool))
+        .await?;
+
+    merge_and_purge_ops(pool, run).await?;
+
+    run.step("populate_vector_db", populate_vector_db(pool))
+        .await?;
 
     // let _ = note_issues(pool).await?;
 
     Ok(())
 }
-pub async fn popuate_dbs_save_issues_open(pool: &Pool) -> anyhow::Result<()> {
+
+pub async fn popuate_dbs_save_issues_open(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_open = inner_query_1_hour(
-        &START_DATE,
+        START_DATE,
         &THIS_HOUR,
         &NEXT_HOUR,
         ISSUE_LABEL,
@@ -89,15 +96,25 @@ pub async fn popuate_dbs_save_issues_open(pool: &Pool) -> anyhow::Result<()> {
     let open_issue_obj: Vec<IssueOpen> = search_issues_open(&query_open).await?;
     let len = open_issue_obj.len();
     log::info!("Open Issues recorded: {:?}", len);
-    for issue in open_issue_obj {
-        let _ = add_issues_open(pool, &issue).await;
 
-        let _ = summarize_issue_add_in_db(pool, &issue).await;
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
+    // as in the_runner::save_issues_open, a summary fills in the row just written
+    let mut summaries = StepStats::default();
+    for issue in open_issue_obj {
+        stats.record(|| add_issues_open(pool, &issue)).await?;
+        summaries
+            .record(|| summarize_issue_add_in_db(pool, &issue))
+            .await?;
     }
-    Ok(())
+    log::info!("Open issues summarized: {}", summaries.rows_written);
+    Ok(stats)
 }
 
-pub async fn force_issue_to_summary_update_db(pool: &Pool) -> anyhow::Result<()> {
+pub async fn force_issue_to_summary_update_db(pool: &Pool) -> anyhow::Result<StepStats> {
+    let mut stats = StepStats::default();
     for page in 2..10 {
         let open_issue_obj: Vec<IssueOpen> = get_issues_open_from_master(pool, page).await?;
         let len = open_issue_obj.len();
@@ -105,30 +122,40 @@ pub async fn force_issue_to_summary_update_db(pool: &Pool) -> anyhow::Result<()>
             "Simulate Open Issues retrieved from issues_master: {:?}",
             len
         );
+        stats.rows_fetched += len as i32;
         for issue in open_issue_obj {
-            let _ = summarize_issue_add_in_db(pool, &issue).await;
+            stats
+                .record(|| summarize_issue_add_in_db(pool, &issue))
+                .await?;
         }
     }
 
-    Ok(())
+    Ok(stats)
 }
 
-pub async fn popuate_dbs_save_issues_comment(pool: &Pool) -> anyhow::Result<()> {
+pub async fn popuate_dbs_save_issues_comment(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_comment =
         "label:hacktoberfest-accepted is:issue updated:>2024-01-01 -label:spam -label:invalid";
     log::info!("query_open: {:?}", query_comment);
 
-    let issue_comment_obj: Vec<IssueComment> = search_issues_comment(&query_comment).await?;
+    let issue_comment_obj: Vec<IssueComment> = search_issues_comment(query_comment).await?;
     let len = issue_comment_obj.len();
     log::info!("Issues comment recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in issue_comment_obj {
-        let _ = add_issues_comment(pool, issue).await;
+        stats
+            .record(|| add_issues_comment(pool, issue.clone()))
+            .await?;
     }
-    Ok(())
+    Ok(stats)
 }
-pub async fn popuate_dbs_save_issues_assigned(pool: &Pool) -> anyhow::Result<()> {
+pub async fn popuate_dbs_save_issues_assigned(pool: &Pool) -> anyhow::Result<StepStats> {
     let _query_assigned = inner_query_1_hour(
-        &START_DATE,
+        START_DATE,
         &THIS_HOUR,
         &NEXT_HOUR,
         ISSUE_LABEL,
@@ -142,14 +169,21 @@ pub async fn popuate_dbs_save_issues_assigned(pool: &Pool) -> anyhow::Result<()>
     let issues_assigned_obj: Vec<IssueAssigned> = search_issues_assigned(&_query_assigned).await?;
     let len = issues_assigned_obj.len();
     log::info!("Assigned issues recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in issues_assigned_obj {
-        let _ = add_issues_assigned(pool, issue).await;
+        stats
+            .record(|| add_issues_assigned(pool, issue.clone()))
+            .await?;
     }
-    Ok(())
+    Ok(stats)
 }
-pub async fn popuate_dbs_save_issues_closed(pool: &Pool) -> anyhow::Result<()> {
+pub async fn popuate_dbs_save_issues_closed(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_closed = inner_query_1_hour(
-        &START_DATE,
+        START_DATE,
         &THIS_HOUR,
         &NEXT_HOUR,
         ISSUE_LABEL,
@@ -162,16 +196,23 @@ pub async fn popuate_dbs_save_issues_closed(pool: &Pool) -> anyhow::Result<()> {
     let close_issue_obj = search_issues_closed(&query_closed).await?;
     let len = close_issue_obj.len();
     log::info!("Closed issues recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in close_issue_obj {
-        let _ = add_issues_closed(pool, issue).await;
+        stats
+            .record(|| add_issues_closed(pool, issue.clone()))
+            .await?;
     }
 
-    Ok(())
+    Ok(stats)
 }
 
-pub async fn popuate_dbs_save_pull_requests(pool: &Pool) -> anyhow::Result<()> {
+pub async fn popuate_dbs_save_pull_requests(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_pull_request = inner_query_1_hour(
-        &START_DATE,
+        START_DATE,
         &THIS_HOUR,
         &NEXT_HOUR,
         ISSUE_LABEL,
@@ -184,90 +225,62 @@ pub async fn popuate_dbs_save_pull_requests(pool: &Pool) -> anyhow::Result<()> {
     let pull_request_obj: Vec<OuterPull> = search_pull_requests(&query_pull_request).await?;
     let len = pull_request_obj.len();
     log::info!("Pull requests recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for pull in pull_request_obj {
-        let _ = add_pull_request(&pool, pull).await;
+        stats
+            .record(|| add_pull_request(pool, pull.clone()))
+            .await?;
     }
 
-    Ok(())
+    Ok(stats)
 }
 
-pub async fn popuate_dbs_fill_projects(pool: &Pool) -> anyhow::Result<()> {
+pub async fn popuate_dbs_fill_projects(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_repos: String = get_projects_as_repo_list(pool, 1).await?;
     let repo_data_vec: Vec<RepoData> = search_repos_in_batch(&query_repos).await?;
 
+    let mut stats = StepStats {
+        rows_fetched: repo_data_vec.len() as i32,
+        ..Default::default()
+    };
     for repo_data in repo_data_vec {
-        let _ = fill_project_w_repo_data(&pool, repo_data.clone()).await?;
-        let _ = summarize_project_add_in_db(&pool, repo_data).await?;
+        stats
+            .record(|| async {
+                fill_project_w_repo_data(pool, repo_data.clone()).await?;
+                summarize_project_add_in_db(pool, repo_data.clone()).await
+            })
+            .await?;
     }
-    Ok(())
+    Ok(stats)
 }
 
-pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
-    for item in get_issues_repos_from_db().await.expect("msg") {
+pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<StepStats> {
+    let items = get_issues_repos_from_db(pool).await?;
+
+    let mut stats = StepStats {
+        rows_fetched: items.len() as i32,
+        ..Default::default()
+    };
+    for item in items {
         log::info!("uploading to vector_db: {:?}", item.0);
-        let _ = upload_to_collection(&item.0, item.1.clone()).await;
-        let _ = mark_id_indexed(&pool, &item.0).await;
+        stats
+            .record(|| async {
+                upload_to_collection(&item.0, item.1.clone()).await?;
+                mark_id_indexed(pool, &item.0).await
+            })
+            .await?;
     }
-    let _ = check_vector_db("gosim_search").await;
+    check_vector_db("gosim_search").await;
 
-    Ok(())
+    Ok(stats)
 }
 
+// Only logs what is owed, posting is left to the hourly runner.
 pub async fn note_issues(pool: &Pool) -> anyhow::Result<()> {
-    let _ = note_budget_allocated(pool).await?;
-    let _ = note_issue_declined(pool).await?;
-    let _ = note_distribute_fund(pool).await?;
-    let _ = note_one_months_no_pr(pool).await?;
-    Ok(())
-}
-
-pub async fn note_budget_allocated(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids = get_issue_ids_with_budget(pool).await?;
-    log::info!(
-        "Issue ids with budget allocated, count: {:?}",
-        issue_ids.len()
-    );
-    for issue_id in issue_ids {
-        let comment = format!("{}/n Congratulations! GOSIM grant approved. Your proposal is approved to get $100 fund to fix the issue.", issue_id);
-
-        // let _ = mock_comment_on_issue(1, &comment).await?;
-    }
-    Ok(())
-}
-
-pub async fn note_issue_declined(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids = get_issue_ids_declined(pool).await?;
-    log::info!(
-        "Issue ids with budget declined, count: {:?}",
-        issue_ids.len()
-    );
-    for issue_id in issue_ids {
-        let comment = format!("{}/n  I’m sorry your proposal wasn't approved", issue_id);
-
-        // let _ = mock_comment_on_issue(2, &comment).await?;
-    }
-    Ok(())
-}
-
-pub async fn note_distribute_fund(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids: Vec<(Option<String>, String, i32)> = get_issue_ids_distribute_fund(pool).await?;
-    log::info!("Issue_ids to split fund, count: {:?}", issue_ids.len());
-    for (issue_assignee, _issue_id, issue_budget) in issue_ids {
-        let comment = format!("@{:?}, Well done!  According to the PR commit history. @{:?} should receive ${}. Please fill in this form to claim your fund. ", issue_assignee, issue_assignee, issue_budget);
-
-        // let _ = mock_comment_on_issue(3, &comment).await?;
-    }
-    Ok(())
-}
-
-pub async fn note_one_months_no_pr(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids = get_issue_ids_one_month_no_activity(pool).await?;
-    log::info!("Issue_ids no activity, count: {:?}", issue_ids.len());
-
-    for issue_id in issue_ids {
-        let comment = format!("{}\n @{} please link your PR to the issue it fixed in three days. Or this issue will be deemed not completed, then we can’t provide the fund.", issue_id, "issue_assignee" );
-
-        // let _ = mock_comment_on_issue(4, &comment).await?;
-    }
+    crate::the_runner::note_issues(pool, true).await?;
     Ok(())
 }
@@ -1,9 +1,20 @@
-use crate::{db_join::*, db_manipulate::*, db_populate::*, issue_tracker::*};
+use crate::currency::Currency;
+use crate::db_import::*;
+use crate::db_snapshot::take_snapshot;
+use crate::error::ErrorAction;
+use crate::{
+    db_join::*, db_manipulate::*, db_populate::*, db_risk::score_risks, db_runs::*,
+    issue_tracker::*,
+};
 use crate::{ISSUE_LABEL, NEXT_HOUR, PR_LABEL, START_DATE, THIS_HOUR};
 
 use anyhow::Ok;
-use mysql_async::Pool;
+use chrono::NaiveDate;
+use mysql_async::{Pool, Transaction, TxOpts};
+use std::collections::HashSet;
 
+// the issue branches are kept apart so the commented alternatives below can be swapped in
+#[allow(clippy::too_many_arguments, clippy::if_same_then_else)]
 pub fn inner_query_1_hour(
     start_date: &str,
     start_hour: &str,
@@ -38,7 +49,9 @@ pub fn inner_query_1_hour(
 
     query
 }
-pub fn inner_query_inject_Variable(
+// the issue branches are kept apart so the commented alternatives below can be swapped in
+#[allow(clippy::too_many_arguments, clippy::if_same_then_else)]
+pub fn inner_query_inject_variable(
     start_date: &str,
     start_hour: &str,
     end_hour: &str,
@@ -50,7 +63,6 @@ pub fn inner_query_inject_Variable(
 ) -> String {
     let date_range =
         std::env::var("DATE_RANGE").unwrap_or_else(|_| format!("{}..{}", start_hour, end_hour));
-    let signature_switch = std::env::var("SIGNATURE_SWITCH").unwrap_or_else(|_| "signature_switch".to_string());
 
     let query = if is_issue && is_start {
         format!("label:{issue_label} is:issue is:closed created:>{start_date} closed:{date_range} -label:spam -label:invalid")
@@ -76,17 +88,223 @@ pub fn inner_query_inject_Variable(
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
-    let _ = popuate_dbs(pool).await?;
-    let _ = join_ops(pool).await?;
-    let _ = cleanup_ops(pool).await?;
+    let run = match PipelineRun::start_locked(pool, "hourly").await? {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let result = run_hourly_steps(pool, &run).await;
+    run.finish(&result).await;
+
+    result
+}
+
+async fn run_hourly_steps(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    popuate_dbs(pool, run).await?;
+    merge_and_purge_ops(pool, run).await?;
     // let _ = note_issues(pool).await?;
     Ok(())
 }
-pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
+
+pub async fn popuate_dbs(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    popuate_dbs_window(pool, run, &THIS_HOUR, &NEXT_HOUR).await?;
+    run.github_step("save_issues_comment", save_issues_comment(pool))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn popuate_dbs_window(
+    pool: &Pool,
+    run: &PipelineRun<'_>,
+    start_hour: &str,
+    end_hour: &str,
+) -> anyhow::Result<()> {
+    run.github_step(
+        "save_issues_open",
+        save_issues_open(pool, start_hour, end_hour),
+    )
+    .await?;
+    run.github_step(
+        "save_issues_assigned",
+        save_issues_assigned(pool, start_hour, end_hour),
+    )
+    .await?;
+    run.github_step(
+        "save_issues_closed",
+        save_issues_closed(pool, start_hour, end_hour),
+    )
+    .await?;
+    run.github_step(
+        "save_pull_requests",
+        save_pull_requests(pool, start_hour, end_hour),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Re-runs the collection one day at a time over [from, to], then joins and cleans up once.
+pub async fn run_backfill(pool: &Pool, from: NaiveDate, to: NaiveDate) -> anyhow::Result<()> {
+    let run = match PipelineRun::start_locked(pool, "backfill").await? {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let result = run_backfill_steps(pool, &run, from, to).await;
+    run.finish(&result).await;
+
+    result
+}
+
+async fn run_backfill_steps(
+    pool: &Pool,
+    run: &PipelineRun<'_>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> anyhow::Result<()> {
+    let mut day = from;
+    while day <= to {
+        let next_day = day
+            .succ_opt()
+            .ok_or_else(|| anyhow::anyhow!("date out of range: {}", day))?;
+        let start_hour = format!("{}T00:00:00Z", day.format("%Y-%m-%d"));
+        let end_hour = format!("{}T00:00:00Z", next_day.format("%Y-%m-%d"));
+        log::info!("Backfilling {}..{}", start_hour, end_hour);
+
+        popuate_dbs_window(pool, run, &start_hour, &end_hour).await?;
+        day = next_day;
+    }
+    run.github_step("save_issues_comment", save_issues_comment(pool))
+        .await?;
+
+    merge_and_purge_ops(pool, run).await?;
+    Ok(())
+}
+
+// Records the day's issue and project state. Safe to call more than once a day,
+// the last call replaces the day's rows.
+pub async fn run_snapshot(pool: &Pool, snapshot_date: NaiveDate) -> anyhow::Result<()> {
+    let run = match PipelineRun::start_locked(pool, "snapshot").await? {
+        Some(run) => run,
+        None => return Ok(()),
+    };
+    let result = run
+        .step("take_snapshot", async {
+            take_snapshot(pool, snapshot_date)
+                .await
+                .map(|(issue_rows, project_rows)| StepStats::written(issue_rows + project_rows))
+        })
+        .await
+        .map(|_| ());
+    run.finish(&result).await;
+
+    result
+}
+
+// Stages hand-picked issues in issues_open and merges them the way a scheduled run
+// does. Returns one outcome per input row, failed rows say why.
+pub async fn run_import(pool: &Pool, rows: Vec<ImportRow>) -> anyhow::Result<Vec<ImportOutcome>> {
+    let run = match PipelineRun::start_locked(pool, "import").await? {
+        Some(run) => run,
+        None => {
+            return Err(anyhow::anyhow!(
+                "another pipeline run is in progress, try again later"
+            ))
+        }
+    };
+    let mut outcomes = Vec::new();
+    let result = run_import_steps(pool, &run, &rows, &mut outcomes).await;
+    run.finish(&result).await;
+
+    result.map(|_| outcomes)
+}
+
+async fn run_import_steps(
+    pool: &Pool,
+    run: &PipelineRun<'_>,
+    rows: &[ImportRow],
+    outcomes: &mut Vec<ImportOutcome>,
+) -> anyhow::Result<()> {
+    run.github_step(
+        "stage_imported_issues",
+        stage_imported_issues(pool, rows, outcomes),
+    )
+    .await?;
+    join_ops(pool, run).await?;
+    run.step(
+        "record_imports",
+        record_imports(pool, run.run_id, rows, outcomes),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn stage_imported_issues(
+    pool: &Pool,
+    rows: &[ImportRow],
+    outcomes: &mut Vec<ImportOutcome>,
+) -> anyhow::Result<StepStats> {
+    let mut stats = StepStats {
+        rows_fetched: rows.len() as i32,
+        ..Default::default()
+    };
+    let mut seen = HashSet::new();
+    for (i, row) in rows.iter().enumerate() {
+        let outcome = match stage_import_row(pool, row, &mut seen).await {
+            std::result::Result::Ok(issue_id) => {
+                stats.rows_written += 1;
+                ImportOutcome {
+                    row: i + 1,
+                    issue_url: row.issue_url.clone(),
+                    issue_id: Some(issue_id),
+                    imported: false,
+                    error: None,
+                }
+            }
+            // rate limits and database errors end the import
+            Err(e) if e.action() == ErrorAction::Abort => return Err(e.into()),
+            Err(e) => {
+                log::warn!("Import of {} failed: {}", row.issue_url, e);
+                ImportOutcome::failed(i + 1, &row.issue_url, e)
+            }
+        };
+        outcomes.push(outcome);
+    }
+
+    Ok(stats)
+}
+
+// The staged rows that made it into issues_master get their issue_imports row.
+async fn record_imports(
+    pool: &Pool,
+    run_id: u64,
+    rows: &[ImportRow],
+    outcomes: &mut [ImportOutcome],
+) -> anyhow::Result<StepStats> {
+    let mut stats = StepStats::default();
+    for outcome in outcomes.iter_mut().filter(|o| o.error.is_none()) {
+        let issue_id = outcome.issue_id.clone().unwrap_or_default();
+        if !issue_in_master(pool, &issue_id).await? {
+            outcome.error = Some(String::from("not merged into issues_master"));
+            continue;
+        }
+        record_import(pool, &issue_id, &rows[outcome.row - 1], run_id).await?;
+        outcome.imported = true;
+        stats.rows_written += 1;
+    }
+
+    Ok(stats)
+}
+
+pub async fn save_issues_open(
+    pool: &Pool,
+    start_hour: &str,
+    end_hour: &str,
+) -> anyhow::Result<StepStats> {
     let query_open = inner_query_1_hour(
-        &START_DATE,
-        &THIS_HOUR,
-        &NEXT_HOUR,
+        START_DATE,
+        start_hour,
+        end_hour,
         ISSUE_LABEL,
         PR_LABEL,
         true,  // is_issue
@@ -98,27 +316,56 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
     let open_issue_obj: Vec<IssueOpen> = search_issues_open(&query_open).await?;
     let len = open_issue_obj.len();
     log::info!("Open Issues recorded: {:?}", len);
-    for issue in open_issue_obj {
-        let _ = add_issues_open(pool, &issue).await;
 
-        let _ = summarize_issue_add_in_db(pool, &issue).await;
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
+    // a summary fills in the row just written rather than adding one, so it's
+    // counted on its own
+    let mut summaries = StepStats::default();
+    for issue in open_issue_obj {
+        stats.record(|| add_issues_open(pool, &issue)).await?;
+        summaries
+            .record(|| summarize_issue_add_in_db(pool, &issue))
+            .await?;
     }
+    log::info!("Open issues summarized: {}", summaries.rows_written);
 
+    Ok(stats)
+}
+
+pub async fn save_issues_comment(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_comment =
         "label:hacktoberfest-accepted is:issue updated:>2024-01-01 -label:spam -label:invalid";
-    log::info!("query_open: {:?}", query_open);
+    log::info!("query_comment: {:?}", query_comment);
 
-    let issue_comment_obj: Vec<IssueComment> = search_issues_comment(&query_comment).await?;
+    let issue_comment_obj: Vec<IssueComment> = search_issues_comment(query_comment).await?;
     let len = issue_comment_obj.len();
     log::info!("Issues comment recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in issue_comment_obj {
-        let _ = add_issues_comment(pool, issue).await;
+        stats
+            .record(|| add_issues_comment(pool, issue.clone()))
+            .await?;
     }
 
+    Ok(stats)
+}
+
+pub async fn save_issues_assigned(
+    pool: &Pool,
+    start_hour: &str,
+    end_hour: &str,
+) -> anyhow::Result<StepStats> {
     let _query_assigned = inner_query_1_hour(
-        &START_DATE,
-        &THIS_HOUR,
-        &NEXT_HOUR,
+        START_DATE,
+        start_hour,
+        end_hour,
         ISSUE_LABEL,
         PR_LABEL,
         false,
@@ -130,14 +377,29 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
     let issues_assigned_obj: Vec<IssueAssigned> = search_issues_assigned(&_query_assigned).await?;
     let len = issues_assigned_obj.len();
     log::info!("Assigned issues recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in issues_assigned_obj {
-        let _ = add_issues_assigned(pool, issue).await;
+        stats
+            .record(|| add_issues_assigned(pool, issue.clone()))
+            .await?;
     }
 
+    Ok(stats)
+}
+
+pub async fn save_issues_closed(
+    pool: &Pool,
+    start_hour: &str,
+    end_hour: &str,
+) -> anyhow::Result<StepStats> {
     let query_closed = inner_query_1_hour(
-        &START_DATE,
-        &THIS_HOUR,
-        &NEXT_HOUR,
+        START_DATE,
+        start_hour,
+        end_hour,
         ISSUE_LABEL,
         PR_LABEL,
         true,
@@ -148,14 +410,29 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
     let close_issue_obj = search_issues_closed(&query_closed).await?;
     let len = close_issue_obj.len();
     log::info!("Closed issues recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for issue in close_issue_obj {
-        let _ = add_issues_closed(pool, issue).await;
+        stats
+            .record(|| add_issues_closed(pool, issue.clone()))
+            .await?;
     }
 
+    Ok(stats)
+}
+
+pub async fn save_pull_requests(
+    pool: &Pool,
+    start_hour: &str,
+    end_hour: &str,
+) -> anyhow::Result<StepStats> {
     let query_pull_request = inner_query_1_hour(
-        &START_DATE,
-        &THIS_HOUR,
-        &NEXT_HOUR,
+        START_DATE,
+        start_hour,
+        end_hour,
         ISSUE_LABEL,
         PR_LABEL,
         false,
@@ -166,11 +443,18 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
     let pull_request_obj: Vec<OuterPull> = search_pull_requests(&query_pull_request).await?;
     let len = pull_request_obj.len();
     log::info!("Pull requests recorded: {:?}", len);
+
+    let mut stats = StepStats {
+        rows_fetched: len as i32,
+        ..Default::default()
+    };
     for pull in pull_request_obj {
-        let _ = add_pull_request(&pool, pull).await;
+        stats
+            .record(|| add_pull_request(pool, pull.clone()))
+            .await?;
     }
 
-    Ok(())
+    Ok(stats)
 }
 
 // pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
@@ -184,90 +468,406 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
 //     Ok(())
 // }
 
-pub async fn join_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = open_master(&pool).await?;
-    let _ = assigned_master(&pool).await?;
+// Staging tables into issues_master and projects.
+async fn merge_steps(tx: &mut Transaction<'_>, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    run.tx_step("open_master", async {
+        open_master(tx).await.map(StepStats::written)
+    })
+    .await?;
+    run.tx_step("assigned_master", async {
+        assigned_master(tx).await.map(StepStats::written)
+    })
+    .await?;
+
+    run.tx_step("closed_master", async {
+        closed_master(tx).await.map(StepStats::written)
+    })
+    .await?;
+    run.tx_step("advance_review_states", async {
+        advance_review_states(tx).await.map(StepStats::written)
+    })
+    .await?;
+    run.tx_step("score_risks", async {
+        score_risks(tx).await.map(StepStats::written)
+    })
+    .await?;
+
+    run.tx_step("master_project", async {
+        master_project(tx).await.map(StepStats::written)
+    })
+    .await?;
+    run.tx_step("sum_budget_to_project", sum_budget_to_project(tx))
+        .await?;
+
+    Ok(())
+}
+
+// Drops the staging rows that issues_master has taken over.
+async fn purge_steps(tx: &mut Transaction<'_>, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    run.tx_step("remove_pull_by_issued_linked_pr", async {
+        remove_pull_by_issued_linked_pr(tx)
+            .await
+            .map(StepStats::written)
+    })
+    .await?;
+    run.tx_step("delete_issues_open_assigned_closed", async {
+        delete_issues_open_assigned_closed(tx)
+            .await
+            .map(StepStats::written)
+    })
+    .await?;
+
+    Ok(())
+}
 
-    let _ = closed_master(&pool).await?;
+async fn finish_transaction(
+    tx: Transaction<'_>,
+    run: &PipelineRun<'_>,
+    result: anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if let Err(e) = result {
+        log::warn!("Rolling back join/cleanup after: {:?}", e);
+        if let Err(rollback_err) = tx.rollback().await {
+            log::error!("Error rolling back: {:?}", rollback_err);
+        }
+        run.settle(false).await;
+        return Err(e);
+    }
 
-    let _ = master_project(&pool).await?;
-    let _ = sum_budget_to_project(&pool).await?;
+    let committed = tx.commit().await;
+    run.settle(committed.is_ok()).await;
+    committed?;
+    Ok(())
+}
 
+// Project details from GitHub, outside the transaction since it calls out to the
+// GitHub and LLM APIs. Only reads issues_master and projects.
+async fn enrich_projects(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    run.github_step("fill_projects", fill_projects(pool))
+        .await?;
+    run.step("project_master_back_sync", async {
+        let mut conn = pool.get_conn().await?;
+        project_master_back_sync(&mut conn)
+            .await
+            .map(StepStats::written)
+    })
+    .await?;
+
+    Ok(())
+}
+
+// Merges the staging tables and purges them in one transaction, so a run either
+// applies completely or leaves the staging rows for the next one.
+pub async fn merge_and_purge_ops(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let result = async {
+        merge_steps(&mut tx, run).await?;
+        purge_steps(&mut tx, run).await
+    }
+    .await;
+    finish_transaction(tx, run, result).await?;
+
+    enrich_projects(pool, run).await
+}
+
+pub async fn join_ops(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let result = merge_steps(&mut tx, run).await;
+    finish_transaction(tx, run, result).await?;
+
+    enrich_projects(pool, run).await
+}
+
+pub async fn fill_projects(pool: &Pool) -> anyhow::Result<StepStats> {
     let query_repos: String = get_projects_as_repo_list(pool, 1).await?;
 
     let repo_data_vec: Vec<RepoData> = search_repos_in_batch(&query_repos).await?;
 
+    let mut stats = StepStats {
+        rows_fetched: repo_data_vec.len() as i32,
+        ..Default::default()
+    };
     for repo_data in repo_data_vec {
-        let _ = fill_project_w_repo_data(&pool, repo_data.clone()).await?;
-        let _ = summarize_project_add_in_db(&pool, repo_data).await?;
+        stats
+            .record(|| async {
+                fill_project_w_repo_data(pool, repo_data.clone()).await?;
+                summarize_project_add_in_db(pool, repo_data.clone()).await
+            })
+            .await?;
     }
-    let _ = project_master_back_sync(&pool).await?;
 
-    Ok(())
+    Ok(stats)
 }
 
-pub async fn cleanup_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
-    let _ = delete_issues_open_assigned_closed(&pool).await?;
+// Summaries for issues in issues_master that have none yet, 50 per call.
+pub async fn summarize_pending_issues(pool: &Pool) -> anyhow::Result<StepStats> {
+    let issues = get_issues_from_db(pool).await?;
 
-    Ok(())
+    let mut stats = StepStats {
+        rows_fetched: issues.len() as i32,
+        ..Default::default()
+    };
+    for (issue_id, issue_title, issue_description, _issue_assignees) in issues {
+        let issue = IssueOpen {
+            issue_id,
+            issue_title,
+            issue_description,
+            ..Default::default()
+        };
+        stats
+            .record(|| summarize_issue_add_in_db(pool, &issue))
+            .await?;
+    }
+
+    Ok(stats)
 }
 
-pub async fn note_issues(pool: &Pool) -> anyhow::Result<()> {
-    let _ = note_budget_allocated(pool).await?;
-    let _ = note_issue_declined(pool).await?;
-    let _ = note_distribute_fund(pool).await?;
-    let _ = note_one_months_no_pr(pool).await?;
-    Ok(())
+pub async fn cleanup_ops(pool: &Pool, run: &PipelineRun<'_>) -> anyhow::Result<()> {
+    let mut tx = pool.start_transaction(TxOpts::default()).await?;
+    let result = purge_steps(&mut tx, run).await;
+    finish_transaction(tx, run, result).await
 }
 
-pub async fn note_budget_allocated(pool: &Pool) -> anyhow::Result<()> {
+// A comment owed to an issue thread.
+#[derive(Clone, Debug)]
+pub struct IssueNote {
+    pub issue_id: String,
+    pub kind: NoteKind,
+    pub comment: String,
+}
+
+impl IssueNote {
+    fn new(issue_id: String, kind: NoteKind, comment: String) -> Self {
+        IssueNote {
+            issue_id,
+            kind,
+            comment,
+        }
+    }
+}
+
+// Comments owed to issue threads. With dry_run they are only returned, otherwise
+// they are posted to GitHub and recorded in sent_notes, so the next run skips them.
+pub async fn note_issues(pool: &Pool, dry_run: bool) -> anyhow::Result<Vec<IssueNote>> {
+    let mut notes = note_budget_allocated(pool).await?;
+    notes.extend(note_issue_declined(pool).await?);
+    notes.extend(note_distribute_fund(pool).await?);
+    notes.extend(note_one_months_no_pr(pool).await?);
+
+    if !dry_run {
+        for note in &notes {
+            comment_on_issue(&note.issue_id, &note.comment).await?;
+            record_note_sent(pool, &note.issue_id, note.kind).await?;
+        }
+    }
+    Ok(notes)
+}
+
+pub async fn note_budget_allocated(pool: &Pool) -> anyhow::Result<Vec<IssueNote>> {
     let issue_ids = get_issue_ids_with_budget(pool).await?;
     log::info!(
         "Issue ids with budget allocated, count: {:?}",
         issue_ids.len()
     );
-    for issue_id in issue_ids {
-        let comment = format!("{}/n Congratulations! GOSIM grant approved. Your proposal is approved to get $100 fund to fix the issue.", issue_id);
+    let mut notes = Vec::new();
+    for (issue_id, issue_budget, currency) in issue_ids {
+        let comment = format!("Congratulations! GOSIM grant approved. Your proposal is approved to get {} {} fund to fix the issue.", issue_budget, currency);
 
-        // let _ = mock_comment_on_issue(1, &comment).await?;
+        notes.push(IssueNote::new(issue_id, NoteKind::BudgetAllocated, comment));
     }
-    Ok(())
+    Ok(notes)
 }
 
-pub async fn note_issue_declined(pool: &Pool) -> anyhow::Result<()> {
+pub async fn note_issue_declined(pool: &Pool) -> anyhow::Result<Vec<IssueNote>> {
     let issue_ids = get_issue_ids_declined(pool).await?;
     log::info!(
         "Issue ids with budget declined, count: {:?}",
         issue_ids.len()
     );
+    let mut notes = Vec::new();
     for issue_id in issue_ids {
-        let comment = format!("{}/n  I’m sorry your proposal wasn't approved", issue_id);
+        let comment = String::from("I’m sorry your proposal wasn't approved");
 
-        // let _ = mock_comment_on_issue(2, &comment).await?;
+        notes.push(IssueNote::new(issue_id, NoteKind::Declined, comment));
     }
-    Ok(())
+    Ok(notes)
 }
 
-pub async fn note_distribute_fund(pool: &Pool) -> anyhow::Result<()> {
-    let issue_ids: Vec<(Option<String>, String, i32)> = get_issue_ids_distribute_fund(pool).await?;
+pub async fn note_distribute_fund(pool: &Pool) -> anyhow::Result<Vec<IssueNote>> {
+    let issue_ids: Vec<(Option<String>, String, i32, Currency)> =
+        get_issue_ids_distribute_fund(pool).await?;
     log::info!("Issue_ids to split fund, count: {:?}", issue_ids.len());
-    for (issue_assignee, _issue_id, issue_budget) in issue_ids {
-        let comment = format!("@{:?}, Well done!  According to the PR commit history. @{:?} should receive ${}. Please fill in this form to claim your fund. ", issue_assignee, issue_assignee, issue_budget);
+    let mut notes = Vec::new();
+    for (issue_assignee, issue_id, issue_budget, currency) in issue_ids {
+        let issue_assignee = issue_assignee.unwrap_or_default();
+        let comment = format!("@{}, Well done!  According to the PR commit history. @{} should receive {} {}. Please fill in this form to claim your fund. ", issue_assignee, issue_assignee, issue_budget, currency);
 
-        // let _ = mock_comment_on_issue(3, &comment).await?;
+        notes.push(IssueNote::new(issue_id, NoteKind::DistributeFund, comment));
     }
-    Ok(())
+    Ok(notes)
 }
 
-pub async fn note_one_months_no_pr(pool: &Pool) -> anyhow::Result<()> {
+pub async fn note_one_months_no_pr(pool: &Pool) -> anyhow::Result<Vec<IssueNote>> {
     let issue_ids = get_issue_ids_one_month_no_activity(pool).await?;
     log::info!("Issue_ids no activity, count: {:?}", issue_ids.len());
 
+    let mut notes = Vec::new();
     for issue_id in issue_ids {
-        let comment = format!("{}\n @{} please link your PR to the issue it fixed in three days. Or this issue will be deemed not completed, then we can’t provide the fund.", issue_id, "issue_assignee" );
+        let comment = String::from("Please link your PR to the issue it fixed in three days. Or this issue will be deemed not completed, then we can’t provide the fund.");
 
-        // let _ = mock_comment_on_issue(4, &comment).await?;
+        notes.push(IssueNote::new(issue_id, NoteKind::NoPr, comment));
+    }
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_db;
+    use mysql_async::prelude::*;
+
+    async fn staged_count(pool: &Pool, table: &str, prefix: &str) -> i64 {
+        let mut conn = pool.get_conn().await.unwrap();
+        let query = format!(
+            "SELECT COUNT(*) FROM {} WHERE issue_id LIKE CONCAT(:prefix, '%')",
+            table
+        );
+        conn.exec_first(query, params! { "prefix" => prefix })
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs MySQL at TEST_DATABASE_URL"]
+    async fn a_failing_step_rolls_back_the_merge() {
+        let pool = test_db::pool().await;
+        let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+        let project_id = format!("https://github.com/{}/test", campaign_id);
+        let staged = format!("{}-1", campaign_id);
+        let merged = format!("{}-2", campaign_id);
+        let pull_id = format!("{}-pull", campaign_id);
+
+        // one issue only staged, one already in issues_master that was closed since
+        test_db::insert_issue(&pool, &campaign_id, &merged).await;
+        add_issues_open(
+            &pool,
+            &IssueOpen {
+                issue_id: staged.clone(),
+                project_id: project_id.clone(),
+                issue_title: String::from("test"),
+                issue_creator: String::from("test"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        add_issues_assigned(
+            &pool,
+            IssueAssigned {
+                issue_id: staged.clone(),
+                issue_assignee: String::from("alice"),
+                date_assigned: String::from("2023-10-02 09:00:00"),
+            },
+        )
+        .await
+        .unwrap();
+        add_issues_closed(
+            &pool,
+            IssueClosed {
+                issue_id: merged.clone(),
+                issue_assignees: Some(vec![String::from("bob")]),
+                issue_linked_pr: Some(pull_id.clone()),
+            },
+        )
+        .await
+        .unwrap();
+        add_pull_request(
+            &pool,
+            OuterPull {
+                pull_id: pull_id.clone(),
+                pull_title: String::from("test"),
+                project_id: project_id.clone(),
+                merged_at: String::from("2023-10-20 12:00:00"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        // the purge's last statement fails, after every other step wrote its rows
+        let trigger = format!("fail_{}", campaign_id.replace('-', "_"));
+        let mut conn = pool.get_conn().await.unwrap();
+        conn.query_drop(format!(
+            r"CREATE TRIGGER {trigger} BEFORE DELETE ON issues_closed FOR EACH ROW
+              BEGIN
+                IF OLD.issue_id LIKE '{campaign_id}%' THEN
+                  SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'injected failure';
+                END IF;
+              END",
+            trigger = trigger,
+            campaign_id = campaign_id
+        ))
+        .await
+        .unwrap();
+
+        let run = PipelineRun::start(&pool, "test").await.unwrap();
+        let result = merge_and_purge_ops(&pool, &run).await;
+        let run_id = run.run_id;
+        run.finish(&result).await;
+        conn.query_drop(format!("DROP TRIGGER {}", trigger))
+            .await
+            .unwrap();
+        assert!(result.is_err());
+
+        // the staging rows are all still there for the next run
+        assert_eq!(staged_count(&pool, "issues_open", &campaign_id).await, 1);
+        assert_eq!(
+            staged_count(&pool, "issues_assigned", &campaign_id).await,
+            1
+        );
+        assert_eq!(staged_count(&pool, "issues_closed", &campaign_id).await, 1);
+        let pulls: Option<i64> = conn
+            .exec_first(
+                "SELECT COUNT(*) FROM pull_requests WHERE pull_id = :pull_id",
+                params! { "pull_id" => &pull_id },
+            )
+            .await
+            .unwrap();
+        assert_eq!(pulls, Some(1));
+
+        // and issues_master is as it was before the run
+        let master: Vec<(String, Option<String>, Option<String>)> = conn
+            .exec(
+                r"SELECT issue_id, issue_linked_pr, date_issue_assigned FROM issues_master
+                  WHERE issue_id LIKE CONCAT(:prefix, '%')",
+                params! { "prefix" => &campaign_id },
+            )
+            .await
+            .unwrap();
+        assert_eq!(master, vec![(merged.clone(), None, None)]);
+        assert_eq!(
+            staged_count(&pool, "issue_assignees", &campaign_id).await,
+            0
+        );
+
+        let steps: Vec<(String, String, i32)> = conn
+            .exec(
+                r"SELECT step_name, step_status, rows_written
+                  FROM pipeline_steps WHERE run_id = :run_id ORDER BY step_id",
+                params! { "run_id" => run_id },
+            )
+            .await
+            .unwrap();
+        let (failed, rolled_back) = steps.split_last().unwrap();
+        assert_eq!(
+            (failed.0.as_str(), failed.1.as_str()),
+            ("delete_issues_open_assigned_closed", "failed")
+        );
+        assert_eq!(rolled_back.len(), 8);
+        for (step_name, step_status, rows_written) in rolled_back {
+            assert_eq!(step_status, "rolled_back", "{}", step_name);
+            assert_eq!(*rows_written, 0, "{}", step_name);
+        }
     }
-    Ok(())
 }
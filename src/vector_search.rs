@@ -1,22 +1,20 @@
+use crate::error::{Error, GosimResult};
 use openai_flows::{embeddings::EmbeddingsInput, OpenAIFlows};
 use regex::Regex;
 use serde_json::json;
 use std::env;
 use vector_store_flows::*;
 
-pub async fn upload_to_collection(
-    issue_or_project_id: &str,
-    content: String,
-) -> anyhow::Result<()> {
+pub async fn upload_to_collection(issue_or_project_id: &str, content: String) -> GosimResult<()> {
     let collection_name = env::var("collection_name").unwrap_or("gosim_search".to_string());
 
     let id: u64 = match collection_info(&collection_name).await {
         Ok(ci) => ci.points_count,
         Err(e) => {
-            return Err(anyhow::anyhow!(
+            return Err(Error::VectorStore(format!(
                 "Cannot get collection, can not init points_count: {}",
                 e
-            ))
+            )))
         }
     };
 
@@ -39,6 +37,10 @@ pub async fn upload_to_collection(
 
             if let Err(e) = upsert_points(&collection_name, p).await {
                 log::error!("Cannot upsert into database! {}", e);
+                return Err(Error::VectorStore(format!(
+                    "Cannot upsert {}: {}",
+                    issue_or_project_id, e
+                )));
             }
             log::debug!(
                 "Created vector {} with length {}",
@@ -50,7 +52,7 @@ pub async fn upload_to_collection(
         }
         Err(e) => {
             log::error!("OpenAI returned an error: {}", e);
-            Err(anyhow::anyhow!("OpenAI returned an error: {}", e))
+            Err(Error::Llm(format!("OpenAI returned an error: {}", e)))
         }
     }
 }
@@ -58,7 +60,7 @@ pub async fn upload_to_collection(
 /* pub async fn upload_to_collection(
     issue_or_project_id: &str,
     content: String,
-) -> anyhow::Result<()> {
+) -> GosimResult<()> {
     let collection_name = env::var("collection_name").unwrap_or("gosim_search".to_string());
 
     let mut id: u64 = match collection_info(&collection_name).await {
@@ -129,15 +131,19 @@ pub async fn check_vector_db(collection_name: &str) -> String {
 
 use std::cmp::Reverse;
 
+fn payload_str<'a>(point: &'a ScoredPoint, key: &str) -> Option<&'a str> {
+    point.payload.as_ref()?.get(key)?.as_str()
+}
+
 pub async fn search_collection_hybrid(
     question: &str,
     collection_name: &str,
-) -> anyhow::Result<Vec<(String, String)>> {
+) -> GosimResult<Vec<(String, String)>> {
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(3);
 
-    let project_regex = Regex::new(r"\bproject\b")?;
-    let issue_regex = Regex::new(r"\bissue\b")?;
+    let project_regex = Regex::new(r"\bproject\b").unwrap();
+    let issue_regex = Regex::new(r"\bissue\b").unwrap();
 
     let is_project = project_regex.is_match(&question.to_ascii_lowercase());
     let is_issue = issue_regex.is_match(&question.to_ascii_lowercase());
@@ -152,7 +158,9 @@ pub async fn search_collection_hybrid(
         Ok(r) if !r.is_empty() => r[0].iter().map(|n| *n as f32).collect(),
         _ => {
             log::error!("Failed to get embeddings for the question");
-            return Err(anyhow::anyhow!("Failed to get embeddings for the question"));
+            return Err(Error::Llm(
+                "Failed to get embeddings for the question".to_string(),
+            ));
         }
     };
 
@@ -161,25 +169,17 @@ pub async fn search_collection_hybrid(
         limit: 10,
     };
 
-    let search_results = search_points(collection_name, &p).await.expect("search point failure");
+    let search_results = search_points(collection_name, &p)
+        .await
+        .map_err(|e| Error::VectorStore(format!("Vector search returns error: {}", e)))?;
     for p in search_results.iter() {
-        let p_text = p
-            .payload
-            .as_ref()
-            .unwrap()
-            .get("text")
-            .unwrap()
-            .as_str()
-            .unwrap();
-
-        let issue_or_project_id = p
-            .payload
-            .as_ref()
-            .unwrap()
-            .get("issue_or_project_id")
-            .unwrap()
-            .as_str()
-            .unwrap();
+        let (Some(p_text), Some(issue_or_project_id)) = (
+            payload_str(p, "text"),
+            payload_str(p, "issue_or_project_id"),
+        ) else {
+            log::warn!("Skipping vector point without text or id");
+            continue;
+        };
         let is_sid = issue_or_project_id.split('/').count() == 7;
 
         if p.score > 0.75 {
@@ -241,7 +241,7 @@ pub async fn search_collection_hybrid(
 pub async fn search_collection(
     question: &str,
     collection_name: &str,
-) -> anyhow::Result<Vec<(String, String)>> {
+) -> GosimResult<Vec<(String, String)>> {
     let mut openai = OpenAIFlows::new();
     openai.set_retry_times(3);
 
@@ -252,17 +252,15 @@ pub async fn search_collection(
         Ok(r) => {
             if r.len() < 1 {
                 log::error!("LLM returned no embedding for the question");
-                return Err(anyhow::anyhow!(
-                    "LLM returned no embedding for the question"
+                return Err(Error::Llm(
+                    "LLM returned no embedding for the question".to_string(),
                 ));
             }
             r[0].iter().map(|n| *n as f32).collect()
         }
         Err(_e) => {
             log::error!("LLM returned an error: {}", _e);
-            return Err(anyhow::anyhow!(
-                "LLM returned no embedding for the question"
-            ));
+            return Err(Error::Llm(format!("LLM returned an error: {}", _e)));
         }
     };
 
@@ -275,23 +273,13 @@ pub async fn search_collection(
     match search_points(&collection_name, &p).await {
         Ok(sp) => {
             for p in sp.iter() {
-                let p_text = p
-                    .payload
-                    .as_ref()
-                    .unwrap()
-                    .get("text")
-                    .unwrap()
-                    .as_str()
-                    .unwrap();
-
-                let issue_or_project_id = p
-                    .payload
-                    .as_ref()
-                    .unwrap()
-                    .get("issue_or_project_id")
-                    .unwrap()
-                    .as_str()
-                    .unwrap();
+                let (Some(p_text), Some(issue_or_project_id)) = (
+                    payload_str(p, "text"),
+                    payload_str(p, "issue_or_project_id"),
+                ) else {
+                    log::warn!("Skipping vector point without text or id");
+                    continue;
+                };
 
                 log::info!(
                     "Received vector score={} and text={}\n",
@@ -381,7 +369,7 @@ pub async fn search_collection(
     Ok(out)
 } */
 
-pub async fn create_my_collection(vector_size: u64, collection_name: &str) -> anyhow::Result<()> {
+pub async fn create_my_collection(vector_size: u64, collection_name: &str) -> GosimResult<()> {
     let params = CollectionCreateParams {
         vector_size: vector_size,
     };
@@ -0,0 +1,38 @@
+// vector_search without the wasi feature: the collection lives in flows.network,
+// so every call fails with Error::VectorStore.
+use crate::error::{Error, GosimResult};
+
+fn unavailable() -> Error {
+    Error::VectorStore("vector search needs the wasi feature".to_string())
+}
+
+pub async fn upload_to_collection(_issue_or_project_id: &str, _content: String) -> GosimResult<()> {
+    Err(unavailable())
+}
+
+pub async fn check_vector_db(collection_name: &str) -> String {
+    log::warn!(
+        "Cannot check collection {}: {}",
+        collection_name,
+        unavailable()
+    );
+    unavailable().to_string()
+}
+
+pub async fn search_collection_hybrid(
+    _question: &str,
+    _collection_name: &str,
+) -> GosimResult<Vec<(String, String)>> {
+    Err(unavailable())
+}
+
+pub async fn search_collection(
+    _question: &str,
+    _collection_name: &str,
+) -> GosimResult<Vec<(String, String)>> {
+    Err(unavailable())
+}
+
+pub async fn create_my_collection(_vector_size: u64, _collection_name: &str) -> GosimResult<()> {
+    Err(unavailable())
+}
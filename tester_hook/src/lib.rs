@@ -3,6 +3,7 @@ use flowsnet_platform_sdk::logger;
 use gosim_project::db_join::*;
 use gosim_project::db_manipulate::*;
 use gosim_project::db_populate::*;
+use gosim_project::db_runs::*;
 use gosim_project::issue_tracker::*;
 use gosim_project::llm_utils::chat_inner_async;
 use gosim_project::the_paced_runner::*;
@@ -299,33 +300,105 @@ async fn trigger(_headers: Vec<(String, String)>, _qry: HashMap<String, Value>,
     let pool: Pool = get_pool().await;
     log::info!("func_id to run: {:?}", load.func_ids);
 
+    let run = match PipelineRun::start_locked(&pool, "manual").await {
+        Ok(Some(run)) => run,
+        Ok(None) => {
+            send_response(
+                409,
+                vec![(
+                    String::from("Access-Control-Allow-Origin"),
+                    String::from("*"),
+                )],
+                b"Another pipeline run is in progress".to_vec(),
+            );
+            return;
+        }
+        Err(e) => {
+            log::error!("Error starting pipeline run: {:?}", e);
+            return;
+        }
+    };
+
+    let mut result = Ok(());
     for func_id in load.func_ids {
-        let _ = match func_id.as_str() {
-            "1" => popuate_dbs_save_issues_open(&pool).await,
-            "2" => open_master(&pool).await,
-            "3" => popuate_dbs_save_issues_assigned(&pool).await,
-            "4" => assigned_master(&pool).await,
-            "5" => popuate_dbs_save_issues_closed(&pool).await,
-            "6" => closed_master(&pool).await,
-            "7" => popuate_dbs_fill_projects(&pool).await,
-            "8" => master_project(&pool).await,
-            "9" => popuate_dbs_save_pull_requests(&pool).await,
-            "10" => project_master_back_sync(&&pool).await,
-            "11" => populate_vector_db(&pool).await,
-            "12" => popuate_dbs_save_issues_comment(&pool).await,
-            "13" => sum_budget_to_project(&pool).await,
-            "14" => remove_pull_by_issued_linked_pr(&pool).await,
-            "15" => delete_issues_open_assigned_closed(&pool).await,
-            "16" => force_issue_to_summary_update_db(&pool).await,
-            _ => panic!(),
+        let step = async {
+            match func_id.as_str() {
+                "1" => popuate_dbs_save_issues_open(&pool).await,
+                "2" => Ok(StepStats::written(
+                    open_master(&mut pool.get_conn().await?).await?,
+                )),
+                "3" => popuate_dbs_save_issues_assigned(&pool).await,
+                "4" => Ok(StepStats::written(
+                    assigned_master(&mut pool.get_conn().await?).await?,
+                )),
+                "5" => popuate_dbs_save_issues_closed(&pool).await,
+                "6" => Ok(StepStats::written(
+                    closed_master(&mut pool.get_conn().await?).await?,
+                )),
+                "7" => popuate_dbs_fill_projects(&pool).await,
+                "8" => Ok(StepStats::written(
+                    master_project(&mut pool.get_conn().await?).await?,
+                )),
+                "9" => popuate_dbs_save_pull_requests(&pool).await,
+                "10" => Ok(StepStats::written(
+                    project_master_back_sync(&mut pool.get_conn().await?).await?,
+                )),
+                "11" => populate_vector_db(&pool).await,
+                "12" => popuate_dbs_save_issues_comment(&pool).await,
+                "13" => Ok(sum_budget_to_project(&mut pool.get_conn().await?).await?),
+                "14" => Ok(StepStats::written(
+                    remove_pull_by_issued_linked_pr(&mut pool.get_conn().await?).await?,
+                )),
+                "15" => Ok(StepStats::written(
+                    delete_issues_open_assigned_closed(&mut pool.get_conn().await?).await?,
+                )),
+                "16" => force_issue_to_summary_update_db(&pool).await,
+                _ => Err(anyhow::anyhow!("Unknown func_id: {}", func_id)),
+            }
         };
+
+        let step_result = if func_calls_github(&func_id) {
+            run.github_step(func_step_name(&func_id), step).await
+        } else {
+            run.step(func_step_name(&func_id), step).await
+        };
+        if let Err(e) = step_result {
+            result = Err(e);
+        }
+    }
+    run.finish(&result).await;
+}
+
+// The steps worth sampling the GraphQL rate limit around.
+fn func_calls_github(func_id: &str) -> bool {
+    matches!(func_id, "1" | "3" | "5" | "7" | "9" | "12")
+}
+
+fn func_step_name(func_id: &str) -> &str {
+    match func_id {
+        "1" => "save_issues_open",
+        "2" => "open_master",
+        "3" => "save_issues_assigned",
+        "4" => "assigned_master",
+        "5" => "save_issues_closed",
+        "6" => "closed_master",
+        "7" => "fill_projects",
+        "8" => "master_project",
+        "9" => "save_pull_requests",
+        "10" => "project_master_back_sync",
+        "11" => "populate_vector_db",
+        "12" => "save_issues_comment",
+        "13" => "sum_budget_to_project",
+        "14" => "remove_pull_by_issued_linked_pr",
+        "15" => "delete_issues_open_assigned_closed",
+        "16" => "force_issue_to_summary_update_db",
+        _ => func_id,
     }
 }
 
 pub async fn run_hourly(pool: &Pool) -> anyhow::Result<()> {
     // let _ = popuate_dbs(pool).await?;
-    // let _ = join_ops(pool).await?;
-    // let _ = cleanup_ops(pool).await?;
+    // let _ = gosim_project::the_runner::merge_and_purge_ops(pool, &run).await?;
     let _ = populate_vector_db(pool).await;
     Ok(())
 }
@@ -373,45 +446,3 @@ pub async fn popuate_dbs(pool: &Pool) -> anyhow::Result<()> {
 
     Ok(())
 }
-
-pub async fn populate_vector_db(pool: &Pool) -> anyhow::Result<()> {
-    for item in get_issues_repos_from_db().await.expect("msg") {
-        log::info!("uploading to vector_db: {:?}", item.0);
-        let _ = upload_to_collection(&item.0, item.1.clone()).await;
-        let _ = mark_id_indexed(&pool, &item.0).await;
-    }
-    let _ = check_vector_db("gosim_search").await;
-
-    Ok(())
-}
-
-pub async fn join_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = open_master(&pool).await?;
-    // let _ = assigned_master(&pool).await?;
-
-    let _ = closed_master(&pool).await?;
-
-    let _ = master_project(&pool).await?;
-    // let _ = sum_budget_to_project(&pool).await?;
-
-    let query_repos: String = get_projects_as_repo_list(pool, 1).await?;
-    log::info!("repos list: {:?}", query_repos.clone());
-
-    let repo_data_vec: Vec<RepoData> = search_repos_in_batch(&query_repos).await?;
-
-    for repo_data in repo_data_vec {
-        log::info!("repo : {:?}", repo_data.project_id.clone());
-
-        let _ = fill_project_w_repo_data(&pool, repo_data.clone()).await?;
-        let _ = summarize_project_add_in_db(&pool, repo_data).await?;
-    }
-
-    Ok(())
-}
-
-pub async fn cleanup_ops(pool: &Pool) -> anyhow::Result<()> {
-    let _ = remove_pull_by_issued_linked_pr(&pool).await?;
-    let _ = delete_issues_open_assigned_closed(&pool).await?;
-
-    Ok(())
-}
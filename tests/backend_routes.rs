@@ -0,0 +1,354 @@
+// Hostile list and export requests through backend_api::route, against MySQL.
+// Names (presets, review statuses, sort fields) have to be on a whitelist, so
+// anything else is a 400. Values are bound as parameters and simply match nothing,
+// the tables are left as they were.
+mod common;
+
+use common::{pool, repo_with_issue};
+use gosim_project::backend_api::{route, ApiResponse};
+use gosim_project::db_export::{export_table, ExportFormat, ExportRequest, ExportTable};
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const INJECTION: &str = "queue' OR '1'='1'; DROP TABLE issues_master; --";
+
+fn page() -> HashMap<String, Value> {
+    HashMap::from([
+        (String::from("page"), json!("1")),
+        (String::from("page_size"), json!("10")),
+    ])
+}
+
+// /export is a reviewer route, so every request carries a token.
+async fn post(pool: &Pool, path: &str, body: &Value) -> ApiResponse {
+    std::env::set_var("GOSIM_REVIEWERS", "rita:rita-token");
+    route(
+        pool,
+        "POST",
+        path,
+        &[(
+            String::from("Authorization"),
+            String::from("Bearer rita-token"),
+        )],
+        &page(),
+        body.to_string().as_bytes(),
+    )
+    .await
+}
+
+async fn assert_rejected(pool: &Pool, path: &str, body: Value) {
+    let res = post(pool, path, &body).await;
+    assert_eq!(
+        res.status,
+        400,
+        "{} {} answered {}: {}",
+        path,
+        body,
+        res.status,
+        String::from_utf8_lossy(&res.body)
+    );
+}
+
+async fn issue_count(pool: &Pool, body: Value) -> usize {
+    let res = post(pool, "/issues", &body).await;
+    assert_eq!(res.status, 200, "{}", String::from_utf8_lossy(&res.body));
+    serde_json::from_slice::<Vec<Value>>(&res.body)
+        .unwrap()
+        .len()
+}
+
+// What a hostile request could drop or rewrite: the issues and projects tables
+// and the test's own rows in them. Other tests only add rows meanwhile.
+#[derive(Debug, PartialEq)]
+struct Contents {
+    issues: usize,
+    projects: usize,
+    own_rows: Vec<Vec<mysql_async::Value>>,
+}
+
+async fn contents(pool: &Pool, repo: &str) -> Contents {
+    let mut conn = pool.get_conn().await.unwrap();
+    let issues: Option<usize> = conn
+        .query_first("SELECT COUNT(*) FROM issues_master")
+        .await
+        .unwrap();
+    let projects: Option<usize> = conn
+        .query_first("SELECT COUNT(*) FROM projects")
+        .await
+        .unwrap();
+    let mut own_rows = Vec::new();
+    for table in ["issues_master", "projects"] {
+        let rows: Vec<mysql_async::Row> = conn
+            .exec(
+                format!("SELECT * FROM {} WHERE project_id = :project_id", table),
+                params! { "project_id" => repo },
+            )
+            .await
+            .unwrap();
+        own_rows.extend(rows.into_iter().map(mysql_async::Row::unwrap));
+    }
+
+    Contents {
+        issues: issues.unwrap_or_default(),
+        projects: projects.unwrap_or_default(),
+        own_rows,
+    }
+}
+
+fn assert_unchanged(before: &Contents, after: &Contents) {
+    assert_eq!(after.own_rows, before.own_rows);
+    assert!(after.issues >= before.issues, "{:?} {:?}", before, after);
+    assert!(
+        after.projects >= before.projects,
+        "{:?} {:?}",
+        before,
+        after
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn injection_in_names_is_rejected() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    let before = contents(&pool, &repo).await;
+
+    assert_rejected(
+        &pool,
+        "/issues",
+        json!({ "filter": { "review_status": INJECTION } }),
+    )
+    .await;
+    assert_rejected(
+        &pool,
+        "/issues",
+        json!({ "filter": { "preset": INJECTION } }),
+    )
+    .await;
+    assert_rejected(&pool, "/issues", json!({ "filter_strs": [INJECTION] })).await;
+    assert_rejected(
+        &pool,
+        "/projects",
+        json!({ "filter": { "preset": INJECTION } }),
+    )
+    .await;
+    assert_rejected(
+        &pool,
+        "/issues",
+        json!({ "sort": [{ "field": INJECTION }] }),
+    )
+    .await;
+
+    let mut qry = page();
+    qry.insert(String::from("list_by"), json!(INJECTION));
+    for path in ["/issues", "/projects"] {
+        let res = route(&pool, "GET", path, &[], &qry, b"").await;
+        assert_eq!(res.status, 400, "GET {}", path);
+    }
+
+    assert_unchanged(&before, &contents(&pool, &repo).await);
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn injection_in_values_matches_nothing() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    let before = contents(&pool, &repo).await;
+
+    for leaf in ["project", "language", "assignee", "label"] {
+        assert_eq!(
+            issue_count(&pool, json!({ "filter": { leaf: INJECTION } })).await,
+            0,
+            "{}",
+            leaf
+        );
+    }
+    // an issue_id is looked up as it is
+    let res = post(&pool, "/issue", &json!({ "issue_id": INJECTION })).await;
+    assert_eq!(res.status, 404);
+    let res = post(&pool, "/decline", &json!({ "issue_ids": [INJECTION] })).await;
+    assert_eq!(res.status, 404);
+
+    // and the table is still there
+    assert_eq!(
+        issue_count(&pool, json!({ "filter": { "project": repo } })).await,
+        1
+    );
+    assert_unchanged(&before, &contents(&pool, &repo).await);
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn unknown_fields_and_operators_are_rejected() {
+    let pool = pool().await;
+
+    for body in [
+        json!({ "filtr": { "language": "Rust" } }),
+        json!({ "filter": { "langauge": "Rust" } }),
+        json!({ "filter": { "xor": [{ "language": "Rust" }] } }),
+        json!({ "filter": { "not": { "language": "Rust" } } }),
+        json!({ "filter": { "language": "Rust", "project": "x" } }),
+        json!({ "filter": { "budget": { "gt": 5 } } }),
+        json!({ "filter": { "budget": { "min": 5, "max": 10, "op": "OR 1=1" } } }),
+        json!({ "filter": { "budget": { "min": "5; DROP TABLE projects" } } }),
+        json!({ "filter": { "date_assigned": { "from": "2023-10-01' --" } } }),
+        json!({ "sort": [{ "field": "repo_stars", "order": "sideways" }] }),
+        json!({ "sort": [{ "field": "repo_stars", "collate": "x" }] }),
+    ] {
+        assert_rejected(&pool, "/issues", body).await;
+    }
+
+    assert_rejected(
+        &pool,
+        "/projects",
+        json!({ "filter": { "stars": { "above": 5 } } }),
+    )
+    .await;
+    assert_rejected(&pool, "/projects", json!({ "limit": 5 })).await;
+    assert_rejected(
+        &pool,
+        "/export",
+        json!({ "table": "issues", "format": "csv", "columns": ["*"] }),
+    )
+    .await;
+    assert_rejected(
+        &pool,
+        "/export",
+        json!({ "table": "issues", "filter": { "xor": [] } }),
+    )
+    .await;
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn oversized_filters_are_rejected() {
+    let pool = pool().await;
+
+    let languages: Vec<Value> = (0..65)
+        .map(|i| json!({ "language": format!("lang{}", i) }))
+        .collect();
+    assert_rejected(&pool, "/issues", json!({ "filter": { "or": languages } })).await;
+
+    // presets bind no values, but still count as terms
+    let presets: Vec<Value> = (0..300).map(|_| json!({ "preset": "queue" })).collect();
+    assert_rejected(&pool, "/issues", json!({ "filter": { "and": presets } })).await;
+    let names: Vec<&str> = vec!["queue"; 300];
+    assert_rejected(&pool, "/issues", json!({ "filter_strs": names })).await;
+
+    let stars: Vec<Value> = (0..40)
+        .map(|i| json!({ "stars": { "min": i, "max": i + 1 } }))
+        .collect();
+    assert_rejected(&pool, "/projects", json!({ "filter": { "or": stars } })).await;
+
+    let sort: Vec<Value> = (0..9).map(|_| json!({ "field": "repo_stars" })).collect();
+    assert_rejected(&pool, "/issues", json!({ "sort": sort })).await;
+
+    // right at the limits is fine
+    let languages: Vec<Value> = (0..64)
+        .map(|i| json!({ "language": format!("lang{}", i) }))
+        .collect();
+    assert_eq!(
+        issue_count(&pool, json!({ "filter": { "or": languages } })).await,
+        0
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn bad_sort_keys_are_rejected() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+
+    for field in [
+        "",
+        "repo_stars DESC",
+        "repo_stars; DROP TABLE issues_master",
+        "issue_id",
+        "JSON_LENGTH(issues_list)",
+        "REPO_STARS",
+    ] {
+        assert_rejected(&pool, "/issues", json!({ "sort": [{ "field": field }] })).await;
+        assert_rejected(&pool, "/projects", json!({ "sort": [{ "field": field }] })).await;
+    }
+    // a project sort isn't an issue sort
+    assert_rejected(
+        &pool,
+        "/issues",
+        json!({ "sort": [{ "field": "issues_count" }] }),
+    )
+    .await;
+    assert_rejected(&pool, "/issues", json!({ "sort": "repo_stars" })).await;
+    assert_rejected(&pool, "/issues", json!({ "sort": [{ "order": "asc" }] })).await;
+
+    assert_eq!(
+        issue_count(
+            &pool,
+            json!({
+                "filter": { "project": repo },
+                "sort": [{ "field": "repo_stars", "order": "asc" }]
+            })
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn huge_pages_are_empty() {
+    let pool = pool().await;
+
+    let max = usize::MAX.to_string();
+    for (page, page_size) in [
+        (max.as_str(), max.as_str()),
+        (max.as_str(), "10"),
+        ("2", max.as_str()),
+    ] {
+        let qry = HashMap::from([
+            (String::from("page"), json!(page)),
+            (String::from("page_size"), json!(page_size)),
+        ]);
+        for path in ["/issues", "/projects"] {
+            let res = route(&pool, "POST", path, &[], &qry, b"{}").await;
+            assert_eq!(res.status, 200, "{} page {} of {}", path, page, page_size);
+            assert!(serde_json::from_slice::<Vec<Value>>(&res.body)
+                .unwrap()
+                .is_empty());
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn export_stops_at_the_row_cap() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    let request = ExportRequest::new(
+        ExportTable::Issues,
+        Some(json!({ "project": repo })),
+        Vec::new(),
+    )
+    .unwrap();
+
+    let (csv, rows) = export_table(&pool, &request, ExportFormat::Csv, Vec::new(), Some(1))
+        .await
+        .unwrap();
+    assert_eq!(rows, 1);
+    assert!(!csv.is_empty());
+
+    let err = export_table(&pool, &request, ExportFormat::Csv, Vec::new(), Some(0))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("gosim export"), "{}", err);
+
+    let res = post(
+        &pool,
+        "/export",
+        &json!({ "table": "issues", "filter": { "project": repo } }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+}
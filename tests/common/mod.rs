@@ -0,0 +1,48 @@
+use gosim_project::db_migrate::run_migrations;
+use gosim_project::db_storage::Storage;
+use gosim_project::issue_tracker::IssueOpen;
+use mysql_async::{Opts, Pool};
+
+// A migrated database from TEST_DATABASE_URL, as in src/test_db.rs. The tests
+// using it are #[ignore]d, `cargo test -- --ignored` runs them.
+pub async fn pool() -> Pool {
+    let url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set for the MySQL tests");
+    let pool = Pool::new(Opts::from_url(&url).expect("invalid TEST_DATABASE_URL"));
+    run_migrations(&pool)
+        .await
+        .expect("migrations failed on the test database");
+
+    pool
+}
+
+// A repo of its own for each test, the database is shared between tests and runs.
+pub fn unique_repo() -> String {
+    format!(
+        "https://github.com/test-{:08x}/widgets",
+        rand::random::<u32>()
+    )
+}
+
+pub fn open_issue(project_id: &str, n: u32) -> IssueOpen {
+    IssueOpen {
+        issue_title: format!("Issue {}", n),
+        issue_id: format!("{}/issues/{}", project_id, n),
+        issue_creator: String::from("carol"),
+        issue_budget: 100,
+        issue_description: String::from("Fix the widget"),
+        project_id: project_id.to_string(),
+        ..Default::default()
+    }
+}
+
+// Issue 1 of a new repo, merged into issues_master and projects.
+pub async fn repo_with_issue(pool: &Pool) -> String {
+    let project_id = unique_repo();
+    pool.add_issues_open(&open_issue(&project_id, 1))
+        .await
+        .unwrap();
+    pool.open_master().await.unwrap();
+    pool.master_project().await.unwrap();
+    project_id
+}
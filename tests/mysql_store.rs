@@ -0,0 +1,748 @@
+// The join/cleanup steps and the backend routes against MySQL. Every test works
+// in a repo and campaign of its own, and only looks at those rows: the steps are
+// global, so other tests may merge or purge staged rows at any time.
+mod common;
+
+use common::{open_issue, pool, repo_with_issue, unique_repo};
+use gosim_project::backend_api::{route, ApiResponse};
+use gosim_project::currency::{Currency, ExchangeRate};
+use gosim_project::db_approval::set_tier;
+use gosim_project::db_audit::AuditInfo;
+use gosim_project::db_caps::{set_caps, BudgetCaps};
+use gosim_project::db_ledger::fund_campaign;
+use gosim_project::db_populate::IssueOut;
+use gosim_project::db_query::{IssueFilter, IssueQuery};
+use gosim_project::db_rates::load_rates;
+use gosim_project::db_storage::Storage;
+use gosim_project::error::Error;
+use gosim_project::issue_tracker::*;
+use gosim_project::review_state::ReviewState;
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+fn issue_id(repo: &str, n: u32) -> String {
+    format!("{}/issues/{}", repo, n)
+}
+
+fn pull_id(repo: &str, n: u32) -> String {
+    format!("{}/pull/{}", repo, n)
+}
+
+fn merged_pull(repo: &str, n: u32, author: &str) -> OuterPull {
+    OuterPull {
+        pull_id: pull_id(repo, n),
+        pull_title: format!("Pull {}", n),
+        pull_author: Some(author.to_string()),
+        project_id: repo.to_string(),
+        merged_at: String::from("2023-10-20 12:00:00"),
+        merged_by: Some(String::from("maintainer")),
+        additions: Some(120),
+        deletions: Some(30),
+        changed_files: Some(4),
+        author_created_at: Some(String::from("2020-01-01 00:00:00")),
+    }
+}
+
+// Issue n staged as opened, assigned to `login` and closed by pull request n,
+// which `login` wrote after commenting on the issue.
+async fn stage_worked_issue(pool: &Pool, repo: &str, n: u32, login: &str) {
+    pool.add_issues_open(&open_issue(repo, n)).await.unwrap();
+    pool.add_issues_assigned(IssueAssigned {
+        issue_id: issue_id(repo, n),
+        issue_assignee: login.to_string(),
+        date_assigned: String::from("2023-10-02 09:00:00"),
+    })
+    .await
+    .unwrap();
+    pool.add_issues_comment(IssueComment {
+        issue_id: issue_id(repo, n),
+        comment_creator: login.to_string(),
+        comment_date: String::from("2023-10-02 10:00:00"),
+        comment_body: String::from("I'll take this"),
+    })
+    .await
+    .unwrap();
+    pool.add_issues_closed(IssueClosed {
+        issue_id: issue_id(repo, n),
+        issue_assignees: Some(vec![login.to_string()]),
+        issue_linked_pr: Some(pull_id(repo, n)),
+    })
+    .await
+    .unwrap();
+    pool.add_pull_request(merged_pull(repo, n, login))
+        .await
+        .unwrap();
+}
+
+// the_runner::merge_steps, in its order
+async fn join(pool: &Pool) {
+    pool.open_master().await.unwrap();
+    pool.assigned_master().await.unwrap();
+    pool.closed_master().await.unwrap();
+    pool.advance_review_states().await.unwrap();
+    pool.score_risks().await.unwrap();
+    pool.master_project().await.unwrap();
+    pool.sum_budget_to_project().await.unwrap();
+}
+
+// the_runner::purge_steps
+async fn cleanup(pool: &Pool) {
+    pool.remove_pull_by_issued_linked_pr().await.unwrap();
+    pool.delete_issues_open_assigned_closed().await.unwrap();
+}
+
+// Moves the repo's issues in issues_master into a new campaign, funded with 1000
+// USD, so its tiers, caps and money are the test's own.
+async fn own_campaign(pool: &Pool, repo: &str) -> String {
+    let campaign_id = format!("test-{:08x}", rand::random::<u32>());
+    fund_campaign(pool, &campaign_id, 1000, Currency::Usd, "test", None)
+        .await
+        .unwrap();
+    let mut conn = pool.get_conn().await.unwrap();
+    conn.exec_drop(
+        "UPDATE issues_master SET campaign_id = :campaign_id WHERE project_id = :project_id",
+        params! { "campaign_id" => &campaign_id, "project_id" => repo },
+    )
+    .await
+    .unwrap();
+    campaign_id
+}
+
+// Rows of `table` whose `column` is `value`.
+async fn staged_rows(pool: &Pool, table: &str, column: &str, value: &str) -> usize {
+    let mut conn = pool.get_conn().await.unwrap();
+    let count: Option<usize> = conn
+        .exec_first(
+            format!("SELECT COUNT(*) FROM {} WHERE {} = :value", table, column),
+            params! { "value" => value },
+        )
+        .await
+        .unwrap();
+    count.unwrap_or_default()
+}
+
+// The reviewers every test runs with. Rita has two tokens, `post` sends the first.
+fn bearer(token: &str) -> Vec<(String, String)> {
+    std::env::set_var(
+        "GOSIM_REVIEWERS",
+        "rita:rita-token,RITA:rita-token-2,bob:bob-token",
+    );
+    vec![(String::from("Authorization"), format!("Bearer {}", token))]
+}
+
+async fn get(pool: &Pool, path: &str, qry: &[(&str, &str)]) -> ApiResponse {
+    let qry: HashMap<String, Value> = qry.iter().map(|(k, v)| (k.to_string(), json!(v))).collect();
+    route(pool, "GET", path, &[], &qry, b"").await
+}
+
+async fn get_as(pool: &Pool, token: &str, path: &str, qry: &[(&str, &str)]) -> ApiResponse {
+    let qry: HashMap<String, Value> = qry.iter().map(|(k, v)| (k.to_string(), json!(v))).collect();
+    route(pool, "GET", path, &bearer(token), &qry, b"").await
+}
+
+async fn post_as(pool: &Pool, token: &str, path: &str, body: Value) -> ApiResponse {
+    route(
+        pool,
+        "POST",
+        path,
+        &bearer(token),
+        &HashMap::new(),
+        body.to_string().as_bytes(),
+    )
+    .await
+}
+
+async fn post(pool: &Pool, path: &str, body: Value) -> ApiResponse {
+    post_as(pool, "rita-token", path, body).await
+}
+
+fn body_json(res: &ApiResponse) -> Value {
+    serde_json::from_slice(&res.body).unwrap()
+}
+
+async fn repo_issues(pool: &Pool, repo: &str) -> Vec<IssueOut> {
+    let query = IssueQuery {
+        filter: Some(IssueFilter::Project(repo.to_string())),
+        ..Default::default()
+    };
+    pool.list_issues_by_multi(&query, 1, 50).await.unwrap()
+}
+
+async fn master_issue(pool: &Pool, repo: &str, n: u32) -> IssueOut {
+    repo_issues(pool, repo)
+        .await
+        .into_iter()
+        .find(|issue| issue.issue_id == issue_id(repo, n))
+        .expect("issue is in issues_master")
+}
+
+async fn issues_list(pool: &Pool, repo: &str) -> Option<Vec<String>> {
+    let mut conn = pool.get_conn().await.unwrap();
+    let list: Option<Option<String>> = conn
+        .exec_first(
+            "SELECT issues_list FROM projects WHERE project_id = :project_id",
+            params! { "project_id" => repo },
+        )
+        .await
+        .unwrap();
+    list.flatten()
+        .map(|list| serde_json::from_str(&list).unwrap())
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn join_merges_staging_into_master() {
+    let pool = pool().await;
+    let repo = unique_repo();
+    stage_worked_issue(&pool, &repo, 1, "alice").await;
+    pool.add_issues_open(&open_issue(&repo, 2)).await.unwrap();
+
+    join(&pool).await;
+
+    assert_eq!(repo_issues(&pool, &repo).await.len(), 2);
+    let worked = master_issue(&pool, &repo, 1).await;
+    assert_eq!(worked.issue_linked_pr, Some(pull_id(&repo, 1)));
+    assert!(worked.issue_assignees.unwrap_or_default().contains("alice"));
+    // no budget was approved, so assigning and linking don't move the review
+    assert_eq!(worked.review_state, ReviewState::Queued);
+    assert_eq!(
+        master_issue(&pool, &repo, 2).await.review_state,
+        ReviewState::Queued
+    );
+    assert_eq!(
+        issues_list(&pool, &repo).await,
+        Some(vec![issue_id(&repo, 1), issue_id(&repo, 2)])
+    );
+
+    // nothing new is staged, a second join leaves the rows as they are
+    join(&pool).await;
+    assert_eq!(repo_issues(&pool, &repo).await.len(), 2);
+    assert_eq!(
+        issues_list(&pool, &repo).await,
+        Some(vec![issue_id(&repo, 1), issue_id(&repo, 2)])
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn cleanup_purges_what_master_took_over() {
+    let pool = pool().await;
+    let repo = unique_repo();
+    stage_worked_issue(&pool, &repo, 1, "alice").await;
+    join(&pool).await;
+
+    // staged after the join, cleanup must not purge them before master has them
+    pool.add_issues_open(&open_issue(&repo, 3)).await.unwrap();
+    pool.add_pull_request(merged_pull(&repo, 4, "bob"))
+        .await
+        .unwrap();
+
+    cleanup(&pool).await;
+    let issue_1 = issue_id(&repo, 1);
+    for table in ["issues_open", "issues_assigned", "issues_closed"] {
+        assert_eq!(
+            staged_rows(&pool, table, "issue_id", &issue_1).await,
+            0,
+            "{}",
+            table
+        );
+    }
+    assert!(pool.pull_request_exists(&pull_id(&repo, 1)).await.is_err());
+    // no issue links pull request 4
+    assert!(pool.pull_request_exists(&pull_id(&repo, 4)).await.unwrap());
+    // issue 3 is staged still, unless a join of another test took it over
+    let issue_3 = issue_id(&repo, 3);
+    let staged = staged_rows(&pool, "issues_open", "issue_id", &issue_3).await;
+    let merged = staged_rows(&pool, "issues_master", "issue_id", &issue_3).await;
+    assert!(staged + merged > 0, "issue 3 was purged before the merge");
+
+    join(&pool).await;
+    cleanup(&pool).await;
+    assert_eq!(
+        staged_rows(&pool, "issues_open", "project_id", &repo).await,
+        0
+    );
+    assert_eq!(repo_issues(&pool, &repo).await.len(), 2);
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn budget_review_through_routes() {
+    let pool = pool().await;
+    let repo = unique_repo();
+    stage_worked_issue(&pool, &repo, 1, "alice").await;
+    pool.add_issues_open(&open_issue(&repo, 2)).await.unwrap();
+    pool.open_master().await.unwrap();
+    let campaign_id = own_campaign(&pool, &repo).await;
+
+    let res = post(
+        &pool,
+        "/budget",
+        json!({ "issue_id": issue_id(&repo, 1), "issue_budget": 150 }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+
+    // the next run sees the assignment and the pull request
+    join(&pool).await;
+    let issue = master_issue(&pool, &repo, 1).await;
+    assert_eq!(issue.review_state, ReviewState::PrLinked);
+    assert_eq!(issue.issue_budget, Some(150));
+
+    let res = post(
+        &pool,
+        "/conclude",
+        json!({ "issue_id": issue_id(&repo, 1), "issue_budget_approved": true }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+    assert!(master_issue(&pool, &repo, 1).await.issue_budget_approved);
+
+    let res = post(&pool, "/states", json!({ "issue_id": issue_id(&repo, 1) })).await;
+    assert_eq!(res.status, 200);
+    let states: Vec<String> = body_json(&res)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["to_state"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        states,
+        ["approved", "in_progress", "pr_linked", "concluded"]
+    );
+
+    let res = post(&pool, "/history", json!({ "issue_id": issue_id(&repo, 1) })).await;
+    assert_eq!(res.status, 200);
+    let actors: Vec<Value> = body_json(&res)
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|action| action["actor"].clone())
+        .collect();
+    assert_eq!(actors, [json!("rita"), json!("rita")]);
+
+    let res = post(&pool, "/ledger", json!({ "issue_id": issue_id(&repo, 1) })).await;
+    assert_eq!(res.status, 200);
+    let txn_ids: Vec<Value> = body_json(&res)["transactions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|txn| txn["txn_id"].clone())
+        .collect();
+    assert!(txn_ids.len() > 1, "{:?}", txn_ids);
+
+    // the same transactions a page at a time
+    let res = post(
+        &pool,
+        "/ledger",
+        json!({ "issue_id": issue_id(&repo, 1), "page": 2, "page_size": 1 }),
+    )
+    .await;
+    let page: Vec<Value> = body_json(&res)["transactions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|txn| txn["txn_id"].clone())
+        .collect();
+    assert_eq!(page, txn_ids[1..2]);
+
+    let res = post(
+        &pool,
+        "/decline",
+        json!({ "issue_ids": [issue_id(&repo, 2)] }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        master_issue(&pool, &repo, 2).await.review_state,
+        ReviewState::Declined
+    );
+
+    let res = get_as(
+        &pool,
+        "rita-token",
+        "/stats/budget",
+        &[("campaign", &campaign_id)],
+    )
+    .await;
+    assert_eq!(res.status, 200);
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn routes_map_errors_to_status_codes() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+
+    let res = get(&pool, "/issues", &[("page", "1"), ("page_size", "10")]).await;
+    assert_eq!(res.status, 200);
+    assert!(!body_json(&res).as_array().unwrap().is_empty());
+
+    // only closed reviews can be reopened
+    let res = post(
+        &pool,
+        "/review",
+        json!({ "issue_id": issue_id(&repo, 1), "action": "reopen" }),
+    )
+    .await;
+    assert_eq!(res.status, 409);
+    let res = post(
+        &pool,
+        "/review",
+        json!({ "issue_id": issue_id(&repo, 1), "action": "pay" }),
+    )
+    .await;
+    assert_eq!(res.status, 400);
+
+    let res = post(&pool, "/issue", json!({ "issue_id": issue_id(&repo, 9) })).await;
+    assert_eq!(res.status, 404);
+    let res = post(
+        &pool,
+        "/conclude",
+        json!({ "issue_id": issue_id(&repo, 9), "issue_budget_approved": true }),
+    )
+    .await;
+    assert_eq!(res.status, 404);
+    let res = post(
+        &pool,
+        "/decline",
+        json!({ "issue_ids": [issue_id(&repo, 9)] }),
+    )
+    .await;
+    assert_eq!(res.status, 404);
+    let audit = AuditInfo::new(Some(String::from("rita")), None);
+    let declined = pool.decline_issue_in_db(&issue_id(&repo, 9), &audit).await;
+    assert!(
+        matches!(declined, Err(Error::NotFound(_))),
+        "{:?}",
+        declined
+    );
+    let res = post(&pool, "/budget", json!("not an object")).await;
+    assert_eq!(res.status, 400);
+    let res = get(&pool, "/projects", &[]).await;
+    assert_eq!(res.status, 400);
+    let res = route(&pool, "DELETE", "/issues", &[], &HashMap::new(), b"").await;
+    assert_eq!(res.status, 405);
+    let res = get(&pool, "/nowhere", &[]).await;
+    assert_eq!(res.status, 404);
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn admin_actions_need_a_reviewer_token() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    own_campaign(&pool, &repo).await;
+
+    let body = json!({ "issue_id": issue_id(&repo, 1), "issue_budget": 150, "actor": "rita" });
+    let res = route(
+        &pool,
+        "POST",
+        "/budget",
+        &[],
+        &HashMap::new(),
+        body.to_string().as_bytes(),
+    )
+    .await;
+    assert_eq!(res.status, 401);
+    for path in [
+        "/budget",
+        "/decline",
+        "/conclude",
+        "/review",
+        "/adjust",
+        "/history",
+        "/states",
+        "/ledger",
+        "/export",
+    ] {
+        let res = post_as(&pool, "not-a-token", path, body.clone()).await;
+        assert_eq!(res.status, 401, "{}", path);
+    }
+    for path in ["/runs", "/stats/budget"] {
+        let res = get(&pool, path, &[]).await;
+        assert_eq!(res.status, 401, "{}", path);
+    }
+    assert_eq!(
+        master_issue(&pool, &repo, 1).await.review_state,
+        ReviewState::Queued
+    );
+
+    // the body's actor is ignored, the token says who voted
+    let res = post_as(&pool, "bob-token", "/budget", body).await;
+    assert_eq!(res.status, 200);
+    let res = post(&pool, "/history", json!({ "issue_id": issue_id(&repo, 1) })).await;
+    assert_eq!(body_json(&res)[0]["actor"], json!("bob"));
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn duplicate_and_case_variant_votes_are_rejected() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    let campaign_id = own_campaign(&pool, &repo).await;
+    set_tier(&pool, &campaign_id, 0, Some(2)).await.unwrap();
+    let vote = json!({ "issue_id": issue_id(&repo, 1), "issue_budget": 150 });
+
+    let res = post(&pool, "/budget", vote.clone()).await;
+    assert_eq!(res.status, 202);
+    // the open vote only shows to reviewers
+    let issue = json!({ "issue_id": issue_id(&repo, 1) });
+    let res = post(&pool, "/issue", issue.clone()).await;
+    assert_eq!(
+        body_json(&res)["pending_votes"][0]["reviewer"],
+        json!("rita")
+    );
+    let res = post_as(&pool, "not-a-token", "/issue", issue).await;
+    assert_eq!(res.status, 200);
+    assert_eq!(body_json(&res)["pending_votes"], json!([]));
+    let res = post(&pool, "/budget", vote.clone()).await;
+    assert_eq!(res.status, 409);
+    // RITA is stored as rita, so her second token doesn't make a second reviewer
+    let res = post_as(&pool, "rita-token-2", "/budget", vote.clone()).await;
+    assert_eq!(res.status, 409);
+    assert_eq!(
+        master_issue(&pool, &repo, 1).await.review_state,
+        ReviewState::Queued
+    );
+
+    let res = post_as(&pool, "bob-token", "/budget", vote).await;
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        master_issue(&pool, &repo, 1).await.review_state,
+        ReviewState::Approved
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn moves_keep_the_quorum_and_concluded_budgets_stay() {
+    let pool = pool().await;
+    let repo = unique_repo();
+    pool.add_issues_open(&open_issue(&repo, 1)).await.unwrap();
+    pool.add_issues_open(&open_issue(&repo, 2)).await.unwrap();
+    join(&pool).await;
+    let campaign_id = own_campaign(&pool, &repo).await;
+    set_tier(&pool, &campaign_id, 200, Some(2)).await.unwrap();
+    for (n, issue_budget) in [(1, 150), (2, 100)] {
+        let res = post(
+            &pool,
+            "/budget",
+            json!({ "issue_id": issue_id(&repo, n), "issue_budget": issue_budget }),
+        )
+        .await;
+        assert_eq!(res.status, 200);
+    }
+
+    // 200 needs two reviewers, a move can't approve it with none
+    let move_to_2 = |amount: i64| {
+        json!({
+            "issue_id": issue_id(&repo, 1),
+            "action": "move",
+            "to_issue_id": issue_id(&repo, 2),
+            "amount": amount,
+        })
+    };
+    let res = post(&pool, "/adjust", move_to_2(100)).await;
+    assert_eq!(res.status, 400);
+    assert_eq!(master_issue(&pool, &repo, 1).await.issue_budget, Some(150));
+    assert_eq!(master_issue(&pool, &repo, 2).await.issue_budget, Some(100));
+
+    let res = post(&pool, "/adjust", move_to_2(50)).await;
+    assert_eq!(res.status, 200);
+    assert_eq!(master_issue(&pool, &repo, 1).await.issue_budget, Some(100));
+    assert_eq!(master_issue(&pool, &repo, 2).await.issue_budget, Some(150));
+
+    let res = post(
+        &pool,
+        "/conclude",
+        json!({ "issue_id": issue_id(&repo, 1), "issue_budget_approved": true }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+    let res = post(
+        &pool,
+        "/adjust",
+        json!({ "issue_id": issue_id(&repo, 1), "action": "clawback" }),
+    )
+    .await;
+    assert_eq!(res.status, 409);
+    assert_eq!(
+        master_issue(&pool, &repo, 1).await.review_state,
+        ReviewState::Concluded
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn contributor_cap_counts_every_currency() {
+    let pool = pool().await;
+    let repo = unique_repo();
+    // a login of its own, caps count what a contributor holds
+    let alice = format!("alice-{:08x}", rand::random::<u32>());
+    for (n, currency) in [(1, Currency::Usd), (2, Currency::Eur)] {
+        pool.add_issues_open(&IssueOpen {
+            issue_budget_currency: currency,
+            ..open_issue(&repo, n)
+        })
+        .await
+        .unwrap();
+        pool.add_issues_assigned(IssueAssigned {
+            issue_id: issue_id(&repo, n),
+            issue_assignee: alice.clone(),
+            date_assigned: String::from("2023-10-02 09:00:00"),
+        })
+        .await
+        .unwrap();
+    }
+    join(&pool).await;
+    let campaign_id = own_campaign(&pool, &repo).await;
+    fund_campaign(&pool, &campaign_id, 1000, Currency::Eur, "test", None)
+        .await
+        .unwrap();
+    // no other test loads EUR rates
+    load_rates(
+        &pool,
+        &[ExchangeRate {
+            rate_date: String::from("2023-10-01"),
+            currency: Currency::Eur,
+            rate: 1.1,
+        }],
+    )
+    .await
+    .unwrap();
+    set_caps(
+        &pool,
+        &BudgetCaps {
+            campaign_id: campaign_id.clone(),
+            contributor_cap: Some(200),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let res = post(
+        &pool,
+        "/budget",
+        json!({ "issue_id": issue_id(&repo, 1), "issue_budget": 150 }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+
+    // 100 EUR is 110 USD, alice would hold 260 USD under a cap of 200
+    let res = post(
+        &pool,
+        "/budget",
+        json!({ "issue_id": issue_id(&repo, 2), "issue_budget": 100 }),
+    )
+    .await;
+    assert_eq!(res.status, 422);
+    let res = post(
+        &pool,
+        "/budget",
+        json!({ "issue_id": issue_id(&repo, 2), "issue_budget": 40 }),
+    )
+    .await;
+    assert_eq!(res.status, 200);
+    assert_eq!(
+        master_issue(&pool, &repo, 2).await.budget_headroom,
+        Some(45)
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs MySQL at TEST_DATABASE_URL"]
+async fn audited_updates_record_the_action_and_the_state_change() {
+    let pool = pool().await;
+    let repo = repo_with_issue(&pool).await;
+    own_campaign(&pool, &repo).await;
+    let issue_1 = issue_id(&repo, 1);
+
+    let rita = AuditInfo::new(Some(String::from(" Rita ")), Some(String::from("worth it")));
+    let bob = AuditInfo::new(Some(String::from("bob")), None);
+    pool.vote_issue_budget_in_db(&issue_1, 150, &rita)
+        .await
+        .unwrap();
+    pool.adjust_budget_in_db(&issue_1, 120, &bob).await.unwrap();
+    pool.decline_issue_in_db(&issue_1, &bob).await.unwrap();
+
+    let history = pool.list_issue_history(&issue_1).await.unwrap();
+    let actions: Vec<_> = history
+        .iter()
+        .map(|a| {
+            (
+                a.action.as_str(),
+                a.actor.as_str(),
+                a.admin_feedback.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            ("approve_budget", "rita", Some("worth it")),
+            ("adjust_budget", "bob", None),
+            ("decline", "bob", None),
+        ]
+    );
+    // each action has the issue as it was before and after it
+    let states: Vec<_> = history
+        .iter()
+        .map(|a| {
+            let (before, after) = (
+                a.before_state.clone().unwrap(),
+                a.after_state.clone().unwrap(),
+            );
+            (
+                (before.review_state, before.issue_budget),
+                (after.review_state, after.issue_budget),
+            )
+        })
+        .collect();
+    assert_eq!(
+        states,
+        [
+            (
+                (ReviewState::Queued, Some(100)),
+                (ReviewState::Approved, Some(150))
+            ),
+            (
+                (ReviewState::Approved, Some(150)),
+                (ReviewState::Approved, Some(120))
+            ),
+            (
+                (ReviewState::Approved, Some(120)),
+                (ReviewState::Declined, None)
+            ),
+        ]
+    );
+
+    // the adjust left the state alone, so only two changes were recorded
+    let changes: Vec<_> = pool
+        .list_state_changes(&issue_1)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.from_state, c.to_state, c.action, c.actor))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (
+                Some(ReviewState::Queued),
+                ReviewState::Approved,
+                String::from("approve"),
+                String::from("rita")
+            ),
+            (
+                Some(ReviewState::Approved),
+                ReviewState::Declined,
+                String::from("decline"),
+                String::from("bob")
+            ),
+        ]
+    );
+}
@@ -46,6 +46,8 @@ pub async fn inner(_body: Vec<u8>) -> anyhow::Result<()> {
     logger::init();
     let pool = get_pool().await;
     let _ = run_hourly(&pool).await;
+    // the last run of the day leaves the day's final state
+    let _ = run_snapshot(&pool, Utc::now().date_naive()).await;
 
     Ok(())
 }